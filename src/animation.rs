@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::metadata::Metadata;
+
+/// Flipbook mesh animation: frames with `mesh_animation` cycle their
+/// `mesh_file` through the listed files at `mesh_animation_hz`, each frame
+/// starting from the first file when it is first seen.
+#[derive(Debug, Clone, Default)]
+pub struct MeshAnimations {
+    start: HashMap<String, Instant>,
+}
+
+impl MeshAnimations {
+    /// Swap in the file of the current animation step at `now`.
+    pub fn apply(&mut self, frame: &str, metadata: &mut Metadata, now: Instant) {
+        let Some(files) = &metadata.mesh_animation else {
+            return;
+        };
+        if files.is_empty() {
+            return;
+        }
+        let start = *self.start.entry(frame.to_string()).or_insert(now);
+        let elapsed = now.duration_since(start).as_secs_f64();
+        let step = animation_step(elapsed, metadata.mesh_animation_hz, files.len());
+        metadata.mesh_file = Some(files[step].clone());
+    }
+
+    /// Forget frames that are gone, they restart from the first file if they return.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.start.retain(|frame, _| keep(frame));
+    }
+}
+
+/// Index of the file shown `elapsed_secs` into an animation of `len` files,
+/// advancing `hz` times per second. A rate of 0 stays on the first file.
+pub fn animation_step(elapsed_secs: f64, hz: f64, len: usize) -> usize {
+    if hz <= 0.0 || len == 0 {
+        return 0;
+    }
+    (elapsed_secs * hz).floor() as usize % len
+}
+
+/// Alpha of a zone pulsing `hz` times per second, `elapsed_secs` into the
+/// pulse: a sine between the ends of `range`, starting halfway. A rate of 0
+/// stays at the upper end.
+pub fn pulse_alpha(elapsed_secs: f64, hz: f64, range: [f32; 2]) -> f32 {
+    let [min, max] = range;
+    if hz <= 0.0 {
+        return max.clamp(0.0, 1.0);
+    }
+    let wave = (elapsed_secs * hz * std::f64::consts::TAU).sin() as f32;
+    (min + (max - min) * (wave + 1.0) / 2.0).clamp(0.0, 1.0)
+}
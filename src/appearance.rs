@@ -0,0 +1,115 @@
+use futures::{Stream, StreamExt};
+use r2r::std_msgs::msg::String as StringMsg;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+
+use crate::metadata::Metadata;
+
+/// Overrides applied to a frame's mesh marker while a given state is active.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Appearance {
+    pub mesh_file: Option<String>,
+    /// RGBA in 0.0..=1.0
+    pub color: Option<[f32; 4]>,
+}
+
+/// Swaps frame appearances based on the last value received on the state topic.
+///
+/// The mapping is loaded from a JSON file of the form
+/// `{ "<state>": { "<child_frame_id>": { "mesh_file": "...", "color": [r, g, b, a] } } }`.
+#[derive(Debug, Clone, Default)]
+pub struct StateAppearances {
+    mapping: HashMap<String, HashMap<String, Appearance>>,
+    state: Arc<Mutex<Option<String>>>,
+}
+
+impl StateAppearances {
+    pub fn from_file(path: &str) -> Result<StateAppearances, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(StateAppearances {
+            mapping: serde_json::from_str(&contents)?,
+            state: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Handle shared with the state topic subscriber.
+    pub fn state(&self) -> Arc<Mutex<Option<String>>> {
+        self.state.clone()
+    }
+
+    pub fn apply(&self, child_frame_id: &str, metadata: &mut Metadata) {
+        let state = self.state.lock().unwrap();
+        let Some(appearance) = state
+            .as_ref()
+            .and_then(|state| self.mapping.get(state))
+            .and_then(|frames| frames.get(child_frame_id))
+        else {
+            return;
+        };
+        if let Some(mesh_file) = &appearance.mesh_file {
+            metadata.mesh_file = Some(mesh_file.clone());
+        }
+        if let Some([r, g, b, a]) = appearance.color {
+            metadata.mesh_r = r;
+            metadata.mesh_g = g;
+            metadata.mesh_b = b;
+            metadata.mesh_a = a;
+        }
+    }
+}
+
+/// Make the states received on the state topic the active one, each in turn.
+pub async fn follow_states(
+    mut messages: impl Stream<Item = StringMsg> + Unpin,
+    state: Arc<Mutex<Option<String>>>,
+) {
+    while let Some(msg) = messages.next().await {
+        *state.lock().unwrap() = Some(msg.data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::decode_metadata;
+    use micro_sp::MapOrUnknown;
+
+    #[tokio::test]
+    async fn a_state_message_swaps_the_mesh_and_color() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mapping = serde_json::json!({
+            "busy": {
+                "gripper": { "mesh_file": "gripper_closed.dae", "color": [1.0, 0.0, 0.0, 1.0] }
+            }
+        });
+        std::fs::write(file.path(), mapping.to_string()).unwrap();
+        let appearances = StateAppearances::from_file(file.path().to_str().unwrap()).unwrap();
+        let stored = decode_metadata(&MapOrUnknown::UNKNOWN);
+
+        let mut metadata = stored.clone();
+        appearances.apply("gripper", &mut metadata);
+        assert_eq!(metadata, stored);
+
+        let states = ["idle", "busy"].map(|state| StringMsg {
+            data: state.to_string(),
+        });
+        follow_states(futures::stream::iter(states), appearances.state()).await;
+        appearances.apply("gripper", &mut metadata);
+        assert_eq!(metadata.mesh_file.as_deref(), Some("gripper_closed.dae"));
+        assert_eq!(
+            [
+                metadata.mesh_r,
+                metadata.mesh_g,
+                metadata.mesh_b,
+                metadata.mesh_a
+            ],
+            [1.0, 0.0, 0.0, 1.0]
+        );
+        // other frames keep their look
+        let mut table = stored.clone();
+        appearances.apply("table", &mut table);
+        assert_eq!(table, stored);
+    }
+}
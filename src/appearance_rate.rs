@@ -0,0 +1,60 @@
+use r2r::visualization_msgs::msg::Marker;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::markers::ADD;
+
+/// Holds back cosmetic marker changes, color and scale, to a slower rate than
+/// structural ones, so fast changing appearances don't make RViz rebuild
+/// materials every tick. A marker whose pose, type, frame, mesh, points or
+/// text changed goes out as is. One whose color or scale alone changed is sent
+/// with the appearance it last went out with until the next appearance update.
+#[derive(Debug, Clone)]
+pub struct AppearanceRate {
+    interval: Duration,
+    last_update: Option<Instant>,
+    /// The last sent version of each added marker, by (ns, id).
+    sent: HashMap<(String, i32), Marker>,
+}
+
+impl AppearanceRate {
+    pub fn new(hz: f64) -> AppearanceRate {
+        AppearanceRate {
+            interval: Duration::from_secs_f64(1.0 / hz),
+            last_update: None,
+            sent: HashMap::new(),
+        }
+    }
+
+    /// Apply the rate to the markers of one publish, `now` being its tick.
+    pub fn apply<'a>(&mut self, markers: impl Iterator<Item = &'a mut Marker>, now: Instant) {
+        let due = match self.last_update {
+            Some(last) => now.duration_since(last) >= self.interval,
+            None => true,
+        };
+        if due {
+            self.last_update = Some(now);
+        }
+        let mut sent = HashMap::new();
+        for marker in markers.filter(|m| m.action == ADD) {
+            let key = (marker.ns.clone(), marker.id);
+            if let Some(previous) = self.sent.get(&key) {
+                if !due && !structural_change(previous, marker) {
+                    marker.color = previous.color.clone();
+                    marker.scale = previous.scale.clone();
+                }
+            }
+            sent.insert(key, marker.clone());
+        }
+        self.sent = sent;
+    }
+}
+
+fn structural_change(previous: &Marker, marker: &Marker) -> bool {
+    previous.header.frame_id != marker.header.frame_id
+        || previous.type_ != marker.type_
+        || previous.pose != marker.pose
+        || previous.mesh_resource != marker.mesh_resource
+        || previous.points != marker.points
+        || previous.text != marker.text
+}
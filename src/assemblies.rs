@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+
+use redis::aio::MultiplexedConnection;
+
+use crate::log_targets;
+
+/// Assemblies currently hidden, the members of the Redis set at `key`.
+///
+/// Hide an assembly with `SADD <key> <assembly>` and show it again with
+/// `SREM <key> <assembly>`, the markers of its frames are deleted/re-added on
+/// the next tick. Read failures are logged and hide nothing.
+pub async fn hidden_assemblies(con: &mut MultiplexedConnection, key: &str) -> HashSet<String> {
+    match redis::cmd("SMEMBERS")
+        .arg(key)
+        .query_async::<HashSet<String>>(con)
+        .await
+    {
+        Ok(hidden) => hidden,
+        Err(e) => {
+            log::error!(target: log_targets::REDIS,
+                "Failed to read the hidden assemblies from '{}' with: {}", key, e
+            );
+            HashSet::new()
+        }
+    }
+}
+
+/// Whether a frame belongs to a hidden assembly.
+pub fn in_hidden_assembly(assembly: Option<&str>, hidden: &HashSet<String>) -> bool {
+    assembly.is_some_and(|assembly| hidden.contains(assembly))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use micro_sp::*;
+    use r2r::visualization_msgs::msg::Marker;
+
+    use crate::markers::{delete_hidden, ADD, DELETE};
+    use crate::metadata::decode_metadata;
+
+    /// The actions sent for one marker per frame, each frame in `assemblies`.
+    fn actions(assemblies: &[Option<&str>], hidden: &HashSet<String>) -> Vec<i32> {
+        let string = |value: &str| SPValue::String(StringOrUnknown::String(value.to_string()));
+        let mut markers = vec![];
+        let mut hidden_ids = HashSet::new();
+        for (id, assembly) in assemblies.iter().enumerate() {
+            let map = match assembly {
+                Some(assembly) => MapOrUnknown::Map(vec![(string("assembly"), string(assembly))]),
+                None => MapOrUnknown::UNKNOWN,
+            };
+            let metadata = decode_metadata(&map);
+            if in_hidden_assembly(metadata.assembly.as_deref(), hidden) {
+                hidden_ids.insert(id as i32);
+            }
+            markers.push(Marker {
+                id: id as i32,
+                action: ADD,
+                ..Marker::default()
+            });
+        }
+        delete_hidden(&mut markers, &hidden_ids);
+        markers.iter().map(|marker| marker.action).collect()
+    }
+
+    #[test]
+    fn hiding_an_assembly_hides_all_of_its_frames() {
+        let assemblies = [Some("arm"), Some("gantry"), Some("arm"), None];
+        let hidden = HashSet::from(["arm".to_string()]);
+        assert_eq!(actions(&assemblies, &hidden), [DELETE, ADD, DELETE, ADD]);
+        // shown again
+        assert_eq!(actions(&assemblies, &HashSet::new()), [ADD; 4]);
+    }
+}
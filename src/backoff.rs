@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+/// Exponential backoff between reconnection attempts, doubling from `min` up
+/// to `max` and back to `min` once a connection succeeds.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Backoff {
+        Backoff {
+            min,
+            max,
+            next: min,
+        }
+    }
+
+    /// How long to wait before the next attempt.
+    pub fn delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    /// Whether attempts failed since the last reset.
+    pub fn backing_off(&self) -> bool {
+        self.next != self.min
+    }
+
+    pub fn reset(&mut self) {
+        self.next = self.min;
+    }
+}
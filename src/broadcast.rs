@@ -0,0 +1,207 @@
+use r2r::geometry_msgs::msg::TransformStamped;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::{Duration, Instant};
+
+/// Tracks when each active frame was last broadcast on `tf`, and limits how
+/// often frames whose `max_broadcast_hz` is below their update rate are.
+#[derive(Debug, Clone, Default)]
+pub struct BroadcastLimiter {
+    last: HashMap<String, Instant>,
+}
+
+impl BroadcastLimiter {
+    /// Whether `frame` should be broadcast at `now`, which is then recorded as
+    /// its last broadcast. Frames without a limit (or a limit of 0) are always due.
+    pub fn due(&mut self, frame: &str, max_hz: Option<f64>, now: Instant) -> bool {
+        let period = match max_hz.filter(|hz| *hz > 0.0) {
+            Some(max_hz) => Duration::from_secs_f64(1.0 / max_hz),
+            None => Duration::ZERO,
+        };
+        match self.last.get(frame) {
+            Some(last) if now.duration_since(*last) < period => false,
+            _ => {
+                self.last.insert(frame.to_string(), now);
+                true
+            }
+        }
+    }
+
+    /// Whether `frame` was broadcast before, but not within `after` of `now`.
+    pub fn stale(&self, frame: &str, now: Instant, after: Duration) -> bool {
+        matches!(self.last.get(frame), Some(last) if now.duration_since(*last) > after)
+    }
+
+    /// Forget frames that are gone.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.last.retain(|frame, _| keep(frame));
+    }
+}
+
+/// Broadcasts the important frames every tick and all others every `every`th
+/// tick, for TF over bandwidth-limited links.
+#[derive(Debug, Clone)]
+pub struct Decimation {
+    /// Frame names, or prefixes when they end in `*`.
+    important: Vec<String>,
+    every: u64,
+}
+
+impl Decimation {
+    /// An `every` of 0 or 1 disables the decimation.
+    pub fn new(important: &[String], every: u64) -> Decimation {
+        Decimation {
+            important: important.to_vec(),
+            every: every.max(1),
+        }
+    }
+
+    pub fn is_important(&self, frame: &str) -> bool {
+        self.important.iter().any(|entry| match entry.strip_suffix('*') {
+            Some(prefix) => frame.starts_with(prefix),
+            None => frame == entry,
+        })
+    }
+
+    pub fn due(&self, frame: &str, tick: u64) -> bool {
+        tick % self.every == 0 || self.is_important(frame)
+    }
+}
+
+/// Remembers the last static transforms sent on `tf_static`, which is latched,
+/// so the set is only sent again when it changed.
+#[derive(Debug, Clone, Default)]
+pub struct StaticChanges {
+    last: Option<u64>,
+}
+
+impl StaticChanges {
+    /// Whether `transforms` differ from the last recorded set, always true
+    /// before the first.
+    pub fn changed(&self, transforms: &[TransformStamped]) -> bool {
+        self.last != Some(static_set_hash(transforms))
+    }
+
+    /// Record `transforms` as sent.
+    pub fn record(&mut self, transforms: &[TransformStamped]) {
+        self.last = Some(static_set_hash(transforms));
+    }
+}
+
+/// Hash of a set of transforms regardless of their order and stamps.
+fn static_set_hash(transforms: &[TransformStamped]) -> u64 {
+    let mut sorted: Vec<&TransformStamped> = transforms.iter().collect();
+    sorted.sort_by(|a, b| a.child_frame_id.cmp(&b.child_frame_id));
+    let mut hasher = DefaultHasher::new();
+    for transform in sorted {
+        let t = &transform.transform.translation;
+        let r = &transform.transform.rotation;
+        transform.header.frame_id.hash(&mut hasher);
+        transform.child_frame_id.hash(&mut hasher);
+        for value in [t.x, t.y, t.z, r.x, r.y, r.z, r.w] {
+            value.to_bits().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r2r::builtin_interfaces::msg::Time;
+    use r2r::geometry_msgs::msg::{Quaternion, Transform, Vector3};
+    use r2r::std_msgs::msg::Header;
+
+    #[test]
+    fn a_1hz_frame_is_broadcast_once_per_second() {
+        let mut limiter = BroadcastLimiter::default();
+        let start = Instant::now();
+        // updated at 10 Hz for 2.5 seconds
+        let due: Vec<u64> = (0..25)
+            .filter(|tick| {
+                let now = start + Duration::from_millis(tick * 100);
+                limiter.due("gripper", Some(1.0), now)
+            })
+            .collect();
+        assert_eq!(due, [0, 10, 20]);
+    }
+
+    #[test]
+    fn frames_without_a_limit_are_always_due() {
+        let mut limiter = BroadcastLimiter::default();
+        let now = Instant::now();
+        for max_hz in [None, Some(0.0), None] {
+            assert!(limiter.due("table", max_hz, now));
+        }
+    }
+
+    #[test]
+    fn important_frames_go_out_every_tick_and_the_rest_decimated() {
+        let important = ["gripper".to_string(), "robot_*".to_string()];
+        let decimation = Decimation::new(&important, 3);
+        let due = |frame: &str| -> Vec<u64> {
+            (0..7).filter(|tick| decimation.due(frame, *tick)).collect()
+        };
+        assert_eq!(due("gripper"), [0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(due("robot_base"), [0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(due("table"), [0, 3, 6]);
+        // only names ending in `*` are prefixes
+        assert_eq!(due("gripper_finger"), [0, 3, 6]);
+    }
+
+    #[test]
+    fn a_decimation_of_zero_or_one_sends_every_frame_every_tick() {
+        for every in [0, 1] {
+            let decimation = Decimation::new(&[], every);
+            assert!((0..5).all(|tick| decimation.due("table", tick)));
+        }
+    }
+
+    fn transform(parent: &str, child: &str, x: f64, sec: i32) -> TransformStamped {
+        TransformStamped {
+            header: Header {
+                stamp: Time { sec, nanosec: 0 },
+                frame_id: parent.to_string(),
+            },
+            child_frame_id: child.to_string(),
+            transform: Transform {
+                translation: Vector3 { x, y: 0.0, z: 0.0 },
+                rotation: Quaternion {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                    w: 1.0,
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn only_changed_static_sets_are_sent_again() {
+        let mut changes = StaticChanges::default();
+        let sent = [
+            transform("world", "table", 1.0, 1),
+            transform("table", "cup", 0.1, 1),
+        ];
+        assert!(changes.changed(&sent));
+        changes.record(&sent);
+        // reordered and restamped
+        let same = [
+            transform("table", "cup", 0.1, 2),
+            transform("world", "table", 1.0, 2),
+        ];
+        assert!(!changes.changed(&same));
+
+        let moved = [
+            transform("world", "table", 1.5, 2),
+            transform("table", "cup", 0.1, 2),
+        ];
+        assert!(changes.changed(&moved));
+        let reparented = [
+            transform("world", "table", 1.0, 2),
+            transform("world", "cup", 0.1, 2),
+        ];
+        assert!(changes.changed(&reparented));
+        assert!(changes.changed(&sent[..1]));
+    }
+}
@@ -0,0 +1,91 @@
+use std::str::FromStr;
+
+use micro_sp::*;
+
+use crate::markers::marker_id_for;
+use crate::metadata::{get_f64, stores_mesh_color, Metadata};
+use crate::scalars::map_scalar;
+
+/// Viridis at 0, 0.25, 0.5, 0.75 and 1, interpolated linearly in between,
+/// close enough to the full table for status colors.
+const VIRIDIS: [[f32; 3]; 5] = [
+    [0.267, 0.005, 0.329],
+    [0.231, 0.322, 0.545],
+    [0.129, 0.569, 0.553],
+    [0.369, 0.788, 0.384],
+    [0.993, 0.906, 0.144],
+];
+
+/// How `color_by` values become mesh colors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Colormap {
+    /// Blue at the low end to red at the high end.
+    BlueRed,
+    Viridis,
+}
+
+impl FromStr for Colormap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "blue_red" => Ok(Colormap::BlueRed),
+            "viridis" => Ok(Colormap::Viridis),
+            _ => Err(format!("unknown colormap '{}', expected blue_red or viridis", s)),
+        }
+    }
+}
+
+impl Colormap {
+    /// RGB at `t` in 0..1, clamped.
+    pub fn color(&self, t: f64) -> [f32; 3] {
+        let t = t.clamp(0.0, 1.0) as f32;
+        match self {
+            Colormap::BlueRed => [t, 0.0, 1.0 - t],
+            Colormap::Viridis => {
+                let position = t * (VIRIDIS.len() - 1) as f32;
+                let index = (position.floor() as usize).min(VIRIDIS.len() - 2);
+                let (low, high) = (VIRIDIS[index], VIRIDIS[index + 1]);
+                let fraction = position - index as f32;
+                [0, 1, 2].map(|i| low[i] + (high[i] - low[i]) * fraction)
+            }
+        }
+    }
+}
+
+/// RGB of `value` on `colormap`, `range` spanning it from end to end.
+pub fn value_color(value: f64, range: [f64; 2], colormap: Colormap) -> [f32; 3] {
+    colormap.color(map_scalar(value, range, [0.0, 1.0]))
+}
+
+/// Replace the mesh color of a frame with `color_by` by the color of the
+/// number its metadata `map` holds under that key, keeping the alpha. The
+/// color is left alone when the value is missing or the colormap unknown.
+pub fn apply_color_by(map: &MapOrUnknown, metadata: &mut Metadata) -> Result<(), String> {
+    let Some(key) = &metadata.color_by else {
+        return Ok(());
+    };
+    let colormap = match &metadata.colormap {
+        Some(name) => name.parse()?,
+        None => Colormap::BlueRed,
+    };
+    let value = get_f64(map, key).ok_or_else(|| format!("'{}' is missing or not a number", key))?;
+    [metadata.mesh_r, metadata.mesh_g, metadata.mesh_b] =
+        value_color(value, metadata.color_range, colormap);
+    Ok(())
+}
+
+/// Give a frame whose metadata `map` stores no mesh color one of `palette`,
+/// picked by its name, so each frame keeps its color from run to run.
+pub fn apply_palette(
+    palette: &[[f32; 4]],
+    child_frame_id: &str,
+    map: &MapOrUnknown,
+    metadata: &mut Metadata,
+) {
+    if palette.is_empty() || stores_mesh_color(map) {
+        return;
+    }
+    let index = marker_id_for("", child_frame_id) as usize % palette.len();
+    [metadata.mesh_r, metadata.mesh_g, metadata.mesh_b, metadata.mesh_a] = palette[index];
+}
@@ -0,0 +1,1107 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::error::VizError;
+use crate::geometry::{FrameConvention, LengthUnits};
+use crate::log_targets;
+use crate::metadata::parse_hex_color;
+use crate::outdated::OutdatedAction;
+use crate::protobuf::FrameFormat;
+use crate::tree::{CaseDuplicatePolicy, ParentConflictPolicy};
+use crate::{FRAME_LIFETIME_SECS, MARKER_PERIOD_MS};
+
+/// Defaults of PRESENTATION=on, a curated look for demos.
+pub const PRESENTATION_PRESET: &[(&str, &str)] = &[
+    ("SHOW_LABELS", "on"),
+    ("SHOW_AXES", "off"),
+    ("SMOOTH_TRANSFORMS", "on"),
+    ("MESH_PALETTE", "#4E79A7,#F28E2B,#E15759,#76B7B2,#59A14F,#EDC948,#B07AA1,#FF9DA7"),
+    ("DESPAWN_SECS", "0.5"),
+    ("SHOW_SHADOWS", "on"),
+    ("ZONE_TRANSITION_SECS", "0.5"),
+    ("HIGHLIGHT_SECS", "10"),
+];
+
+/// The root every tree is expected to hang from, unless WORLD_FRAME says otherwise.
+pub const DEFAULT_WORLD_FRAME: &str = "world";
+
+/// Added on top of the publish period when clamping marker lifetimes.
+const LIFETIME_MARGIN_SECS: f64 = 0.1;
+
+/// Runtime configuration, resolved once at startup from environment variables.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub meshes_dir: String,
+    /// Directories the scenario is loaded from, later ones overriding the
+    /// frames of earlier ones (SCENARIO_DIR, colon separated).
+    pub scenario_dirs: Vec<String>,
+    /// The root every tree is expected to hang from, other roots are dangling
+    /// parents (WORLD_FRAME).
+    pub world_frame: String,
+    /// Topic of the mesh markers (MESH_MARKERS_TOPIC).
+    pub mesh_markers_topic: String,
+    /// Topic of the zone markers (ZONE_MARKERS_TOPIC).
+    pub zone_markers_topic: String,
+    /// Topic of the active transforms (TF_TOPIC).
+    pub tf_topic: String,
+    /// Topic of the static transforms (TF_STATIC_TOPIC).
+    pub tf_static_topic: String,
+    /// Size in meters of primitive markers (CUBE/SPHERE/CYLINDER) that set
+    /// neither `primitive_size` nor a usable `mesh_scale` (DEFAULT_PRIMITIVE_SIZE).
+    pub default_primitive_size: f64,
+    /// Let primitives without `primitive_size` take their size from `mesh_scale`,
+    /// as older scenarios do (PRIMITIVE_SIZE_FROM_MESH_SCALE, default on).
+    pub primitive_size_from_mesh_scale: bool,
+    /// Seconds between info-level heartbeat logs, 0 disables them (HEARTBEAT_SECS).
+    pub heartbeat_secs: u64,
+    /// Read replica endpoints as `host:port`, used while the primary is unhealthy
+    /// (REDIS_REPLICAS, comma separated).
+    pub redis_replicas: Vec<String>,
+    /// std_msgs/String topic whose value selects frame appearances (STATE_TOPIC).
+    pub state_topic: Option<String>,
+    /// JSON mapping from state values to per-frame appearances (STATE_APPEARANCE_FILE).
+    pub state_appearance_file: Option<String>,
+    /// Publish a DiagnosticArray on `/diagnostics` (DIAGNOSTICS).
+    pub diagnostics: bool,
+    /// WARN below this many frames, 0 disables (DIAGNOSTICS_MIN_FRAMES).
+    pub diagnostics_min_frames: usize,
+    /// WARN above this many frames, 0 disables (DIAGNOSTICS_MAX_FRAMES).
+    pub diagnostics_max_frames: usize,
+    /// Project a flat shadow disc beneath each mesh onto the ground (SHOW_SHADOWS).
+    pub show_shadows: bool,
+    /// Height of the ground plane in the root frame (SHADOW_GROUND_HEIGHT).
+    pub shadow_ground_height: f64,
+    /// Shadow radius for meshes without a known footprint (SHADOW_RADIUS).
+    pub shadow_radius: f64,
+    /// `old=new` frame renames applied at publish time (FRAME_RENAMES, comma separated).
+    pub frame_renames: Vec<String>,
+    /// Publish markers less often while ticks overrun their period (ADAPTIVE_RATE).
+    pub adaptive_rate: bool,
+    /// Largest factor the marker rate is divided by (ADAPTIVE_RATE_MAX_DIVISOR).
+    pub adaptive_rate_max_divisor: u32,
+    /// Prefix of the Redis keys frames are stored under (TRANSFORM_KEY_PREFIX).
+    pub transform_key_prefix: String,
+    /// Show an indicator shrinking as a frame's key approaches expiry (SHOW_TTL).
+    pub show_ttl: bool,
+    /// Remaining TTL at and above which the indicator is full size (TTL_FULL_SECS).
+    pub ttl_full_secs: f64,
+    /// Diameter of a full size TTL indicator in meters (TTL_INDICATOR_SIZE).
+    pub ttl_indicator_size: f64,
+    /// Lifetime in seconds of mesh markers, 0 keeps them until replaced (MESH_LIFETIME).
+    pub mesh_lifetime: f64,
+    /// Lifetime in seconds of zone markers, 0 keeps them until replaced (ZONE_LIFETIME).
+    pub zone_lifetime: f64,
+    /// MQTT broker to mirror the scene to, requires the `mqtt` feature (MQTT_HOST).
+    pub mqtt_host: Option<String>,
+    pub mqtt_port: u16,
+    pub mqtt_topic: String,
+    /// Port of the HTTP status endpoint, requires the `http-status` feature
+    /// (HTTP_STATUS_PORT, 0 is off).
+    pub http_status_port: u16,
+    /// Address the HTTP status endpoint listens on (HTTP_STATUS_ADDRESS).
+    pub http_status_address: String,
+    /// Raise marker lifetimes shorter than the publish period so markers don't
+    /// flicker between publishes (CLAMP_LIFETIMES, default on).
+    pub clamp_lifetimes: bool,
+    /// Publish for rosbridge/ros3djs viewers, see the README (WEB_COMPAT).
+    pub web_compat: bool,
+    /// HTTP base URL serving MESHES_DIR to browsers (WEB_MESH_URL).
+    pub web_mesh_url: Option<String>,
+    /// Draw zones as flat discs on the floor instead of spheres (ZONE_2D).
+    pub zone_2d: bool,
+    /// Floor height in the root frame for flat zones (ZONE_FLOOR_HEIGHT).
+    pub zone_floor_height: f64,
+    /// Thickness of flat zones in meters (ZONE_2D_THICKNESS).
+    pub zone_2d_thickness: f64,
+    /// Read all frames in one Redis transaction for a consistent snapshot (ATOMIC_READS).
+    pub atomic_reads: bool,
+    /// Read all frames with one MGET instead of the per-key micro_sp reads (PIPELINED_READS).
+    pub pipelined_reads: bool,
+    /// Build everything but log a summary instead of publishing (DRY_RUN).
+    pub dry_run: bool,
+    /// Publish the scene in a short burst and then only every
+    /// ONE_SHOT_REPUBLISH_SECS, with markers that never expire (ONE_SHOT).
+    pub one_shot: bool,
+    /// Seconds between republishes after the ONE_SHOT burst, 0 never
+    /// republishes (ONE_SHOT_REPUBLISH_SECS).
+    pub one_shot_republish_secs: f64,
+    /// Stop the node after the ONE_SHOT burst (`--exit-after-publish`).
+    pub exit_after_publish: bool,
+    /// Stamp with the time on `/clock` instead of the ROS clock (USE_SIM_TIME).
+    pub use_sim_time: bool,
+    /// Only rebuild after Redis keyspace notifications for the frames (REDIS_NOTIFY).
+    pub redis_notify: bool,
+    /// Seconds between rebuilds without changes with REDIS_NOTIFY (REDIS_NOTIFY_HEARTBEAT_SECS).
+    pub redis_notify_heartbeat_secs: f64,
+    /// Log a validation report of the scene after loading it (STARTUP_REPORT, default on).
+    pub startup_report: bool,
+    /// Redis set of hidden assembly ids (ASSEMBLY_VISIBILITY_KEY).
+    pub assembly_visibility_key: Option<String>,
+    /// Redis set of hidden layers (LAYER_VISIBILITY_KEY).
+    pub layer_visibility_key: Option<String>,
+    /// Seconds over which zone size changes are animated, 0 snaps (ZONE_TRANSITION_SECS).
+    pub zone_transition_secs: f64,
+    /// Axis convention of the stored frames, converted to ENU for display
+    /// (FRAME_CONVENTION, ENU or NED, default ENU).
+    pub frame_convention: FrameConvention,
+    /// Unit of the translations in the scenario files, converted to meters
+    /// on load (LENGTH_UNITS, m, cm or mm, default m).
+    pub length_units: LengthUnits,
+    /// Scenario shown next to the live one, read from disk only (SECONDARY_SCENARIO_DIR).
+    pub secondary_scenario_dir: Option<String>,
+    /// Prefix of the secondary frames (SECONDARY_PREFIX, default `secondary/`).
+    pub secondary_prefix: String,
+    /// XY offset of the secondary roots (SECONDARY_OFFSET, `x,y`).
+    pub secondary_offset: [f64; 2],
+    /// RGB color of the secondary meshes (SECONDARY_TINT, `r,g,b`).
+    pub secondary_tint: [f32; 3],
+    /// Each frame logs a kind of warning at most once per interval (WARNING_INTERVAL_SECS).
+    pub warning_interval_secs: f64,
+    /// Publish the floor zones as an OccupancyGrid on `zone_grid` (ZONE_GRID).
+    pub zone_grid: bool,
+    /// Cell size of the zone grid in meters (ZONE_GRID_RESOLUTION).
+    pub zone_grid_resolution: f64,
+    /// Root frame the zone grid is built in, zones under other roots are left
+    /// out (ZONE_GRID_FRAME).
+    pub zone_grid_frame: String,
+    /// Log the unique mesh files of the scene at startup (MESH_WARMUP).
+    pub mesh_warmup: bool,
+    /// Markers follow their frame live, overridable with the `frame_locked`
+    /// metadata (FRAME_LOCKED, default on).
+    pub frame_locked: bool,
+    /// Tokio worker threads, 0 for one per core (TOKIO_WORKER_THREADS).
+    pub worker_threads: usize,
+    /// Run everything on a single-threaded runtime (CURRENT_THREAD_RUNTIME).
+    pub current_thread_runtime: bool,
+    /// Send a DELETEALL on the marker topics at startup, twice before the
+    /// first marker publish (CLEAR_ON_START, default on).
+    pub clear_on_start: bool,
+    /// Two frames `a,b` to draw the offset between on `delta_markers` (DELTA_FRAMES).
+    pub delta_frames: Vec<String>,
+    /// Publish every frame on `tf_static`, whether active or not (FORCE_STATIC).
+    pub force_static: bool,
+    /// Answer transform lookups on the `transform_requests` topic (TRANSFORM_LOOKUP_TOPICS).
+    pub transform_lookup_topics: bool,
+    /// Step published translations are snapped to, 0 is off (QUANTIZE_TRANSLATION).
+    pub quantize_translation: f64,
+    /// Step published quaternion components are snapped to, 0 is off (QUANTIZE_ROTATION).
+    pub quantize_rotation: f64,
+    /// Glide active transforms toward their latest pose instead of jumping
+    /// (SMOOTH_TRANSFORMS).
+    pub smooth_transforms: bool,
+    /// Fraction of the way to the latest pose moved per TF tick, 1 follows it
+    /// exactly (SMOOTHING_FACTOR).
+    pub smoothing_factor: f64,
+    /// Also publish the markers of each namespace on `markers/<ns>` (MARKER_GROUP_TOPICS).
+    pub marker_group_topics: bool,
+    /// Warn about frames deeper than this below their root, 0 is off (MAX_TREE_DEPTH).
+    pub max_tree_depth: usize,
+    /// How long a `highlight_frame` call highlights a frame (HIGHLIGHT_SECS).
+    pub highlight_secs: f64,
+    /// Edge of the highlight box around meshes, primitives get one slightly
+    /// larger than themselves (HIGHLIGHT_SIZE).
+    pub highlight_size: f64,
+    /// Publish appeared/disappeared/moved events on `frame_events` (FRAME_EVENTS).
+    pub frame_events: bool,
+    /// Meters a frame has to move for a moved event (FRAME_EVENT_THRESHOLD).
+    pub frame_event_threshold: f64,
+    /// Broadcast active frames other than IMPORTANT_FRAMES only every this many
+    /// ticks, 1 is every tick (TF_DECIMATION).
+    pub tf_decimation: u64,
+    /// Frames broadcast every tick regardless of TF_DECIMATION, `prefix*`
+    /// entries match by prefix (IMPORTANT_FRAMES).
+    pub important_frames: Vec<String>,
+    /// Anchor the markers of active frames not broadcast for this long in their
+    /// root frame, 0 is off (STALE_TF_SECS).
+    pub stale_tf_secs: f64,
+    /// Age in seconds of a frame's `last_updated` it counts as outdated at, 0
+    /// is off (MAX_TRANSFORM_AGE_SECS).
+    pub max_transform_age_secs: f64,
+    /// Skip or tint outdated frames (OUTDATED_TRANSFORMS, default tint).
+    pub outdated_transforms: OutdatedAction,
+    /// Publish the JSON scene summary on `tf_snapshot` (EXPORT_SNAPSHOT).
+    pub export_snapshot: bool,
+    /// Seconds between `tf_snapshot` messages (EXPORT_SNAPSHOT_SECS).
+    pub export_snapshot_secs: f64,
+    /// Publish mesh and zone markers together on `markers` (COMBINED_MARKERS).
+    pub combined_markers: bool,
+    /// Topic to publish the mesh, zone, label and axis markers together on
+    /// (COMBINED_MARKER_TOPIC).
+    pub combined_marker_topic: Option<String>,
+    /// Markers per MarkerArray message, larger arrays are split, 0 never
+    /// splits (MAX_MARKERS_PER_MSG).
+    pub max_markers_per_msg: usize,
+    /// Which definition of a child frame with several parents is published
+    /// (PARENT_CONFLICT_POLICY, error, first or last, default first).
+    pub parent_conflict_policy: ParentConflictPolicy,
+    /// Lowest alpha of added markers, keeps faint markers selectable, 0 is off
+    /// (MIN_MARKER_ALPHA, formerly MIN_ALPHA).
+    pub min_alpha: f32,
+    /// Seconds between TF tree statistics logs, 0 disables them (TREE_STATS_SECS).
+    pub tree_stats_secs: u64,
+    /// Frame names differing only by case, off, warn or merge (CASE_DUPLICATES).
+    pub case_duplicates: CaseDuplicatePolicy,
+    /// `pattern=roll pitch yaw` default mesh marker orientations (MESH_ORIENTATIONS).
+    pub mesh_orientations: Vec<String>,
+    /// How frames are serialized in Redis, json or protobuf (FRAME_FORMAT).
+    pub frame_format: FrameFormat,
+    /// Milliseconds between marker publishes (MARKER_PERIOD_MS), the `marker_period_ms`
+    /// ROS parameter wins when set, see `set_marker_period_ms`.
+    pub marker_period_ms: u64,
+    /// Milliseconds between reads of the frames and TF broadcasts (TF_PERIOD_MS,
+    /// default MARKER_PERIOD_MS), the `tf_period_ms` ROS parameter wins when set.
+    pub tf_period_ms: u64,
+    /// Frame drawn with axes and an approach arrow, e.g. a TCP (TOOL_FRAME).
+    pub tool_frame: Option<String>,
+    /// Frame the tool's pose is labeled in (TOOL_REFERENCE_FRAME).
+    pub tool_reference_frame: Option<String>,
+    /// Frame the level of detail distance is measured from (LOD_VIEWPOINT_FRAME).
+    pub lod_viewpoint_frame: Option<String>,
+    /// Frames further than this many meters from LOD_VIEWPOINT_FRAME lose
+    /// their mesh markers (LOD_MAX_DISTANCE, 0 is off).
+    pub lod_max_distance: f64,
+    /// Length of the tool axes in meters (TOOL_AXIS_LENGTH).
+    pub tool_axis_length: f64,
+    /// Rate of color and scale only marker changes, 0 sends them every publish
+    /// (APPEARANCE_UPDATE_HZ).
+    pub appearance_update_hz: f64,
+    /// Frame a reference grid is drawn in, e.g. a table surface (REFERENCE_GRID_FRAME).
+    pub reference_grid_frame: Option<String>,
+    /// Edge length of the square reference grid in meters (REFERENCE_GRID_SIZE).
+    pub reference_grid_size: f64,
+    /// Distance between grid lines in meters (REFERENCE_GRID_SPACING).
+    pub reference_grid_spacing: f64,
+    /// RGBA of the grid lines (REFERENCE_GRID_COLOR).
+    pub reference_grid_color: [f32; 4],
+    /// Seconds to hold the previous scene when reads suddenly return no frames,
+    /// 0 is off (EMPTY_READ_GRACE_SECS).
+    pub empty_read_grace_secs: f64,
+    /// Metadata key holding a per-frame confidence score (CONFIDENCE_KEY).
+    pub confidence_key: Option<String>,
+    /// Confidence scores mapped onto CONFIDENCE_ALPHA (CONFIDENCE_RANGE).
+    pub confidence_range: [f64; 2],
+    /// Alpha factors of the lowest and highest confidence (CONFIDENCE_ALPHA).
+    pub confidence_alpha: [f64; 2],
+    /// Frame the confidence legend is drawn in (CONFIDENCE_LEGEND_FRAME).
+    pub confidence_legend_frame: String,
+    /// Give frames whose hashed marker ids collide the next free id instead
+    /// (MARKER_ID_PROBING).
+    pub marker_id_probing: bool,
+    /// Prepended to every marker namespace and hashed into the marker ids,
+    /// for several instances in one viewer (MARKER_NS_PREFIX, empty is off).
+    pub marker_ns_prefix: String,
+    /// Frames drawn with a trail of their past orientations, `*` matches
+    /// anything (ORIENTATION_TRAIL_FRAMES).
+    pub orientation_trail_frames: Vec<String>,
+    /// Poses kept per trail frame (ORIENTATION_TRAIL_SAMPLES).
+    pub orientation_trail_samples: usize,
+    /// Seconds between trail samples (ORIENTATION_TRAIL_SECS).
+    pub orientation_trail_secs: f64,
+    /// Length in meters of the trail axes (ORIENTATION_TRAIL_LENGTH).
+    pub orientation_trail_length: f64,
+    /// Publish every transform from child to parent, inverted, for legacy
+    /// consumers expecting the reverse labeling (SWAP_PARENT_CHILD).
+    pub swap_parent_child: bool,
+    /// Frames written per pipelined round trip when loading the scenario, 0
+    /// loads them one by one through micro_sp (LOAD_BATCH_SIZE).
+    pub load_batch_size: usize,
+    /// Reload the scenario into Redis when its files change (WATCH_SCENARIO).
+    pub watch_scenario: bool,
+    /// Draw a line from every frame to its parent on `link_markers`, overridable
+    /// with the `show_parent_link` metadata (SHOW_PARENT_LINKS).
+    pub show_parent_links: bool,
+    /// Label every frame with its name, overridable with the `show_label`
+    /// metadata (SHOW_LABELS).
+    pub show_labels: bool,
+    /// Draw the axes of every frame, overridable with the `show_axes`
+    /// metadata (SHOW_AXES).
+    pub show_axes: bool,
+    /// Colors of the meshes whose metadata sets none, picked by frame name
+    /// (MESH_PALETTE, comma separated `#RRGGBB(AA)`, empty is off).
+    pub mesh_palette: Vec<[f32; 4]>,
+    /// Seconds the mesh and zone markers of vanished frames fade out over,
+    /// 0 deletes them at once (DESPAWN_SECS).
+    pub despawn_secs: f64,
+    /// Regex frames need to match to get markers, all frames stay on TF (FRAME_FILTER).
+    pub frame_filter: Option<String>,
+    /// Write the transforms other nodes publish on `tf_topic` into Redis (MIRROR_TF).
+    pub mirror_tf: bool,
+    /// Change in meters or quaternion components a mirrored frame is written
+    /// again after (MIRROR_TF_EPSILON).
+    pub mirror_tf_epsilon: f64,
+    /// Every resolved setting with where its value came from.
+    pub entries: Vec<ConfigEntry>,
+}
+
+impl Config {
+    /// Resolve the configuration from the process arguments and environment.
+    pub fn from_env() -> Result<Config, VizError> {
+        let args: Vec<String> = std::env::args().collect();
+        Config::from_settings(Settings::from_args(&args))
+    }
+
+    /// Fails only when the CONFIG_FILE can't be read, bad values fall back to
+    /// their defaults with a warning.
+    pub fn from_settings(mut env: Settings) -> Result<Config, VizError> {
+        if let Some(path) = env.opt("CONFIG_FILE") {
+            env.load_file(&path).map_err(|e| {
+                VizError::Config(format!("failed to read CONFIG_FILE {} with: {}", path, e))
+            })?;
+        }
+        if env.flag("PRESENTATION", false) {
+            env.apply_preset(PRESENTATION_PRESET);
+        }
+        // read by micro_sp itself, recorded so they show up in the dump
+        for name in ["REDIS_HOST", "REDIS_PORT", "REDIS_USERNAME", "REDIS_PASSWORD"] {
+            env.opt(name);
+        }
+        // shared default of both marker lifetimes, named after the former constant
+        env.rename("FRAME_LIFETIME", "MARKER_LIFETIME_SECS");
+        let marker_lifetime =
+            lifetime_setting(&mut env, "MARKER_LIFETIME_SECS", FRAME_LIFETIME_SECS);
+        // a period, not a rate, as the former name suggested
+        env.rename("MARKER_PUBLISH_RATE", "MARKER_PERIOD_MS");
+        let marker_period_ms = env.or("MARKER_PERIOD_MS", MARKER_PERIOD_MS);
+        // named like the other marker settings
+        env.rename("MIN_ALPHA", "MIN_MARKER_ALPHA");
+        let world_frame = env.or("WORLD_FRAME", DEFAULT_WORLD_FRAME.to_string());
+        let mut config = Config {
+            // a trailing slash would double up in the mesh URIs
+            meshes_dir: trim_dir(env.required("MESHES_DIR")),
+            scenario_dirs: env
+                .required("SCENARIO_DIR")
+                .split(':')
+                .filter(|dir| !dir.trim().is_empty())
+                .map(|dir| trim_dir(dir.trim().to_string()))
+                .collect(),
+            world_frame,
+            mesh_markers_topic: env.or("MESH_MARKERS_TOPIC", "mesh_markers".to_string()),
+            zone_markers_topic: env.or("ZONE_MARKERS_TOPIC", "zone_markers".to_string()),
+            tf_topic: env.or("TF_TOPIC", "tf".to_string()),
+            tf_static_topic: env.or("TF_STATIC_TOPIC", "tf_static".to_string()),
+            default_primitive_size: env.or("DEFAULT_PRIMITIVE_SIZE", 1.0),
+            primitive_size_from_mesh_scale: env.flag("PRIMITIVE_SIZE_FROM_MESH_SCALE", true),
+            heartbeat_secs: env.or("HEARTBEAT_SECS", 0),
+            redis_replicas: env.list("REDIS_REPLICAS"),
+            state_topic: env.opt("STATE_TOPIC"),
+            state_appearance_file: env.opt("STATE_APPEARANCE_FILE"),
+            diagnostics: env.flag("DIAGNOSTICS", false),
+            diagnostics_min_frames: env.or("DIAGNOSTICS_MIN_FRAMES", 0),
+            diagnostics_max_frames: env.or("DIAGNOSTICS_MAX_FRAMES", 0),
+            show_shadows: env.flag("SHOW_SHADOWS", false),
+            shadow_ground_height: env.or("SHADOW_GROUND_HEIGHT", 0.0),
+            shadow_radius: env.or("SHADOW_RADIUS", 0.2),
+            frame_renames: env.list("FRAME_RENAMES"),
+            adaptive_rate: env.flag("ADAPTIVE_RATE", false),
+            adaptive_rate_max_divisor: env.or("ADAPTIVE_RATE_MAX_DIVISOR", 8),
+            transform_key_prefix: env.or("TRANSFORM_KEY_PREFIX", "transform:".to_string()),
+            show_ttl: env.flag("SHOW_TTL", false),
+            ttl_full_secs: env.or("TTL_FULL_SECS", 10.0),
+            ttl_indicator_size: env.or("TTL_INDICATOR_SIZE", 0.1),
+            mesh_lifetime: lifetime_setting(&mut env, "MESH_LIFETIME", marker_lifetime),
+            zone_lifetime: lifetime_setting(&mut env, "ZONE_LIFETIME", marker_lifetime),
+            mqtt_host: env.opt("MQTT_HOST"),
+            mqtt_port: env.or("MQTT_PORT", 1883),
+            mqtt_topic: env.or("MQTT_TOPIC", "r2r_redis_visualization/scene".to_string()),
+            http_status_port: env.or("HTTP_STATUS_PORT", 0),
+            http_status_address: env.or("HTTP_STATUS_ADDRESS", "127.0.0.1".to_string()),
+            clamp_lifetimes: env.flag("CLAMP_LIFETIMES", true),
+            web_compat: env.flag("WEB_COMPAT", false),
+            web_mesh_url: env.opt("WEB_MESH_URL"),
+            zone_2d: env.flag("ZONE_2D", false),
+            zone_floor_height: env.or("ZONE_FLOOR_HEIGHT", 0.0),
+            zone_2d_thickness: env.or("ZONE_2D_THICKNESS", 0.01),
+            atomic_reads: env.flag("ATOMIC_READS", false),
+            pipelined_reads: env.flag("PIPELINED_READS", false),
+            dry_run: env.flag("DRY_RUN", false),
+            one_shot: env.flag("ONE_SHOT", false),
+            one_shot_republish_secs: env.or("ONE_SHOT_REPUBLISH_SECS", 10.0),
+            exit_after_publish: false,
+            use_sim_time: env.flag("USE_SIM_TIME", false),
+            redis_notify: env.flag("REDIS_NOTIFY", false),
+            redis_notify_heartbeat_secs: env.or("REDIS_NOTIFY_HEARTBEAT_SECS", 1.0),
+            startup_report: env.flag("STARTUP_REPORT", true),
+            assembly_visibility_key: env.opt("ASSEMBLY_VISIBILITY_KEY"),
+            layer_visibility_key: env.opt("LAYER_VISIBILITY_KEY"),
+            zone_transition_secs: env.or("ZONE_TRANSITION_SECS", 0.0),
+            frame_convention: env.or("FRAME_CONVENTION", FrameConvention::Enu),
+            length_units: env.or("LENGTH_UNITS", LengthUnits::Meters),
+            secondary_scenario_dir: env.opt("SECONDARY_SCENARIO_DIR"),
+            secondary_prefix: env.or("SECONDARY_PREFIX", "secondary/".to_string()),
+            secondary_offset: numbers("SECONDARY_OFFSET", env.list("SECONDARY_OFFSET"), [5.0, 0.0]),
+            secondary_tint: numbers("SECONDARY_TINT", env.list("SECONDARY_TINT"), [0.3, 0.5, 1.0])
+                .map(|c| c as f32),
+            warning_interval_secs: env.or("WARNING_INTERVAL_SECS", 60.0),
+            zone_grid: env.flag("ZONE_GRID", false),
+            zone_grid_resolution: env.or("ZONE_GRID_RESOLUTION", 0.05),
+            zone_grid_frame: env.or("ZONE_GRID_FRAME", world_frame.clone()),
+            mesh_warmup: env.flag("MESH_WARMUP", false),
+            frame_locked: env.flag("FRAME_LOCKED", true),
+            worker_threads: env.or("TOKIO_WORKER_THREADS", 0),
+            current_thread_runtime: env.flag("CURRENT_THREAD_RUNTIME", false),
+            clear_on_start: env.flag("CLEAR_ON_START", true),
+            delta_frames: env.list("DELTA_FRAMES"),
+            force_static: env.flag("FORCE_STATIC", false),
+            transform_lookup_topics: env.flag("TRANSFORM_LOOKUP_TOPICS", false),
+            quantize_translation: env.or("QUANTIZE_TRANSLATION", 0.0),
+            quantize_rotation: env.or("QUANTIZE_ROTATION", 0.0),
+            smooth_transforms: env.flag("SMOOTH_TRANSFORMS", false),
+            smoothing_factor: env.or("SMOOTHING_FACTOR", 0.3),
+            marker_group_topics: env.flag("MARKER_GROUP_TOPICS", false),
+            max_tree_depth: env.or("MAX_TREE_DEPTH", 64),
+            highlight_secs: env.or("HIGHLIGHT_SECS", 5.0),
+            highlight_size: env.or("HIGHLIGHT_SIZE", 0.3),
+            frame_events: env.flag("FRAME_EVENTS", false),
+            frame_event_threshold: env.or("FRAME_EVENT_THRESHOLD", 0.01),
+            tf_decimation: env.or("TF_DECIMATION", 1),
+            important_frames: env.list("IMPORTANT_FRAMES"),
+            stale_tf_secs: env.or("STALE_TF_SECS", 0.0),
+            max_transform_age_secs: env.or("MAX_TRANSFORM_AGE_SECS", 0.0),
+            outdated_transforms: env.or("OUTDATED_TRANSFORMS", OutdatedAction::Tint),
+            combined_markers: env.flag("COMBINED_MARKERS", false),
+            combined_marker_topic: env.opt("COMBINED_MARKER_TOPIC"),
+            export_snapshot: env.flag("EXPORT_SNAPSHOT", false),
+            export_snapshot_secs: env.or("EXPORT_SNAPSHOT_SECS", 1.0),
+            max_markers_per_msg: env.or("MAX_MARKERS_PER_MSG", 256),
+            parent_conflict_policy: env.or("PARENT_CONFLICT_POLICY", ParentConflictPolicy::First),
+            min_alpha: env.or("MIN_MARKER_ALPHA", 0.0),
+            tree_stats_secs: env.or("TREE_STATS_SECS", 0),
+            case_duplicates: env.or("CASE_DUPLICATES", CaseDuplicatePolicy::Off),
+            mesh_orientations: env.list("MESH_ORIENTATIONS"),
+            frame_format: env.or("FRAME_FORMAT", FrameFormat::Json),
+            marker_period_ms,
+            tf_period_ms: env.or("TF_PERIOD_MS", marker_period_ms),
+            tool_frame: env.opt("TOOL_FRAME"),
+            tool_reference_frame: env.opt("TOOL_REFERENCE_FRAME"),
+            lod_viewpoint_frame: env.opt("LOD_VIEWPOINT_FRAME"),
+            lod_max_distance: env.or("LOD_MAX_DISTANCE", 0.0),
+            tool_axis_length: env.or("TOOL_AXIS_LENGTH", 0.1),
+            appearance_update_hz: env.or("APPEARANCE_UPDATE_HZ", 0.0),
+            reference_grid_frame: env.opt("REFERENCE_GRID_FRAME"),
+            reference_grid_size: env.or("REFERENCE_GRID_SIZE", 1.0),
+            reference_grid_spacing: env.or("REFERENCE_GRID_SPACING", 0.1),
+            reference_grid_color: numbers(
+                "REFERENCE_GRID_COLOR",
+                env.list("REFERENCE_GRID_COLOR"),
+                [0.7, 0.7, 0.7, 0.5],
+            )
+            .map(|c| c as f32),
+            empty_read_grace_secs: env.or("EMPTY_READ_GRACE_SECS", 0.0),
+            confidence_key: env.opt("CONFIDENCE_KEY"),
+            confidence_range: numbers("CONFIDENCE_RANGE", env.list("CONFIDENCE_RANGE"), [0.0, 1.0]),
+            confidence_alpha: numbers("CONFIDENCE_ALPHA", env.list("CONFIDENCE_ALPHA"), [0.2, 1.0]),
+            confidence_legend_frame: env.or("CONFIDENCE_LEGEND_FRAME", world_frame.clone()),
+            marker_id_probing: env.flag("MARKER_ID_PROBING", true),
+            marker_ns_prefix: env.or("MARKER_NS_PREFIX", String::new()),
+            orientation_trail_frames: env.list("ORIENTATION_TRAIL_FRAMES"),
+            orientation_trail_samples: env.or("ORIENTATION_TRAIL_SAMPLES", 10),
+            orientation_trail_secs: env.or("ORIENTATION_TRAIL_SECS", 0.5),
+            orientation_trail_length: env.or("ORIENTATION_TRAIL_LENGTH", 0.1),
+            swap_parent_child: env.flag("SWAP_PARENT_CHILD", false),
+            load_batch_size: env.or("LOAD_BATCH_SIZE", 500),
+            watch_scenario: env.flag("WATCH_SCENARIO", false),
+            show_parent_links: env.flag("SHOW_PARENT_LINKS", false),
+            show_labels: env.flag("SHOW_LABELS", false),
+            show_axes: env.flag("SHOW_AXES", false),
+            mesh_palette: hex_colors("MESH_PALETTE", env.list("MESH_PALETTE")),
+            despawn_secs: env.or("DESPAWN_SECS", 0.0),
+            frame_filter: env.opt("FRAME_FILTER"),
+            mirror_tf: env.flag("MIRROR_TF", false),
+            mirror_tf_epsilon: env.or("MIRROR_TF_EPSILON", 1e-4),
+            entries: vec![],
+        };
+        if !config.delta_frames.is_empty() && config.delta_frames.len() != 2 {
+            log::warn!(target: log_targets::NODE,
+                "Ignoring DELTA_FRAMES, expected two frames like 'a,b'."
+            );
+            config.delta_frames.clear();
+        }
+        if config.clamp_lifetimes {
+            let period = config.marker_period_secs();
+            config.mesh_lifetime = clamp_lifetime("MESH_LIFETIME", config.mesh_lifetime, period);
+            config.zone_lifetime = clamp_lifetime("ZONE_LIFETIME", config.zone_lifetime, period);
+            env.resolve("MESH_LIFETIME", config.mesh_lifetime);
+            env.resolve("ZONE_LIFETIME", config.zone_lifetime);
+        }
+        if config.one_shot {
+            // nothing refreshes the markers, so they must not expire
+            config.mesh_lifetime = 0.0;
+            config.zone_lifetime = 0.0;
+            env.resolve("MESH_LIFETIME", config.mesh_lifetime);
+            env.resolve("ZONE_LIFETIME", config.zone_lifetime);
+        }
+        config.entries = env.entries;
+        Ok(config)
+    }
+
+    /// The effective configuration, one `NAME = value (source)` line per
+    /// setting, with secrets redacted.
+    pub fn dump(&self) -> String {
+        let mut lines = vec!["Effective configuration:".to_string()];
+        for entry in &self.entries {
+            let secret = ["PASSWORD", "SECRET", "TOKEN"]
+                .iter()
+                .any(|word| entry.name.contains(word));
+            let value = match (&entry.value, secret) {
+                (Some(_), true) => "<redacted>",
+                (Some(value), false) => value.as_str(),
+                (None, _) => "<unset>",
+            };
+            lines.push(format!("  {} = {} ({:?})", entry.name, value, entry.source));
+        }
+        lines.join("\n")
+    }
+
+    /// SCENARIO_DIR as paths, in load order.
+    pub fn scenario_paths(&self) -> Vec<std::path::PathBuf> {
+        self.scenario_dirs.iter().map(std::path::PathBuf::from).collect()
+    }
+
+    /// Check that MESHES_DIR and SCENARIO_DIR are set and are readable
+    /// directories, with an error naming the first that isn't.
+    pub fn check_dirs(&self) -> Result<(), String> {
+        if self.scenario_dirs.is_empty() {
+            return Err("SCENARIO_DIR is not set".to_string());
+        }
+        let scenario_dirs = self.scenario_dirs.iter().map(|dir| ("SCENARIO_DIR", dir));
+        for (name, dir) in std::iter::once(("MESHES_DIR", &self.meshes_dir)).chain(scenario_dirs) {
+            if dir.is_empty() {
+                return Err(format!("{} is not set", name));
+            }
+            let path = std::path::Path::new(dir);
+            if !path.exists() {
+                return Err(format!("{} '{}' does not exist", name, dir));
+            }
+            if !path.is_dir() {
+                return Err(format!("{} '{}' is not a directory", name, dir));
+            }
+            if let Err(e) = std::fs::read_dir(path) {
+                return Err(format!("{} '{}' is not readable: {}", name, dir, e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that MARKER_PERIOD_MS and TF_PERIOD_MS are positive, a timer
+    /// can't tick every 0ms.
+    pub fn check_periods(&self) -> Result<(), String> {
+        let periods = [
+            ("MARKER_PERIOD_MS", self.marker_period_ms),
+            ("TF_PERIOD_MS", self.tf_period_ms),
+        ];
+        for (name, period_ms) in periods {
+            if period_ms == 0 {
+                return Err(format!("{} must be a positive number of milliseconds, got 0", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// The configurable topic names with their settings, by setting name.
+    pub fn topics(&self) -> [(&'static str, &str); 4] {
+        [
+            ("MESH_MARKERS_TOPIC", &self.mesh_markers_topic),
+            ("ZONE_MARKERS_TOPIC", &self.zone_markers_topic),
+            ("TF_TOPIC", &self.tf_topic),
+            ("TF_STATIC_TOPIC", &self.tf_static_topic),
+        ]
+    }
+
+    /// Seconds between the ONE_SHOT republishes, 0 with `--exit-after-publish`.
+    pub fn one_shot_idle_secs(&self) -> f64 {
+        if self.exit_after_publish {
+            0.0
+        } else {
+            self.one_shot_republish_secs
+        }
+    }
+
+    /// The single topic the mesh and zone markers share, COMBINED_MARKER_TOPIC
+    /// or `markers` with COMBINED_MARKERS. None publishes them separately.
+    pub fn combined_topic(&self) -> Option<&str> {
+        match &self.combined_marker_topic {
+            Some(topic) => Some(topic),
+            None if self.combined_markers => Some("markers"),
+            None => None,
+        }
+    }
+
+    pub fn check_topics(&self) -> Result<(), String> {
+        for (name, topic) in self.topics() {
+            if topic.trim().is_empty() {
+                return Err(format!("{} must name a topic, got an empty name", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Longest time between two marker publishes, including adaptive slowdown.
+    pub fn marker_period_secs(&self) -> f64 {
+        let divisor = if self.adaptive_rate {
+            self.adaptive_rate_max_divisor.max(1)
+        } else {
+            1
+        };
+        self.marker_period_ms as f64 / 1000.0 * divisor as f64
+    }
+
+    /// Use the tick period read from the node parameters, raising the clamped
+    /// lifetimes again should the period have grown.
+    pub fn set_marker_period_ms(&mut self, period_ms: u64) {
+        self.marker_period_ms = period_ms;
+        if self.clamp_lifetimes {
+            let period = self.marker_period_secs();
+            self.mesh_lifetime = clamp_lifetime("MESH_LIFETIME", self.mesh_lifetime, period);
+            self.zone_lifetime = clamp_lifetime("ZONE_LIFETIME", self.zone_lifetime, period);
+        }
+    }
+}
+
+/// Raise a lifetime to at least one publish period plus a margin, 0 (never
+/// expire) is left as is.
+pub fn clamp_lifetime(name: &str, lifetime: f64, period_secs: f64) -> f64 {
+    let min = period_secs + LIFETIME_MARGIN_SECS;
+    if lifetime > 0.0 && lifetime < min {
+        log::warn!(target: log_targets::NODE,
+            "{} of {}s is shorter than the publish period, using {}s.", name, lifetime, min
+        );
+        min
+    } else {
+        lifetime
+    }
+}
+
+/// A lifetime in seconds, which may have a fraction. Negative and non-finite
+/// values fall back to `default` with a warning, rather than to 0, which
+/// keeps markers forever.
+fn lifetime_setting(env: &mut Settings, name: &str, default: f64) -> f64 {
+    let secs = env.or(name, default);
+    if secs.is_finite() && secs >= 0.0 {
+        return secs;
+    }
+    log::warn!(target: log_targets::NODE,
+        "Ignoring {} of {}, a lifetime is 0 (never expire) or a positive number of seconds.",
+        name, secs
+    );
+    env.resolve(name, default);
+    default
+}
+
+/// The frequency in Hz of a period in milliseconds.
+pub fn period_hz(period_ms: u64) -> f64 {
+    1000.0 / period_ms as f64
+}
+
+/// A directory without trailing slashes, `/` stays as it is.
+fn trim_dir(dir: String) -> String {
+    match dir.trim_end_matches('/') {
+        "" if dir.starts_with('/') => "/".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+/// A fixed number of comma separated numbers, the default when unset or when
+/// the list doesn't fit (with a warning).
+fn numbers<const N: usize>(name: &str, list: Vec<String>, default: [f64; N]) -> [f64; N] {
+    if list.is_empty() {
+        return default;
+    }
+    let parsed: Vec<f64> = list.iter().filter_map(|v| v.parse().ok()).collect();
+    match <[f64; N]>::try_from(parsed) {
+        Ok(values) => values,
+        Err(_) => {
+            log::warn!(target: log_targets::NODE,
+                "Ignoring {}, expected {} comma separated numbers.", name, N
+            );
+            default
+        }
+    }
+}
+
+/// The colors of a list of `#RRGGBB(AA)` strings, invalid ones are logged
+/// and left out.
+fn hex_colors(name: &str, list: Vec<String>) -> Vec<[f32; 4]> {
+    list.iter()
+        .filter_map(|color| match parse_hex_color(color) {
+            Ok(color) => Some(color),
+            Err(e) => {
+                log::warn!(target: log_targets::NODE, "Ignoring a color of {}: {}.", name, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// A scalar TOML value as the string an env var would hold.
+fn toml_setting(value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(value) => Ok(value.clone()),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        value => Err(format!("unsupported setting value {}", value)),
+    }
+}
+
+/// Where the value of a setting came from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Default,
+    Preset,
+    File,
+    Env,
+    Flag,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    pub name: String,
+    /// None when an optional setting is unset
+    pub value: Option<String>,
+    pub source: Source,
+}
+
+/// Raw settings, from `--set NAME=VALUE` command line flags, which take
+/// precedence, environment variables and the CONFIG_FILE. Records every
+/// setting it resolves.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    flags: HashMap<String, String>,
+    file: HashMap<String, String>,
+    preset: HashMap<String, String>,
+    /// Former names of renamed settings, by their current name.
+    renamed: HashMap<String, String>,
+    entries: Vec<ConfigEntry>,
+}
+
+impl Settings {
+    pub fn from_args(args: &[String]) -> Settings {
+        let mut flags = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--set" {
+                match args.next().and_then(|pair| pair.split_once('=')) {
+                    Some((name, value)) => {
+                        flags.insert(name.trim().to_string(), value.to_string());
+                    }
+                    None => log::warn!(target: log_targets::NODE,
+                        "Ignoring --set without a NAME=VALUE argument."
+                    ),
+                }
+            }
+        }
+        Settings {
+            flags,
+            file: HashMap::new(),
+            preset: HashMap::new(),
+            renamed: HashMap::new(),
+            entries: vec![],
+        }
+    }
+
+    /// Read settings from a TOML file of `name = value` entries, named like
+    /// the environment variables in lower case, e.g. `meshes_dir = "..."`.
+    /// Arrays are read as comma separated lists. Flags and env vars still win.
+    pub fn load_file(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let table: toml::Table = std::fs::read_to_string(path)?.parse()?;
+        for (name, value) in table {
+            let value = match value {
+                toml::Value::Array(items) => items
+                    .iter()
+                    .map(toml_setting)
+                    .collect::<Result<Vec<String>, String>>()?
+                    .join(","),
+                value => toml_setting(&value)?,
+            };
+            self.file.insert(name.to_uppercase(), value);
+        }
+        Ok(())
+    }
+
+    /// Replace the defaults of the given settings, flags and env vars still win.
+    pub fn apply_preset(&mut self, preset: &[(&str, &str)]) {
+        for (name, value) in preset {
+            self.preset.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    /// Keep reading a setting under its former name, with a warning, while the
+    /// current name isn't set.
+    pub fn rename(&mut self, former: &str, name: &str) {
+        if self.raw(name).is_none() && self.raw(former).is_some() {
+            log::warn!(target: log_targets::NODE,
+                "{} has been renamed to {}, please update your setup.", former, name
+            );
+        }
+        self.renamed.insert(name.to_string(), former.to_string());
+    }
+
+    fn raw(&self, name: &str) -> Option<(String, Source)> {
+        if let Some(value) = self.flags.get(name) {
+            return Some((value.clone(), Source::Flag));
+        }
+        if let Ok(value) = std::env::var(name) {
+            return Some((value, Source::Env));
+        }
+        if let Some(value) = self.file.get(name) {
+            return Some((value.clone(), Source::File));
+        }
+        if let Some(value) = self.preset.get(name) {
+            return Some((value.clone(), Source::Preset));
+        }
+        self.renamed.get(name).and_then(|former| self.raw(former))
+    }
+
+    fn record(&mut self, name: &str, value: Option<String>, source: Source) {
+        self.entries.push(ConfigEntry {
+            name: name.to_string(),
+            value,
+            source,
+        });
+    }
+
+    /// Replace the recorded value of a setting that was adjusted after reading.
+    fn resolve(&mut self, name: &str, value: impl Display) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.name == name) {
+            entry.value = Some(value.to_string());
+        }
+    }
+
+    /// A setting that must be given, empty when it is missing, which
+    /// `Config::check_dirs` reports.
+    pub fn required(&mut self, name: &str) -> String {
+        match self.raw(name) {
+            Some((value, source)) => {
+                self.record(name, Some(value.clone()), source);
+                value
+            }
+            None => {
+                self.record(name, None, Source::Default);
+                String::new()
+            }
+        }
+    }
+
+    /// An optional string setting, unset and empty both mean None.
+    pub fn opt(&mut self, name: &str) -> Option<String> {
+        match self.raw(name) {
+            Some((value, source)) if !value.trim().is_empty() => {
+                let value = value.trim().to_string();
+                self.record(name, Some(value.clone()), source);
+                Some(value)
+            }
+            _ => {
+                self.record(name, None, Source::Default);
+                None
+            }
+        }
+    }
+
+    /// Parse a setting, falling back to `default` when it is unset or cannot
+    /// be parsed. Unparsable values are logged.
+    pub fn or<T: FromStr + Display>(&mut self, name: &str, default: T) -> T {
+        let (value, source) = match self.raw(name) {
+            Some((value, source)) => match value.trim().parse::<T>() {
+                Ok(parsed) => (parsed, source),
+                Err(_) => {
+                    log::warn!(target: log_targets::NODE,
+                        "Ignoring invalid value '{}' for {}, using the default.", value, name
+                    );
+                    (default, Source::Default)
+                }
+            },
+            None => (default, Source::Default),
+        };
+        self.record(name, Some(value.to_string()), source);
+        value
+    }
+
+    /// Read a boolean flag like `SHOW_SHADOWS=1`. Accepts 1/0, true/false, on/off, yes/no.
+    pub fn flag(&mut self, name: &str, default: bool) -> bool {
+        let (value, source) = match self.raw(name) {
+            Some((value, source)) => match value.trim().to_lowercase().as_str() {
+                "1" | "true" | "on" | "yes" => (true, source),
+                "0" | "false" | "off" | "no" | "" => (false, source),
+                _ => {
+                    log::warn!(target: log_targets::NODE,
+                        "Ignoring invalid flag '{}' for {}, using the default.", value, name
+                    );
+                    (default, Source::Default)
+                }
+            },
+            None => (default, Source::Default),
+        };
+        self.record(name, Some(value.to_string()), source);
+        value
+    }
+
+    /// Read a comma separated list, empty entries are dropped.
+    pub fn list(&mut self, name: &str) -> Vec<String> {
+        let (raw, source) = self
+            .raw(name)
+            .unwrap_or_else(|| (String::new(), Source::Default));
+        let list: Vec<String> = raw
+            .split(',')
+            .map(|entry| entry.trim().to_string())
+            .filter(|entry| !entry.is_empty())
+            .collect();
+        self.record(name, Some(list.join(",")), source);
+        list
+    }
+}
+
+/// The configuration with `--set` flags, which win over any env vars of the
+/// test process.
+#[cfg(test)]
+pub fn config_with(settings: &[&str]) -> Config {
+    let mut args = vec!["r2r_redis_visualization".to_string()];
+    for setting in settings {
+        args.push("--set".to_string());
+        args.push(setting.to_string());
+    }
+    Config::from_settings(Settings::from_args(&args)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(config: &Config, name: &str) -> Source {
+        config
+            .entries
+            .iter()
+            .find(|e| e.name == name)
+            .unwrap()
+            .source
+    }
+
+    #[test]
+    fn lifetimes_keep_fractions_and_reject_negatives() {
+        let config = config_with(&["MARKER_LIFETIME_SECS=0.5"]);
+        assert_eq!(config.mesh_lifetime, 0.5);
+        assert_eq!(config.zone_lifetime, 0.5);
+        let config = config_with(&["MARKER_LIFETIME_SECS=-1", "ZONE_LIFETIME=inf"]);
+        assert_eq!(config.mesh_lifetime, FRAME_LIFETIME_SECS);
+        assert_eq!(config.zone_lifetime, FRAME_LIFETIME_SECS);
+    }
+
+    #[test]
+    fn presentation_applies_its_defaults() {
+        let config = config_with(&["PRESENTATION=on"]);
+        assert!(config.show_labels);
+        assert!(!config.show_axes);
+        assert!(config.smooth_transforms);
+        assert_eq!(config.mesh_palette.len(), 8);
+        assert_eq!(config.despawn_secs, 0.5);
+        assert!(config.show_shadows);
+        assert_eq!(config.zone_transition_secs, 0.5);
+        assert_eq!(config.highlight_secs, 10.0);
+        assert_eq!(source(&config, "SHOW_LABELS"), Source::Preset);
+
+        let config = config_with(&["PRESENTATION=off"]);
+        assert!(!config.show_labels);
+        assert!(!config.smooth_transforms);
+        assert!(config.mesh_palette.is_empty());
+        assert_eq!(config.despawn_secs, 0.0);
+    }
+
+    #[test]
+    fn explicit_settings_win_over_presentation() {
+        let config = config_with(&[
+            "PRESENTATION=on",
+            "SHOW_LABELS=off",
+            "SHOW_AXES=on",
+            "DESPAWN_SECS=2",
+            "MESH_PALETTE=#FF0000",
+        ]);
+        assert!(!config.show_labels);
+        assert!(config.show_axes);
+        assert_eq!(config.despawn_secs, 2.0);
+        assert_eq!(config.mesh_palette, vec![[1.0, 0.0, 0.0, 1.0]]);
+        assert_eq!(source(&config, "SHOW_LABELS"), Source::Flag);
+        // the rest of the preset still applies
+        assert!(config.smooth_transforms);
+    }
+
+    #[test]
+    fn an_unreadable_config_file_is_an_error() {
+        let args = [
+            "r2r_redis_visualization",
+            "--set",
+            "CONFIG_FILE=/nonexistent/viz.toml",
+        ]
+        .map(String::from);
+        let config = Config::from_settings(Settings::from_args(&args));
+        assert!(matches!(config, Err(VizError::Config(_))));
+    }
+
+    #[test]
+    fn periods_convert_to_hz() {
+        assert_eq!(period_hz(20), 50.0);
+        assert_eq!(period_hz(50), 20.0);
+        assert_eq!(period_hz(1000), 1.0);
+    }
+
+    #[test]
+    fn zero_periods_are_rejected() {
+        assert!(config_with(&["MARKER_PERIOD_MS=20"])
+            .check_periods()
+            .is_ok());
+        assert!(config_with(&["MARKER_PERIOD_MS=0"])
+            .check_periods()
+            .is_err());
+        let config = config_with(&["MARKER_PERIOD_MS=20", "TF_PERIOD_MS=0"]);
+        assert!(config.check_periods().is_err());
+    }
+
+    #[test]
+    fn the_former_rate_setting_is_read_as_a_period() {
+        let config = config_with(&["MARKER_PUBLISH_RATE=100"]);
+        assert_eq!(config.marker_period_ms, 100);
+        // the TF period follows the marker period unless set
+        assert_eq!(config.tf_period_ms, 100);
+    }
+
+    #[test]
+    fn the_former_alpha_floor_name_is_still_read() {
+        assert_eq!(config_with(&[]).min_alpha, 0.0);
+        assert_eq!(config_with(&["MIN_ALPHA=0.3"]).min_alpha, 0.3);
+        let both = config_with(&["MIN_ALPHA=0.3", "MIN_MARKER_ALPHA=0.5"]);
+        assert_eq!(both.min_alpha, 0.5);
+    }
+
+    #[test]
+    fn a_config_file_fills_in_the_config() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("viz.toml");
+        let toml = r#"
+            meshes_dir = "/data/meshes/"
+            scenario_dir = "/data/transforms"
+            marker_period_ms = 100
+            frame_lifetime = 5.5
+            mesh_markers_topic = "cell/meshes"
+            tf_static_topic = "cell/tf_static"
+            show_shadows = true
+            important_frames = ["gripper", "robot1/*"]
+        "#;
+        std::fs::write(&path, toml).unwrap();
+        let config_file = format!("CONFIG_FILE={}", path.display());
+        let config = config_with(&[&config_file, "TF_PERIOD_MS=20"]);
+
+        assert_eq!(config.meshes_dir, "/data/meshes");
+        assert_eq!(config.scenario_dirs, ["/data/transforms"]);
+        assert_eq!(config.marker_period_ms, 100);
+        assert_eq!(config.mesh_lifetime, 5.5);
+        assert_eq!(config.zone_lifetime, 5.5);
+        assert_eq!(config.mesh_markers_topic, "cell/meshes");
+        assert_eq!(config.zone_markers_topic, "zone_markers");
+        assert_eq!(config.tf_static_topic, "cell/tf_static");
+        assert!(config.show_shadows);
+        assert_eq!(config.important_frames, ["gripper", "robot1/*"]);
+        assert_eq!(source(&config, "MARKER_PERIOD_MS"), Source::File);
+        // flags win over the file
+        assert_eq!(config.tf_period_ms, 20);
+        assert_eq!(source(&config, "TF_PERIOD_MS"), Source::Flag);
+    }
+}
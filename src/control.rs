@@ -0,0 +1,91 @@
+use futures::{Stream, StreamExt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::log_targets;
+
+/// Whether the publish loop is paused through the `control` topic. While
+/// paused, ticks are skipped entirely, nothing is read or published.
+#[derive(Debug, Clone, Default)]
+pub struct PublishControl {
+    paused: Arc<AtomicBool>,
+}
+
+impl PublishControl {
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Apply a `pause` or `resume` command, an Err for anything else.
+    /// Returns whether the state changed.
+    pub fn command(&self, command: &str) -> Result<bool, String> {
+        let pause = match command.trim() {
+            "pause" => true,
+            "resume" => false,
+            other => return Err(format!("unknown control command '{}'", other)),
+        };
+        Ok(self.paused.swap(pause, Ordering::Relaxed) != pause)
+    }
+}
+
+/// Follow `pause`/`resume` commands on the `control` topic.
+pub async fn serve_control(
+    mut commands: impl Stream<Item = r2r::std_msgs::msg::String> + Unpin,
+    control: PublishControl,
+) {
+    while let Some(msg) = commands.next().await {
+        match control.command(&msg.data) {
+            Ok(true) if control.paused() => {
+                log::info!(target: log_targets::SERVICES,
+                    "Publishing paused, no transforms or markers until 'resume'."
+                );
+            }
+            Ok(true) => {
+                log::info!(target: log_targets::SERVICES, "Publishing resumed.");
+            }
+            Ok(false) => (),
+            Err(e) => {
+                log::warn!(target: log_targets::SERVICES,
+                    "Ignoring {}, expected 'pause' or 'resume'.", e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r2r::std_msgs::msg::String as StringMsg;
+
+    async fn send(control: &PublishControl, commands: &[&str]) {
+        let messages = commands.iter().map(|command| StringMsg {
+            data: command.to_string(),
+        });
+        serve_control(futures::stream::iter(messages), control.clone()).await;
+    }
+
+    #[tokio::test]
+    async fn publishing_stops_while_paused_and_resumes_after() {
+        let control = PublishControl::default();
+        // what the server's ticks see, through their own clone
+        let server = control.clone();
+        assert!(!server.paused());
+        send(&control, &["pause"]).await;
+        assert!(server.paused());
+        // unknown commands change nothing
+        send(&control, &["stop", " pause\n"]).await;
+        assert!(server.paused());
+        send(&control, &["resume"]).await;
+        assert!(!server.paused());
+    }
+
+    #[test]
+    fn commands_report_whether_they_changed_the_state() {
+        let control = PublishControl::default();
+        assert_eq!(control.command("pause"), Ok(true));
+        assert_eq!(control.command("pause"), Ok(false));
+        assert_eq!(control.command("resume"), Ok(true));
+        assert!(control.command("halt").is_err());
+    }
+}
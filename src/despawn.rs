@@ -0,0 +1,110 @@
+use r2r::visualization_msgs::msg::Marker;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::markers::ADD;
+
+/// Fades out and shrinks the markers of vanished frames over DESPAWN_SECS
+/// instead of deleting them at once. One per marker topic.
+#[derive(Debug)]
+pub struct Despawns {
+    duration: Duration,
+    /// The markers added on the last publish, by namespace and id.
+    last: HashMap<(String, i32), Marker>,
+    /// Vanished markers, with when they vanished.
+    fading: HashMap<(String, i32), (Marker, Instant)>,
+}
+
+impl Despawns {
+    pub fn new(secs: f64) -> Despawns {
+        Despawns {
+            duration: Duration::from_secs_f64(secs.max(0.0)),
+            last: HashMap::new(),
+            fading: HashMap::new(),
+        }
+    }
+
+    /// Add a fading copy of every marker that was added on the last publish
+    /// but is missing from `markers`, until it has faded out and is left to
+    /// be deleted. A marker that comes back stops fading.
+    pub fn apply(&mut self, markers: &mut Vec<Marker>, now: Instant) {
+        let present: HashSet<(String, i32)> =
+            markers.iter().map(|m| (m.ns.clone(), m.id)).collect();
+        for (key, marker) in self.last.drain() {
+            if !present.contains(&key) {
+                self.fading.entry(key).or_insert((marker, now));
+            }
+        }
+        self.last = markers
+            .iter()
+            .filter(|m| m.action == ADD)
+            .map(|m| ((m.ns.clone(), m.id), m.clone()))
+            .collect();
+        let duration = self.duration;
+        self.fading.retain(|key, (_, since)| {
+            !present.contains(key) && now.duration_since(*since) < duration
+        });
+        for (marker, since) in self.fading.values() {
+            let left = 1.0 - now.duration_since(*since).as_secs_f64() / duration.as_secs_f64();
+            let mut faded = marker.clone();
+            faded.color.a *= left as f32;
+            faded.scale.x *= left;
+            faded.scale.y *= left;
+            faded.scale.z *= left;
+            markers.push(faded);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marker(id: i32) -> Marker {
+        let mut marker = Marker {
+            ns: "mesh".to_string(),
+            id,
+            action: ADD,
+            ..Default::default()
+        };
+        marker.color.a = 1.0;
+        marker.scale.x = 2.0;
+        marker
+    }
+
+    #[test]
+    fn vanished_markers_fade_out_and_are_then_left_out() {
+        let mut despawns = Despawns::new(1.0);
+        let start = Instant::now();
+        despawns.apply(&mut vec![marker(1), marker(2)], start);
+
+        let mut markers = vec![marker(1)];
+        despawns.apply(&mut markers, start + Duration::from_millis(500));
+        // 2 vanished at this publish, and starts fading from full
+        assert_eq!(markers.len(), 2);
+        assert_eq!(markers[1].id, 2);
+        assert_eq!(markers[1].color.a, 1.0);
+
+        let mut markers = vec![marker(1)];
+        despawns.apply(&mut markers, start + Duration::from_millis(1000));
+        assert_eq!(markers.len(), 2);
+        assert!((markers[1].color.a - 0.5).abs() < 1e-6);
+        assert!((markers[1].scale.x - 1.0).abs() < 1e-9);
+
+        let mut markers = vec![marker(1)];
+        despawns.apply(&mut markers, start + Duration::from_millis(1500));
+        assert_eq!(markers.len(), 1);
+    }
+
+    #[test]
+    fn markers_coming_back_stop_fading() {
+        let mut despawns = Despawns::new(1.0);
+        let start = Instant::now();
+        despawns.apply(&mut vec![marker(1)], start);
+        despawns.apply(&mut vec![], start + Duration::from_millis(100));
+        let mut markers = vec![marker(1)];
+        despawns.apply(&mut markers, start + Duration::from_millis(200));
+        assert_eq!(markers.len(), 1);
+        assert_eq!(markers[0].color.a, 1.0);
+    }
+}
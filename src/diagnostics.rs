@@ -0,0 +1,206 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use micro_sp::*;
+use r2r::diagnostic_msgs::msg::{DiagnosticArray, DiagnosticStatus, KeyValue};
+use r2r::std_msgs::msg::Header;
+
+use crate::log_targets;
+use crate::sim_time::StampClock;
+use crate::NODE_ID;
+
+pub const OK: u8 = 0;
+pub const WARN: u8 = 1;
+pub const ERROR: u8 = 2;
+
+/// What a single tick observed, mapped to a diagnostic status.
+#[derive(Debug, Clone, Default)]
+pub struct TickStatus {
+    pub redis_healthy: bool,
+    pub frame_count: usize,
+    pub mesh_markers: usize,
+    pub zone_markers: usize,
+    pub publish_errors: Vec<String>,
+    /// When the tick ran, None before the first one.
+    pub last_tick: Option<std::time::SystemTime>,
+}
+
+/// Frame count bounds outside of which the status is WARN, 0 disables a bound.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameThresholds {
+    pub min_frames: usize,
+    pub max_frames: usize,
+}
+
+/// ERROR when Redis is unhealthy, WARN on publish errors or frame counts outside
+/// the thresholds, OK otherwise.
+pub fn diagnostic_status(status: &TickStatus, thresholds: FrameThresholds) -> DiagnosticStatus {
+    let (level, message) = if !status.redis_healthy {
+        (ERROR, "Redis is unhealthy".to_string())
+    } else if !status.publish_errors.is_empty() {
+        (WARN, format!("{} publish errors", status.publish_errors.len()))
+    } else if thresholds.min_frames != 0 && status.frame_count < thresholds.min_frames {
+        (WARN, format!("Only {} frames, expected at least {}", status.frame_count, thresholds.min_frames))
+    } else if thresholds.max_frames != 0 && status.frame_count > thresholds.max_frames {
+        (WARN, format!("{} frames, expected at most {}", status.frame_count, thresholds.max_frames))
+    } else {
+        (OK, "OK".to_string())
+    };
+
+    let mut values = vec![
+        key_value("redis_healthy", status.redis_healthy),
+        key_value("frames", status.frame_count),
+        key_value("mesh_markers", status.mesh_markers),
+        key_value("zone_markers", status.zone_markers),
+    ];
+    if let Some(error) = status.publish_errors.last() {
+        values.push(key_value("last_publish_error", error));
+    }
+
+    DiagnosticStatus {
+        level,
+        name: format!("{}: scene", NODE_ID),
+        message,
+        hardware_id: NODE_ID.to_string(),
+        values,
+    }
+}
+
+fn key_value(key: &str, value: impl ToString) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+/// The status of the last tick, handed from the server to the diagnostics task
+/// and the HTTP status endpoint.
+#[derive(Debug, Clone, Default)]
+pub struct SharedTickStatus(Arc<Mutex<TickStatus>>);
+
+impl SharedTickStatus {
+    pub fn set(&self, status: TickStatus) {
+        *self.0.lock().unwrap() = status;
+    }
+
+    pub fn get(&self) -> TickStatus {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Publishes the tick status on `/diagnostics` once per second.
+pub struct DiagnosticsPublisher {
+    publisher: r2r::Publisher<DiagnosticArray>,
+    thresholds: FrameThresholds,
+    clock: StampClock,
+}
+
+impl DiagnosticsPublisher {
+    pub fn new(
+        publisher: r2r::Publisher<DiagnosticArray>,
+        thresholds: FrameThresholds,
+        clock: StampClock,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(DiagnosticsPublisher {
+            publisher,
+            thresholds,
+            clock,
+        })
+    }
+
+    /// Report the last tick's status every second, on its own timer so the
+    /// reports continue while the server is stuck waiting for Redis. The
+    /// Redis health is checked here rather than taken from the tick.
+    pub async fn run(
+        mut self,
+        status: SharedTickStatus,
+        connection_manager: Arc<ConnectionManager>,
+    ) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            let mut status = status.get();
+            status.redis_healthy = connection_manager.check_redis_health(NODE_ID).await.is_ok();
+            self.report(&status);
+        }
+    }
+
+    fn report(&mut self, status: &TickStatus) {
+        let stamp = self.clock.now().unwrap_or_default();
+        let msg = DiagnosticArray {
+            header: Header {
+                stamp,
+                frame_id: "".to_string(),
+            },
+            status: vec![diagnostic_status(status, self.thresholds)],
+        };
+        if let Err(e) = self.publisher.publish(&msg) {
+            log::error!(target: log_targets::NODE,
+                "Diagnostics publisher failed to send a message with: '{}'",
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLDS: FrameThresholds = FrameThresholds {
+        min_frames: 2,
+        max_frames: 10,
+    };
+
+    fn healthy(frame_count: usize) -> TickStatus {
+        TickStatus {
+            redis_healthy: true,
+            frame_count,
+            mesh_markers: 3,
+            ..Default::default()
+        }
+    }
+
+    fn value<'a>(status: &'a DiagnosticStatus, key: &str) -> Option<&'a str> {
+        let value = status.values.iter().find(|value| value.key == key);
+        value.map(|value| value.value.as_str())
+    }
+
+    #[test]
+    fn frame_counts_and_health_set_the_level() {
+        let status = diagnostic_status(&healthy(5), THRESHOLDS);
+        assert_eq!((status.level, status.message.as_str()), (OK, "OK"));
+        assert_eq!(value(&status, "frames"), Some("5"));
+        assert_eq!(value(&status, "mesh_markers"), Some("3"));
+        assert_eq!(value(&status, "redis_healthy"), Some("true"));
+
+        assert_eq!(diagnostic_status(&healthy(1), THRESHOLDS).level, WARN);
+        assert_eq!(diagnostic_status(&healthy(11), THRESHOLDS).level, WARN);
+        let unbounded = FrameThresholds {
+            min_frames: 0,
+            max_frames: 0,
+        };
+        assert_eq!(diagnostic_status(&healthy(0), unbounded).level, OK);
+
+        // Redis wins over everything else
+        let down = TickStatus {
+            redis_healthy: false,
+            ..healthy(0)
+        };
+        let status = diagnostic_status(&down, THRESHOLDS);
+        assert_eq!(status.level, ERROR);
+        assert_eq!(value(&status, "redis_healthy"), Some("false"));
+    }
+
+    #[test]
+    fn the_last_publish_error_is_reported() {
+        let status = TickStatus {
+            publish_errors: vec!["first".to_string(), "last".to_string()],
+            ..healthy(5)
+        };
+        let status = diagnostic_status(&status, THRESHOLDS);
+        assert_eq!(status.level, WARN);
+        assert_eq!(status.message, "2 publish errors");
+        assert_eq!(value(&status, "last_publish_error"), Some("last"));
+    }
+}
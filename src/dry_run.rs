@@ -0,0 +1,31 @@
+/// The last summary of what a DRY_RUN tick would have published, so it is
+/// only logged when it changes, the tick rate would flood the log.
+#[derive(Debug, Default)]
+pub struct DryRunLog {
+    last: String,
+}
+
+impl DryRunLog {
+    /// Whether `summary` differs from the previous tick's, and should be logged.
+    pub fn changed(&mut self, summary: &str) -> bool {
+        if self.last == summary {
+            return false;
+        }
+        self.last = summary.to_string();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_summary_is_logged_once_per_change() {
+        let mut log = DryRunLog::default();
+        assert!(log.changed("2 active and 1 static transforms"));
+        assert!(!log.changed("2 active and 1 static transforms"));
+        assert!(log.changed("3 active and 1 static transforms"));
+        assert!(log.changed("2 active and 1 static transforms"));
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use micro_sp::*;
+
+use crate::log_targets;
+
+/// Holds the previous scene when a read suddenly returns no frames at all, as
+/// partial reads during a Redis failover can, so the scene doesn't flash empty.
+/// After `grace` of empty reads the scene is let go and cleared.
+pub struct EmptyReadHold {
+    grace: Duration,
+    previous: HashMap<String, SPTransformStamped>,
+    empty_since: Option<Instant>,
+}
+
+impl EmptyReadHold {
+    pub fn new(grace_secs: f64) -> EmptyReadHold {
+        EmptyReadHold {
+            grace: Duration::from_secs_f64(grace_secs.max(0.0)),
+            previous: HashMap::new(),
+            empty_since: None,
+        }
+    }
+
+    /// The frames to publish for a read at `now`.
+    pub fn filter(
+        &mut self,
+        frames: HashMap<String, SPTransformStamped>,
+        now: Instant,
+    ) -> HashMap<String, SPTransformStamped> {
+        if !frames.is_empty() {
+            if self.empty_since.take().is_some() {
+                log::info!(target: log_targets::REDIS,
+                    "Frames are back after empty reads, publishing the live scene again."
+                );
+            }
+            self.previous = frames.clone();
+            return frames;
+        }
+        if self.previous.is_empty() {
+            return frames;
+        }
+        let since = *self.empty_since.get_or_insert_with(|| {
+            log::warn!(target: log_targets::REDIS,
+                "Read no frames after a scene of {}, holding it for up to {:?}.",
+                self.previous.len(),
+                self.grace
+            );
+            now
+        });
+        if now.duration_since(since) < self.grace {
+            return self.previous.clone();
+        }
+        log::warn!(target: log_targets::REDIS,
+            "Still no frames after {:?}, clearing the held scene.", self.grace
+        );
+        self.previous.clear();
+        self.empty_since = None;
+        frames
+    }
+}
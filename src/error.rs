@@ -0,0 +1,51 @@
+/// What the servers and their helpers fail with, so callers can tell Redis
+/// trouble from bad scenario data or a broken ROS graph.
+#[derive(Debug, thiserror::Error)]
+pub enum VizError {
+    #[error("Redis failed with: {0}")]
+    Redis(#[from] redis::RedisError),
+    /// Reading the stored frames, or the scenario files, failed.
+    #[error("failed to load the transforms: {0}")]
+    TransformLoad(String),
+    #[error("publisher failed to send a {topic} message with: '{source}'")]
+    Publish { topic: String, source: r2r::Error },
+    /// A stored frame or JSON document that doesn't decode.
+    #[error("failed to decode {0}")]
+    Decode(String),
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    /// Timers, clocks and other ROS calls outside of publishing.
+    #[error("ROS failed with: {0}")]
+    Ros(#[from] r2r::Error),
+}
+
+impl From<serde_json::Error> for VizError {
+    fn from(e: serde_json::Error) -> VizError {
+        VizError::Decode(format!("JSON: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protobuf::FrameFormat;
+    use crate::redis_reads::parse_frame;
+
+    #[test]
+    fn undecodable_frames_are_decode_errors() {
+        let parsed = parse_frame("table", b"{\"child_frame_id\": 3", FrameFormat::Json);
+        let Err(VizError::Decode(e)) = parsed else {
+            panic!("expected a decode error, got {:?}", parsed.map(|_| ()));
+        };
+        assert!(e.starts_with("the stored frame 'table': "), "{}", e);
+        let parsed = parse_frame("table", &[0xff, 0x01], FrameFormat::Protobuf);
+        assert!(matches!(parsed, Err(VizError::Decode(_))));
+    }
+
+    #[test]
+    fn json_errors_convert_to_decode_errors() {
+        let e: VizError = serde_json::from_str::<u32>("\"three\"").unwrap_err().into();
+        assert!(matches!(&e, VizError::Decode(message) if message.starts_with("JSON: ")));
+        assert!(e.to_string().starts_with("failed to decode JSON: "));
+    }
+}
@@ -0,0 +1,158 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use micro_sp::*;
+
+use crate::geometry::Isometry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameEventKind {
+    Appeared,
+    Disappeared,
+    Moved,
+}
+
+/// Published as JSON on `frame_events`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrameEvent {
+    pub frame: String,
+    pub event: FrameEventKind,
+    /// Seconds since the UNIX epoch.
+    pub stamp: f64,
+}
+
+/// Turns the frames of consecutive ticks into appeared/disappeared/moved events.
+#[derive(Debug, Clone)]
+pub struct FrameEventTracker {
+    move_threshold: f64,
+    /// Translation of each frame at its last event.
+    positions: HashMap<String, [f64; 3]>,
+}
+
+impl FrameEventTracker {
+    /// Frames move once they are `move_threshold` meters from where they were
+    /// at their last event.
+    pub fn new(move_threshold: f64) -> FrameEventTracker {
+        FrameEventTracker {
+            move_threshold,
+            positions: HashMap::new(),
+        }
+    }
+
+    /// Events since the previous update, sorted by frame.
+    pub fn update(
+        &mut self,
+        frames: &HashMap<String, SPTransformStamped>,
+        stamp: f64,
+    ) -> Vec<FrameEvent> {
+        let mut events = vec![];
+        for (name, frame) in frames {
+            let position = Isometry::from_frame(frame).translation;
+            let event = match self.positions.get(name) {
+                None => FrameEventKind::Appeared,
+                Some(last) if distance(*last, position) >= self.move_threshold => {
+                    FrameEventKind::Moved
+                }
+                Some(_) => continue,
+            };
+            self.positions.insert(name.clone(), position);
+            events.push(FrameEvent {
+                frame: name.clone(),
+                event,
+                stamp,
+            });
+        }
+        let gone: Vec<String> = self
+            .positions
+            .keys()
+            .filter(|name| !frames.contains_key(*name))
+            .cloned()
+            .collect();
+        for name in gone {
+            self.positions.remove(&name);
+            events.push(FrameEvent {
+                frame: name,
+                event: FrameEventKind::Disappeared,
+                stamp,
+            });
+        }
+        events.sort_by(|a, b| a.frame.cmp(&b.frame));
+        events
+    }
+}
+
+fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r2r::geometry_msgs::msg::{Quaternion, Transform, Vector3};
+
+    use crate::scene_edits::new_frame;
+
+    fn frames(positions: &[(&str, f64)]) -> HashMap<String, SPTransformStamped> {
+        positions
+            .iter()
+            .map(|(name, x)| {
+                let transform = Transform {
+                    translation: Vector3 {
+                        x: *x,
+                        y: 0.0,
+                        z: 0.0,
+                    },
+                    rotation: Quaternion {
+                        x: 0.0,
+                        y: 0.0,
+                        z: 0.0,
+                        w: 1.0,
+                    },
+                };
+                (
+                    name.to_string(),
+                    new_frame("world", name, &transform, true).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn kinds(events: &[FrameEvent]) -> Vec<(&str, FrameEventKind)> {
+        events
+            .iter()
+            .map(|event| (event.frame.as_str(), event.event))
+            .collect()
+    }
+
+    #[test]
+    fn consecutive_snapshots_give_appeared_disappeared_and_moved() {
+        let mut tracker = FrameEventTracker::new(0.05);
+        let first = tracker.update(&frames(&[("robot", 0.0), ("table", 1.0)]), 1.0);
+        assert_eq!(
+            kinds(&first),
+            [
+                ("robot", FrameEventKind::Appeared),
+                ("table", FrameEventKind::Appeared)
+            ]
+        );
+        assert!(first.iter().all(|event| event.stamp == 1.0));
+
+        // the table nudged below the threshold, the robot gone and a camera new
+        let second = tracker.update(&frames(&[("camera", 0.5), ("table", 1.01)]), 2.0);
+        assert_eq!(
+            kinds(&second),
+            [
+                ("camera", FrameEventKind::Appeared),
+                ("robot", FrameEventKind::Disappeared)
+            ]
+        );
+
+        // moves add up from the last event, not the last tick
+        let third = tracker.update(&frames(&[("camera", 0.5), ("table", 1.05)]), 3.0);
+        assert_eq!(kinds(&third), [("table", FrameEventKind::Moved)]);
+        assert!(tracker
+            .update(&frames(&[("camera", 0.5), ("table", 1.05)]), 4.0)
+            .is_empty());
+    }
+}
@@ -0,0 +1,438 @@
+use ordered_float::OrderedFloat;
+use r2r::geometry_msgs::msg::{Quaternion, Transform, Vector3};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use micro_sp::*;
+
+/// A rigid transform, rotation as a unit quaternion `[x, y, z, w]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Isometry {
+    pub translation: [f64; 3],
+    pub rotation: [f64; 4],
+}
+
+impl Isometry {
+    pub fn identity() -> Isometry {
+        Isometry {
+            translation: [0.0, 0.0, 0.0],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    /// The stored transform. micro_sp keeps every component as an
+    /// `OrderedFloat<f64>`, so unwrapping it is exact, and all conversions to
+    /// messages go through here to stay that way.
+    pub fn from_frame(frame: &SPTransformStamped) -> Isometry {
+        Isometry {
+            translation: [
+                frame.transform.translation.x.into_inner(),
+                frame.transform.translation.y.into_inner(),
+                frame.transform.translation.z.into_inner(),
+            ],
+            rotation: [
+                frame.transform.rotation.x.into_inner(),
+                frame.transform.rotation.y.into_inner(),
+                frame.transform.rotation.z.into_inner(),
+                frame.transform.rotation.w.into_inner(),
+            ],
+        }
+    }
+
+    /// The transform as a message, both are f64 so nothing is lost.
+    pub fn to_msg(&self) -> Transform {
+        let [x, y, z] = self.translation;
+        let [qx, qy, qz, qw] = self.rotation;
+        Transform {
+            translation: Vector3 { x, y, z },
+            rotation: Quaternion {
+                x: qx,
+                y: qy,
+                z: qz,
+                w: qw,
+            },
+        }
+    }
+
+    pub fn write_to(&self, frame: &mut SPTransformStamped) {
+        let [x, y, z] = self.translation;
+        frame.transform.translation.x = OrderedFloat(x);
+        frame.transform.translation.y = OrderedFloat(y);
+        frame.transform.translation.z = OrderedFloat(z);
+        let [x, y, z, w] = self.rotation;
+        frame.transform.rotation.x = OrderedFloat(x);
+        frame.transform.rotation.y = OrderedFloat(y);
+        frame.transform.rotation.z = OrderedFloat(z);
+        frame.transform.rotation.w = OrderedFloat(w);
+    }
+
+    /// `self * other`, i.e. `other` expressed in the frame `self` is expressed in.
+    pub fn compose(&self, other: &Isometry) -> Isometry {
+        let rotated = rotate(self.rotation, other.translation);
+        Isometry {
+            translation: [
+                self.translation[0] + rotated[0],
+                self.translation[1] + rotated[1],
+                self.translation[2] + rotated[2],
+            ],
+            rotation: quaternion_multiply(self.rotation, other.rotation),
+        }
+    }
+
+    pub fn inverse(&self) -> Isometry {
+        let [x, y, z, w] = self.rotation;
+        let conjugate = [-x, -y, -z, w];
+        let t = rotate(conjugate, self.translation);
+        Isometry {
+            translation: [-t[0], -t[1], -t[2]],
+            rotation: conjugate,
+        }
+    }
+
+    /// The pose a fraction `t` of the way to `target`, translation lerped and
+    /// rotation slerped, `self` at 0 and `target` at 1.
+    pub fn interpolate(&self, target: &Isometry, t: f64) -> Isometry {
+        Isometry {
+            translation: lerp(self.translation, target.translation, t),
+            rotation: slerp(self.rotation, target.rotation, t),
+        }
+    }
+}
+
+pub fn quaternion_multiply(a: [f64; 4], b: [f64; 4]) -> [f64; 4] {
+    let [ax, ay, az, aw] = a;
+    let [bx, by, bz, bw] = b;
+    [
+        aw * bx + ax * bw + ay * bz - az * by,
+        aw * by - ax * bz + ay * bw + az * bx,
+        aw * bz + ax * by - ay * bx + az * bw,
+        aw * bw - ax * bx - ay * by - az * bz,
+    ]
+}
+
+pub fn lerp(a: [f64; 3], b: [f64; 3], t: f64) -> [f64; 3] {
+    [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+/// Spherical interpolation between unit quaternions along the shorter arc,
+/// falling back to a normalized lerp when they are almost parallel.
+pub fn slerp(a: [f64; 4], b: [f64; 4], t: f64) -> [f64; 4] {
+    let mut dot: f64 = (0..4).map(|i| a[i] * b[i]).sum();
+    // q and -q are the same rotation, flip b to take the shorter way
+    let b = if dot < 0.0 {
+        dot = -dot;
+        b.map(|v| -v)
+    } else {
+        b
+    };
+    let (wa, wb) = if dot > 0.9995 {
+        (1.0 - t, t)
+    } else {
+        let angle = dot.acos();
+        let sin = angle.sin();
+        (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin)
+    };
+    let q = [0, 1, 2, 3].map(|i| wa * a[i] + wb * b[i]);
+    let Quaternion { x, y, z, w } = sanitize_quaternion(Quaternion {
+        x: q[0],
+        y: q[1],
+        z: q[2],
+        w: q[3],
+    });
+    [x, y, z, w]
+}
+
+/// Quaternion `[x, y, z, w]` of fixed-axis roll, pitch and yaw in radians.
+pub fn quaternion_from_rpy(roll: f64, pitch: f64, yaw: f64) -> [f64; 4] {
+    let (sr, cr) = (roll / 2.0).sin_cos();
+    let (sp, cp) = (pitch / 2.0).sin_cos();
+    let (sy, cy) = (yaw / 2.0).sin_cos();
+    [
+        sr * cp * cy - cr * sp * sy,
+        cr * sp * cy + sr * cp * sy,
+        cr * cp * sy - sr * sp * cy,
+        cr * cp * cy + sr * sp * sy,
+    ]
+}
+
+/// Unit quaternion of fixed-axis roll, pitch and yaw in radians, as written
+/// into frames given with `rpy`.
+pub fn rpy_to_quaternion(roll: f64, pitch: f64, yaw: f64) -> Quaternion {
+    let [x, y, z, w] = quaternion_from_rpy(roll, pitch, yaw);
+    sanitize_quaternion(Quaternion { x, y, z, w })
+}
+
+/// Quaternions shorter than this carry no usable rotation.
+pub const MIN_QUATERNION_NORM: f64 = 1e-9;
+
+pub fn quaternion_norm(q: &Quaternion) -> f64 {
+    (q.x * q.x + q.y * q.y + q.z * q.z + q.w * q.w).sqrt()
+}
+
+/// `q` scaled to unit length, identity when its norm is about zero or not
+/// finite, as stored frames can hold quaternions TF consumers turn into NaN.
+pub fn sanitize_quaternion(q: Quaternion) -> Quaternion {
+    let norm = quaternion_norm(&q);
+    if !(norm >= MIN_QUATERNION_NORM && norm.is_finite()) {
+        return Quaternion {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            w: 1.0,
+        };
+    }
+    Quaternion {
+        x: q.x / norm,
+        y: q.y / norm,
+        z: q.z / norm,
+        w: q.w / norm,
+    }
+}
+
+/// Rotate a vector by a unit quaternion.
+pub fn rotate(q: [f64; 4], v: [f64; 3]) -> [f64; 3] {
+    let [x, y, z, w] = q;
+    let p = quaternion_multiply(quaternion_multiply(q, [v[0], v[1], v[2], 0.0]), [-x, -y, -z, w]);
+    [p[0], p[1], p[2]]
+}
+
+/// Pose of `child_frame_id` in the root of its tree, composed by walking the
+/// parent chain in a loop, so deep chains can't overflow the stack. Returns
+/// the root frame name with the pose, or None on a cycle.
+pub fn pose_in_root(
+    frames: &HashMap<String, SPTransformStamped>,
+    child_frame_id: &str,
+) -> Option<(String, Isometry)> {
+    let mut pose = Isometry::identity();
+    let mut current = child_frame_id.to_string();
+    let mut visited = 0;
+    while let Some(frame) = frames
+        .get(&current)
+        .or_else(|| frames.values().find(|f| f.child_frame_id == current))
+    {
+        visited += 1;
+        if visited > frames.len() {
+            return None;
+        }
+        pose = Isometry::from_frame(frame).compose(&pose);
+        current = frame.parent_frame_id.clone();
+    }
+    Some((current, pose))
+}
+
+/// Axis convention the frames are stored in. RViz expects ENU.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameConvention {
+    Enu,
+    Ned,
+}
+
+impl FromStr for FrameConvention {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "ENU" => Ok(FrameConvention::Enu),
+            "NED" => Ok(FrameConvention::Ned),
+            _ => Err(format!("unknown frame convention '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for FrameConvention {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameConvention::Enu => write!(f, "ENU"),
+            FrameConvention::Ned => write!(f, "NED"),
+        }
+    }
+}
+
+/// Length unit of the stored translations. ROS expects meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LengthUnits {
+    Meters,
+    Centimeters,
+    Millimeters,
+}
+
+impl LengthUnits {
+    /// Meters per unit.
+    pub fn factor(&self) -> f64 {
+        match self {
+            LengthUnits::Meters => 1.0,
+            LengthUnits::Centimeters => 0.01,
+            LengthUnits::Millimeters => 0.001,
+        }
+    }
+}
+
+impl FromStr for LengthUnits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "m" => Ok(LengthUnits::Meters),
+            "cm" => Ok(LengthUnits::Centimeters),
+            "mm" => Ok(LengthUnits::Millimeters),
+            _ => Err(format!("unknown length units '{}', expected m, cm or mm", s)),
+        }
+    }
+}
+
+impl fmt::Display for LengthUnits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LengthUnits::Meters => write!(f, "m"),
+            LengthUnits::Centimeters => write!(f, "cm"),
+            LengthUnits::Millimeters => write!(f, "mm"),
+        }
+    }
+}
+
+/// Convert every translation from `units` to meters, rotations are untouched.
+pub fn to_meters(frames: &mut HashMap<String, SPTransformStamped>, units: LengthUnits) {
+    if units == LengthUnits::Meters {
+        return;
+    }
+    for frame in frames.values_mut() {
+        let mut pose = Isometry::from_frame(frame);
+        pose.translation = pose.translation.map(|v| v * units.factor());
+        pose.write_to(frame);
+    }
+}
+
+/// NED to ENU, a half turn about the axis between north and east, so that
+/// (north, east, down) becomes (east, north, up). It is its own inverse.
+pub const NED_TO_ENU: Isometry = Isometry {
+    translation: [0.0, 0.0, 0.0],
+    rotation: [std::f64::consts::FRAC_1_SQRT_2, std::f64::consts::FRAC_1_SQRT_2, 0.0, 0.0],
+};
+
+/// Re-express every transform in ENU. Each transform is conjugated with the
+/// fixed rotation, which converts both the parent and child axes, so body
+/// frames go from FRD to FLU as well and meshes are expected in ROS convention.
+pub fn to_enu(frames: &mut HashMap<String, SPTransformStamped>, convention: FrameConvention) {
+    if convention == FrameConvention::Enu {
+        return;
+    }
+    for frame in frames.values_mut() {
+        NED_TO_ENU
+            .compose(&Isometry::from_frame(frame))
+            .compose(&NED_TO_ENU.inverse())
+            .write_to(frame);
+    }
+}
+
+/// Snap `value` to the nearest multiple of `step`, a step of 0 leaves it as is.
+pub fn quantize(value: f64, step: f64) -> f64 {
+    if step > 0.0 {
+        (value / step).round() * step
+    } else {
+        value
+    }
+}
+
+/// Snap every translation to `translation_step` and every quaternion component
+/// to `rotation_step`, so jitter below the steps doesn't change the published
+/// values. Snapped quaternions are not renormalized.
+pub fn quantize_frames(
+    frames: &mut HashMap<String, SPTransformStamped>,
+    translation_step: f64,
+    rotation_step: f64,
+) {
+    if translation_step <= 0.0 && rotation_step <= 0.0 {
+        return;
+    }
+    for frame in frames.values_mut() {
+        let pose = Isometry::from_frame(frame);
+        Isometry {
+            translation: pose.translation.map(|v| quantize(v, translation_step)),
+            rotation: pose.rotation.map(|v| quantize(v, rotation_step)),
+        }
+        .write_to(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quaternion(x: f64, y: f64, z: f64, w: f64) -> Quaternion {
+        Quaternion { x, y, z, w }
+    }
+
+    fn components(q: &Quaternion) -> [f64; 4] {
+        [q.x, q.y, q.z, q.w]
+    }
+
+    #[test]
+    fn unit_quaternions_are_kept() {
+        let q = quaternion(0.0, 0.0, 0.6, 0.8);
+        assert_eq!(sanitize_quaternion(q.clone()), q);
+    }
+
+    #[test]
+    fn scaled_quaternions_are_normalized() {
+        let q = sanitize_quaternion(quaternion(0.0, 0.0, 3.0, 4.0));
+        let expected = [0.0, 0.0, 0.6, 0.8];
+        for (got, want) in components(&q).iter().zip(expected) {
+            assert!((got - want).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn degenerate_quaternions_become_identity() {
+        let identity = quaternion(0.0, 0.0, 0.0, 1.0);
+        assert_eq!(
+            sanitize_quaternion(quaternion(0.0, 0.0, 0.0, 0.0)),
+            identity
+        );
+        assert_eq!(
+            sanitize_quaternion(quaternion(f64::NAN, 0.0, 0.0, 1.0)),
+            identity
+        );
+        assert_eq!(
+            sanitize_quaternion(quaternion(f64::INFINITY, 0.0, 0.0, 1.0)),
+            identity
+        );
+    }
+
+    fn assert_close(q: &Quaternion, expected: [f64; 4]) {
+        for (got, want) in components(q).iter().zip(expected) {
+            assert!((got - want).abs() < 1e-9, "{:?} isn't {:?}", q, expected);
+        }
+    }
+
+    #[test]
+    fn rpy_gives_the_known_quaternions() {
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        let quarter_turn = std::f64::consts::FRAC_PI_2;
+        assert_close(&rpy_to_quaternion(0.0, 0.0, 0.0), [0.0, 0.0, 0.0, 1.0]);
+        assert_close(
+            &rpy_to_quaternion(0.0, 0.0, quarter_turn),
+            [0.0, 0.0, half, half],
+        );
+        assert_close(
+            &rpy_to_quaternion(quarter_turn, 0.0, 0.0),
+            [half, 0.0, 0.0, half],
+        );
+        assert_close(
+            &rpy_to_quaternion(0.0, quarter_turn, 0.0),
+            [0.0, half, 0.0, half],
+        );
+        assert_close(
+            &rpy_to_quaternion(0.0, 0.0, std::f64::consts::PI),
+            [0.0, 0.0, 1.0, 0.0],
+        );
+    }
+
+    #[test]
+    fn rpy_quaternions_have_unit_norm() {
+        for (roll, pitch, yaw) in [(0.3, -1.2, 2.9), (3.0, 3.0, 3.0), (-0.7, 0.1, -2.2)] {
+            let norm = quaternion_norm(&rpy_to_quaternion(roll, pitch, yaw));
+            assert!((norm - 1.0).abs() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::log_targets;
+
+const WINDOW: usize = 20;
+
+/// Lowers the marker publish rate while ticks keep overrunning their period,
+/// TF keeps going out every tick.
+///
+/// Markers are published every `divisor`-th tick. When more than half of the
+/// last `WINDOW` ticks missed their deadline the divisor doubles (up to
+/// `max_divisor`), after a full window without misses it halves again.
+pub struct RateGovernor {
+    enabled: bool,
+    period: Duration,
+    max_divisor: u32,
+    divisor: u32,
+    tick: u64,
+    misses: VecDeque<bool>,
+}
+
+impl RateGovernor {
+    pub fn new(enabled: bool, period: Duration, max_divisor: u32) -> RateGovernor {
+        RateGovernor {
+            enabled,
+            period,
+            max_divisor: max_divisor.max(1),
+            divisor: 1,
+            tick: 0,
+            misses: VecDeque::with_capacity(WINDOW),
+        }
+    }
+
+    /// Whether markers should be published on the current tick.
+    pub fn publish_markers(&self) -> bool {
+        self.tick % self.divisor as u64 == 0
+    }
+
+    /// Record how long the finished tick took and adjust the divisor.
+    pub fn record(&mut self, tick_duration: Duration) {
+        self.tick += 1;
+        if !self.enabled {
+            return;
+        }
+        if self.misses.len() == WINDOW {
+            self.misses.pop_front();
+        }
+        self.misses.push_back(tick_duration > self.period);
+        if self.misses.len() < WINDOW {
+            return;
+        }
+        let missed = self.misses.iter().filter(|m| **m).count();
+        let divisor = if missed > WINDOW / 2 && self.divisor < self.max_divisor {
+            (self.divisor * 2).min(self.max_divisor)
+        } else if missed == 0 && self.divisor > 1 {
+            self.divisor / 2
+        } else {
+            return;
+        };
+        log::warn!(target: log_targets::MARKERS,
+            "{} of the last {} ticks overran {:?}, publishing markers every {} ticks.",
+            missed, WINDOW, self.period, divisor
+        );
+        self.divisor = divisor;
+        self.misses.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    fn record_window(governor: &mut RateGovernor, tick_duration: Duration) {
+        for _ in 0..WINDOW {
+            governor.record(tick_duration);
+        }
+    }
+
+    #[test]
+    fn the_period_grows_while_ticks_overrun_and_shrinks_back() {
+        let mut governor = RateGovernor::new(true, PERIOD, 4);
+        let overrun = Duration::from_millis(15);
+        record_window(&mut governor, overrun);
+        assert_eq!(governor.divisor, 2);
+        record_window(&mut governor, overrun);
+        assert_eq!(governor.divisor, 4);
+        // capped at max_divisor
+        record_window(&mut governor, overrun);
+        assert_eq!(governor.divisor, 4);
+        let published = (0..8)
+            .filter(|_| {
+                let publish = governor.publish_markers();
+                governor.record(overrun);
+                publish
+            })
+            .count();
+        assert_eq!(published, 2);
+
+        governor.misses.clear();
+        let on_time = Duration::from_millis(5);
+        record_window(&mut governor, on_time);
+        assert_eq!(governor.divisor, 2);
+        record_window(&mut governor, on_time);
+        assert_eq!(governor.divisor, 1);
+    }
+
+    #[test]
+    fn recovery_waits_for_a_window_without_misses() {
+        let mut governor = RateGovernor::new(true, PERIOD, 4);
+        record_window(&mut governor, Duration::from_millis(15));
+        governor.record(Duration::from_millis(15));
+        for _ in 1..WINDOW {
+            governor.record(Duration::from_millis(5));
+        }
+        assert_eq!(governor.divisor, 2);
+        governor.record(Duration::from_millis(5));
+        assert_eq!(governor.divisor, 1);
+    }
+
+    #[test]
+    fn a_disabled_governor_publishes_every_tick() {
+        let mut governor = RateGovernor::new(false, PERIOD, 4);
+        record_window(&mut governor, Duration::from_millis(50));
+        assert_eq!(governor.divisor, 1);
+        assert!(governor.publish_markers());
+    }
+}
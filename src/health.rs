@@ -0,0 +1,67 @@
+use r2r::std_msgs::msg::Bool;
+
+use crate::server::publish;
+use crate::sink::MessageSink;
+
+/// Tracks the Redis health last published on `redis_visualization/ok`, so
+/// only transitions are published. A failed publish isn't recorded and is
+/// retried with the next tick.
+#[derive(Debug, Default)]
+pub struct HealthTransitions {
+    last: Option<bool>,
+}
+
+impl HealthTransitions {
+    /// Whether `healthy` differs from what was last published, true before
+    /// anything was.
+    pub fn changed(&self, healthy: bool) -> bool {
+        self.last != Some(healthy)
+    }
+
+    pub fn record(&mut self, healthy: bool) {
+        self.last = Some(healthy);
+    }
+
+    /// Publish `healthy` when it changed, recording it once it went out.
+    pub async fn publish<S: MessageSink<Bool> + ?Sized>(&mut self, publisher: &S, healthy: bool) {
+        if !self.changed(healthy) {
+            return;
+        }
+        let mut errors = vec![];
+        let msg = Bool { data: healthy };
+        publish(publisher, &msg, "redis_visualization/ok", &mut errors).await;
+        if errors.is_empty() {
+            self.record(healthy);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[tokio::test]
+    async fn the_published_health_follows_redis_reachability() {
+        let sink = RecordingSink::default();
+        let mut health = HealthTransitions::default();
+        for reachable in [true, true, false, false, true] {
+            health.publish(&sink, reachable).await;
+        }
+        let sent: Vec<bool> = sink.sent().iter().map(|msg| msg.data).collect();
+        assert_eq!(sent, vec![true, false, true]);
+    }
+
+    #[tokio::test]
+    async fn a_failed_publish_is_retried_with_the_next_tick() {
+        // fails every attempt of the first publish
+        let sink = RecordingSink::failing(3);
+        let mut health = HealthTransitions::default();
+        health.publish(&sink, false).await;
+        assert!(sink.sent().is_empty());
+        assert!(health.changed(false));
+        health.publish(&sink, false).await;
+        let sent: Vec<bool> = sink.sent().iter().map(|msg| msg.data).collect();
+        assert_eq!(sent, vec![false]);
+    }
+}
@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+
+use crate::log_targets;
+
+/// Throttled liveness log, emitted at info level at most once per interval.
+pub struct Heartbeat {
+    interval: Option<Duration>,
+    last: Instant,
+}
+
+impl Heartbeat {
+    /// An interval of 0 seconds disables the heartbeat.
+    pub fn new(interval_secs: u64) -> Heartbeat {
+        Heartbeat {
+            interval: match interval_secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            last: Instant::now(),
+        }
+    }
+
+    /// Returns true at most once per interval, measured from the last time it fired.
+    pub fn due(&mut self, now: Instant) -> bool {
+        match self.interval {
+            Some(interval) if now.duration_since(self.last) >= interval => {
+                self.last = now;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn beat(&mut self, frames: usize, markers: usize, redis_healthy: bool) {
+        if self.due(Instant::now()) {
+            log::info!(target: log_targets::NODE,
+                "Heartbeat: {} frames, {} markers, redis {}.",
+                frames,
+                markers,
+                if redis_healthy { "healthy" } else { "unhealthy" }
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn beats_at_most_once_per_interval() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(10);
+        assert!(!heartbeat.due(start + Duration::from_secs(5)));
+        assert!(heartbeat.due(start + Duration::from_secs(10)));
+        // measured from the last beat, not from the start
+        assert!(!heartbeat.due(start + Duration::from_secs(19)));
+        assert!(heartbeat.due(start + Duration::from_secs(21)));
+    }
+
+    #[test]
+    fn an_interval_of_zero_never_beats() {
+        let mut heartbeat = Heartbeat::new(0);
+        assert!(!heartbeat.due(Instant::now() + Duration::from_secs(3600)));
+    }
+}
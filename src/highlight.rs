@@ -0,0 +1,102 @@
+use futures::{Stream, StreamExt};
+use r2r::scene_manipulation_msgs::srv::ManipulateScene;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::log_targets;
+
+/// Frames currently highlighted through the `highlight_frame` service, with
+/// when each highlight ends.
+#[derive(Debug, Clone)]
+pub struct Highlights {
+    duration: Duration,
+    until: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl Highlights {
+    pub fn new(duration_secs: f64) -> Highlights {
+        Highlights {
+            duration: Duration::from_secs_f64(duration_secs.max(0.0)),
+            until: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Highlight `frame` for the configured duration from `now`, restarting
+    /// an active highlight.
+    pub fn highlight(&self, frame: &str, now: Instant) {
+        self.until
+            .lock()
+            .unwrap()
+            .insert(frame.to_string(), now + self.duration);
+    }
+
+    /// Time left of every active highlight at `now`, dropping the expired ones.
+    pub fn active(&self, now: Instant) -> HashMap<String, Duration> {
+        let mut until = self.until.lock().unwrap();
+        until.retain(|_, end| *end > now);
+        until
+            .iter()
+            .map(|(frame, end)| (frame.clone(), end.duration_since(now)))
+            .collect()
+    }
+}
+
+/// Highlight the `child_frame_id` of a `highlight_frame` request at `now`.
+fn highlight_request(
+    highlights: &Highlights,
+    request: &ManipulateScene::Request,
+    now: Instant,
+) -> ManipulateScene::Response {
+    let frame = &request.child_frame_id;
+    highlights.highlight(frame, now);
+    ManipulateScene::Response {
+        success: true,
+        info: format!("Highlighting '{}' for {:?}.", frame, highlights.duration),
+    }
+}
+
+/// Serve `highlight_frame`, which highlights the request's `child_frame_id`.
+pub async fn serve_highlights(
+    mut requests: impl Stream<Item = r2r::ServiceRequest<ManipulateScene::Service>> + Unpin,
+    highlights: Highlights,
+) {
+    while let Some(request) = requests.next().await {
+        let response = highlight_request(&highlights, &request.message, Instant::now());
+        if let Err(e) = request.respond(response) {
+            log::error!(target: log_targets::SERVICES,
+                "Failed to respond to a highlight_frame request with: '{}'", e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markers::{highlight_marker, lifetime};
+
+    #[test]
+    fn a_request_highlights_the_named_frame_for_a_while() {
+        let highlights = Highlights::new(2.0);
+        let start = Instant::now();
+        let request = ManipulateScene::Request {
+            child_frame_id: "gripper".to_string(),
+            ..Default::default()
+        };
+        let response = highlight_request(&highlights, &request, start);
+        assert!(response.success);
+
+        let active = highlights.active(start + Duration::from_millis(500));
+        assert_eq!(active.len(), 1);
+        let remaining = active["gripper"];
+        assert_eq!(remaining, Duration::from_millis(1500));
+        let marker = highlight_marker("gripper", 0.2, 7, lifetime(remaining.as_secs_f64()));
+        assert_eq!(marker.header.frame_id, "gripper");
+        assert_eq!(marker.lifetime.sec, 1);
+        // the 12 edges of a box
+        assert_eq!(marker.points.len(), 24);
+
+        assert!(highlights.active(start + Duration::from_secs(2)).is_empty());
+    }
+}
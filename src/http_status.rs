@@ -0,0 +1,147 @@
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+use micro_sp::*;
+
+use crate::diagnostics::{SharedTickStatus, TickStatus};
+use crate::log_targets;
+use crate::NODE_ID;
+
+/// Longest request head read, anything longer is refused.
+const MAX_REQUEST_BYTES: usize = 8192;
+
+/// Status code and JSON body answering a GET of `path`. `scene` is the JSON
+/// list of frame summaries the TF task shares, empty before the first read.
+pub fn respond(
+    path: &str,
+    status: &TickStatus,
+    redis_healthy: bool,
+    uptime: Duration,
+    scene: &str,
+) -> (u16, String) {
+    match path {
+        "/status" => {
+            let last_tick = status
+                .last_tick
+                .and_then(|tick| tick.duration_since(UNIX_EPOCH).ok())
+                .map(|since| since.as_secs_f64());
+            let body = json!({
+                "redis_healthy": redis_healthy,
+                "last_tick": last_tick,
+                "frames": status.frame_count,
+                "mesh_markers": status.mesh_markers,
+                "zone_markers": status.zone_markers,
+                "publish_errors": status.publish_errors,
+                "uptime_secs": uptime.as_secs_f64(),
+            });
+            (200, body.to_string())
+        }
+        "/frames" if scene.is_empty() => (200, "[]".to_string()),
+        "/frames" => (200, scene.to_string()),
+        _ => (404, json!({"error": format!("no such resource '{}'", path)}).to_string()),
+    }
+}
+
+/// The path of an HTTP GET request head, or the status code refusing it.
+fn request_path(head: &str) -> Result<&str, u16> {
+    let mut parts = head.lines().next().unwrap_or_default().split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => Ok(path.split('?').next().unwrap_or(path)),
+        (Some(_), Some(_)) => Err(405),
+        _ => Err(400),
+    }
+}
+
+fn reason(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Error",
+    }
+}
+
+/// Answer `GET /status` and `GET /frames` on `address`, one request per
+/// connection. Runs until the node stops, logging when the port can't be bound.
+pub async fn serve_http_status(
+    address: String,
+    status: SharedTickStatus,
+    scene: watch::Receiver<String>,
+    connection_manager: Arc<ConnectionManager>,
+) {
+    let listener = match TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!(target: log_targets::SERVICES,
+                "Failed to serve the HTTP status on {} with: {}", address, e
+            );
+            return;
+        }
+    };
+    log::info!(target: log_targets::SERVICES,
+        "Serving the HTTP status on http://{}/status.", address
+    );
+    let started = Instant::now();
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                log::warn!(target: log_targets::SERVICES,
+                    "Failed to accept an HTTP status connection with: {}", e
+                );
+                continue;
+            }
+        };
+        let (status, scene) = (status.clone(), scene.clone());
+        let connection_manager = connection_manager.clone();
+        tokio::task::spawn(async move {
+            if let Err(e) =
+                answer(stream, &status, &scene, &connection_manager, started.elapsed()).await
+            {
+                log::debug!(target: log_targets::SERVICES,
+                    "Failed to answer an HTTP status request with: {}", e
+                );
+            }
+        });
+    }
+}
+
+async fn answer(
+    mut stream: TcpStream,
+    status: &SharedTickStatus,
+    scene: &watch::Receiver<String>,
+    connection_manager: &ConnectionManager,
+    uptime: Duration,
+) -> std::io::Result<()> {
+    let mut head = vec![];
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || head.len() + read > MAX_REQUEST_BYTES {
+            break;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    let (code, body) = match request_path(&String::from_utf8_lossy(&head)) {
+        Ok(path) => {
+            let redis_healthy = connection_manager.check_redis_health(NODE_ID).await.is_ok();
+            let scene = scene.borrow().clone();
+            respond(path, &status.get(), redis_healthy, uptime, &scene)
+        }
+        Err(code) => (code, json!({"error": reason(code)}).to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason(code),
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
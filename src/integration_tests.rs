@@ -0,0 +1,517 @@
+//! End-to-end runs, all but one against a live Redis, built with
+//! `cargo test --features integration-tests` in a sourced ROS 2 environment.
+//! Redis is found through REDIS_HOST and REDIS_PORT like the node's. The
+//! frames are stored under micro_sp's `transform:` prefix with names no
+//! scenario uses, and deleted again, but other frames in the same Redis
+//! show up in the servers' output, so point the tests at a Redis of their own.
+
+use micro_sp::*;
+use r2r::builtin_interfaces::msg::Time;
+use r2r::geometry_msgs::msg::{Quaternion, Transform, Vector3};
+use r2r::std_msgs::msg::Bool;
+use r2r::tf2_msgs::msg::TFMessage;
+use r2r::visualization_msgs::msg::{Marker, MarkerArray};
+use r2r::QosProfile;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::config::{config_with, Config};
+use crate::error::VizError;
+use crate::loader::load_scenario;
+use crate::markers::ADD;
+use crate::protobuf::FrameFormat;
+use crate::redis_reads::{frame_names, read_frames};
+use crate::scene_edits::{add_transform, new_frame, remove_transform};
+use crate::scene_ready::{Reloads, SceneReady};
+use crate::server::{Publishers, VisualizationServer};
+use crate::sim_time::StampClock;
+use crate::sink::RecordingSink;
+use crate::tf_server::{FramePublishers, FrameSnapshot, TfServer};
+use crate::throttle::WarningThrottle;
+
+const KEY_PREFIX: &str = "transform:";
+
+/// A frame translated by `x` from its parent.
+fn frame_at(parent: &str, child: &str, x: f64) -> SPTransformStamped {
+    let transform = Transform {
+        translation: Vector3 { x, y: 0.0, z: 0.0 },
+        rotation: Quaternion {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            w: 1.0,
+        },
+    };
+    new_frame(parent, child, &transform, false).unwrap()
+}
+
+/// A frame translated by `x`, with a mesh marker showing `mesh_file` when
+/// there is one.
+fn mesh_frame(parent: &str, child: &str, x: f64, mesh_file: Option<&str>) -> SPTransformStamped {
+    let mut frame = frame_at(parent, child, x);
+    if let Some(mesh_file) = mesh_file {
+        let string = |value: &str| SPValue::String(StringOrUnknown::String(value.to_string()));
+        frame.metadata = MapOrUnknown::Map(vec![
+            (
+                string("visualize_mesh"),
+                SPValue::Bool(BoolOrUnknown::Bool(true)),
+            ),
+            (string("mesh_file"), string(mesh_file)),
+        ]);
+    }
+    frame
+}
+
+/// Write a `mesh_frame` into `dir/<child>.json`.
+fn write_frame(dir: &TempDir, parent: &str, child: &str, x: f64, mesh_file: Option<&str>) {
+    let frame = mesh_frame(parent, child, x, mesh_file);
+    let path = dir.path().join(format!("{}.json", child));
+    std::fs::write(path, serde_json::to_string(&frame).unwrap()).unwrap();
+}
+
+/// The configuration loading `scenario` one frame at a time through
+/// micro_sp, ticking every 50ms.
+fn scenario_config(scenario: &TempDir) -> Config {
+    let scenario_dir = format!("SCENARIO_DIR={}", scenario.path().display());
+    config_with(&[
+        "MESHES_DIR=/meshes",
+        &scenario_dir,
+        "LOAD_BATCH_SIZE=0",
+        "TF_PERIOD_MS=50",
+        "MARKER_PERIOD_MS=50",
+    ])
+}
+
+async fn delete_frames(con: &mut redis::aio::MultiplexedConnection, names: &[&str]) {
+    let keys: Vec<String> = names
+        .iter()
+        .map(|name| format!("{}{}", KEY_PREFIX, name))
+        .collect();
+    redis::cmd("DEL")
+        .arg(&keys)
+        .query_async::<()>(con)
+        .await
+        .unwrap();
+}
+
+/// Spin the node until the returned flag is set, like the node's spin thread.
+fn spin(node: Arc<Mutex<r2r::Node>>) -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    std::thread::spawn(move || {
+        while !stopped.load(Ordering::Relaxed) {
+            node.lock().unwrap().spin_once(Duration::from_millis(10));
+        }
+    });
+    stop
+}
+
+/// Both servers running on `config` against recording sinks, on a node of
+/// their own whose timers a spin thread drives.
+struct Servers {
+    active_frames: RecordingSink<TFMessage>,
+    static_frames: RecordingSink<TFMessage>,
+    mesh: RecordingSink<MarkerArray>,
+    scene_ready: RecordingSink<Bool>,
+    tf_task: JoinHandle<Result<(), VizError>>,
+    marker_task: JoinHandle<Result<(), VizError>>,
+    stop_spinning: Arc<AtomicBool>,
+}
+
+impl Servers {
+    async fn start(node_name: &str, config: Config) -> Servers {
+        let connection_manager = ConnectionManager::new().await;
+        let ctx = r2r::Context::create().unwrap();
+        let mut node = r2r::Node::create(ctx, node_name, "").unwrap();
+        let tf_timer = node
+            .create_wall_timer(Duration::from_millis(config.tf_period_ms))
+            .unwrap();
+        let marker_timer = node
+            .create_wall_timer(Duration::from_millis(config.marker_period_ms))
+            .unwrap();
+        let health = node
+            .create_publisher::<Bool>("redis_visualization/ok", QosProfile::default())
+            .unwrap();
+        let stop_spinning = spin(Arc::new(Mutex::new(node)));
+
+        let active_frames = RecordingSink::<TFMessage>::default();
+        let static_frames = RecordingSink::<TFMessage>::default();
+        let mesh = RecordingSink::<MarkerArray>::default();
+        let scene_ready = RecordingSink::<Bool>::default();
+        let (snapshot_sender, snapshot_receiver) = watch::channel(None);
+        let tf_server = TfServer::builder()
+            .publishers(FramePublishers {
+                active_frames: Box::new(active_frames.clone()),
+                static_frames: Box::new(static_frames.clone()),
+                frame_events: None,
+                health,
+            })
+            .connection_manager(Arc::new(connection_manager))
+            .timer(tf_timer)
+            .config(config.clone())
+            .clock(StampClock::new(None).unwrap())
+            .snapshots(snapshot_sender)
+            .build()
+            .unwrap();
+        let visualization_server = VisualizationServer::builder()
+            .publishers(Publishers {
+                mesh: Box::new(mesh.clone()),
+                zone: None,
+                collision: Box::new(RecordingSink::<MarkerArray>::default()),
+                text: None,
+                axes: None,
+                links: Box::new(RecordingSink::<MarkerArray>::default()),
+                web: None,
+                diagnostics: None,
+                zone_grid: None,
+                delta: None,
+                reference_grid: None,
+                orientation_trail: None,
+                marker_topics: None,
+                scene_ready: SceneReady::new(Box::new(scene_ready.clone()), Reloads::default()),
+            })
+            .snapshots(snapshot_receiver)
+            .timer(marker_timer)
+            .config(config)
+            .build()
+            .unwrap();
+        Servers {
+            active_frames,
+            static_frames,
+            mesh,
+            scene_ready,
+            tf_task: tokio::task::spawn(tf_server.run()),
+            marker_task: tokio::task::spawn(visualization_server.run()),
+            stop_spinning,
+        }
+    }
+
+    /// Wait up to 10 seconds for the scene to be published, whether it was.
+    async fn scene_published(&self) -> bool {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !self.scene_ready.sent().iter().any(|msg| msg.data) {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+
+    fn stop(&self) {
+        self.tf_task.abort();
+        self.marker_task.abort();
+        self.stop_spinning.store(true, Ordering::Relaxed);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_stored_scene_is_broadcast_and_drawn() {
+    let (table, camera) = ("integration_scene_table", "integration_scene_camera");
+    let scenario = TempDir::new().unwrap();
+    write_frame(&scenario, "world", table, 1.0, Some("table.stl"));
+    write_frame(&scenario, table, camera, 0.1, None);
+    let config = scenario_config(&scenario);
+    let connection_manager = ConnectionManager::new().await;
+    let mut con = connection_manager.get_connection().await;
+    load_scenario(&mut con, &config).await.unwrap();
+
+    let servers = Servers::start("redis_visualization_integration_test", config).await;
+    let published = servers.scene_published().await;
+    servers.stop();
+    delete_frames(&mut con, &[table, camera]).await;
+    assert!(published, "the scene was never published");
+
+    let static_msg = servers
+        .static_frames
+        .sent()
+        .pop()
+        .expect("no static transforms");
+    let transform = static_msg
+        .transforms
+        .iter()
+        .find(|transform| transform.child_frame_id == camera)
+        .expect("the camera isn't broadcast");
+    assert_eq!(transform.header.frame_id, table);
+    assert_eq!(transform.transform.translation.x, 0.1);
+    assert!(servers
+        .mesh
+        .sent()
+        .iter()
+        .flat_map(|msg| &msg.markers)
+        .any(|marker| {
+            marker.header.frame_id == table && marker.mesh_resource.ends_with("table.stl")
+        }));
+}
+
+#[tokio::test]
+async fn pipelined_reads_match_micro_sp() {
+    let names = ["integration_reads_table", "integration_reads_robot"];
+    let scenario = TempDir::new().unwrap();
+    write_frame(&scenario, "world", names[0], 1.0, Some("table.stl"));
+    write_frame(&scenario, names[0], names[1], 0.5, None);
+    let config = scenario_config(&scenario);
+    let connection_manager = ConnectionManager::new().await;
+    let mut con = connection_manager.get_connection().await;
+    load_scenario(&mut con, &config).await.unwrap();
+
+    let by_micro_sp = TransformsManager::get_all_transforms(&mut con)
+        .await
+        .unwrap();
+    let mut warnings = WarningThrottle::new(config.warning_interval_secs);
+    let mut reads = vec![];
+    for atomic in [false, true] {
+        let read = read_frames(
+            &mut con,
+            KEY_PREFIX,
+            FrameFormat::Json,
+            atomic,
+            &mut warnings,
+        )
+        .await;
+        reads.push(read);
+    }
+    delete_frames(&mut con, &names).await;
+    for read in reads {
+        let read = read.unwrap();
+        for name in names {
+            assert_eq!(
+                serde_json::to_value(&read[name]).unwrap(),
+                serde_json::to_value(&by_micro_sp[name]).unwrap()
+            );
+        }
+    }
+    assert_eq!(
+        by_micro_sp[names[1]].transform.translation.x.into_inner(),
+        0.5
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn atomic_reads_never_see_half_a_write() {
+    let names = ["integration_atomic_table", "integration_atomic_robot"];
+    let connection_manager = ConnectionManager::new().await;
+    let mut con = connection_manager.get_connection().await;
+    let mut writer_con = connection_manager.get_connection().await;
+    // moves both frames to the same x in one transaction, over and over
+    let writer = tokio::task::spawn(async move {
+        for x in 0..500 {
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            for name in names {
+                let frame = frame_at("world", name, x as f64);
+                pipe.set(
+                    format!("{}{}", KEY_PREFIX, name),
+                    serde_json::to_string(&frame).unwrap(),
+                );
+            }
+            pipe.query_async::<()>(&mut writer_con).await.unwrap();
+        }
+    });
+    let mut warnings = WarningThrottle::new(0.0);
+    let mut snapshots = vec![];
+    while !writer.is_finished() {
+        let read = read_frames(&mut con, KEY_PREFIX, FrameFormat::Json, true, &mut warnings).await;
+        snapshots.push(read);
+    }
+    writer.await.unwrap();
+    delete_frames(&mut con, &names).await;
+    let x = |read: &HashMap<String, SPTransformStamped>, name: &str| {
+        read.get(name)
+            .map(|frame| frame.transform.translation.x.into_inner())
+    };
+    for read in snapshots {
+        let read = read.unwrap();
+        assert_eq!(x(&read, names[0]), x(&read, names[1]));
+    }
+}
+
+#[tokio::test]
+async fn removing_a_frame_deletes_it_and_unknown_frames_are_rejected() {
+    let (table, cup) = ("integration_remove_table", "integration_remove_cup");
+    let config = config_with(&[]);
+    let connection_manager = ConnectionManager::new().await;
+    for frame in [frame_at("world", table, 1.0), frame_at(table, cup, 0.1)] {
+        add_transform(&connection_manager, KEY_PREFIX, &frame)
+            .await
+            .unwrap();
+    }
+    let mut warnings = WarningThrottle::new(0.0);
+    let mut results = vec![];
+    // the cup still hangs from the table at first, and the table is gone the last time
+    for frame in [table, cup, table, table] {
+        let result = remove_transform(&connection_manager, &config, frame, &mut warnings).await;
+        results.push(result);
+    }
+    let mut con = connection_manager.get_connection().await;
+    let left = frame_names(&mut con, KEY_PREFIX).await.unwrap();
+    delete_frames(&mut con, &[table, cup]).await;
+
+    assert!(matches!(&results[0], Err(e) if e.contains(cup)));
+    assert_eq!(results[1..], [Ok(true), Ok(true), Ok(false)]);
+    assert!(!left.iter().any(|name| name == table || name == cup));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_tasks_keep_their_own_periods_and_outlive_each_other() {
+    let table = "integration_periods_table";
+    let scenario = TempDir::new().unwrap();
+    write_frame(&scenario, "world", table, 1.0, Some("table.stl"));
+    let config = scenario_config(&scenario);
+    let connection_manager = ConnectionManager::new().await;
+    let mut con = connection_manager.get_connection().await;
+    load_scenario(&mut con, &config).await.unwrap();
+
+    let config = Config {
+        tf_period_ms: 20,
+        marker_period_ms: 100,
+        ..config
+    };
+    let servers = Servers::start("redis_visualization_periods_test", config).await;
+    let published = servers.scene_published().await;
+    let (tf_before, markers_before) = (
+        servers.active_frames.sent().len(),
+        servers.mesh.sent().len(),
+    );
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let tf_ticks = servers.active_frames.sent().len() - tf_before;
+    let marker_ticks = servers.mesh.sent().len() - markers_before;
+    // the marker task failing leaves TF running
+    servers.marker_task.abort();
+    let tf_after_abort = servers.active_frames.sent().len();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let tf_ticks_after_abort = servers.active_frames.sent().len() - tf_after_abort;
+    servers.stop();
+    delete_frames(&mut con, &[table]).await;
+
+    assert!(published, "the scene was never published");
+    // about 50 and 10, leaving room for a loaded machine
+    assert!(tf_ticks >= 25, "{} TF ticks in a second", tf_ticks);
+    assert!(
+        (5..=12).contains(&marker_ticks),
+        "{} marker ticks in a second",
+        marker_ticks
+    );
+    assert!(tf_ticks > 2 * marker_ticks);
+    assert!(tf_ticks_after_abort >= 10);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_dry_run_publishes_nothing() {
+    let table = "integration_dry_run_table";
+    let scenario = TempDir::new().unwrap();
+    write_frame(&scenario, "world", table, 1.0, Some("table.stl"));
+    let config = Config {
+        dry_run: true,
+        ..scenario_config(&scenario)
+    };
+    let connection_manager = ConnectionManager::new().await;
+    let mut con = connection_manager.get_connection().await;
+    load_scenario(&mut con, &config).await.unwrap();
+
+    let servers = Servers::start("redis_visualization_dry_run_test", config).await;
+    // 20 ticks of both tasks
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let running = !servers.tf_task.is_finished() && !servers.marker_task.is_finished();
+    servers.stop();
+    delete_frames(&mut con, &[table]).await;
+
+    assert!(running);
+    assert_eq!(servers.active_frames.attempts(), 0);
+    assert_eq!(servers.static_frames.attempts(), 0);
+    assert_eq!(servers.mesh.attempts(), 0);
+    assert_eq!(servers.scene_ready.attempts(), 0);
+}
+
+/// Needs no Redis: the snapshot the TF task would read goes straight into
+/// the marker task's channel.
+#[tokio::test(flavor = "multi_thread")]
+async fn a_snapshot_is_drawn_on_the_marker_sinks() {
+    let frames = HashMap::from([
+        (
+            "table".to_string(),
+            mesh_frame("world", "table", 1.0, Some("table.stl")),
+        ),
+        ("camera".to_string(), frame_at("table", "camera", 0.1)),
+    ]);
+    let snapshot = FrameSnapshot {
+        frames,
+        ttls: HashMap::new(),
+        hidden_assemblies: HashSet::new(),
+        hidden_layers: HashSet::new(),
+        scalars: HashMap::new(),
+        time_stamp: Time { sec: 1, nanosec: 0 },
+        stale: HashSet::new(),
+        outdated: HashSet::new(),
+        publish_errors: vec![],
+        read_at: std::time::Instant::now(),
+        fetch_duration: Duration::ZERO,
+        active_count: 0,
+        static_count: 2,
+    };
+    let (snapshot_sender, snapshot_receiver) = watch::channel(Some(Arc::new(snapshot)));
+
+    let config = config_with(&["MESHES_DIR=/meshes", "MARKER_PERIOD_MS=50"]);
+    let ctx = r2r::Context::create().unwrap();
+    let mut node = r2r::Node::create(ctx, "redis_visualization_snapshot_test", "").unwrap();
+    let marker_timer = node
+        .create_wall_timer(Duration::from_millis(config.marker_period_ms))
+        .unwrap();
+    let stop_spinning = spin(Arc::new(Mutex::new(node)));
+    let mesh = RecordingSink::<MarkerArray>::default();
+    let zone = RecordingSink::<MarkerArray>::default();
+    let scene_ready = RecordingSink::<Bool>::default();
+    let visualization_server = VisualizationServer::builder()
+        .publishers(Publishers {
+            mesh: Box::new(mesh.clone()),
+            zone: Some(Box::new(zone.clone())),
+            collision: Box::new(RecordingSink::<MarkerArray>::default()),
+            text: None,
+            axes: None,
+            links: Box::new(RecordingSink::<MarkerArray>::default()),
+            web: None,
+            diagnostics: None,
+            zone_grid: None,
+            delta: None,
+            reference_grid: None,
+            orientation_trail: None,
+            marker_topics: None,
+            scene_ready: SceneReady::new(Box::new(scene_ready.clone()), Reloads::default()),
+        })
+        .snapshots(snapshot_receiver)
+        .timer(marker_timer)
+        .config(config)
+        .build()
+        .unwrap();
+    let marker_task = tokio::task::spawn(visualization_server.run());
+    let published = tokio::time::timeout(Duration::from_secs(10), async {
+        while !scene_ready.sent().iter().any(|msg| msg.data) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .is_ok();
+    marker_task.abort();
+    stop_spinning.store(true, Ordering::Relaxed);
+    drop(snapshot_sender);
+    assert!(published, "the scene was never published");
+
+    let msg = mesh.sent().pop().unwrap();
+    let added: Vec<&Marker> = msg
+        .markers
+        .iter()
+        .filter(|marker| marker.action == ADD)
+        .collect();
+    // the camera has no mesh
+    assert_eq!(added.len(), 1);
+    assert_eq!(added[0].header.frame_id, "table");
+    assert_eq!(added[0].mesh_resource, "file:///meshes/table.stl");
+    assert!(zone
+        .sent()
+        .iter()
+        .all(|msg| msg.markers.iter().all(|m| m.action != ADD)));
+}
@@ -0,0 +1,202 @@
+use futures::StreamExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::log_targets;
+use crate::replicas::{primary_endpoint, redis_url};
+
+/// Whether the stored frames changed since the last rebuild, as told by
+/// Redis keyspace notifications. Until notifications are known to work, and
+/// whenever they stop, every tick rebuilds like without REDIS_NOTIFY.
+#[derive(Debug, Clone)]
+pub struct SceneChanges {
+    changed: Arc<AtomicBool>,
+    listening: Arc<AtomicBool>,
+}
+
+impl Default for SceneChanges {
+    fn default() -> SceneChanges {
+        SceneChanges {
+            // the first tick always builds the scene
+            changed: Arc::new(AtomicBool::new(true)),
+            listening: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl SceneChanges {
+    pub fn mark(&self) {
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    fn set_listening(&self, listening: bool) {
+        self.listening.store(listening, Ordering::Relaxed);
+    }
+
+    fn take(&self) -> bool {
+        self.changed.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Decides which ticks rebuild and publish: those after a change, and one per
+/// heartbeat interval so markers with a lifetime don't expire.
+#[derive(Debug)]
+pub struct RebuildTrigger {
+    changes: SceneChanges,
+    heartbeat: Duration,
+    last: Option<Instant>,
+}
+
+impl RebuildTrigger {
+    pub fn new(changes: SceneChanges, heartbeat_secs: f64) -> RebuildTrigger {
+        RebuildTrigger {
+            changes,
+            heartbeat: Duration::from_secs_f64(heartbeat_secs.max(0.0)),
+            last: None,
+        }
+    }
+
+    pub fn due(&mut self, now: Instant) -> bool {
+        let changed = self.changes.take();
+        let polling = !self.changes.listening.load(Ordering::Relaxed);
+        let heartbeat = match self.last {
+            Some(last) => now.duration_since(last) >= self.heartbeat,
+            None => true,
+        };
+        if changed || polling || heartbeat {
+            self.last = Some(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Whether a `notify-keyspace-events` value sends keyspace events for the
+/// writes, expiries and deletes of string keys.
+fn notifies_strings(events: &str) -> bool {
+    let covers = |class: char| events.contains(class) || events.contains('A');
+    events.contains('K') && covers('$') && covers('g') && covers('x')
+}
+
+/// Mark `changes` on every keyspace notification for a key under
+/// `key_prefix`. Returns, leaving the ticks polling, when the server doesn't
+/// have keyspace notifications enabled or doesn't allow checking that.
+/// A lost subscription is retried while the ticks poll.
+pub async fn watch_keyspace(changes: SceneChanges, key_prefix: String) {
+    let client = match redis::Client::open(redis_url(&primary_endpoint())) {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!(target: log_targets::REDIS,
+                "REDIS_NOTIFY is unavailable, polling instead: {}", e
+            );
+            return;
+        }
+    };
+    loop {
+        match subscribe(&client, &key_prefix).await {
+            Ok(Some(mut pubsub)) => {
+                log::info!(target: log_targets::REDIS,
+                    "Rebuilding on keyspace notifications for '{}*'.", key_prefix
+                );
+                changes.set_listening(true);
+                // anything written while not listening
+                changes.mark();
+                let mut messages = pubsub.on_message();
+                while messages.next().await.is_some() {
+                    changes.mark();
+                }
+                changes.set_listening(false);
+                log::warn!(target: log_targets::REDIS,
+                    "Lost the keyspace notifications, polling until they are back."
+                );
+            }
+            Ok(None) => return,
+            Err(e) => {
+                log::warn!(target: log_targets::REDIS,
+                    "Failed to subscribe to keyspace notifications with: {}", e
+                );
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// A subscription to the keyspace of `key_prefix`, None when notifications
+/// aren't enabled on the server.
+async fn subscribe(
+    client: &redis::Client,
+    key_prefix: &str,
+) -> Result<Option<redis::aio::PubSub>, redis::RedisError> {
+    let mut con = client.get_multiplexed_tokio_connection().await?;
+    let config: Result<Vec<String>, _> = redis::cmd("CONFIG")
+        .arg("GET")
+        .arg("notify-keyspace-events")
+        .query_async(&mut con)
+        .await;
+    let events = match config {
+        Ok(config) => config.get(1).cloned().unwrap_or_default(),
+        Err(e) => {
+            log::warn!(target: log_targets::REDIS,
+                "Can't read notify-keyspace-events, polling instead of REDIS_NOTIFY: {}", e
+            );
+            return Ok(None);
+        }
+    };
+    if !notifies_strings(&events) {
+        log::warn!(target: log_targets::REDIS,
+            "notify-keyspace-events is '{}', polling instead of REDIS_NOTIFY. \
+             Set it to at least 'K$gx' to enable it.",
+            events
+        );
+        return Ok(None);
+    }
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.psubscribe(format!("__keyspace@*__:{}*", key_prefix)).await?;
+    Ok(Some(pubsub))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifications_trigger_rebuilds_and_quiet_ticks_skip() {
+        let changes = SceneChanges::default();
+        changes.set_listening(true);
+        let mut trigger = RebuildTrigger::new(changes.clone(), 10.0);
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        // the first tick always builds the scene
+        assert!(trigger.due(at(0)));
+        assert!(!trigger.due(at(50)));
+        changes.mark();
+        assert!(trigger.due(at(100)));
+        assert!(!trigger.due(at(150)));
+        // markers with a lifetime are refreshed once per heartbeat
+        assert!(trigger.due(at(10_100)));
+        assert!(!trigger.due(at(10_150)));
+    }
+
+    #[test]
+    fn every_tick_rebuilds_while_not_listening() {
+        let changes = SceneChanges::default();
+        let mut trigger = RebuildTrigger::new(changes.clone(), 10.0);
+        let now = Instant::now();
+        assert!(trigger.due(now));
+        assert!(trigger.due(now));
+        changes.set_listening(true);
+        assert!(!trigger.due(now));
+    }
+
+    #[test]
+    fn string_writes_expiries_and_deletes_must_be_notified() {
+        assert!(notifies_strings("K$gx"));
+        assert!(notifies_strings("KA"));
+        assert!(notifies_strings("AKE"));
+        assert!(!notifies_strings(""));
+        assert!(!notifies_strings("E$gx"));
+        assert!(!notifies_strings("K$g"));
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashSet;
+
+use redis::aio::MultiplexedConnection;
+
+use crate::log_targets;
+
+/// Layers currently hidden, the members of the Redis set at `key`.
+///
+/// A frame lists its layers in its `layers` metadata and is hidden while any
+/// of them is, so `SADD <key> fixtures` hides every fixture and `SREM` shows
+/// them again on the next tick. Read failures are logged and hide nothing.
+pub async fn hidden_layers(con: &mut MultiplexedConnection, key: &str) -> HashSet<String> {
+    match redis::cmd("SMEMBERS")
+        .arg(key)
+        .query_async::<HashSet<String>>(con)
+        .await
+    {
+        Ok(hidden) => hidden,
+        Err(e) => {
+            log::error!(target: log_targets::REDIS,
+                "Failed to read the hidden layers from '{}' with: {}", key, e
+            );
+            HashSet::new()
+        }
+    }
+}
+
+/// Whether any of a frame's layers is hidden.
+pub fn in_hidden_layer(layers: &[String], hidden: &HashSet<String>) -> bool {
+    layers.iter().any(|layer| hidden.contains(layer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use micro_sp::*;
+    use r2r::visualization_msgs::msg::Marker;
+
+    use crate::markers::{delete_hidden, ADD, DELETE};
+    use crate::metadata::decode_metadata;
+
+    /// The actions sent for the mesh and label markers of frames on `layers`,
+    /// mesh markers first.
+    fn actions(layers: &[&str], hidden: &HashSet<String>) -> Vec<(String, i32)> {
+        let string = |value: &str| SPValue::String(StringOrUnknown::String(value.to_string()));
+        let mut markers = vec![];
+        let mut hidden_ids = HashSet::new();
+        for ns in ["mesh", "label"] {
+            for (id, frame_layers) in layers.iter().enumerate() {
+                let map = MapOrUnknown::Map(vec![(string("layers"), string(frame_layers))]);
+                if in_hidden_layer(&decode_metadata(&map).layers, hidden) {
+                    hidden_ids.insert(id as i32);
+                }
+                markers.push(Marker {
+                    ns: ns.to_string(),
+                    id: id as i32,
+                    action: ADD,
+                    ..Marker::default()
+                });
+            }
+        }
+        delete_hidden(&mut markers, &hidden_ids);
+        markers
+            .into_iter()
+            .map(|marker| (format!("{}/{}", marker.ns, marker.id), marker.action))
+            .collect()
+    }
+
+    #[test]
+    fn hiding_a_layer_deletes_exactly_its_frames_markers() {
+        let layers = ["fixtures", "robots", "fixtures, cell_1", ""];
+        let hidden = HashSet::from(["fixtures".to_string()]);
+        let deleted: Vec<String> = actions(&layers, &hidden)
+            .into_iter()
+            .filter(|(_, action)| *action == DELETE)
+            .map(|(marker, _)| marker)
+            .collect();
+        assert_eq!(deleted, ["mesh/0", "mesh/2", "label/0", "label/2"]);
+
+        // shown again, every marker is added back
+        let shown = actions(&layers, &HashSet::new());
+        assert!(shown.iter().all(|(_, action)| *action == ADD));
+        assert_eq!(shown.len(), 8);
+    }
+}
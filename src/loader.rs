@@ -0,0 +1,294 @@
+use redis::aio::MultiplexedConnection;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use micro_sp::*;
+
+use crate::config::Config;
+use crate::error::VizError;
+use crate::geometry::{to_meters, LengthUnits};
+use crate::log_targets;
+use crate::scene_ready::Reloads;
+use crate::secondary::read_scene_dir;
+
+/// Store frames in Redis as the JSON micro_sp reads, `batch_size` frames
+/// per pipelined round trip instead of one each. A failed batch stops the
+/// load with an error naming its frames, the batches before it stay written.
+/// Returns the number of round trips.
+pub async fn load_scenario_batched(
+    con: &mut MultiplexedConnection,
+    frames: &HashMap<String, SPTransformStamped>,
+    key_prefix: &str,
+    batch_size: usize,
+) -> Result<usize, VizError> {
+    let mut names: Vec<&String> = frames.keys().collect();
+    names.sort();
+    let batches = names.chunks(batch_size.max(1));
+    let round_trips = batches.len();
+    for batch in batches {
+        let mut pipe = redis::pipe();
+        for name in batch {
+            let value = serde_json::to_string(&frames[*name])?;
+            pipe.set(format!("{}{}", key_prefix, name), value).ignore();
+        }
+        if let Err(e) = pipe.query_async::<()>(con).await {
+            let names: Vec<&str> = batch.iter().map(|name| name.as_str()).collect();
+            return Err(VizError::TransformLoad(format!(
+                "writing the frames {} to Redis failed with: {}",
+                names.join(", "),
+                e
+            )));
+        }
+    }
+    Ok(round_trips)
+}
+
+/// The frames of several scenario directories, merged in order and converted
+/// from `units` to meters, along with the files that don't decode. A frame of
+/// a later directory replaces the frame of the same `child_frame_id` from an
+/// earlier one.
+fn read_scenarios(
+    dirs: &[PathBuf],
+    units: LengthUnits,
+) -> Result<(HashMap<String, SPTransformStamped>, Vec<(PathBuf, serde_json::Error)>), VizError> {
+    let mut frames = HashMap::new();
+    let mut origins: HashMap<String, &Path> = HashMap::new();
+    let mut skipped = vec![];
+    for dir in dirs {
+        let (dir_frames, dir_skipped) = read_scene_dir(&dir.to_string_lossy())?;
+        skipped.extend(dir_skipped);
+        for (name, frame) in dir_frames {
+            if let Some(earlier) = origins.insert(name.clone(), dir) {
+                log::warn!(target: log_targets::REDIS,
+                    "Frame '{}' of {} overrides the one of {}.",
+                    name,
+                    dir.display(),
+                    earlier.display()
+                );
+            }
+            frames.insert(name, frame);
+        }
+    }
+    let mut origins: Vec<(String, &Path)> = origins.into_iter().collect();
+    origins.sort();
+    for (name, dir) in origins {
+        log::debug!(target: log_targets::REDIS,
+            "Frame '{}' is loaded from {}.", name, dir.display()
+        );
+    }
+    to_meters(&mut frames, units);
+    Ok((frames, skipped))
+}
+
+/// The frames of several scenario directories, merged in order with later
+/// directories overriding the frames of earlier ones. Files that don't decode
+/// are skipped with a warning.
+pub fn merge_scenarios(
+    dirs: &[PathBuf],
+    units: LengthUnits,
+) -> Result<HashMap<String, SPTransformStamped>, VizError> {
+    let (frames, skipped) = read_scenarios(dirs, units)?;
+    for (path, e) in skipped {
+        log::warn!(target: log_targets::REDIS,
+            "Skipping scenario file {} with: {}", path.display(), e
+        );
+    }
+    Ok(frames)
+}
+
+/// Store the scenario directories in Redis, in batches of LOAD_BATCH_SIZE or
+/// one by one through micro_sp when that is 0. micro_sp stores the files as
+/// they are, so frames in other LENGTH_UNITS are always written here, one per
+/// round trip with a batch size of 0. Returns the merged frames.
+pub async fn load_scenario(
+    con: &mut MultiplexedConnection,
+    config: &Config,
+) -> Result<HashMap<String, SPTransformStamped>, VizError> {
+    let frames = merge_scenarios(&config.scenario_paths(), config.length_units)?;
+    if config.load_batch_size > 0 || config.length_units != LengthUnits::Meters {
+        let prefix = &config.transform_key_prefix;
+        let round_trips =
+            load_scenario_batched(con, &frames, prefix, config.load_batch_size).await?;
+        log::info!(target: log_targets::REDIS,
+            "Loaded {} frames from {} in {} round trips.",
+            frames.len(),
+            config.scenario_dirs.join(":"),
+            round_trips
+        );
+    } else {
+        // later directories overwrite the frames of earlier ones
+        for dir in &config.scenario_dirs {
+            TransformsManager::load_transforms_from_path(con, dir)
+                .await
+                .map_err(|e| VizError::TransformLoad(e.to_string()))?;
+        }
+    }
+    Ok(frames)
+}
+
+/// Frames a reload of the scenario added, changed or dropped, each sorted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScenarioDiff {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl std::fmt::Display for ScenarioDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} updated, {} removed",
+            self.added.len(),
+            self.updated.len(),
+            self.removed.len()
+        )
+    }
+}
+
+/// What changed from the frames `old` to `new`. A frame counts as updated
+/// when any of its fields, metadata included, differs.
+pub fn scenario_diff(
+    old: &HashMap<String, SPTransformStamped>,
+    new: &HashMap<String, SPTransformStamped>,
+) -> ScenarioDiff {
+    // compared through their JSON, which covers every field
+    let same = |a: &SPTransformStamped, b: &SPTransformStamped| {
+        serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+    };
+    let mut diff = ScenarioDiff::default();
+    for (name, frame) in new {
+        match old.get(name) {
+            None => diff.added.push(name.clone()),
+            Some(previous) if !same(previous, frame) => diff.updated.push(name.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = old.keys().filter(|name| !new.contains_key(*name)).cloned().collect();
+    diff.added.sort();
+    diff.updated.sort();
+    diff.removed.sort();
+    diff
+}
+
+/// Store the scenario directories again and delete the frames their files no
+/// longer have. `previous` holds the frames of the last load and is replaced
+/// with the current ones. Nothing is written, and `VizError::Decode`
+/// returned, while a file doesn't decode, e.g. because an editor is halfway
+/// through saving it.
+pub async fn reload_scenario(
+    con: &mut MultiplexedConnection,
+    config: &Config,
+    previous: &mut HashMap<String, SPTransformStamped>,
+) -> Result<ScenarioDiff, VizError> {
+    let (frames, skipped) = read_scenarios(&config.scenario_paths(), config.length_units)?;
+    if let Some((path, e)) = skipped.first() {
+        return Err(VizError::Decode(format!("{}: {}", path.display(), e)));
+    }
+    let diff = scenario_diff(previous, &frames);
+    load_scenario(con, config).await?;
+    if !diff.removed.is_empty() {
+        let keys: Vec<String> = diff
+            .removed
+            .iter()
+            .map(|name| format!("{}{}", config.transform_key_prefix, name))
+            .collect();
+        redis::cmd("DEL").arg(&keys).query_async::<()>(con).await?;
+    }
+    *previous = frames;
+    Ok(diff)
+}
+
+/// Reloads the scenario for the watcher and the `reload_scenario` service,
+/// one at a time, each diffed against the load before it.
+#[derive(Clone)]
+pub struct ScenarioReloader {
+    connection_manager: Arc<ConnectionManager>,
+    config: Config,
+    /// The frames of the last load, locked for the whole of a reload.
+    previous: Arc<tokio::sync::Mutex<HashMap<String, SPTransformStamped>>>,
+    reloads: Reloads,
+}
+
+impl ScenarioReloader {
+    /// Diffing the first reload against `loaded`, the frames of the startup load.
+    pub fn new(
+        connection_manager: Arc<ConnectionManager>,
+        config: Config,
+        loaded: HashMap<String, SPTransformStamped>,
+    ) -> ScenarioReloader {
+        ScenarioReloader {
+            connection_manager,
+            config,
+            previous: Arc::new(tokio::sync::Mutex::new(loaded)),
+            reloads: Reloads::default(),
+        }
+    }
+
+    /// The count of successful reloads, which re-asserts `scene_ready`.
+    pub fn reloads(&self) -> Reloads {
+        self.reloads.clone()
+    }
+
+    pub fn scenario_dirs(&self) -> &[String] {
+        &self.config.scenario_dirs
+    }
+
+    /// Reload the scenario, waiting for a reload already running to finish.
+    pub async fn reload(&self) -> Result<ScenarioDiff, VizError> {
+        let mut previous = self.previous.lock().await;
+        let mut con = self.connection_manager.get_connection().await;
+        let diff = reload_scenario(&mut con, &self.config, &mut previous).await?;
+        self.reloads.record();
+        Ok(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_edits::new_frame;
+    use r2r::geometry_msgs::msg::{Quaternion, Transform, Vector3};
+    use tempfile::TempDir;
+
+    /// Write a frame translated by `x` into `dir/<child>.json`.
+    fn write_frame(dir: &TempDir, parent: &str, child: &str, x: f64) {
+        let transform = Transform {
+            translation: Vector3 { x, y: 0.0, z: 0.0 },
+            rotation: Quaternion {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+                w: 1.0,
+            },
+        };
+        let frame = new_frame(parent, child, &transform, false).unwrap();
+        let path = dir.path().join(format!("{}.json", child));
+        std::fs::write(path, serde_json::to_string(&frame).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn later_scenario_dirs_override_earlier_ones() {
+        let library = TempDir::new().unwrap();
+        write_frame(&library, "world", "table", 1.0);
+        write_frame(&library, "world", "robot", 0.5);
+        let project = TempDir::new().unwrap();
+        write_frame(&project, "world", "table", 2.0);
+        write_frame(&project, "table", "camera", 0.1);
+        std::fs::write(project.path().join("broken.json"), "{").unwrap();
+
+        let dirs = [library.path().to_path_buf(), project.path().to_path_buf()];
+        let frames = merge_scenarios(&dirs, LengthUnits::Meters).unwrap();
+        let mut names: Vec<&str> = frames.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["camera", "robot", "table"]);
+        assert_eq!(frames["table"].transform.translation.x.into_inner(), 2.0);
+        assert_eq!(frames["robot"].transform.translation.x.into_inner(), 0.5);
+
+        // the other way around the library wins
+        let dirs = [project.path().to_path_buf(), library.path().to_path_buf()];
+        let frames = merge_scenarios(&dirs, LengthUnits::Meters).unwrap();
+        assert_eq!(frames["table"].transform.translation.x.into_inner(), 1.0);
+    }
+}
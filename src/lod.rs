@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+use micro_sp::*;
+
+use crate::geometry::pose_in_root;
+
+/// Where LOD_MAX_DISTANCE is measured from: the origin of LOD_VIEWPOINT_FRAME
+/// in the root of its tree.
+#[derive(Debug, Clone)]
+pub struct LodViewpoint {
+    root: String,
+    position: [f64; 3],
+    max_distance: f64,
+}
+
+impl LodViewpoint {
+    /// None while the viewpoint frame isn't among `frames`.
+    pub fn locate(
+        frames: &HashMap<String, SPTransformStamped>,
+        viewpoint_frame: &str,
+        max_distance: f64,
+    ) -> Option<LodViewpoint> {
+        if !frames.contains_key(viewpoint_frame) {
+            return None;
+        }
+        let (root, pose) = pose_in_root(frames, viewpoint_frame)?;
+        Some(LodViewpoint {
+            root,
+            position: pose.translation,
+            max_distance,
+        })
+    }
+
+    /// Meters from the viewpoint to the origin of `child_frame_id`, None for a
+    /// frame in another tree.
+    pub fn distance(
+        &self,
+        frames: &HashMap<String, SPTransformStamped>,
+        child_frame_id: &str,
+    ) -> Option<f64> {
+        let (root, pose) = pose_in_root(frames, child_frame_id)?;
+        if root != self.root {
+            return None;
+        }
+        let squared: f64 = pose
+            .translation
+            .iter()
+            .zip(self.position)
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum();
+        Some(squared.sqrt())
+    }
+
+    /// Whether the frame keeps its mesh markers, see `within_lod`.
+    pub fn shows_meshes(
+        &self,
+        frames: &HashMap<String, SPTransformStamped>,
+        child_frame_id: &str,
+    ) -> bool {
+        within_lod(self.distance(frames, child_frame_id), self.max_distance)
+    }
+}
+
+/// Whether a frame `distance` meters from the viewpoint is drawn with its
+/// meshes: up to `max_distance` inclusive, and always when the distance is
+/// unknown.
+pub fn within_lod(distance: Option<f64>, max_distance: f64) -> bool {
+    match distance {
+        Some(distance) => distance <= max_distance,
+        None => true,
+    }
+}
@@ -0,0 +1,15 @@
+//! Log targets of the subsystems, so `RUST_LOG` can set their levels apart,
+//! e.g. `RUST_LOG=r2r_redis_visualization=info,r2r_redis_visualization::redis=debug`.
+//! They all start with the crate name, so a level set for it covers them all.
+
+/// Startup, configuration and everything not in a subsystem.
+pub const NODE: &str = "r2r_redis_visualization";
+/// Reading and writing Redis: frame reads, replicas, keyspace notifications,
+/// live scalars and the scenario loader.
+pub const REDIS: &str = "r2r_redis_visualization::redis";
+/// Building and publishing the markers.
+pub const MARKERS: &str = "r2r_redis_visualization::markers";
+/// Broadcasting and mirroring TF.
+pub const TF: &str = "r2r_redis_visualization::tf";
+/// ROS services and topics answering requests, and the HTTP status endpoint.
+pub const SERVICES: &str = "r2r_redis_visualization::services";
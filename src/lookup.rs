@@ -0,0 +1,98 @@
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use micro_sp::*;
+
+use crate::config::Config;
+use crate::geometry::to_enu;
+use crate::log_targets;
+use crate::protobuf::FrameFormat;
+use crate::redis_reads::read_frames;
+use crate::rename::FrameRenames;
+use crate::scene::{frame_summary, FrameSummary};
+use crate::throttle::WarningThrottle;
+
+/// A transform lookup received on `transform_requests`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LookupRequest {
+    /// Echoed in the response so requesters can match the two.
+    pub id: String,
+    pub frame: String,
+}
+
+/// The answer to a `LookupRequest`, published on `transform_responses`.
+#[derive(Debug, Clone, Serialize)]
+pub struct LookupResponse {
+    pub id: String,
+    pub frame: String,
+    /// The frame as published, None when it isn't in the scene.
+    pub transform: Option<FrameSummary>,
+    pub error: Option<String>,
+}
+
+/// Answer the JSON lookup requests on `requests` with the frame as the
+/// visualization server publishes it, i.e. renamed and in ENU.
+pub async fn serve_lookups(
+    mut requests: impl Stream<Item = r2r::std_msgs::msg::String> + Unpin,
+    responses: r2r::Publisher<r2r::std_msgs::msg::String>,
+    connection_manager: Arc<ConnectionManager>,
+    config: Config,
+) {
+    let mut renames = FrameRenames::parse(&config.frame_renames);
+    let mut warnings = WarningThrottle::new(config.warning_interval_secs);
+    while let Some(msg) = requests.next().await {
+        let request: LookupRequest = match serde_json::from_str(&msg.data) {
+            Ok(request) => request,
+            Err(e) => {
+                log::warn!(target: log_targets::SERVICES,
+                    "Ignoring malformed transform request '{}' with: {}", msg.data, e
+                );
+                continue;
+            }
+        };
+        let mut con = connection_manager.get_connection().await;
+        let frames = if config.frame_format != FrameFormat::Json {
+            let prefix = &config.transform_key_prefix;
+            read_frames(&mut con, prefix, config.frame_format, false, &mut warnings)
+                .await
+                .map_err(|e| e.to_string())
+        } else {
+            TransformsManager::get_all_transforms(&mut con)
+                .await
+                .map_err(|e| e.to_string())
+        };
+        let response = match frames {
+            Ok(frames) => {
+                let mut frames = renames.apply(frames);
+                to_enu(&mut frames, config.frame_convention);
+                LookupResponse {
+                    id: request.id,
+                    transform: frames.get(&request.frame).map(frame_summary),
+                    error: None,
+                    frame: request.frame,
+                }
+            }
+            Err(e) => LookupResponse {
+                id: request.id,
+                frame: request.frame,
+                transform: None,
+                error: Some(format!("failed to read the frames with: {}", e)),
+            },
+        };
+        let data = match serde_json::to_string(&response) {
+            Ok(data) => data,
+            Err(e) => {
+                log::error!(target: log_targets::SERVICES,
+                    "Failed to serialize a transform response with: {}", e
+                );
+                continue;
+            }
+        };
+        if let Err(e) = responses.publish(&r2r::std_msgs::msg::String { data }) {
+            log::error!(target: log_targets::SERVICES,
+                "Publisher failed to send a transform_responses message with: '{}'", e
+            );
+        }
+    }
+}
@@ -1,325 +1,708 @@
-use r2r::builtin_interfaces::msg::Duration;
-
+use r2r::nav_msgs::msg::OccupancyGrid;
+use r2r::scene_manipulation_msgs::srv::ManipulateScene;
+use r2r::std_srvs::srv::Trigger;
 use r2r::tf2_msgs::msg::TFMessage;
-use r2r::visualization_msgs::msg::{Marker, MarkerArray};
+use r2r::visualization_msgs::msg::MarkerArray;
 use r2r::QosProfile;
 use std::error::Error;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use r2r::geometry_msgs::msg::{Transform, TransformStamped, Vector3};
+use micro_sp::*;
 
-use r2r::builtin_interfaces::msg::Time;
-use r2r::geometry_msgs::msg::{Point, Pose, Quaternion};
-use r2r::std_msgs::msg::{ColorRGBA, Header};
+mod animation;
+mod appearance;
+mod appearance_rate;
+mod assemblies;
+mod backoff;
+mod broadcast;
+mod colormap;
+mod config;
+mod control;
+mod despawn;
+mod diagnostics;
+mod dry_run;
+mod empty_reads;
+mod error;
+mod events;
+mod geometry;
+mod governor;
+mod health;
+mod heartbeat;
+mod highlight;
+#[cfg(feature = "http-status")]
+mod http_status;
+#[cfg(all(test, feature = "integration-tests"))]
+mod integration_tests;
+mod keyspace;
+mod layers;
+mod loader;
+mod lod;
+mod log_targets;
+mod lookup;
+mod marker_topics;
+mod markers;
+mod mesh_cache;
+mod mesh_orientation;
+mod metadata;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod one_shot;
+mod orientation_trail;
+mod outdated;
+mod protobuf;
+mod redis_reads;
+mod rename;
+mod replicas;
+mod scalars;
+mod scene;
+mod scenario_watch;
+mod scene_edits;
+mod scene_ready;
+mod secondary;
+mod server;
+mod shutdown;
+mod sidecar;
+mod sim_time;
+mod sink;
+mod smoothing;
+mod throttle;
+mod tick_metrics;
+mod tf_mirror;
+mod tf_server;
+mod tf_snapshot;
+mod tree;
+mod tree_stats;
+mod ttl;
+mod validation;
+mod web;
+mod zone_grid;
+mod zone_transition;
 
-use micro_sp::*;
+use appearance::{follow_states, StateAppearances};
+use config::{period_hz, Config};
+use control::{serve_control, PublishControl};
+use diagnostics::{DiagnosticsPublisher, FrameThresholds, SharedTickStatus};
+use error::VizError;
+use geometry::{to_enu, to_meters, LengthUnits};
+use highlight::{serve_highlights, Highlights};
+use keyspace::{watch_keyspace, SceneChanges};
+use loader::{load_scenario, ScenarioReloader};
+use lookup::serve_lookups;
+use marker_topics::marker_topics;
+use markers::{confidence_legend, prefix_namespaces};
+use scenario_watch::{serve_reload_scenario, watch_scenario};
+use scene_edits::{serve_add_transform, serve_remove_transform, serve_save_scenario};
+use scene_ready::SceneReady;
+use secondary::{load_scene_dir, SecondaryScene};
+use server::{FrameOverrides, Publishers, VisualizationServer};
+use shutdown::{clear_previous_markers, shut_down_after_panic, spin_until_panic};
+use sim_time::{follow_sim_time, StampClock};
+use sink::MarkerSink;
+use tf_mirror::mirror_tf;
+use tf_server::{FramePublishers, TfServer};
+use tf_snapshot::publish_tf_snapshots;
+use tree::find_roots;
+use validation::{mesh_working_set, validate_scene};
+use web::tf_qos;
 
 pub static NODE_ID: &'static str = "redis_visualization";
-pub static BUFFER_MAINTAIN_RATE: u64 = 20;
-pub static MARKER_PUBLISH_RATE: u64 = 50;
-pub static FRAME_LIFETIME: i32 = 3; //seconds
+pub static MARKER_PERIOD_MS: u64 = 50;
+pub static FRAME_LIFETIME_SECS: f64 = 3.0;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+fn main() -> Result<(), Box<dyn Error>> {
     initialize_env_logger();
 
+    let mut config = Config::from_env()?;
+    config.exit_after_publish = std::env::args().any(|arg| arg == "--exit-after-publish");
+    if config.exit_after_publish && !config.one_shot {
+        log::warn!(target: log_targets::NODE,
+            "Ignoring --exit-after-publish, it only applies with ONE_SHOT."
+        );
+    }
+    if std::env::args().any(|arg| arg == "--print-config") {
+        println!("{}", config.dump());
+        std::process::exit(0);
+    }
+    log::info!(target: log_targets::NODE, "{}", config.dump());
+    config.check_dirs().map_err(VizError::Config)?;
+    config.check_periods().map_err(VizError::Config)?;
+    log::info!(target: log_targets::NODE,
+        "Meshes from '{}', scenario from '{}'.",
+        config.meshes_dir,
+        config.scenario_dirs.join("', '")
+    );
+
+    build_runtime(&config)?.block_on(run(config))
+}
+
+/// The tokio runtime, multi-threaded with one worker per core unless configured otherwise.
+fn build_runtime(config: &Config) -> std::io::Result<tokio::runtime::Runtime> {
+    let mut builder = if config.current_thread_runtime {
+        tokio::runtime::Builder::new_current_thread()
+    } else {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        if config.worker_threads > 0 {
+            builder.worker_threads(config.worker_threads);
+        }
+        builder
+    };
+    builder.enable_all().build()
+}
+
+/// A period in milliseconds from a node parameter, read once at startup.
+/// Unset or mistyped parameters fall back to `default`, and so do periods that
+/// aren't positive, with a warning.
+fn period_parameter(node: &r2r::Node, name: &str, default: u64) -> u64 {
+    match node.get_parameter::<i64>(name) {
+        Ok(period) if period > 0 => period as u64,
+        Ok(period) => {
+            log::warn!(target: log_targets::NODE,
+                "Parameter {} must be positive, got {}, using {}ms.", name, period, default
+            );
+            default
+        }
+        Err(_) => default,
+    }
+}
+
+async fn run(mut config: Config) -> Result<(), Box<dyn Error>> {
     // setup the node
     let ctx = r2r::Context::create()?;
     let mut node = r2r::Node::create(ctx, NODE_ID, "")?;
-
-    let meshes_dir = std::env::var("MESHES_DIR").expect("MESHES_DIR is not set");
-    let scenario_dir = std::env::var("SCENARIO_DIR").expect("SCENARIO_DIR is not set");
+    // the former parameter name still works
+    let marker_period_ms = period_parameter(&node, "marker_publish_rate", config.marker_period_ms);
+    let marker_period_ms = period_parameter(&node, "marker_period_ms", marker_period_ms);
+    if marker_period_ms != config.marker_period_ms {
+        log::info!(target: log_targets::NODE,
+            "Using a marker period of {}ms per the marker_period_ms parameter.", marker_period_ms
+        );
+        config.set_marker_period_ms(marker_period_ms);
+    }
+    let tf_period_ms = period_parameter(&node, "tf_period_ms", config.tf_period_ms);
+    if tf_period_ms != config.tf_period_ms {
+        log::info!(target: log_targets::NODE,
+            "Using a TF period of {}ms per the tf_period_ms parameter.", tf_period_ms
+        );
+        config.tf_period_ms = tf_period_ms;
+    }
+    if let Ok(world_frame) = node.get_parameter::<String>("world_frame") {
+        if world_frame != config.world_frame && !world_frame.is_empty() {
+            log::info!(target: log_targets::NODE,
+                "Using '{}' as the world frame per the world_frame parameter.", world_frame
+            );
+            config.world_frame = world_frame;
+        }
+    }
+    let topic_parameters = [
+        ("mesh_markers_topic", &mut config.mesh_markers_topic),
+        ("zone_markers_topic", &mut config.zone_markers_topic),
+        ("tf_topic", &mut config.tf_topic),
+        ("tf_static_topic", &mut config.tf_static_topic),
+    ];
+    for (name, topic) in topic_parameters {
+        if let Ok(parameter) = node.get_parameter::<String>(name) {
+            *topic = parameter;
+        }
+    }
+    config.check_topics().map_err(VizError::Config)?;
+    if let Ok(prefix) = node.get_parameter::<String>("marker_ns_prefix") {
+        config.marker_ns_prefix = prefix;
+    }
+    if let Ok(use_sim_time) = node.get_parameter::<bool>("use_sim_time") {
+        config.use_sim_time = use_sim_time;
+    }
+    // every clock of the node follows the same /clock subscription
+    let sim_time = if config.use_sim_time {
+        log::info!(target: log_targets::NODE,
+            "Stamping with the simulated time on /clock per use_sim_time."
+        );
+        let (sender, receiver) = tokio::sync::watch::channel(Default::default());
+        let clock_subscriber =
+            node.subscribe::<r2r::rosgraph_msgs::msg::Clock>("/clock", QosProfile::default())?;
+        tokio::task::spawn(follow_sim_time(clock_subscriber, sender));
+        Some(receiver)
+    } else {
+        None
+    };
+    log::info!(target: log_targets::NODE,
+        "Publishing markers every {}ms ({:.1}Hz), broadcasting TF every {}ms ({:.1}Hz).",
+        config.marker_period_ms,
+        period_hz(config.marker_period_ms),
+        config.tf_period_ms,
+        period_hz(config.tf_period_ms)
+    );
+    if config.length_units != LengthUnits::Meters {
+        log::info!(target: log_targets::NODE,
+            "Scenario translations are in {}, scaling them by {} to meters.",
+            config.length_units,
+            config.length_units.factor()
+        );
+    }
 
     let connection_manager = ConnectionManager::new().await;
     let mut con = connection_manager.get_connection().await;
-    let _ = TransformsManager::load_transforms_from_path(&mut con, &scenario_dir.to_string()).await?;
+    let loaded = load_scenario(&mut con, &config).await?;
+    if !loaded.is_empty() && !loaded.values().any(|f| f.parent_frame_id == config.world_frame) {
+        log::warn!(target: log_targets::NODE,
+            "No frame of the scenario hangs from the world frame '{}', its roots are {}. Set WORLD_FRAME if the scenario uses another root.",
+            config.world_frame,
+            find_roots(&loaded).join(", ")
+        );
+    }
+
+    // `--validate-only [report_file]` prints (or writes) the report and exits,
+    // with a non-zero code when the scene has problems.
+    let args: Vec<String> = std::env::args().collect();
+    let validate_only = args.iter().position(|arg| arg == "--validate-only");
+    if config.startup_report || validate_only.is_some() {
+        let frames = TransformsManager::get_all_transforms(&mut con).await?;
+        let report = validate_scene(&frames, &config);
+        if let Some(position) = validate_only {
+            match args.get(position + 1) {
+                Some(path) => std::fs::write(path, format!("{}\n", report))?,
+                None => println!("{}", report),
+            }
+            std::process::exit(if report.has_problems() { 1 } else { 0 });
+        }
+        if report.has_problems() {
+            log::warn!(target: log_targets::NODE, "{}", report);
+        } else {
+            log::info!(target: log_targets::NODE, "{}", report);
+        }
+    }
+
+    let secondary = match &config.secondary_scenario_dir {
+        Some(dir) => {
+            let mut frames = load_scene_dir(dir)?;
+            to_meters(&mut frames, config.length_units);
+            to_enu(&mut frames, config.frame_convention);
+            Some(SecondaryScene::new(
+                frames,
+                &config.secondary_prefix,
+                config.secondary_offset,
+                config.secondary_tint,
+            ))
+        }
+        None => None,
+    };
+
+    // surface the meshes RViz will have to load, so they can be staged up front
+    if config.mesh_warmup {
+        let frames = TransformsManager::get_all_transforms(&mut con).await?;
+        let meshes = mesh_working_set(&frames, &config);
+        let missing = meshes.values().filter(|exists| !**exists).count();
+        let lines: Vec<String> = meshes
+            .iter()
+            .map(|(path, exists)| {
+                format!("  {}{}", path.display(), if *exists { "" } else { " (missing)" })
+            })
+            .collect();
+        log::info!(target: log_targets::NODE,
+            "Mesh working set of {} files, {} missing:\n{}",
+            meshes.len(),
+            missing,
+            lines.join("\n")
+        );
+    }
 
     let marker_publisher_timer =
-        node.create_wall_timer(std::time::Duration::from_millis(MARKER_PUBLISH_RATE))?;
+        node.create_wall_timer(std::time::Duration::from_millis(config.marker_period_ms))?;
+    let tf_publisher_timer =
+        node.create_wall_timer(std::time::Duration::from_millis(config.tf_period_ms))?;
 
-    let zone_marker_publisher =
-        node.create_publisher::<MarkerArray>("zone_markers", QosProfile::default())?;
+    // a single topic for both on targets where publishers are costly, or for
+    // a single RViz display
+    let zone_marker_publisher = if config.combined_topic().is_some() {
+        None
+    } else {
+        let topic = &config.zone_markers_topic;
+        Some(node.create_publisher::<MarkerArray>(topic, QosProfile::default())?)
+    };
 
+    let mesh_topic = match config.combined_topic() {
+        Some(topic) => topic.to_string(),
+        None => config.mesh_markers_topic.clone(),
+    };
     let mesh_marker_publisher =
-        node.create_publisher::<MarkerArray>("mesh_markers", QosProfile::default())?;
+        node.create_publisher::<MarkerArray>(&mesh_topic, QosProfile::default())?;
+
+    let collision_marker_publisher =
+        node.create_publisher::<MarkerArray>("collision_markers", QosProfile::default())?;
+    // with COMBINED_MARKER_TOPIC the labels and axes go out on it as well
+    let (text_marker_publisher, axis_marker_publisher) = if config.combined_marker_topic.is_some() {
+        (None, None)
+    } else {
+        (
+            Some(node.create_publisher::<MarkerArray>("text_markers", QosProfile::default())?),
+            Some(node.create_publisher::<MarkerArray>("axis_markers", QosProfile::default())?),
+        )
+    };
+    let link_marker_publisher =
+        node.create_publisher::<MarkerArray>("link_markers", QosProfile::default())?;
 
     let static_frame_broadcaster = node.create_publisher::<TFMessage>(
-        "tf_static",
+        &config.tf_static_topic,
         QosProfile::transient_local(QosProfile::default()),
     )?;
 
-    let active_frame_broadcaster = node
-        .create_publisher::<TFMessage>("tf", QosProfile::transient_local(QosProfile::default()))?;
+    let active_frame_broadcaster =
+        node.create_publisher::<TFMessage>(&config.tf_topic, tf_qos(config.web_compat))?;
+    log::info!(target: log_targets::NODE,
+        "Publishing markers on {}{}, transforms on {} and {}.",
+        mesh_topic,
+        zone_marker_publisher
+            .as_ref()
+            .map(|_| format!(" and {}", config.zone_markers_topic))
+            .unwrap_or_default(),
+        config.tf_topic,
+        config.tf_static_topic
+    );
 
-    let con_arc = Arc::new(connection_manager);
-    tokio::task::spawn(async move {
-        let result = visualization_server(
-            mesh_marker_publisher,
-            zone_marker_publisher,
-            active_frame_broadcaster,
-            static_frame_broadcaster,
-            con_arc,
-            marker_publisher_timer,
-            meshes_dir,
-        )
-        .await;
-        match result {
-            Ok(()) => {
-                log::info!(target: &&format!("r2r_redis_visualization"), "Visualization Server suceeded.")
-            }
-            Err(e) => {
-                log::error!(target: &&format!("r2r_redis_visualization"), "Visualization Server failed with: {}.", e)
-            }
-        };
-    });
+    let web_marker_publisher = if config.web_compat {
+        Some(node.create_publisher::<MarkerArray>("web_markers", QosProfile::default())?)
+    } else {
+        None
+    };
 
-    // keep the node alive
-    let handle = std::thread::spawn(move || loop {
-        node.spin_once(std::time::Duration::from_millis(1000));
-    });
+    let zone_grid_publisher = if config.zone_grid {
+        Some(node.create_publisher::<OccupancyGrid>("zone_grid", QosProfile::default())?)
+    } else {
+        None
+    };
 
-    log::warn!(target: &&format!("r2r_redis_visualization"), "Node started.");
+    let delta_marker_publisher = if config.delta_frames.len() == 2 {
+        Some(node.create_publisher::<MarkerArray>("delta_markers", QosProfile::default())?)
+    } else {
+        None
+    };
 
-    handle.join().unwrap();
+    let reference_grid_publisher = if config.reference_grid_frame.is_some() {
+        Some(node.create_publisher::<MarkerArray>("reference_grid", QosProfile::default())?)
+    } else {
+        None
+    };
 
-    Ok(())
-}
+    let orientation_trail_publisher = if !config.orientation_trail_frames.is_empty() {
+        Some(node.create_publisher::<MarkerArray>("orientation_trail", QosProfile::default())?)
+    } else {
+        None
+    };
+
+    let frame_events_publisher = if config.frame_events {
+        Some(node.create_publisher::<r2r::std_msgs::msg::String>(
+            "frame_events",
+            QosProfile::default(),
+        )?)
+    } else {
+        None
+    };
+
+    // latched, so nodes starting later still learn the scene is ready
+    let scene_ready_publisher = node.create_publisher::<r2r::std_msgs::msg::Bool>(
+        "scene_ready",
+        QosProfile::transient_local(QosProfile::default()),
+    )?;
+    scene_ready_publisher.publish(&r2r::std_msgs::msg::Bool { data: false })?;
+    // latched, late subscribers see the last state right away
+    let health_publisher = node.create_publisher::<r2r::std_msgs::msg::Bool>(
+        "redis_visualization/ok",
+        QosProfile::transient_local(QosProfile::default()),
+    )?;
 
-pub async fn visualization_server(
-    mesh_publisher: r2r::Publisher<MarkerArray>,
-    zone_publisher: r2r::Publisher<MarkerArray>,
-    active_frame_broadcaster: r2r::Publisher<TFMessage>,
-    static_frame_broadcaster: r2r::Publisher<TFMessage>,
-    connection_manager: Arc<ConnectionManager>,
-    mut timer: r2r::Timer,
-    meshes_dir: String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    
-    loop {
-        timer.tick().await?;
-        let mut con = connection_manager.get_connection().await;
-        if let Err(_) = connection_manager
-            .check_redis_health("redis_visualization")
-            .await
-        {
-            continue;
+    // the mapping doesn't change, so the legend is published once and latched,
+    // the publisher is kept for late subscribers
+    let _confidence_legend_publisher = match &config.confidence_key {
+        Some(_) => {
+            let publisher = node.create_publisher::<MarkerArray>(
+                "confidence_legend",
+                QosProfile::transient_local(QosProfile::default()),
+            )?;
+            let mut markers = confidence_legend(
+                &config.confidence_legend_frame,
+                config.confidence_range,
+                config.confidence_alpha,
+            );
+            prefix_namespaces(&mut markers, &config.marker_ns_prefix);
+            publisher.publish(&MarkerArray { markers })?;
+            Some(publisher)
         }
-        let mut mesh_markers: Vec<Marker> = vec![];
-        let mut zone_markers: Vec<Marker> = vec![];
-        let mut active_transforms = vec![];
-        let mut static_transforms = vec![];
-        let frames_local = TransformsManager::get_all_transforms(&mut con).await?;
-        let mut id: i32 = 0;
-        for (_, frame) in frames_local {
-            let mut clock = r2r::Clock::create(r2r::ClockType::RosTime).unwrap();
-            let now = clock.get_now().unwrap();
-            let time_stamp = r2r::Clock::to_builtin_time(&now);
-
-            if frame.active_transform {
-                active_transforms.push(TransformStamped {
-                    header: Header {
-                        stamp: time_stamp.clone(),
-                        frame_id: frame.parent_frame_id.clone(),
-                    },
-                    child_frame_id: frame.child_frame_id.clone(),
-                    transform: Transform {
-                        translation: Vector3 {
-                            x: *frame.transform.translation.x,
-                            y: *frame.transform.translation.y,
-                            z: *frame.transform.translation.z,
-                        },
-                        rotation: Quaternion {
-                            x: *frame.transform.rotation.x,
-                            y: *frame.transform.rotation.y,
-                            z: *frame.transform.rotation.z,
-                            w: *frame.transform.rotation.w,
-                        },
-                    },
-                });
-            } else {
-                static_transforms.push(TransformStamped {
-                    header: Header {
-                        stamp: time_stamp.clone(),
-                        frame_id: frame.parent_frame_id.clone(),
-                    },
-                    child_frame_id: frame.child_frame_id.clone(),
-                    transform: Transform {
-                        translation: Vector3 {
-                            x: *frame.transform.translation.x,
-                            y: *frame.transform.translation.y,
-                            z: *frame.transform.translation.z,
-                        },
-                        rotation: Quaternion {
-                            x: *frame.transform.rotation.x,
-                            y: *frame.transform.rotation.y,
-                            z: *frame.transform.rotation.z,
-                            w: *frame.transform.rotation.w,
-                        },
-                    },
-                });
-            }
+        None => None,
+    };
 
-            let metadata = decode_metadata(&frame.metadata);
-            if metadata.visualize_mesh {
-                match metadata.mesh_file {
-                    Some(path) => {
-                        id = id + 1;
-                        let indiv_marker = Marker {
-                            header: Header {
-                                stamp: Time { sec: 0, nanosec: 0 },
-                                frame_id: frame.child_frame_id.to_string(),
-                            },
-                            ns: "".to_string(),
-                            id,
-                            type_: metadata.mesh_type,
-                            action: 0,
-                            pose: Pose {
-                                position: Point {
-                                    x: 0.0,
-                                    y: 0.0,
-                                    z: 0.0,
-                                },
-                                orientation: Quaternion {
-                                    x: 0.0,
-                                    y: 0.0,
-                                    z: 0.0,
-                                    w: 1.0,
-                                },
-                            },
-                            lifetime: Duration { sec: 2, nanosec: 0 },
-                            scale: Vector3 {
-                                x: if metadata.mesh_scale != 0.0 {
-                                    metadata.mesh_scale as f64
-                                } else {
-                                    1.0
-                                },
-                                y: if metadata.mesh_scale != 0.0 {
-                                    metadata.mesh_scale as f64
-                                } else {
-                                    1.0
-                                },
-                                z: if metadata.mesh_scale != 0.0 {
-                                    metadata.mesh_scale as f64
-                                } else {
-                                    1.0
-                                },
-                            },
-                            color: ColorRGBA {
-                                r: metadata.mesh_r,
-                                g: metadata.mesh_g,
-                                b: metadata.mesh_b,
-                                a: metadata.mesh_a,
-                            },
-                            mesh_resource: match metadata.override_meshes_dir {
-                                Some(override_dir) => format!("file://{}/{}", override_dir, path.to_string()),
-                                None => format!("file://{}/{}", meshes_dir, path.to_string()),
-                            },
-                            mesh_use_embedded_materials: true,
-                            ..Marker::default()
-                        };
-                        mesh_markers.push(indiv_marker);
-                    }
-                    None => (),
-                }
-            }
-            if metadata.visualize_zone {
-                if !(metadata.zone == 0.0) {
-                    id = id + 1;
-                    let indiv_marker = Marker {
-                        header: Header {
-                            stamp: Time { sec: 0, nanosec: 0 },
-                            frame_id: frame.child_frame_id.to_string(),
-                        },
-                        ns: "".to_string(),
-                        id,
-                        type_: 2,
-                        action: 0,
-                        pose: Pose {
-                            position: Point {
-                                x: 0.0,
-                                y: 0.0,
-                                z: 0.0,
-                            },
-                            orientation: Quaternion {
-                                x: 0.0,
-                                y: 0.0,
-                                z: 0.0,
-                                w: 1.0,
-                            },
-                        },
-                        lifetime: Duration { sec: 2, nanosec: 0 },
-                        scale: Vector3 {
-                            x: metadata.zone,
-                            y: metadata.zone,
-                            z: metadata.zone,
-                        },
-                        color: ColorRGBA {
-                            r: 0.0,
-                            g: 255.0,
-                            b: 0.0,
-                            a: 0.15,
-                        },
-                        ..Marker::default()
-                    };
-                    zone_markers.push(indiv_marker)
-                }
+    let appearances = match &config.state_appearance_file {
+        Some(path) => StateAppearances::from_file(path)?,
+        None => StateAppearances::default(),
+    };
+    if let Some(topic) = &config.state_topic {
+        let state_subscriber =
+            node.subscribe::<r2r::std_msgs::msg::String>(topic, QosProfile::default())?;
+        tokio::task::spawn(follow_states(state_subscriber, appearances.state()));
+    }
+
+    let diagnostics_publisher = if config.diagnostics {
+        Some(DiagnosticsPublisher::new(
+            node.create_publisher("diagnostics", QosProfile::default())?,
+            FrameThresholds {
+                min_frames: config.diagnostics_min_frames,
+                max_frames: config.diagnostics_max_frames,
+            },
+            StampClock::new(sim_time.clone())?,
+        )?)
+    } else {
+        None
+    };
+
+    let (scene_sender, scene_receiver) = tokio::sync::watch::channel(String::new());
+    if config.export_snapshot && !config.dry_run {
+        let publisher = node.create_publisher::<r2r::std_msgs::msg::String>(
+            "tf_snapshot",
+            QosProfile::default(),
+        )?;
+        tokio::task::spawn(publish_tf_snapshots(
+            publisher,
+            scene_receiver.clone(),
+            config.export_snapshot_secs,
+        ));
+    }
+    // the frame list of the HTTP status endpoint
+    #[cfg(feature = "http-status")]
+    let http_scene = (config.http_status_port > 0).then(|| scene_receiver.clone());
+    match &config.mqtt_host {
+        #[cfg(feature = "mqtt")]
+        Some(host) => mqtt::spawn_mqtt_bridge(
+            host.clone(),
+            config.mqtt_port,
+            config.mqtt_topic.clone(),
+            scene_receiver,
+        ),
+        #[cfg(not(feature = "mqtt"))]
+        Some(_) => {
+            log::warn!(target: log_targets::NODE,
+                "MQTT_HOST is set but the node was built without the mqtt feature."
+            );
+            drop(scene_receiver);
+        }
+        None => drop(scene_receiver),
+    }
+
+    let zone_topic = config.zone_markers_topic.clone();
+    let mut cleanup_publishers = vec![
+        (mesh_topic.as_str(), mesh_marker_publisher.clone()),
+        ("collision_markers", collision_marker_publisher.clone()),
+        ("link_markers", link_marker_publisher.clone()),
+    ];
+    if let Some(publisher) = &zone_marker_publisher {
+        cleanup_publishers.push((zone_topic.as_str(), publisher.clone()));
+    }
+    if let Some(publisher) = &text_marker_publisher {
+        cleanup_publishers.push(("text_markers", publisher.clone()));
+    }
+    if let Some(publisher) = &axis_marker_publisher {
+        cleanup_publishers.push(("axis_markers", publisher.clone()));
+    }
+    if config.clear_on_start {
+        clear_previous_markers(&cleanup_publishers).await;
+    }
+
+    let con_arc = Arc::new(connection_manager);
+    let diagnostics_status = diagnostics_publisher.map(|publisher| {
+        let status = SharedTickStatus::default();
+        tokio::task::spawn(publisher.run(status.clone(), con_arc.clone()));
+        status
+    });
+    let diagnostics_status = match config.http_status_port {
+        0 => diagnostics_status,
+        #[cfg(feature = "http-status")]
+        port => {
+            let status = diagnostics_status.unwrap_or_default();
+            if let Some(scene) = http_scene {
+                tokio::task::spawn(http_status::serve_http_status(
+                    format!("{}:{}", config.http_status_address, port),
+                    status.clone(),
+                    scene,
+                    con_arc.clone(),
+                ));
             }
+            Some(status)
+        }
+        #[cfg(not(feature = "http-status"))]
+        _ => {
+            log::warn!(target: log_targets::NODE,
+                "HTTP_STATUS_PORT is set but the node was built without the http-status feature."
+            );
+            diagnostics_status
         }
+    };
+    if config.transform_lookup_topics {
+        let requests =
+            node.subscribe::<r2r::std_msgs::msg::String>("transform_requests", QosProfile::default())?;
+        let responses = node.create_publisher::<r2r::std_msgs::msg::String>(
+            "transform_responses",
+            QosProfile::default(),
+        )?;
+        tokio::task::spawn(serve_lookups(requests, responses, con_arc.clone(), config.clone()));
+    }
 
-        let active_msg = TFMessage {
-            transforms: active_transforms,
-        };
+    if config.mirror_tf {
+        let tf_subscriber = node.subscribe::<TFMessage>(&config.tf_topic, QosProfile::default())?;
+        tokio::task::spawn(mirror_tf(tf_subscriber, con_arc.clone(), config.clone()));
+    }
 
-        let static_msg = TFMessage {
-            transforms: static_transforms,
-        };
+    let control = PublishControl::default();
+    let control_subscriber =
+        node.subscribe::<r2r::std_msgs::msg::String>("control", QosProfile::default())?;
+    tokio::task::spawn(serve_control(control_subscriber, control.clone()));
 
-        let zone_array_msg = MarkerArray {
-            markers: zone_markers,
-        };
+    let highlights = Highlights::new(config.highlight_secs);
+    let highlight_requests = node
+        .create_service::<ManipulateScene::Service>("highlight_frame", QosProfile::default())?;
+    tokio::task::spawn(serve_highlights(highlight_requests, highlights.clone()));
 
-        let mesh_array_msg = MarkerArray {
-            markers: mesh_markers,
-        };
+    let add_requests =
+        node.create_service::<ManipulateScene::Service>("add_transform", QosProfile::default())?;
+    tokio::task::spawn(serve_add_transform(add_requests, con_arc.clone(), config.clone()));
+    let remove_requests =
+        node.create_service::<ManipulateScene::Service>("remove_transform", QosProfile::default())?;
+    tokio::task::spawn(serve_remove_transform(remove_requests, con_arc.clone(), config.clone()));
+    let save_requests =
+        node.create_service::<ManipulateScene::Service>("save_scenario", QosProfile::default())?;
+    tokio::task::spawn(serve_save_scenario(save_requests, con_arc.clone(), config.clone()));
+    // the watcher and the service share it so their reloads don't interleave
+    let reloader = ScenarioReloader::new(con_arc.clone(), config.clone(), loaded);
+    let reloads = reloader.reloads();
+    let reload_requests =
+        node.create_service::<Trigger::Service>("reload_scenario", QosProfile::default())?;
+    tokio::task::spawn(serve_reload_scenario(reload_requests, reloader.clone()));
+    if config.watch_scenario {
+        tokio::task::spawn(watch_scenario(reloader));
+    }
 
-        match active_frame_broadcaster.publish(&active_msg) {
-            Ok(()) => (),
-            Err(e) => {
-                log::error!(target: &&format!("r2r_redis_visualization"),
-                    "Active broadcaster failed to send a message with: '{}'",
-                    e
-                );
-            }
-        };
+    // marker topics are created on the fly by the spin thread, which owns the node
+    let node = Arc::new(Mutex::new(node));
+    let (marker_topics, marker_topic_factory) = if config.marker_group_topics {
+        let (topics, factory) = marker_topics();
+        (Some(topics), Some(factory))
+    } else {
+        (None, None)
+    };
 
-        match static_frame_broadcaster.publish(&static_msg) {
-            Ok(()) => (),
+    let scene_changes = config.redis_notify.then(|| {
+        let changes = SceneChanges::default();
+        let prefix = config.transform_key_prefix.clone();
+        tokio::task::spawn(watch_keyspace(changes.clone(), prefix));
+        changes
+    });
+
+    // the frames are read once per TF tick and shared with the marker task
+    let (snapshot_sender, snapshot_receiver) = tokio::sync::watch::channel(None);
+    let frame_publishers = FramePublishers {
+        active_frames: Box::new(active_frame_broadcaster),
+        static_frames: Box::new(static_frame_broadcaster),
+        frame_events: frame_events_publisher,
+        health: health_publisher,
+    };
+    let tf_server = TfServer::builder()
+        .publishers(frame_publishers)
+        .connection_manager(con_arc)
+        .timer(tf_publisher_timer)
+        .config(config.clone())
+        .clock(StampClock::new(sim_time)?)
+        .snapshots(snapshot_sender)
+        .secondary(secondary.clone())
+        .control(control.clone())
+        .scene_sender(scene_sender)
+        .changes(scene_changes)
+        .build()?;
+    let tf_task = tokio::task::spawn(tf_server.run());
+    let tf_task = tokio::task::spawn(async move {
+        match tf_task.await {
+            Ok(Ok(())) => {
+                log::info!(target: log_targets::NODE, "TF Server suceeded.")
+            }
+            Ok(Err(e)) => {
+                log::error!(target: log_targets::NODE, "TF Server failed with: {}.", e)
+            }
             Err(e) => {
-                log::error!(target: &&format!("r2r_redis_visualization"),
-                    "Static broadcaster failed to send a message with: '{}'",
-                    e
-                );
+                log::error!(target: log_targets::NODE, "TF Server stopped with: {}.", e)
             }
-        };
+        }
+    });
 
-        match zone_publisher.publish(&zone_array_msg) {
-            Ok(()) => (),
+    let exit_after_publish = config.one_shot && config.exit_after_publish;
+    let publishers = Publishers {
+        mesh: Box::new(mesh_marker_publisher),
+        zone: zone_marker_publisher.map(|publisher| Box::new(publisher) as MarkerSink),
+        collision: Box::new(collision_marker_publisher),
+        text: text_marker_publisher.map(|publisher| Box::new(publisher) as MarkerSink),
+        axes: axis_marker_publisher.map(|publisher| Box::new(publisher) as MarkerSink),
+        links: Box::new(link_marker_publisher),
+        web: web_marker_publisher.map(|publisher| Box::new(publisher) as MarkerSink),
+        diagnostics: diagnostics_status,
+        zone_grid: zone_grid_publisher,
+        delta: delta_marker_publisher.map(|publisher| Box::new(publisher) as MarkerSink),
+        reference_grid: reference_grid_publisher
+            .map(|publisher| Box::new(publisher) as MarkerSink),
+        orientation_trail: orientation_trail_publisher
+            .map(|publisher| Box::new(publisher) as MarkerSink),
+        marker_topics,
+        scene_ready: SceneReady::new(Box::new(scene_ready_publisher), reloads),
+    };
+    let visualization_server = VisualizationServer::builder()
+        .publishers(publishers)
+        .snapshots(snapshot_receiver)
+        .timer(marker_publisher_timer)
+        .config(config)
+        .overrides(FrameOverrides {
+            appearances,
+            secondary,
+            highlights,
+        })
+        .control(control)
+        .build()?;
+    let server = tokio::task::spawn(async move {
+        let result = visualization_server.run().await;
+        match result {
+            Ok(()) => {
+                log::info!(target: log_targets::NODE, "Visualization Server suceeded.")
+            }
             Err(e) => {
-                log::error!(target: &&format!("r2r_redis_visualization"),
-                    "Publisher failed to send zone marker message with: {}",
-                    e
-                );
+                log::error!(target: log_targets::NODE, "Visualization Server failed with: {}.", e)
             }
         };
+    });
 
-        match mesh_publisher.publish(&mesh_array_msg) {
-            Ok(()) => (),
-            Err(e) => {
-                log::error!(target: &&format!("r2r_redis_visualization"),
-                    "Publisher failed to send mesh marker message with: {}",
-                    e
-                );
+    // keep the node alive, a panic in spin_once ends the thread with its
+    // message, the node stays alive so the markers can still be cleared
+    let spin_node = node.clone();
+    let handle = std::thread::spawn(move || {
+        spin_until_panic(|| {
+            if let Ok(mut node) = spin_node.lock() {
+                if let Some(factory) = &marker_topic_factory {
+                    factory.create_requested(&mut node);
+                }
+                // short, so requested marker topics don't wait long
+                node.spin_once(std::time::Duration::from_millis(100));
             }
-        };
+        })
+    });
+
+    log::warn!(target: log_targets::NODE, "Node started.");
+
+    if exit_after_publish {
+        // the marker task returns after its last one-shot publish, which
+        // follows the last one of the TF task
+        let _ = server.await;
+        log::info!(target: log_targets::NODE,
+            "Published the scene, exiting per --exit-after-publish."
+        );
+        tf_task.abort();
+        std::process::exit(0);
     }
+
+    // joined off the runtime, so a current-thread runtime keeps running the server
+    let crash = tokio::task::spawn_blocking(move || handle.join()).await?;
+    let exit_code = shut_down_after_panic(crash, vec![tf_task, server], &cleanup_publishers).await;
+    drop(node);
+    std::process::exit(exit_code);
 }
+
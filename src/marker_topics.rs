@@ -0,0 +1,187 @@
+use r2r::visualization_msgs::msg::{Marker, MarkerArray};
+use r2r::QosProfile;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::mpsc;
+
+use crate::log_targets;
+use crate::sink::MarkerSink;
+
+/// A group's publisher as created by the spin thread, or why it couldn't be.
+type Created = (String, Result<MarkerSink, String>);
+
+/// Publishers of the per-group marker topics, `markers/<group>`, created the
+/// first time a group has markers.
+///
+/// The node belongs to the spin thread, so new topics are requested from it
+/// and picked up on a later publish instead of waiting for the node here.
+pub struct MarkerTopics {
+    requests: mpsc::Sender<String>,
+    created: mpsc::Receiver<Created>,
+    /// Groups whose publisher has been requested but not received yet.
+    pending: HashSet<String>,
+    publishers: HashMap<String, MarkerSink>,
+}
+
+/// The spin thread's end of `MarkerTopics`, creating the requested
+/// publishers between spins.
+pub struct MarkerTopicFactory {
+    requests: mpsc::Receiver<String>,
+    created: mpsc::Sender<Created>,
+}
+
+/// A `MarkerTopics` and the factory the spin thread serves it with.
+pub fn marker_topics() -> (MarkerTopics, MarkerTopicFactory) {
+    let (request_sender, request_receiver) = mpsc::channel();
+    let (created_sender, created_receiver) = mpsc::channel();
+    let topics = MarkerTopics {
+        requests: request_sender,
+        created: created_receiver,
+        pending: HashSet::new(),
+        publishers: HashMap::new(),
+    };
+    let factory = MarkerTopicFactory {
+        requests: request_receiver,
+        created: created_sender,
+    };
+    (topics, factory)
+}
+
+impl MarkerTopicFactory {
+    /// Create the publishers requested since the last call.
+    pub fn create_requested(&self, node: &mut r2r::Node) {
+        while let Ok(group) = self.requests.try_recv() {
+            let topic = MarkerTopics::topic(&group);
+            let created = node
+                .create_publisher::<MarkerArray>(&topic, QosProfile::default())
+                .map(|publisher| Box::new(publisher) as MarkerSink)
+                .map_err(|e| e.to_string());
+            // the marker task is gone when this fails, and so is the need for the topic
+            let _ = self.created.send((group, created));
+        }
+    }
+}
+
+impl MarkerTopics {
+    pub fn topic(group: &str) -> String {
+        format!("markers/{}", group)
+    }
+
+    /// Publish the markers of `group` on its topic, at most `max_per_msg` per
+    /// message. A new group's topic is requested from the spin thread, its
+    /// markers go out from the first publish after it has been created.
+    /// Failures are recorded in `errors`.
+    pub async fn publish(
+        &mut self,
+        group: &str,
+        markers: Vec<Marker>,
+        max_per_msg: usize,
+        errors: &mut Vec<String>,
+    ) {
+        self.receive_created(errors);
+        let topic = MarkerTopics::topic(group);
+        let Some(publisher) = self.publishers.get(group) else {
+            let requested = self.pending.insert(group.to_string());
+            if requested && self.requests.send(group.to_string()).is_err() {
+                self.pending.remove(group);
+                errors.push(format!("{}: the node is unavailable", topic));
+            }
+            return;
+        };
+        let msg = MarkerArray { markers };
+        crate::server::publish_markers(publisher, &msg, &topic, max_per_msg, errors).await;
+    }
+
+    fn receive_created(&mut self, errors: &mut Vec<String>) {
+        while let Ok((group, created)) = self.created.try_recv() {
+            self.pending.remove(&group);
+            let topic = MarkerTopics::topic(&group);
+            match created {
+                Ok(publisher) => {
+                    log::info!(target: log_targets::MARKERS,
+                        "Publishing the '{}' markers on {}.", group, topic
+                    );
+                    self.publishers.insert(group, publisher);
+                }
+                Err(e) => {
+                    log::error!(target: log_targets::MARKERS,
+                        "Failed to create the {} publisher with: '{}'", topic, e
+                    );
+                    errors.push(format!("{}: {}", topic, e));
+                }
+            }
+        }
+    }
+}
+
+/// Markers by group, their namespace without `ns_prefix` or `fallback` when
+/// that is empty. Sorted by group so topics are published in a stable order.
+pub fn group_markers(
+    markers: &[Marker],
+    fallback: &str,
+    ns_prefix: &str,
+    groups: &mut BTreeMap<String, Vec<Marker>>,
+) {
+    for marker in markers {
+        let ns = match marker.ns.strip_prefix(ns_prefix) {
+            Some(ns) if !ns_prefix.is_empty() => ns.strip_prefix('/').unwrap_or(ns),
+            _ => &marker.ns,
+        };
+        let group = if ns.is_empty() { fallback } else { ns };
+        groups
+            .entry(group.to_string())
+            .or_default()
+            .push(marker.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    fn marker(ns: &str, id: i32) -> Marker {
+        Marker {
+            ns: ns.to_string(),
+            id,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_new_group_gets_its_topic_and_then_its_markers() {
+        let (mut topics, factory) = marker_topics();
+        let mut errors = vec![];
+        topics
+            .publish("trail", vec![marker("trail", 1)], 0, &mut errors)
+            .await;
+        assert_eq!(factory.requests.try_recv().as_deref(), Ok("trail"));
+        // requested once, however many publishes come before it exists
+        topics
+            .publish("trail", vec![marker("trail", 1)], 0, &mut errors)
+            .await;
+        assert!(factory.requests.try_recv().is_err());
+
+        let sink = RecordingSink::default();
+        factory
+            .created
+            .send(("trail".to_string(), Ok(Box::new(sink.clone()))))
+            .unwrap();
+        topics
+            .publish("trail", vec![marker("trail", 2)], 0, &mut errors)
+            .await;
+        assert!(errors.is_empty());
+        let sent = sink.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].markers[0].id, 2);
+        assert_eq!(MarkerTopics::topic("trail"), "markers/trail");
+    }
+
+    #[test]
+    fn groups_are_the_namespaces_without_the_prefix() {
+        let mut groups = BTreeMap::new();
+        let markers = [marker("viz/mesh", 1), marker("viz/zone", 2), marker("", 3)];
+        group_markers(&markers, "links", "viz", &mut groups);
+        let names: Vec<&str> = groups.keys().map(|group| group.as_str()).collect();
+        assert_eq!(names, ["links", "mesh", "zone"]);
+    }
+}
@@ -0,0 +1,1800 @@
+use r2r::builtin_interfaces::msg::{Duration, Time};
+use r2r::geometry_msgs::msg::{Point, Pose, Quaternion, Vector3};
+use r2r::std_msgs::msg::{ColorRGBA, Header};
+use r2r::visualization_msgs::msg::{Marker, MarkerArray};
+use std::collections::{HashMap, HashSet};
+
+use micro_sp::*;
+
+use crate::config::Config;
+use crate::geometry::{quaternion_from_rpy, Isometry};
+use crate::log_targets;
+use crate::metadata::{parse_hex_color, MeshDescriptor, Metadata};
+
+pub const ARROW: i32 = 0;
+pub const CUBE: i32 = 1;
+pub const SPHERE: i32 = 2;
+pub const CYLINDER: i32 = 3;
+pub const LINE_LIST: i32 = 5;
+pub const TEXT_VIEW_FACING: i32 = 9;
+pub const MESH_RESOURCE: i32 = 10;
+pub const TRIANGLE_LIST: i32 = 11;
+
+pub const ADD: i32 = 0;
+pub const DELETE: i32 = 2;
+pub const DELETEALL: i32 = 3;
+
+/// How the `scale` of a marker was resolved.
+///
+/// For primitives (CUBE/SPHERE/CYLINDER) RViz reads `scale` as the physical
+/// size in meters, while for MESH_RESOURCE it multiplies the mesh geometry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MarkerScale {
+    /// Multiplier on the mesh geometry, from `mesh_scale` (0.0 means 1.0).
+    MeshMultiplier(f64),
+    /// Primitive size taken from the `primitive_size` metadata.
+    PrimitiveSize(f64),
+    /// Primitive size that fell back to `mesh_scale` or the configured default.
+    /// A value of 1.0 here is usually a mesh-style scale rendering a 1m primitive.
+    PrimitiveFallback(f64),
+}
+
+impl MarkerScale {
+    pub fn value(&self) -> f64 {
+        match self {
+            MarkerScale::MeshMultiplier(v)
+            | MarkerScale::PrimitiveSize(v)
+            | MarkerScale::PrimitiveFallback(v) => *v,
+        }
+    }
+
+    /// The same kind of scale multiplied by `factor`.
+    pub fn scaled(self, factor: f64) -> MarkerScale {
+        match self {
+            MarkerScale::MeshMultiplier(v) => MarkerScale::MeshMultiplier(v * factor),
+            MarkerScale::PrimitiveSize(v) => MarkerScale::PrimitiveSize(v * factor),
+            MarkerScale::PrimitiveFallback(v) => MarkerScale::PrimitiveFallback(v * factor),
+        }
+    }
+
+    pub fn is_suspicious(&self) -> bool {
+        matches!(self, MarkerScale::PrimitiveFallback(v) if *v == 1.0)
+    }
+}
+
+/// `type_` when it is one of the visualization_msgs Marker types, ARROW up to
+/// TRIANGLE_LIST, MESH_RESOURCE otherwise, as RViz draws nothing, or fails,
+/// for other values.
+pub fn validate_marker_type(type_: i32) -> i32 {
+    if (ARROW..=TRIANGLE_LIST).contains(&type_) {
+        type_
+    } else {
+        MESH_RESOURCE
+    }
+}
+
+pub fn is_primitive(type_: i32) -> bool {
+    matches!(type_, CUBE | SPHERE | CYLINDER)
+}
+
+pub fn resolve_scale(metadata: &Metadata, config: &Config) -> MarkerScale {
+    if is_primitive(metadata.mesh_type) {
+        match metadata.primitive_size {
+            Some(size) if size > 0.0 => MarkerScale::PrimitiveSize(size),
+            _ if config.primitive_size_from_mesh_scale && metadata.mesh_scale != 0.0 => {
+                MarkerScale::PrimitiveFallback(metadata.mesh_scale as f64)
+            }
+            _ => MarkerScale::PrimitiveFallback(config.default_primitive_size),
+        }
+    } else if axis_scale(metadata).is_some() {
+        // the axes carry the scale, see `axis_scale`
+        MarkerScale::MeshMultiplier(1.0)
+    } else if metadata.mesh_scale != 0.0 {
+        MarkerScale::MeshMultiplier(metadata.mesh_scale as f64)
+    } else {
+        MarkerScale::MeshMultiplier(1.0)
+    }
+}
+
+/// Per-axis mesh scale from `mesh_scale_x/y/z`, None when none is set and the
+/// uniform `mesh_scale` applies. Axes that are unset or 0 are 1.
+pub fn axis_scale(metadata: &Metadata) -> Option<[f64; 3]> {
+    let axes = [
+        metadata.mesh_scale_x,
+        metadata.mesh_scale_y,
+        metadata.mesh_scale_z,
+    ];
+    if axes.iter().all(Option::is_none) {
+        return None;
+    }
+    Some(axes.map(|axis| match axis {
+        Some(value) if value != 0.0 => value,
+        _ => 1.0,
+    }))
+}
+
+/// The marker scale of a frame's mesh: the uniform scale, stretched per axis
+/// for meshes with `mesh_scale_x/y/z`. Primitive sizes are never stretched.
+fn scale_vector(scale: MarkerScale, metadata: &Metadata) -> Vector3 {
+    let value = scale.value();
+    let [x, y, z] = match (scale, axis_scale(metadata)) {
+        (MarkerScale::MeshMultiplier(_), Some(axes)) => axes.map(|axis| axis * value),
+        _ => [value; 3],
+    };
+    Vector3 { x, y, z }
+}
+
+/// Build the mesh marker of a frame. Primitives don't need a `mesh_file`,
+/// every other type is skipped without one.
+pub fn mesh_marker(
+    frame: &SPTransformStamped,
+    metadata: &Metadata,
+    scale: MarkerScale,
+    id: i32,
+    config: &Config,
+) -> Option<Marker> {
+    let mesh_resource = match &metadata.mesh_file {
+        Some(path) => mesh_uri(path, metadata, config),
+        None if is_primitive(metadata.mesh_type) => "".to_string(),
+        None => return None,
+    };
+    let scale = scale_vector(scale, metadata);
+    let embedded_materials = metadata.mesh_use_embedded_materials.unwrap_or(true);
+    let color = ColorRGBA {
+        r: metadata.mesh_r,
+        g: metadata.mesh_g,
+        b: metadata.mesh_b,
+        // RViz only leaves the embedded materials untinted at alpha 0
+        a: if metadata.mesh_use_embedded_materials == Some(true) {
+            0.0
+        } else {
+            metadata.mesh_a
+        },
+    };
+    let channels = [color.r, color.g, color.b, color.a];
+    if channels.iter().any(|c| !(0.0..=1.0).contains(c)) {
+        log::debug!(target: log_targets::MARKERS,
+            "Mesh color of '{}' has channels {:?} outside 0..1, clamping. Colors are not 0..255.",
+            frame.child_frame_id, channels
+        );
+    }
+    Some(Marker {
+        header: Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: frame.child_frame_id.to_string(),
+        },
+        ns: "mesh".to_string(),
+        id,
+        type_: metadata.mesh_type,
+        action: ADD,
+        pose: identity_pose(),
+        lifetime: lifetime(metadata.lifetime_secs.unwrap_or(config.mesh_lifetime)),
+        frame_locked: frame_locked(metadata, config),
+        scale,
+        color: normalize_color(color),
+        mesh_resource,
+        mesh_use_embedded_materials: embedded_materials,
+        ..Marker::default()
+    })
+}
+
+/// The frame's metadata as each of its `meshes` sees it, their file, type,
+/// scale, color and offset replacing the frame's own. Empty without `meshes`.
+pub fn mesh_descriptors(metadata: &Metadata) -> Result<Vec<Metadata>, String> {
+    let Some(meshes) = &metadata.meshes else {
+        return Ok(vec![]);
+    };
+    let descriptors: Vec<MeshDescriptor> =
+        serde_json::from_str(meshes).map_err(|e| format!("invalid meshes: {}", e))?;
+    Ok(descriptors
+        .into_iter()
+        .map(|descriptor| {
+            let scale = descriptor.scale.unwrap_or(1.0);
+            let [r, g, b, a] = descriptor.color.unwrap_or([
+                metadata.mesh_r,
+                metadata.mesh_g,
+                metadata.mesh_b,
+                metadata.mesh_a,
+            ]);
+            Metadata {
+                mesh_file: descriptor.file,
+                mesh_type: descriptor.mesh_type.unwrap_or(MESH_RESOURCE),
+                mesh_scale: scale as f32,
+                primitive_size: Some(scale),
+                mesh_scale_x: None,
+                mesh_scale_y: None,
+                mesh_scale_z: None,
+                mesh_r: r,
+                mesh_g: g,
+                mesh_b: b,
+                mesh_a: a,
+                mesh_offset_xyz: descriptor.offset_xyz,
+                mesh_offset_rpy: descriptor.offset_rpy,
+                mesh_offset_quat: descriptor.offset_quat,
+                meshes: None,
+                ..metadata.clone()
+            }
+        })
+        .collect())
+}
+
+/// URI of a mesh file, relative to `override_meshes_dir` when set, otherwise MESHES_DIR.
+pub fn mesh_uri(path: &str, metadata: &Metadata, config: &Config) -> String {
+    let dir = metadata
+        .override_meshes_dir
+        .as_ref()
+        .unwrap_or(&config.meshes_dir);
+    resolve_mesh_uri(dir, path)
+}
+
+/// `package://` and `file://` URIs are used as they are, absolute paths become
+/// `file://` URIs and bare paths are resolved against `meshes_dir`.
+pub fn resolve_mesh_uri(meshes_dir: &str, path: &str) -> String {
+    if path.starts_with("package://") || path.starts_with("file://") {
+        path.to_string()
+    } else if path.starts_with('/') {
+        format!("file://{}", path)
+    } else {
+        format!("file://{}/{}", meshes_dir, path)
+    }
+}
+
+/// Translucent overlay of the frame's `collision_mesh_file`, if it has one.
+pub fn collision_marker(
+    frame: &SPTransformStamped,
+    metadata: &Metadata,
+    id: i32,
+    config: &Config,
+) -> Option<Marker> {
+    let path = metadata.collision_mesh_file.as_ref()?;
+    let uniform = if metadata.mesh_scale != 0.0 {
+        metadata.mesh_scale as f64
+    } else {
+        1.0
+    };
+    let [x, y, z] = axis_scale(metadata).unwrap_or([uniform; 3]);
+    Some(Marker {
+        header: Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: frame.child_frame_id.to_string(),
+        },
+        ns: "collision".to_string(),
+        id,
+        type_: MESH_RESOURCE,
+        action: ADD,
+        pose: identity_pose(),
+        lifetime: lifetime(config.mesh_lifetime),
+        frame_locked: frame_locked(metadata, config),
+        scale: Vector3 { x, y, z },
+        color: ColorRGBA {
+            r: 1.0,
+            g: 0.3,
+            b: 0.3,
+            a: 0.4,
+        },
+        mesh_resource: mesh_uri(path, metadata, config),
+        mesh_use_embedded_materials: false,
+        ..Marker::default()
+    })
+}
+
+/// Shape, per-axis size and orientation of a zone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZoneGeometry {
+    pub type_: i32,
+    pub scale: [f64; 3],
+    /// `[x, y, z, w]`, normalized
+    pub orientation: [f64; 4],
+}
+
+/// The zone geometry of a frame's metadata: a sphere of diameter `zone` unless
+/// `zone_shape`, `zone_x/y/z` and `zone_rpy`/`zone_quat` say otherwise. Errors
+/// describe metadata that doesn't make a valid zone.
+pub fn zone_geometry(metadata: &Metadata) -> Result<ZoneGeometry, String> {
+    let shape = metadata.zone_shape.as_deref().map(str::to_lowercase);
+    let type_ = match shape.as_deref() {
+        None | Some("sphere") => SPHERE,
+        Some("box") => CUBE,
+        Some("cylinder") => CYLINDER,
+        Some(other) => return Err(format!("unknown zone_shape '{}'", other)),
+    };
+    let scale = [
+        metadata.zone_x.unwrap_or(metadata.zone),
+        metadata.zone_y.unwrap_or(metadata.zone),
+        metadata.zone_z.unwrap_or(metadata.zone),
+    ];
+    if scale.iter().any(|v| !v.is_finite() || *v < 0.0) {
+        return Err(format!(
+            "zone dimensions {:?} must be finite and not negative",
+            scale
+        ));
+    }
+    let orientation =
+        rotation("zone", &metadata.zone_rpy, &metadata.zone_quat)?.unwrap_or([0.0, 0.0, 0.0, 1.0]);
+    Ok(ZoneGeometry {
+        type_,
+        scale,
+        orientation,
+    })
+}
+
+/// `len` comma separated finite numbers of the metadata entry `name`.
+fn numbers(name: &str, value: &str, len: usize) -> Result<Vec<f64>, String> {
+    let parsed: Vec<f64> = value
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("{} '{}' is not a list of numbers", name, value))?;
+    if parsed.len() != len || parsed.iter().any(|v| !v.is_finite()) {
+        return Err(format!("{} '{}' needs {} finite numbers", name, value, len));
+    }
+    Ok(parsed)
+}
+
+/// The normalized rotation of the `<prefix>_rpy` or `<prefix>_quat` metadata
+/// entries, None when neither is set.
+fn rotation(
+    prefix: &str,
+    rpy: &Option<String>,
+    quat: &Option<String>,
+) -> Result<Option<[f64; 4]>, String> {
+    match (rpy, quat) {
+        (Some(_), Some(_)) => Err(format!("set {0}_rpy or {0}_quat, not both", prefix)),
+        (Some(rpy), None) => {
+            let rpy = numbers(&format!("{}_rpy", prefix), rpy, 3)?;
+            Ok(Some(quaternion_from_rpy(rpy[0], rpy[1], rpy[2])))
+        }
+        (None, Some(quat)) => {
+            let q = numbers(&format!("{}_quat", prefix), quat, 4)?;
+            let norm = q.iter().map(|v| v * v).sum::<f64>().sqrt();
+            if norm < 1e-6 {
+                return Err(format!("{}_quat '{}' is not a rotation", prefix, quat));
+            }
+            Ok(Some([q[0] / norm, q[1] / norm, q[2] / norm, q[3] / norm]))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// Pose of the mesh in its frame from `mesh_offset_xyz` and `mesh_offset_rpy`
+/// or `mesh_offset_quat`, for CAD models whose origin isn't the frame's. None
+/// without any of them, the mesh then sits at the frame origin.
+pub fn mesh_offset(metadata: &Metadata) -> Result<Option<Pose>, String> {
+    let rotation = rotation(
+        "mesh_offset",
+        &metadata.mesh_offset_rpy,
+        &metadata.mesh_offset_quat,
+    )?;
+    let translation = match &metadata.mesh_offset_xyz {
+        Some(xyz) => Some(numbers("mesh_offset_xyz", xyz, 3)?),
+        None => None,
+    };
+    if rotation.is_none() && translation.is_none() {
+        return Ok(None);
+    }
+    let [x, y, z] = match translation {
+        Some(xyz) => [xyz[0], xyz[1], xyz[2]],
+        None => [0.0; 3],
+    };
+    let [qx, qy, qz, qw] = rotation.unwrap_or([0.0, 0.0, 0.0, 1.0]);
+    Ok(Some(Pose {
+        position: Point { x, y, z },
+        orientation: Quaternion {
+            x: qx,
+            y: qy,
+            z: qz,
+            w: qw,
+        },
+    }))
+}
+
+/// Build the zone marker of a frame, None when the zone has no size.
+pub fn zone_marker(
+    frame: &SPTransformStamped,
+    metadata: &Metadata,
+    geometry: &ZoneGeometry,
+    id: i32,
+    config: &Config,
+) -> Option<Marker> {
+    if geometry.scale.iter().all(|v| *v == 0.0) {
+        return None;
+    }
+    let [x, y, z, w] = geometry.orientation;
+    Some(Marker {
+        header: Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: frame.child_frame_id.to_string(),
+        },
+        ns: "zone".to_string(),
+        id,
+        type_: geometry.type_,
+        action: ADD,
+        pose: Pose {
+            orientation: Quaternion { x, y, z, w },
+            ..identity_pose()
+        },
+        lifetime: lifetime(metadata.lifetime_secs.unwrap_or(config.zone_lifetime)),
+        frame_locked: frame_locked(metadata, config),
+        scale: Vector3 {
+            x: geometry.scale[0],
+            y: geometry.scale[1],
+            z: geometry.scale[2],
+        },
+        color: normalize_color(zone_color(metadata)),
+        ..Marker::default()
+    })
+}
+
+/// The zone of a frame as a flat disc on the floor of the root frame, beneath
+/// `position` and with the same footprint as the zone sphere.
+pub fn zone_disc_marker(
+    root_frame_id: &str,
+    position: [f64; 3],
+    metadata: &Metadata,
+    id: i32,
+    config: &Config,
+) -> Option<Marker> {
+    let thickness = config.zone_2d_thickness;
+    if metadata.zone == 0.0 {
+        return None;
+    }
+    Some(Marker {
+        header: Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: root_frame_id.to_string(),
+        },
+        ns: "zone".to_string(),
+        id,
+        type_: CYLINDER,
+        action: ADD,
+        pose: Pose {
+            position: Point {
+                x: position[0],
+                y: position[1],
+                z: config.zone_floor_height + thickness / 2.0,
+            },
+            ..identity_pose()
+        },
+        lifetime: lifetime(metadata.lifetime_secs.unwrap_or(config.zone_lifetime)),
+        scale: Vector3 {
+            x: metadata.zone,
+            y: metadata.zone,
+            z: thickness,
+        },
+        color: normalize_color(zone_color(metadata)),
+        ..Marker::default()
+    })
+}
+
+/// Whether markers in a frame follow it live rather than staying where the
+/// frame was when they were published. Only matters for markers with a lifetime.
+pub fn frame_locked(metadata: &Metadata, config: &Config) -> bool {
+    metadata.frame_locked.unwrap_or(config.frame_locked)
+}
+
+fn zone_color(metadata: &Metadata) -> ColorRGBA {
+    ColorRGBA {
+        r: metadata.zone_r,
+        g: metadata.zone_g,
+        b: metadata.zone_b,
+        a: metadata.zone_a,
+    }
+}
+
+/// Clamp each channel into the 0..1 range RViz expects.
+fn normalize_color(c: ColorRGBA) -> ColorRGBA {
+    ColorRGBA {
+        r: c.r.clamp(0.0, 1.0),
+        g: c.g.clamp(0.0, 1.0),
+        b: c.b.clamp(0.0, 1.0),
+        a: c.a.clamp(0.0, 1.0),
+    }
+}
+
+/// Marker lifetime from seconds, where 0 means the marker never expires, as
+/// does anything that isn't a positive number. Fractions go into `nanosec`,
+/// and a positive lifetime below a nanosecond is kept at 1ns rather than
+/// becoming a zero, which RViz would keep forever.
+pub fn lifetime(secs: f64) -> Duration {
+    if !(secs > 0.0) {
+        return Duration { sec: 0, nanosec: 0 };
+    }
+    let nanos = (secs * 1e9).round().clamp(1.0, i32::MAX as f64 * 1e9) as u64;
+    Duration {
+        sec: (nanos / 1_000_000_000) as i32,
+        nanosec: (nanos % 1_000_000_000) as u32,
+    }
+}
+
+pub fn identity_pose() -> Pose {
+    Pose {
+        position: Point {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        orientation: Quaternion {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            w: 1.0,
+        },
+    }
+}
+
+/// Radius of a frame's shadow disc: its `shadow_radius`, half the size of a
+/// primitive, or `default` for meshes, whose extent isn't known.
+pub fn shadow_radius(metadata: &Metadata, scale: MarkerScale, default: f64) -> f64 {
+    match (metadata.shadow_radius, scale) {
+        (Some(radius), _) => radius,
+        (None, MarkerScale::PrimitiveSize(size)) | (None, MarkerScale::PrimitiveFallback(size)) => {
+            size / 2.0
+        }
+        (None, MarkerScale::MeshMultiplier(_)) => default,
+    }
+}
+
+/// A flat translucent disc on the ground plane of the root frame, beneath `position`.
+pub fn shadow_marker(
+    root_frame_id: &str,
+    position: [f64; 3],
+    radius: f64,
+    ground_height: f64,
+    id: i32,
+    lifetime: Duration,
+) -> Marker {
+    const THICKNESS: f64 = 0.002;
+    Marker {
+        header: Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: root_frame_id.to_string(),
+        },
+        ns: "shadow".to_string(),
+        id,
+        type_: CYLINDER,
+        action: ADD,
+        pose: Pose {
+            position: Point {
+                x: position[0],
+                y: position[1],
+                z: ground_height + THICKNESS / 2.0,
+            },
+            ..identity_pose()
+        },
+        lifetime,
+        scale: Vector3 {
+            x: 2.0 * radius,
+            y: 2.0 * radius,
+            z: THICKNESS,
+        },
+        color: ColorRGBA {
+            r: 0.0,
+            g: 0.0,
+            b: 0.0,
+            a: 0.3,
+        },
+        ..Marker::default()
+    }
+}
+
+/// Stable id of the markers of a frame, the FNV-1a hash of MARKER_NS_PREFIX
+/// and its name folded into a non-negative i32, so a frame keeps its ids
+/// whatever other frames come and go, and gets other ids in another instance.
+/// The markers of a frame share the id and differ by namespace.
+pub fn marker_id_for(ns_prefix: &str, name: &str) -> i32 {
+    let bytes = ns_prefix.bytes().chain(name.bytes());
+    let hash = bytes.fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    ((hash ^ (hash >> 32)) & 0x7fff_ffff) as i32
+}
+
+/// Marker ids of the frames of a tick and how many frames were moved off their
+/// hashed id. With `probe` the frames are taken in name order and one whose
+/// hashed id is already taken gets the next free id, so colliding frames get
+/// distinct ids that only change when one of them comes or goes. Without it
+/// every frame keeps its hashed id, collisions included.
+pub fn assign_marker_ids<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+    ns_prefix: &str,
+    probe: bool,
+) -> (HashMap<&'a str, i32>, usize) {
+    let mut names: Vec<&str> = names.into_iter().collect();
+    names.sort_unstable();
+    let mut ids = HashMap::with_capacity(names.len());
+    let mut taken = HashSet::with_capacity(names.len());
+    let mut moved = 0;
+    for name in names {
+        let mut id = marker_id_for(ns_prefix, name);
+        if probe && !taken.insert(id) {
+            moved += 1;
+            while !taken.insert(id) {
+                id = id.checked_add(1).unwrap_or(0);
+            }
+        }
+        ids.insert(name, id);
+    }
+    (ids, moved)
+}
+
+/// Clears every marker previously published on the topic, in all namespaces.
+pub fn delete_all_marker() -> Marker {
+    Marker {
+        action: DELETEALL,
+        ..Marker::default()
+    }
+}
+
+/// Append a DELETE for every `(ns, id)` added on the previous publish of a
+/// topic that isn't added anymore, e.g. of a frame removed from Redis, then
+/// remember what is added now.
+pub fn delete_vanished(markers: &mut Vec<Marker>, previous: &mut HashSet<(String, i32)>) {
+    let current: HashSet<(String, i32)> = markers
+        .iter()
+        .filter(|m| m.action == ADD)
+        .map(|m| (m.ns.clone(), m.id))
+        .collect();
+    for (ns, id) in previous.difference(&current) {
+        markers.push(Marker {
+            ns: ns.clone(),
+            id: *id,
+            action: DELETE,
+            ..Marker::default()
+        });
+    }
+    *previous = current;
+}
+
+/// Send the markers of hidden frames as DELETE. They are still built, so
+/// they keep their ids and are added again once their frame is shown.
+pub fn delete_hidden<'a>(
+    markers: impl IntoIterator<Item = &'a mut Marker>,
+    hidden_ids: &HashSet<i32>,
+) {
+    for marker in markers {
+        if hidden_ids.contains(&marker.id) {
+            marker.action = DELETE;
+        }
+    }
+}
+
+/// `ns` under MARKER_NS_PREFIX, `<prefix>/<ns>`, or the prefix alone for an
+/// empty namespace. Unchanged without a prefix.
+pub fn prefixed_ns(ns_prefix: &str, ns: &str) -> String {
+    match (ns_prefix, ns) {
+        ("", ns) => ns.to_string(),
+        (prefix, "") => prefix.to_string(),
+        (prefix, ns) => format!("{}/{}", prefix, ns),
+    }
+}
+
+/// Move every marker into its namespace under MARKER_NS_PREFIX, see
+/// `prefixed_ns`, so instances sharing a viewer don't replace each other's
+/// markers.
+pub fn prefix_namespaces(markers: &mut [Marker], ns_prefix: &str) {
+    if ns_prefix.is_empty() {
+        return;
+    }
+    for marker in markers {
+        marker.ns = prefixed_ns(ns_prefix, &marker.ns);
+    }
+}
+
+/// Stamp every marker with `stamp`, the time of the tick's transforms, so
+/// RViz looks their frames up at the time the markers were made.
+pub fn stamp_markers(markers: &mut [Marker], stamp: &Time) {
+    for marker in markers {
+        marker.header.stamp = stamp.clone();
+    }
+}
+
+/// Raise the alpha of added markers below `floor` to it so faint markers stay
+/// selectable in RViz, leaving alphas at or above the floor as they are. Meshes
+/// with alpha 0 and embedded materials keep it, RViz shows the materials instead.
+pub fn apply_alpha_floor(markers: &mut [Marker], floor: f32) {
+    for marker in markers.iter_mut().filter(|m| m.action == ADD) {
+        if shows_embedded_materials(marker) {
+            continue;
+        }
+        marker.color.a = marker.color.a.max(floor.min(1.0));
+    }
+}
+
+/// Whether RViz draws the marker's embedded materials untinted: a mesh with
+/// embedded materials and alpha 0.
+pub fn shows_embedded_materials(marker: &Marker) -> bool {
+    marker.type_ == MESH_RESOURCE && marker.mesh_use_embedded_materials && marker.color.a == 0.0
+}
+
+/// Whether an added marker has nothing RViz could draw: alpha 0 without
+/// embedded materials to show instead.
+pub fn is_invisible(marker: &Marker) -> bool {
+    marker.action == ADD && marker.color.a == 0.0 && !shows_embedded_materials(marker)
+}
+
+/// Order a MarkerArray the way RViz digests it most reliably: DELETEALL, then
+/// DELETE, then ADD/MODIFY. Within those, markers go by ascending render
+/// priority and are then grouped by namespace. The sort is stable, so markers
+/// keep their relative order within a group.
+///
+/// The render priority only decides the order markers arrive in. RViz sorts
+/// translucent geometry by depth itself, so this is a hint that helps in some
+/// setups (e.g. overlapping zones at the same depth) rather than a guarantee.
+pub fn order_markers(markers: &mut [Marker], render_priority: impl Fn(&Marker) -> i32) {
+    markers.sort_by(|a, b| {
+        let rank = |m: &Marker| match m.action {
+            DELETEALL => 0,
+            DELETE => 1,
+            _ => 2,
+        };
+        rank(a)
+            .cmp(&rank(b))
+            .then_with(|| render_priority(a).cmp(&render_priority(b)))
+            .then_with(|| a.ns.cmp(&b.ns))
+    });
+}
+
+/// `markers` split into arrays of at most `max_per_msg` markers, in order.
+/// Deletions are moved to the front first, so no array deletes markers an
+/// earlier array of the same tick added.
+pub fn chunk_markers(mut markers: Vec<Marker>, max_per_msg: usize) -> Vec<MarkerArray> {
+    markers.sort_by_key(|m| match m.action {
+        DELETEALL => 0,
+        DELETE => 1,
+        _ => 2,
+    });
+    markers
+        .chunks(max_per_msg.max(1))
+        .map(|chunk| MarkerArray {
+            markers: chunk.to_vec(),
+        })
+        .collect()
+}
+
+/// A small sphere at the frame origin that shrinks and fades as the frame's
+/// Redis key approaches expiry, `fraction` being the remaining share of the TTL.
+pub fn ttl_marker(
+    child_frame_id: &str,
+    fraction: f64,
+    size: f64,
+    id: i32,
+    lifetime: Duration,
+    frame_locked: bool,
+) -> Marker {
+    let size = size * fraction;
+    Marker {
+        header: Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: child_frame_id.to_string(),
+        },
+        ns: "ttl".to_string(),
+        id,
+        type_: SPHERE,
+        action: ADD,
+        pose: identity_pose(),
+        lifetime,
+        frame_locked,
+        scale: Vector3 {
+            x: size,
+            y: size,
+            z: size,
+        },
+        color: ColorRGBA {
+            r: 1.0 - fraction as f32,
+            g: fraction as f32,
+            b: 0.0,
+            a: 0.2 + 0.8 * fraction as f32,
+        },
+        ..Marker::default()
+    }
+}
+
+/// Text height of frame labels in meters.
+const LABEL_HEIGHT: f64 = 0.05;
+
+/// Confidences shown in the confidence legend.
+const LEGEND_ROWS: usize = 3;
+
+/// The name of a frame as white text `label_offset` above its origin.
+pub fn label_marker(
+    frame: &SPTransformStamped,
+    metadata: &Metadata,
+    id: i32,
+    config: &Config,
+) -> Marker {
+    Marker {
+        header: Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: frame.child_frame_id.to_string(),
+        },
+        ns: "label".to_string(),
+        id,
+        type_: TEXT_VIEW_FACING,
+        action: ADD,
+        pose: Pose {
+            position: Point {
+                x: 0.0,
+                y: 0.0,
+                z: metadata.label_offset,
+            },
+            ..identity_pose()
+        },
+        lifetime: lifetime(config.mesh_lifetime),
+        frame_locked: frame_locked(metadata, config),
+        scale: Vector3 {
+            x: 0.0,
+            y: 0.0,
+            // text height
+            z: LABEL_HEIGHT,
+        },
+        color: ColorRGBA {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+            a: 1.0,
+        },
+        text: frame.child_frame_id.clone(),
+        ..Marker::default()
+    }
+}
+
+/// An arrow from the origin of `from_frame_id` to `offset`, the position of
+/// another frame in it, and a label with the distance at its middle.
+pub fn delta_markers(from_frame_id: &str, offset: [f64; 3], lifetime: Duration) -> Vec<Marker> {
+    let distance = (offset[0].powi(2) + offset[1].powi(2) + offset[2].powi(2)).sqrt();
+    let header = Header {
+        stamp: Time { sec: 0, nanosec: 0 },
+        frame_id: from_frame_id.to_string(),
+    };
+    let color = ColorRGBA {
+        r: 1.0,
+        g: 1.0,
+        b: 0.0,
+        a: 1.0,
+    };
+    let arrow = Marker {
+        header: header.clone(),
+        ns: "delta".to_string(),
+        id: 1,
+        type_: ARROW,
+        action: ADD,
+        pose: identity_pose(),
+        lifetime: lifetime.clone(),
+        // shaft diameter, head diameter, head length
+        scale: Vector3 {
+            x: 0.01,
+            y: 0.02,
+            z: 0.03,
+        },
+        color: color.clone(),
+        points: vec![
+            Point {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Point {
+                x: offset[0],
+                y: offset[1],
+                z: offset[2],
+            },
+        ],
+        ..Marker::default()
+    };
+    let label = Marker {
+        header,
+        ns: "delta".to_string(),
+        id: 2,
+        type_: TEXT_VIEW_FACING,
+        action: ADD,
+        pose: Pose {
+            position: Point {
+                x: offset[0] / 2.0,
+                y: offset[1] / 2.0,
+                z: offset[2] / 2.0,
+            },
+            ..identity_pose()
+        },
+        lifetime,
+        // text height
+        scale: Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.05,
+        },
+        color,
+        text: format!("{:.3} m", distance),
+        ..Marker::default()
+    };
+    vec![arrow, label]
+}
+
+/// Text rows explaining how confidence maps onto transparency, drawn in
+/// `frame_id` at `LEGEND_ROWS` confidences evenly spread over `range`, each in
+/// the transparency it stands for. Never expires, it is published once.
+pub fn confidence_legend(frame_id: &str, range: [f64; 2], alpha: [f64; 2]) -> Vec<Marker> {
+    let row = |id: i32, text: String, a: f32| Marker {
+        header: Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: frame_id.to_string(),
+        },
+        ns: "confidence_legend".to_string(),
+        id,
+        type_: TEXT_VIEW_FACING,
+        action: ADD,
+        pose: Pose {
+            position: Point {
+                x: 0.0,
+                y: 0.0,
+                z: -(id as f64) * LABEL_HEIGHT * 1.5,
+            },
+            ..identity_pose()
+        },
+        lifetime: lifetime(0.0),
+        // text height
+        scale: Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: LABEL_HEIGHT,
+        },
+        color: ColorRGBA {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+            a,
+        },
+        text,
+        ..Marker::default()
+    };
+    let mut markers = vec![row(0, "confidence: marker alpha x".to_string(), 1.0)];
+    for i in 0..LEGEND_ROWS {
+        let t = i as f64 / (LEGEND_ROWS - 1) as f64;
+        let confidence = range[0] + t * (range[1] - range[0]);
+        let factor = alpha[0] + t * (alpha[1] - alpha[0]);
+        let text = format!("{:.2}: x{:.2}", confidence, factor);
+        markers.push(row(i as i32 + 1, text, factor.clamp(0.0, 1.0) as f32));
+    }
+    markers
+}
+
+/// A square grid of `size` in the XY plane of `frame_id`, centered on its
+/// origin, with lines every `spacing`. Frame locked, so it follows the frame.
+pub fn reference_grid_marker(
+    frame_id: &str,
+    size: f64,
+    spacing: f64,
+    color: [f32; 4],
+    lifetime: Duration,
+) -> Marker {
+    let half = size / 2.0;
+    let lines = if spacing > 0.0 {
+        (size / spacing).round() as i64
+    } else {
+        0
+    };
+    let point = |x: f64, y: f64| Point { x, y, z: 0.0 };
+    let mut points = vec![];
+    for i in 0..=lines {
+        let offset = (-half + i as f64 * spacing).min(half);
+        points.extend([point(offset, -half), point(offset, half)]);
+        points.extend([point(-half, offset), point(half, offset)]);
+    }
+    let [r, g, b, a] = color;
+    Marker {
+        header: Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: frame_id.to_string(),
+        },
+        ns: "reference_grid".to_string(),
+        id: 1,
+        type_: LINE_LIST,
+        action: ADD,
+        pose: identity_pose(),
+        lifetime,
+        frame_locked: true,
+        // line width
+        scale: Vector3 {
+            x: 0.005,
+            y: 0.0,
+            z: 0.0,
+        },
+        color: ColorRGBA { r, g, b, a },
+        points,
+        ..Marker::default()
+    }
+}
+
+/// A thin line in the parent frame from its origin to the frame's origin,
+/// drawing the tree structure. Frame locked, so it follows the parent.
+pub fn parent_link_marker(frame: &SPTransformStamped, id: i32, lifetime: Duration) -> Marker {
+    let [x, y, z] = Isometry::from_frame(frame).translation;
+    Marker {
+        header: Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: frame.parent_frame_id.clone(),
+        },
+        ns: "parent_link".to_string(),
+        id,
+        type_: LINE_LIST,
+        action: ADD,
+        pose: identity_pose(),
+        lifetime,
+        frame_locked: true,
+        // line width
+        scale: Vector3 {
+            x: 0.003,
+            y: 0.0,
+            z: 0.0,
+        },
+        color: ColorRGBA {
+            r: 0.8,
+            g: 0.8,
+            b: 0.8,
+            a: 0.8,
+        },
+        points: vec![Point { x: 0.0, y: 0.0, z: 0.0 }, Point { x, y, z }],
+        ..Marker::default()
+    }
+}
+
+/// Frame locked arrow from the frame origin to `tip`, `width` is the shaft
+/// diameter.
+fn arrow_marker(
+    header: &Header,
+    ns: &str,
+    id: i32,
+    tip: [f64; 3],
+    width: f64,
+    (r, g, b): (f32, f32, f32),
+    lifetime: &Duration,
+) -> Marker {
+    Marker {
+        header: header.clone(),
+        ns: ns.to_string(),
+        id,
+        type_: ARROW,
+        action: ADD,
+        pose: identity_pose(),
+        lifetime: lifetime.clone(),
+        frame_locked: true,
+        // shaft diameter, head diameter, head length
+        scale: Vector3 {
+            x: width,
+            y: width * 2.0,
+            z: width * 3.0,
+        },
+        color: ColorRGBA { r, g, b, a: 1.0 },
+        points: vec![
+            Point {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Point {
+                x: tip[0],
+                y: tip[1],
+                z: tip[2],
+            },
+        ],
+        ..Marker::default()
+    }
+}
+
+/// Red, green and blue arrows along the X, Y and Z axes of a frame, see
+/// `show_axes`. The arrows share the frame's id in namespaces `axis_x`,
+/// `axis_y` and `axis_z`.
+pub fn frame_axes(
+    frame: &SPTransformStamped,
+    metadata: &Metadata,
+    id: i32,
+    lifetime: Duration,
+) -> Vec<Marker> {
+    let header = Header {
+        stamp: Time { sec: 0, nanosec: 0 },
+        frame_id: frame.child_frame_id.clone(),
+    };
+    let length = metadata.axis_length;
+    let width = length / 20.0;
+    let axes = [
+        ("axis_x", [length, 0.0, 0.0], (1.0, 0.0, 0.0)),
+        ("axis_y", [0.0, length, 0.0], (0.0, 1.0, 0.0)),
+        ("axis_z", [0.0, 0.0, length], (0.0, 0.0, 1.0)),
+    ];
+    axes.into_iter()
+        .map(|(ns, tip, color)| arrow_marker(&header, ns, id, tip, width, color, &lifetime))
+        .collect()
+}
+
+/// Axes of a tool frame (x red, y green, z blue) of `length`, a longer yellow
+/// arrow along its approach direction +z and, when `relative` holds a
+/// reference frame and the tool's pose in it, a label with that pose.
+pub fn tool_markers(
+    tool_frame_id: &str,
+    length: f64,
+    relative: Option<(&str, &Isometry)>,
+    lifetime: Duration,
+) -> Vec<Marker> {
+    let header = Header {
+        stamp: Time { sec: 0, nanosec: 0 },
+        frame_id: tool_frame_id.to_string(),
+    };
+    let arrow = |id: i32, tip: [f64; 3], width: f64, color: (f32, f32, f32)| {
+        arrow_marker(&header, "tool", id, tip, width, color, &lifetime)
+    };
+    let width = length / 20.0;
+    let mut markers = vec![
+        arrow(1, [length, 0.0, 0.0], width, (1.0, 0.0, 0.0)),
+        arrow(2, [0.0, length, 0.0], width, (0.0, 1.0, 0.0)),
+        arrow(3, [0.0, 0.0, length], width, (0.0, 0.0, 1.0)),
+        arrow(4, [0.0, 0.0, length * 2.0], width * 1.5, (1.0, 1.0, 0.0)),
+    ];
+    if let Some((reference, pose)) = relative {
+        let [x, y, z] = pose.translation;
+        let [qx, qy, qz, qw] = pose.rotation;
+        markers.push(Marker {
+            header: header.clone(),
+            ns: "tool".to_string(),
+            id: 5,
+            type_: TEXT_VIEW_FACING,
+            action: ADD,
+            pose: Pose {
+                position: Point {
+                    x: 0.0,
+                    y: 0.0,
+                    z: length * 2.0 + LABEL_HEIGHT,
+                },
+                ..identity_pose()
+            },
+            lifetime: lifetime.clone(),
+            frame_locked: true,
+            // text height
+            scale: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: LABEL_HEIGHT,
+            },
+            color: ColorRGBA {
+                r: 1.0,
+                g: 1.0,
+                b: 1.0,
+                a: 1.0,
+            },
+            text: format!(
+                "in {}: [{:.3}, {:.3}, {:.3}] m, q [{:.3}, {:.3}, {:.3}, {:.3}]",
+                reference, x, y, z, qx, qy, qz, qw
+            ),
+            ..Marker::default()
+        });
+    }
+    markers
+}
+
+/// A bright wireframe box of edge `size` around the origin of a frame.
+pub fn highlight_marker(child_frame_id: &str, size: f64, id: i32, lifetime: Duration) -> Marker {
+    let h = size / 2.0;
+    let corner = |i: usize| Point {
+        x: if i & 1 == 0 { -h } else { h },
+        y: if i & 2 == 0 { -h } else { h },
+        z: if i & 4 == 0 { -h } else { h },
+    };
+    // the 12 edges join the corners that differ in exactly one axis
+    let mut points = vec![];
+    for i in 0..8 {
+        for axis in [1, 2, 4] {
+            if i & axis == 0 {
+                points.push(corner(i));
+                points.push(corner(i | axis));
+            }
+        }
+    }
+    Marker {
+        header: Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: child_frame_id.to_string(),
+        },
+        ns: "highlight".to_string(),
+        id,
+        type_: LINE_LIST,
+        action: ADD,
+        pose: identity_pose(),
+        lifetime,
+        frame_locked: true,
+        // line width
+        scale: Vector3 {
+            x: 0.01,
+            y: 0.0,
+            z: 0.0,
+        },
+        color: ColorRGBA {
+            r: 1.0,
+            g: 0.0,
+            b: 1.0,
+            a: 1.0,
+        },
+        points,
+        ..Marker::default()
+    }
+}
+
+/// Fading axis triads of `frame` at its past poses, oldest first, each pose
+/// given with the root frame it is expressed in. The oldest triad is the most
+/// transparent. Each triad is in namespace `orientation_trail/<frame>`, with
+/// ids three apart.
+pub fn orientation_trail_markers(
+    frame: &str,
+    poses: &[(String, Isometry)],
+    length: f64,
+    lifetime: Duration,
+) -> Vec<Marker> {
+    let ns = format!("orientation_trail/{}", frame);
+    let header = Header {
+        stamp: Time { sec: 0, nanosec: 0 },
+        frame_id: frame.to_string(),
+    };
+    let width = length / 20.0;
+    let axes = [
+        ([length, 0.0, 0.0], (1.0, 0.0, 0.0)),
+        ([0.0, length, 0.0], (0.0, 1.0, 0.0)),
+        ([0.0, 0.0, length], (0.0, 0.0, 1.0)),
+    ];
+    let mut markers = vec![];
+    for (sample, (root, pose)) in poses.iter().enumerate() {
+        let alpha = (sample + 1) as f32 / poses.len() as f32;
+        for (axis, (tip, color)) in axes.into_iter().enumerate() {
+            let id = (sample * 3 + axis) as i32;
+            let mut marker = arrow_marker(&header, &ns, id, tip, width, color, &lifetime);
+            marker.color.a = alpha;
+            anchor(&mut marker, root, pose);
+            markers.push(marker);
+        }
+    }
+    markers
+}
+
+/// Angle between the triangles of a joint range sector.
+const JOINT_RANGE_STEP: f64 = 5.0 * std::f64::consts::PI / 180.0;
+
+/// Unit axis of `joint_axis`, `x`, `y`, `z` (default) or an `x,y,z` vector.
+fn joint_axis(metadata: &Metadata) -> Result<[f64; 3], String> {
+    let (vector, axis) = match metadata.joint_axis.as_deref().map(str::trim) {
+        None | Some("z") => return Ok([0.0, 0.0, 1.0]),
+        Some("x") => return Ok([1.0, 0.0, 0.0]),
+        Some("y") => return Ok([0.0, 1.0, 0.0]),
+        Some(vector) => (vector, numbers("joint_axis", vector, 3)?),
+    };
+    let norm = axis.iter().map(|v| v * v).sum::<f64>().sqrt();
+    if norm < 1e-6 {
+        return Err(format!("joint_axis '{}' has no direction", vector));
+    }
+    Ok([axis[0] / norm, axis[1] / norm, axis[2] / norm])
+}
+
+/// A translucent sector around `joint_axis` from `joint_min` to `joint_max`
+/// radians (namespace `joint_range`) and an arrow at the joint's current
+/// angle `value` (namespace `joint_value`), yellow within the range and red
+/// outside it. Angles are measured right handed about the axis, from y for an
+/// x axis, z for a y axis and x for a z axis. None without a range.
+pub fn joint_range_markers(
+    frame: &SPTransformStamped,
+    metadata: &Metadata,
+    value: Option<f64>,
+    id: i32,
+    lifetime: Duration,
+) -> Result<Option<Vec<Marker>>, String> {
+    let (min, max) = match (metadata.joint_min, metadata.joint_max) {
+        (Some(min), Some(max)) => (min, max),
+        (None, None) => return Ok(None),
+        _ => return Err("set both joint_min and joint_max".to_string()),
+    };
+    if !min.is_finite() || !max.is_finite() || min > max {
+        return Err(format!("joint range {}..{} is not a range", min, max));
+    }
+    let axis = joint_axis(metadata)?;
+    // the zero direction is the basis vector following the dominant axis
+    // component, made perpendicular to the axis
+    let dominant = (0..3)
+        .max_by(|a, b| axis[*a].abs().total_cmp(&axis[*b].abs()))
+        .unwrap_or(2);
+    let mut zero = [0.0; 3];
+    zero[(dominant + 1) % 3] = 1.0;
+    let along = zero.iter().zip(axis).map(|(z, a)| z * a).sum::<f64>();
+    let zero = [
+        zero[0] - along * axis[0],
+        zero[1] - along * axis[1],
+        zero[2] - along * axis[2],
+    ];
+    let norm = zero.iter().map(|v| v * v).sum::<f64>().sqrt();
+    let u = [zero[0] / norm, zero[1] / norm, zero[2] / norm];
+    let v = [
+        axis[1] * u[2] - axis[2] * u[1],
+        axis[2] * u[0] - axis[0] * u[2],
+        axis[0] * u[1] - axis[1] * u[0],
+    ];
+    let radius = metadata.joint_radius;
+    let at = |angle: f64, length: f64| {
+        let (sin, cos) = angle.sin_cos();
+        Point {
+            x: length * (cos * u[0] + sin * v[0]),
+            y: length * (cos * u[1] + sin * v[1]),
+            z: length * (cos * u[2] + sin * v[2]),
+        }
+    };
+    let origin = Point {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+    let steps = ((max - min) / JOINT_RANGE_STEP).ceil().max(1.0) as usize;
+    let mut points = Vec::with_capacity(steps * 3);
+    for step in 0..steps {
+        let from = min + (max - min) * step as f64 / steps as f64;
+        let to = min + (max - min) * (step + 1) as f64 / steps as f64;
+        points.extend([origin.clone(), at(from, radius), at(to, radius)]);
+    }
+    let header = Header {
+        stamp: Time { sec: 0, nanosec: 0 },
+        frame_id: frame.child_frame_id.clone(),
+    };
+    let mut markers = vec![Marker {
+        header: header.clone(),
+        ns: "joint_range".to_string(),
+        id,
+        type_: TRIANGLE_LIST,
+        action: ADD,
+        pose: identity_pose(),
+        lifetime: lifetime.clone(),
+        frame_locked: true,
+        scale: Vector3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        },
+        color: ColorRGBA {
+            r: 0.3,
+            g: 0.6,
+            b: 1.0,
+            a: 0.3,
+        },
+        points,
+        ..Marker::default()
+    }];
+    if let Some(value) = value.filter(|value| value.is_finite()) {
+        let color = if (min..=max).contains(&value) {
+            (1.0, 1.0, 0.0)
+        } else {
+            (1.0, 0.0, 0.0)
+        };
+        let Point { x, y, z } = at(value, radius * 1.2);
+        let width = radius / 20.0;
+        markers.push(arrow_marker(
+            &header,
+            "joint_value",
+            id,
+            [x, y, z],
+            width,
+            color,
+            &lifetime,
+        ));
+    }
+    Ok(Some(markers))
+}
+
+/// The `triangles` of a frame as a TRIANGLE_LIST in the frame (namespace
+/// `triangles`), colored `triangles_color`. None without `triangles`, an error
+/// when its points don't make whole triangles.
+pub fn triangle_list_marker(
+    frame: &SPTransformStamped,
+    metadata: &Metadata,
+    id: i32,
+    lifetime: Duration,
+) -> Result<Option<Marker>, String> {
+    let Some(triangles) = &metadata.triangles else {
+        return Ok(None);
+    };
+    let points: Vec<[f64; 3]> = serde_json::from_str(triangles)
+        .map_err(|e| format!("triangles isn't a list of [x, y, z] points: {}", e))?;
+    if points.is_empty() || points.len() % 3 != 0 {
+        return Err(format!("triangles needs a multiple of 3 points, got {}", points.len()));
+    }
+    let [r, g, b, a] = match &metadata.triangles_color {
+        Some(color) => parse_hex_color(color)?,
+        None => [1.0, 0.5, 0.0, 0.5],
+    };
+    Ok(Some(Marker {
+        header: Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: frame.child_frame_id.clone(),
+        },
+        ns: "triangles".to_string(),
+        id,
+        type_: TRIANGLE_LIST,
+        action: ADD,
+        pose: identity_pose(),
+        lifetime,
+        frame_locked: true,
+        scale: Vector3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        },
+        color: normalize_color(ColorRGBA { r, g, b, a }),
+        points: points.into_iter().map(|[x, y, z]| Point { x, y, z }).collect(),
+        ..Marker::default()
+    }))
+}
+
+/// Move a marker out of its frame into `root_frame_id`, at the pose its frame
+/// has in the root, so it stays put instead of tracking the frame's TF.
+pub fn anchor(marker: &mut Marker, root_frame_id: &str, frame_pose: &Isometry) {
+    let local = Isometry {
+        translation: [
+            marker.pose.position.x,
+            marker.pose.position.y,
+            marker.pose.position.z,
+        ],
+        rotation: [
+            marker.pose.orientation.x,
+            marker.pose.orientation.y,
+            marker.pose.orientation.z,
+            marker.pose.orientation.w,
+        ],
+    };
+    let pose = frame_pose.compose(&local);
+    let [x, y, z] = pose.translation;
+    let [qx, qy, qz, qw] = pose.rotation;
+    marker.header.frame_id = root_frame_id.to_string();
+    marker.pose = Pose {
+        position: Point { x, y, z },
+        orientation: Quaternion {
+            x: qx,
+            y: qy,
+            z: qz,
+            w: qw,
+        },
+    };
+    marker.frame_locked = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config_with;
+
+    fn secs(duration: Duration) -> (i32, u32) {
+        (duration.sec, duration.nanosec)
+    }
+
+    #[test]
+    fn lifetimes_keep_their_fractions() {
+        assert_eq!(secs(lifetime(3.0)), (3, 0));
+        assert_eq!(secs(lifetime(0.5)), (0, 500_000_000));
+        assert_eq!(secs(lifetime(1.25)), (1, 250_000_000));
+        // rounds up into the next second instead of a nanosec of 1e9
+        assert_eq!(secs(lifetime(1.999_999_999_9)), (2, 0));
+    }
+
+    #[test]
+    fn only_zero_lifetimes_never_expire() {
+        assert_eq!(secs(lifetime(0.0)), (0, 0));
+        assert_eq!(secs(lifetime(-1.0)), (0, 0));
+        assert_eq!(secs(lifetime(f64::NAN)), (0, 0));
+        assert_eq!(secs(lifetime(1e-12)), (0, 1));
+        assert_eq!(lifetime(f64::INFINITY).sec, i32::MAX);
+    }
+
+    fn scenario_frames() -> Vec<String> {
+        let links = [
+            "base_link",
+            "shoulder_link",
+            "forearm_link",
+            "wrist_link",
+            "tool0",
+            "camera",
+        ];
+        let mut names = vec!["world".to_string()];
+        for robot in 1..=20 {
+            names.extend(links.iter().map(|link| format!("robot{robot}_{link}")));
+        }
+        names.extend((0..50).map(|i| format!("table_{i}")));
+        names.extend((0..500).map(|i| format!("part_{i}")));
+        names
+    }
+
+    #[test]
+    fn marker_ids_are_stable() {
+        assert_eq!(
+            marker_id_for("", "robot1_tool0"),
+            marker_id_for("", "robot1_tool0")
+        );
+        assert!(marker_id_for("", "robot1_tool0") >= 0);
+        assert_ne!(
+            marker_id_for("", "robot1_tool0"),
+            marker_id_for("viz", "robot1_tool0")
+        );
+    }
+
+    #[test]
+    fn marker_ids_of_a_scenario_dont_collide() {
+        let names = scenario_frames();
+        let ids: HashSet<i32> = names.iter().map(|name| marker_id_for("", name)).collect();
+        assert_eq!(ids.len(), names.len());
+
+        let (assigned, moved) = assign_marker_ids(names.iter().map(String::as_str), "", true);
+        assert_eq!(moved, 0);
+        assert_eq!(assigned["robot1_tool0"], marker_id_for("", "robot1_tool0"));
+    }
+
+    #[test]
+    fn probing_moves_colliding_frames_apart() {
+        // a known FNV collision
+        assert_eq!(
+            marker_id_for("", "frame_12938"),
+            marker_id_for("", "frame_102310")
+        );
+        let (ids, moved) = assign_marker_ids(["frame_12938", "frame_102310"], "", true);
+        assert_eq!(moved, 1);
+        assert_ne!(ids["frame_12938"], ids["frame_102310"]);
+        // "frame_102310" sorts first and keeps the hashed id
+        assert_eq!(ids["frame_102310"], 687_202_789);
+
+        let (ids, moved) = assign_marker_ids(["frame_12938", "frame_102310"], "", false);
+        assert_eq!(moved, 0);
+        assert_eq!(ids["frame_12938"], ids["frame_102310"]);
+    }
+
+    #[test]
+    fn mesh_uris_resolve_by_their_kind() {
+        let dir = "/opt/meshes";
+        assert_eq!(
+            resolve_mesh_uri(dir, "ur5/base.stl"),
+            "file:///opt/meshes/ur5/base.stl"
+        );
+        assert_eq!(
+            resolve_mesh_uri(dir, "package://ur_description/meshes/base.dae"),
+            "package://ur_description/meshes/base.dae"
+        );
+        assert_eq!(
+            resolve_mesh_uri(dir, "file:///tmp/base.stl"),
+            "file:///tmp/base.stl"
+        );
+        assert_eq!(
+            resolve_mesh_uri(dir, "/tmp/base.stl"),
+            "file:///tmp/base.stl"
+        );
+    }
+
+    fn zone_metadata(shape: Option<&str>) -> Metadata {
+        let mut metadata = crate::metadata::decode_metadata(&MapOrUnknown::UNKNOWN);
+        metadata.zone = 0.5;
+        metadata.zone_shape = shape.map(str::to_string);
+        metadata
+    }
+
+    #[test]
+    fn zone_shapes_pick_their_marker_type() {
+        let type_of = |shape| zone_geometry(&zone_metadata(shape)).map(|zone| zone.type_);
+        assert_eq!(type_of(None), Ok(SPHERE));
+        assert_eq!(type_of(Some("sphere")), Ok(SPHERE));
+        assert_eq!(type_of(Some("box")), Ok(CUBE));
+        assert_eq!(type_of(Some("Cylinder")), Ok(CYLINDER));
+        assert!(type_of(Some("cone")).is_err());
+    }
+
+    fn mesh_marker_with(id: i32, action: i32) -> Marker {
+        Marker {
+            ns: "mesh".to_string(),
+            id,
+            action,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chunks_keep_every_marker() {
+        let markers: Vec<Marker> = (0..600).map(|id| mesh_marker_with(id, ADD)).collect();
+        let chunks = chunk_markers(markers, 256);
+        let sizes: Vec<usize> = chunks.iter().map(|chunk| chunk.markers.len()).collect();
+        assert_eq!(sizes, [256, 256, 88]);
+        let ids: HashSet<i32> = chunks
+            .iter()
+            .flat_map(|chunk| chunk.markers.iter().map(|m| m.id))
+            .collect();
+        assert_eq!(ids.len(), 600);
+    }
+
+    #[test]
+    fn deletions_go_out_in_the_first_chunks() {
+        let mut markers: Vec<Marker> = (0..300).map(|id| mesh_marker_with(id, ADD)).collect();
+        markers.push(mesh_marker_with(1000, DELETE));
+        markers.push(delete_all_marker());
+        let chunks = chunk_markers(markers, 256);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].markers[0].action, DELETEALL);
+        assert_eq!(chunks[0].markers[1].action, DELETE);
+        assert_eq!(chunks[1].markers.len(), 46);
+    }
+
+    #[test]
+    fn frame_locked_is_set_on_the_built_markers() {
+        let identity = r2r::geometry_msgs::msg::Transform {
+            rotation: Quaternion {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+                w: 1.0,
+            },
+            ..Default::default()
+        };
+        let frame = crate::scene_edits::new_frame("world", "gripper", &identity, true).unwrap();
+        let mut metadata = zone_metadata(None);
+        metadata.mesh_file = Some("gripper.stl".to_string());
+        let zone = zone_geometry(&metadata).unwrap();
+        let locked = |metadata: &Metadata, config: &Config| {
+            let scale = MarkerScale::MeshMultiplier(1.0);
+            [
+                mesh_marker(&frame, metadata, scale, 1, config).unwrap(),
+                zone_marker(&frame, metadata, &zone, 1, config).unwrap(),
+                label_marker(&frame, metadata, 1, config),
+            ]
+            .map(|marker| marker.frame_locked)
+        };
+        assert_eq!(locked(&metadata, &config_with(&[])), [true; 3]);
+        assert_eq!(
+            locked(&metadata, &config_with(&["FRAME_LOCKED=off"])),
+            [false; 3]
+        );
+        // the metadata wins over the setting
+        metadata.frame_locked = Some(false);
+        assert_eq!(locked(&metadata, &config_with(&[])), [false; 3]);
+    }
+
+    fn scaled_metadata(mesh_type: i32, mesh_scale: f32, primitive_size: Option<f64>) -> Metadata {
+        let mut metadata = zone_metadata(None);
+        metadata.mesh_type = mesh_type;
+        metadata.mesh_scale = mesh_scale;
+        metadata.primitive_size = primitive_size;
+        metadata
+    }
+
+    #[test]
+    fn primitives_are_sized_and_meshes_multiplied() {
+        let config = config_with(&[]);
+        let scale = |mesh_type, mesh_scale, size| {
+            resolve_scale(&scaled_metadata(mesh_type, mesh_scale, size), &config)
+        };
+        assert_eq!(scale(CUBE, 2.0, Some(0.3)), MarkerScale::PrimitiveSize(0.3));
+        // without a usable primitive_size, mesh_scale and then the default
+        assert_eq!(
+            scale(SPHERE, 2.0, Some(0.0)),
+            MarkerScale::PrimitiveFallback(2.0)
+        );
+        assert_eq!(
+            scale(CYLINDER, 0.0, None),
+            MarkerScale::PrimitiveFallback(1.0)
+        );
+        // meshes multiply their geometry, a mesh_scale of 0 meaning 1
+        assert_eq!(
+            scale(MESH_RESOURCE, 2.0, Some(0.3)),
+            MarkerScale::MeshMultiplier(2.0)
+        );
+        assert_eq!(
+            scale(MESH_RESOURCE, 0.0, None),
+            MarkerScale::MeshMultiplier(1.0)
+        );
+
+        let config = config_with(&[
+            "PRIMITIVE_SIZE_FROM_MESH_SCALE=off",
+            "DEFAULT_PRIMITIVE_SIZE=0.2",
+        ]);
+        assert_eq!(
+            resolve_scale(&scaled_metadata(CUBE, 2.0, None), &config),
+            MarkerScale::PrimitiveFallback(0.2)
+        );
+    }
+
+    #[test]
+    fn only_unit_fallback_sizes_are_suspicious() {
+        assert!(MarkerScale::PrimitiveFallback(1.0).is_suspicious());
+        assert!(!MarkerScale::PrimitiveFallback(0.5).is_suspicious());
+        assert!(!MarkerScale::PrimitiveSize(1.0).is_suspicious());
+        assert!(!MarkerScale::MeshMultiplier(1.0).is_suspicious());
+    }
+
+    fn frame_at(parent: &str, child: &str, [x, y, z]: [f64; 3]) -> SPTransformStamped {
+        let transform = r2r::geometry_msgs::msg::Transform {
+            translation: Vector3 { x, y, z },
+            rotation: Quaternion {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+                w: 1.0,
+            },
+        };
+        crate::scene_edits::new_frame(parent, child, &transform, false).unwrap()
+    }
+
+    #[test]
+    fn shadows_lie_on_the_ground_beneath_their_frame() {
+        let frames: HashMap<String, SPTransformStamped> = [
+            frame_at("world", "table", [1.0, 2.0, 0.8]),
+            frame_at("table", "cup", [0.1, 0.0, 0.2]),
+        ]
+        .into_iter()
+        .map(|frame| (frame.child_frame_id.clone(), frame))
+        .collect();
+        let (root, pose) = crate::geometry::pose_in_root(&frames, "cup").unwrap();
+        assert_eq!(root, "world");
+        let shadow = shadow_marker(&root, pose.translation, 0.25, 0.1, 7, lifetime(0.0));
+        assert_eq!(shadow.header.frame_id, "world");
+        assert!((shadow.pose.position.x - 1.1).abs() < 1e-9);
+        assert!((shadow.pose.position.y - 2.0).abs() < 1e-9);
+        // the disc's bottom touches the ground, whatever the frame's height
+        assert!((shadow.pose.position.z - shadow.scale.z / 2.0 - 0.1).abs() < 1e-9);
+        assert_eq!((shadow.scale.x, shadow.scale.y), (0.5, 0.5));
+    }
+
+    #[test]
+    fn shadow_radii_follow_the_metadata_then_the_size() {
+        let mut metadata = zone_metadata(None);
+        let primitive = MarkerScale::PrimitiveSize(0.4);
+        assert_eq!(shadow_radius(&metadata, primitive, 0.3), 0.2);
+        let fallback = MarkerScale::PrimitiveFallback(1.0);
+        assert_eq!(shadow_radius(&metadata, fallback, 0.3), 0.5);
+        let mesh = MarkerScale::MeshMultiplier(2.0);
+        assert_eq!(shadow_radius(&metadata, mesh, 0.3), 0.3);
+        metadata.shadow_radius = Some(0.05);
+        assert_eq!(shadow_radius(&metadata, primitive, 0.3), 0.05);
+        assert_eq!(shadow_radius(&metadata, mesh, 0.3), 0.05);
+    }
+
+    fn mesh_with_alpha(a: f32, embedded_materials: bool) -> Marker {
+        Marker {
+            type_: MESH_RESOURCE,
+            mesh_use_embedded_materials: embedded_materials,
+            color: ColorRGBA {
+                r: 1.0,
+                g: 1.0,
+                b: 1.0,
+                a,
+            },
+            ..mesh_marker_with(1, ADD)
+        }
+    }
+
+    #[test]
+    fn only_zero_alpha_without_embedded_materials_is_invisible() {
+        assert!(is_invisible(&mesh_with_alpha(0.0, false)));
+        assert!(!is_invisible(&mesh_with_alpha(0.0, true)));
+        assert!(!is_invisible(&mesh_with_alpha(0.1, false)));
+        let cube = Marker {
+            type_: CUBE,
+            ..mesh_with_alpha(0.0, true)
+        };
+        assert!(is_invisible(&cube));
+        let deleted = Marker {
+            action: DELETE,
+            ..mesh_with_alpha(0.0, false)
+        };
+        assert!(!is_invisible(&deleted));
+    }
+
+    #[test]
+    fn the_alpha_floor_raises_only_faint_added_markers() {
+        let mut markers = vec![
+            mesh_with_alpha(0.0, false),
+            mesh_with_alpha(0.1, false),
+            mesh_with_alpha(0.8, false),
+            mesh_with_alpha(0.0, true),
+            Marker {
+                action: DELETE,
+                ..mesh_with_alpha(0.0, false)
+            },
+        ];
+        apply_alpha_floor(&mut markers, 0.3);
+        let alphas: Vec<f32> = markers.iter().map(|marker| marker.color.a).collect();
+        assert_eq!(alphas, vec![0.3, 0.3, 0.8, 0.0, 0.0]);
+        // a floor above 1 is capped
+        apply_alpha_floor(&mut markers, 2.0);
+        assert_eq!(markers[0].color.a, 1.0);
+    }
+}
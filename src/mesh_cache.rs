@@ -0,0 +1,118 @@
+use r2r::visualization_msgs::msg::Marker;
+use std::collections::HashMap;
+
+use crate::markers::MarkerScale;
+use crate::metadata::Metadata;
+
+/// What a mesh marker is built from besides the frame's metadata.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshKey {
+    pub scale: MarkerScale,
+    pub id: i32,
+    /// Whether the frame's stored rotation is the identity, which decides
+    /// whether a MESH_ORIENTATIONS default applies.
+    pub unrotated: bool,
+}
+
+#[derive(Debug, Clone)]
+struct CachedMesh {
+    metadata: Metadata,
+    key: MeshKey,
+    /// None for a frame that has no mesh marker.
+    marker: Option<Marker>,
+}
+
+/// The mesh marker of each frame as last built. Mesh markers live in their
+/// frame, so only a change of the metadata they are built from, after the
+/// animations, appearances and tints, changes them, and a frame that just
+/// moves reuses its marker. Stamps are set on the copies handed out.
+#[derive(Debug, Clone, Default)]
+pub struct MeshMarkerCache {
+    entries: HashMap<String, CachedMesh>,
+}
+
+impl MeshMarkerCache {
+    /// The marker of `frame`, rebuilt with `build` when `metadata` or `key`
+    /// differ from the last build. Warnings `build` logs are therefore only
+    /// logged when it runs.
+    pub fn get_or_build(
+        &mut self,
+        frame: &str,
+        metadata: &Metadata,
+        key: MeshKey,
+        build: impl FnOnce() -> Option<Marker>,
+    ) -> Option<Marker> {
+        match self.entries.get(frame) {
+            Some(cached) if cached.key == key && cached.metadata == *metadata => {
+                cached.marker.clone()
+            }
+            _ => {
+                let marker = build();
+                self.entries.insert(
+                    frame.to_string(),
+                    CachedMesh {
+                        metadata: metadata.clone(),
+                        key,
+                        marker: marker.clone(),
+                    },
+                );
+                marker
+            }
+        }
+    }
+
+    /// Forget frames that are gone.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.entries.retain(|frame, _| keep(frame));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::decode_metadata;
+    use micro_sp::MapOrUnknown;
+
+    #[test]
+    fn an_unchanged_key_reuses_the_marker_and_a_changed_one_rebuilds_it() {
+        let mut cache = MeshMarkerCache::default();
+        let metadata = decode_metadata(&MapOrUnknown::UNKNOWN);
+        let key = MeshKey {
+            scale: MarkerScale::MeshMultiplier(1.0),
+            id: 7,
+            unrotated: true,
+        };
+        let mut builds = 0;
+        let mut get = |cache: &mut MeshMarkerCache, metadata: &Metadata, key: MeshKey| {
+            cache.get_or_build("table", metadata, key, || {
+                builds += 1;
+                Some(Marker {
+                    id: key.id,
+                    ..Default::default()
+                })
+            })
+        };
+        assert_eq!(get(&mut cache, &metadata, key).unwrap().id, 7);
+        assert_eq!(get(&mut cache, &metadata, key).unwrap().id, 7);
+        let changed = [
+            MeshKey {
+                scale: MarkerScale::MeshMultiplier(2.0),
+                ..key
+            },
+            MeshKey { id: 8, ..key },
+            MeshKey {
+                unrotated: false,
+                ..key
+            },
+        ];
+        for changed in changed {
+            assert_eq!(get(&mut cache, &metadata, changed).unwrap().id, changed.id);
+            // back to the original key, which was overwritten
+            get(&mut cache, &metadata, key);
+        }
+        let mut retyped = metadata.clone();
+        retyped.mesh_type += 1;
+        get(&mut cache, &retyped, key);
+        assert_eq!(builds, 1 + 2 * 3 + 1);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::geometry::quaternion_from_rpy;
+use crate::log_targets;
+
+/// Default marker orientations of mesh files, for imported meshes that are
+/// consistently modeled in another "up" convention. Only applied to frames
+/// without a rotation of their own, and only to the marker, not the frame.
+#[derive(Debug, Clone, Default)]
+pub struct MeshOrientations {
+    /// (mesh file pattern, `[x, y, z, w]`), the first matching pattern wins.
+    defaults: Vec<(String, [f64; 4])>,
+}
+
+impl MeshOrientations {
+    /// Parse `pattern=roll pitch yaw` entries with angles in radians, malformed
+    /// ones are logged and skipped. Patterns match the `mesh_file` metadata,
+    /// `*` matches any run of characters.
+    pub fn parse(entries: &[String]) -> MeshOrientations {
+        let mut defaults = vec![];
+        for entry in entries {
+            let parsed = entry.split_once('=').and_then(|(pattern, angles)| {
+                let angles: Vec<f64> = angles
+                    .split_whitespace()
+                    .map(|angle| angle.parse::<f64>())
+                    .collect::<Result<_, _>>()
+                    .ok()?;
+                match angles[..] {
+                    [roll, pitch, yaw] if !pattern.trim().is_empty() => Some((
+                        pattern.trim().to_string(),
+                        quaternion_from_rpy(roll, pitch, yaw),
+                    )),
+                    _ => None,
+                }
+            });
+            match parsed {
+                Some(default) => defaults.push(default),
+                None => log::warn!(target: log_targets::MARKERS,
+                    "Ignoring malformed mesh orientation '{}', expected pattern=roll pitch yaw.", entry
+                ),
+            }
+        }
+        MeshOrientations { defaults }
+    }
+
+    pub fn orientation(&self, mesh_file: &str) -> Option<[f64; 4]> {
+        self.defaults
+            .iter()
+            .find(|(pattern, _)| wildcard_match(pattern, mesh_file))
+            .map(|(_, orientation)| *orientation)
+    }
+}
+
+/// Whether `text` matches `pattern` as a whole, where `*` matches any run of
+/// characters, including none.
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // no `*`, the prefix has to be all of it
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
@@ -0,0 +1,569 @@
+use micro_sp::*;
+use serde::Deserialize;
+
+/// Visualization metadata of a frame.
+///
+/// Wraps the fields decoded by `micro_sp::decode_metadata` and adds the
+/// visualization-only keys that micro_sp does not know about.
+///
+/// A key that is missing, or holds a value of the wrong type, takes the
+/// default noted on its field, or micro_sp's for the fields up to
+/// `override_meshes_dir`. An unset `Option` leaves the feature off or to its
+/// setting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub visualize_mesh: bool,
+    pub visualize_zone: bool,
+    /// Zone diameter in meters, doubled from the stored radius with `zone_is_radius`.
+    pub zone: f64,
+    pub mesh_type: i32,
+    pub mesh_file: Option<String>,
+    /// Multiplier applied to the mesh geometry. Only meaningful for MESH_RESOURCE.
+    pub mesh_scale: f32,
+    pub mesh_r: f32,
+    pub mesh_g: f32,
+    pub mesh_b: f32,
+    pub mesh_a: f32,
+    pub override_meshes_dir: Option<String>,
+    /// Show the mesh's own materials instead of the color when true, color only
+    /// when false, unset tints the materials with the color as before.
+    pub mesh_use_embedded_materials: Option<bool>,
+    /// Physical size in meters of a primitive (CUBE/SPHERE/CYLINDER) marker.
+    pub primitive_size: Option<f64>,
+    /// Radius of the ground shadow disc, overriding the footprint estimate.
+    pub shadow_radius: Option<f64>,
+    /// Draw the zone as a flat disc on the floor, overriding ZONE_2D.
+    pub zone_2d: Option<bool>,
+    /// Markers are emitted in ascending priority order, 0 by default.
+    pub render_priority: i32,
+    /// Collision geometry, drawn as a translucent overlay on `collision_markers`.
+    pub collision_mesh_file: Option<String>,
+    /// Logical assembly the frame belongs to, shown and hidden as a whole.
+    pub assembly: Option<String>,
+    /// Layers the frame is in, from a comma separated string, see LAYER_VISIBILITY_KEY.
+    pub layers: Vec<String>,
+    /// Whether markers in the frame track it live, overriding FRAME_LOCKED.
+    pub frame_locked: Option<bool>,
+    /// Upper limit on how often an active frame is broadcast on `tf`.
+    pub max_broadcast_hz: Option<f64>,
+    /// Redis key of a live float that scales the marker, see `scale_factor`.
+    pub scale_scalar_key: Option<String>,
+    /// Scalar values mapped onto `scale_range`, 0..1 by default.
+    pub scale_scalar_range: [f64; 2],
+    /// Factors on the marker scale at the ends of `scale_scalar_range`, 0.5..2 by default.
+    pub scale_range: [f64; 2],
+    /// Mesh files cycled through as a flipbook, from a comma separated string.
+    pub mesh_animation: Option<Vec<String>>,
+    /// Animation steps per second, 1 by default.
+    pub mesh_animation_hz: f64,
+    /// `sphere` (default), `box` or `cylinder`.
+    pub zone_shape: Option<String>,
+    /// Per-axis zone dimensions, each defaulting to `zone`, full extents like `zone`.
+    pub zone_x: Option<f64>,
+    pub zone_y: Option<f64>,
+    pub zone_z: Option<f64>,
+    /// Zone orientation as `roll,pitch,yaw` in radians, or `zone_quat` as `x,y,z,w`.
+    pub zone_rpy: Option<String>,
+    pub zone_quat: Option<String>,
+    /// Zone color, translucent green (0, 1, 0, 0.15) by default.
+    pub zone_r: f32,
+    pub zone_g: f32,
+    pub zone_b: f32,
+    pub zone_a: f32,
+    /// Pulse the zone's alpha within `zone_pulse_alpha` instead of `zone_a`,
+    /// false by default.
+    pub zone_pulse: bool,
+    /// Pulses per second, 1 by default.
+    pub zone_pulse_hz: f64,
+    /// Alpha range of a pulsing zone, `zone_pulse_min` and `zone_pulse_max`,
+    /// 0.05..0.5 by default.
+    pub zone_pulse_alpha: [f32; 2],
+    /// Show the frame's name above its origin, overriding SHOW_LABELS.
+    pub show_label: Option<bool>,
+    /// Draw RGB arrows along the X/Y/Z axes of the frame on `axis_markers`,
+    /// overriding SHOW_AXES.
+    pub show_axes: Option<bool>,
+    /// Draw a line from the parent's origin to the frame's, overriding SHOW_PARENT_LINKS.
+    pub show_parent_link: Option<bool>,
+    /// Arrow length in meters of `show_axes`, 0.1 by default.
+    pub axis_length: f64,
+    /// Joint axis, `x`, `y`, `z` (default) or `x,y,z`, of a joint range marker.
+    pub joint_axis: Option<String>,
+    /// Joint limits in radians, the range marker is drawn when both are set.
+    pub joint_min: Option<f64>,
+    pub joint_max: Option<f64>,
+    /// Current joint angle in radians, or the Redis key of a live one.
+    pub joint_value: Option<f64>,
+    pub joint_value_key: Option<String>,
+    /// Radius in meters of the joint range sector, 0.1 by default.
+    pub joint_radius: f64,
+    /// Height of the label above the frame origin in meters, 0.1 by default.
+    pub label_offset: f64,
+    /// Per-axis mesh scale, replacing `mesh_scale` when any is set, 0 or unset is 1.
+    pub mesh_scale_x: Option<f64>,
+    pub mesh_scale_y: Option<f64>,
+    pub mesh_scale_z: Option<f64>,
+    /// Mesh position in the frame as `x,y,z` meters.
+    pub mesh_offset_xyz: Option<String>,
+    /// Mesh rotation in the frame as `roll,pitch,yaw` radians, or `mesh_offset_quat` as `x,y,z,w`.
+    pub mesh_offset_rpy: Option<String>,
+    pub mesh_offset_quat: Option<String>,
+    /// Further meshes of the frame, a JSON list of `MeshDescriptor`s.
+    pub meshes: Option<String>,
+    /// Metadata key of a number the mesh color is taken from, see `apply_color_by`.
+    pub color_by: Option<String>,
+    /// `color_min` and `color_max`, the values at the ends of the colormap, 0..1
+    /// by default.
+    pub color_range: [f64; 2],
+    /// `blue_red` (default) or `viridis`.
+    pub colormap: Option<String>,
+    /// Explicit triangles in the frame, a JSON list of `[x, y, z]` points
+    /// taken three at a time.
+    pub triangles: Option<String>,
+    /// `#RRGGBB` or `#RRGGBBAA` of the triangles, translucent orange by default.
+    pub triangles_color: Option<String>,
+    /// Written by the TF mirror, the frame's source broadcasts it already.
+    /// False by default.
+    pub mirrored_from_tf: bool,
+    /// Lifetime in seconds of the mesh and zone markers, overriding
+    /// MESH_LIFETIME and ZONE_LIFETIME, 0 never expires.
+    pub lifetime_secs: Option<f64>,
+    /// The stored `zone`, `zone_x`, `zone_y` and `zone_z` are radii (half
+    /// extents) rather than diameters, false by default.
+    pub zone_is_radius: bool,
+    /// Seconds since the epoch the frame's data was last written, checked
+    /// against MAX_TRANSFORM_AGE_SECS.
+    pub last_updated: Option<f64>,
+}
+
+/// One of the further meshes of a frame, e.g. the arm of an assembly whose
+/// base is the frame's `mesh_file`. Unset colors are the frame's.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct MeshDescriptor {
+    pub file: Option<String>,
+    /// Marker type, MESH_RESOURCE by default.
+    #[serde(rename = "type")]
+    pub mesh_type: Option<i32>,
+    /// Mesh multiplier or primitive size, 1 by default.
+    pub scale: Option<f64>,
+    /// RGBA in 0.0..=1.0
+    pub color: Option<[f32; 4]>,
+    /// Pose in the frame, like `mesh_offset_xyz`, `mesh_offset_rpy` and `mesh_offset_quat`.
+    pub offset_xyz: Option<String>,
+    pub offset_rpy: Option<String>,
+    pub offset_quat: Option<String>,
+}
+
+/// Decode the metadata of a frame. Total: `UNKNOWN` metadata, missing keys
+/// and values of the wrong type all decode to the defaults, which
+/// `decode_problems` tells apart. Metadata that isn't valid JSON never gets
+/// here, the frame fails to decode as a whole and is skipped with a warning.
+pub fn decode_metadata(map: &MapOrUnknown) -> Metadata {
+    let base = micro_sp::decode_metadata(map);
+    // a malformed mesh_color falls back to the channels, decode_problems reports it
+    let [mesh_r, mesh_g, mesh_b, mesh_a] = get_string(map, "mesh_color")
+        .and_then(|color| parse_hex_color(&color).ok())
+        .unwrap_or([base.mesh_r, base.mesh_g, base.mesh_b, base.mesh_a]);
+    // zone sizes are diameters from here on
+    let zone_is_radius = get_bool(map, "zone_is_radius").unwrap_or(false);
+    let zone_size = |size: f64| if zone_is_radius { size * 2.0 } else { size };
+    Metadata {
+        visualize_mesh: base.visualize_mesh,
+        visualize_zone: base.visualize_zone,
+        zone: zone_size(base.zone),
+        mesh_type: base.mesh_type,
+        mesh_file: base.mesh_file,
+        mesh_scale: base.mesh_scale,
+        mesh_r,
+        mesh_g,
+        mesh_b,
+        mesh_a,
+        override_meshes_dir: base.override_meshes_dir,
+        mesh_use_embedded_materials: get_bool(map, "mesh_use_embedded_materials"),
+        primitive_size: get_f64(map, "primitive_size"),
+        shadow_radius: get_f64(map, "shadow_radius"),
+        zone_2d: get_bool(map, "zone_2d"),
+        render_priority: get_f64(map, "render_priority").unwrap_or(0.0) as i32,
+        collision_mesh_file: get_string(map, "collision_mesh_file"),
+        assembly: get_string(map, "assembly"),
+        layers: get_string(map, "layers")
+            .map(|layers| {
+                layers
+                    .split(',')
+                    .map(|layer| layer.trim().to_string())
+                    .filter(|layer| !layer.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        frame_locked: get_bool(map, "frame_locked"),
+        max_broadcast_hz: get_f64(map, "max_broadcast_hz"),
+        scale_scalar_key: get_string(map, "scale_scalar_key"),
+        scale_scalar_range: [
+            get_f64(map, "scale_scalar_min").unwrap_or(0.0),
+            get_f64(map, "scale_scalar_max").unwrap_or(1.0),
+        ],
+        scale_range: [
+            get_f64(map, "scale_min").unwrap_or(0.5),
+            get_f64(map, "scale_max").unwrap_or(2.0),
+        ],
+        mesh_animation: get_string(map, "mesh_animation").map(|files| {
+            files
+                .split(',')
+                .map(|file| file.trim().to_string())
+                .filter(|file| !file.is_empty())
+                .collect()
+        }),
+        mesh_animation_hz: get_f64(map, "mesh_animation_hz").unwrap_or(1.0),
+        zone_shape: get_string(map, "zone_shape"),
+        zone_x: get_f64(map, "zone_x").map(zone_size),
+        zone_y: get_f64(map, "zone_y").map(zone_size),
+        zone_z: get_f64(map, "zone_z").map(zone_size),
+        zone_rpy: get_string(map, "zone_rpy"),
+        zone_quat: get_string(map, "zone_quat"),
+        zone_r: get_f64(map, "zone_r").unwrap_or(0.0) as f32,
+        zone_g: get_f64(map, "zone_g").unwrap_or(1.0) as f32,
+        zone_b: get_f64(map, "zone_b").unwrap_or(0.0) as f32,
+        zone_a: get_f64(map, "zone_a").unwrap_or(0.15) as f32,
+        zone_pulse: get_bool(map, "zone_pulse").unwrap_or(false),
+        zone_pulse_hz: get_f64(map, "zone_pulse_hz").unwrap_or(1.0),
+        zone_pulse_alpha: [
+            get_f64(map, "zone_pulse_min").unwrap_or(0.05) as f32,
+            get_f64(map, "zone_pulse_max").unwrap_or(0.5) as f32,
+        ],
+        show_label: get_bool(map, "show_label"),
+        show_axes: get_bool(map, "show_axes"),
+        show_parent_link: get_bool(map, "show_parent_link"),
+        axis_length: get_f64(map, "axis_length").unwrap_or(0.1),
+        joint_axis: get_string(map, "joint_axis"),
+        joint_min: get_f64(map, "joint_min"),
+        joint_max: get_f64(map, "joint_max"),
+        joint_value: get_f64(map, "joint_value"),
+        joint_value_key: get_string(map, "joint_value_key"),
+        joint_radius: get_f64(map, "joint_radius").unwrap_or(0.1),
+        label_offset: get_f64(map, "label_offset").unwrap_or(0.1),
+        mesh_scale_x: get_f64(map, "mesh_scale_x"),
+        mesh_scale_y: get_f64(map, "mesh_scale_y"),
+        mesh_scale_z: get_f64(map, "mesh_scale_z"),
+        mesh_offset_xyz: get_string(map, "mesh_offset_xyz"),
+        mesh_offset_rpy: get_string(map, "mesh_offset_rpy"),
+        mesh_offset_quat: get_string(map, "mesh_offset_quat"),
+        meshes: get_string(map, "meshes"),
+        color_by: get_string(map, "color_by"),
+        color_range: [
+            get_f64(map, "color_min").unwrap_or(0.0),
+            get_f64(map, "color_max").unwrap_or(1.0),
+        ],
+        colormap: get_string(map, "colormap"),
+        triangles: get_string(map, "triangles"),
+        triangles_color: get_string(map, "triangles_color"),
+        mirrored_from_tf: get_bool(map, "mirrored_from_tf").unwrap_or(false),
+        lifetime_secs: get_f64(map, "lifetime_secs").filter(|secs| secs.is_finite() && *secs >= 0.0),
+        zone_is_radius,
+        last_updated: get_f64(map, "last_updated"),
+    }
+}
+
+/// RGBA in 0.0..=1.0 from `#RRGGBB` or `#RRGGBBAA`, opaque without alpha.
+pub fn parse_hex_color(color: &str) -> Result<[f32; 4], String> {
+    let digits = color
+        .trim()
+        .strip_prefix('#')
+        .ok_or_else(|| format!("'{}' doesn't start with '#'", color))?;
+    if !(digits.len() == 6 || digits.len() == 8) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("'{}' isn't #RRGGBB or #RRGGBBAA", color));
+    }
+    let channel = |i: usize| {
+        digits
+            .get(i..i + 2)
+            .map_or(Ok(255), |hex| u8::from_str_radix(hex, 16))
+            .map(|value| value as f32 / 255.0)
+            .map_err(|e| e.to_string())
+    };
+    Ok([channel(0)?, channel(2)?, channel(4)?, channel(6)?])
+}
+
+/// Metadata keys this node reads, with the kind of value each expects.
+const KNOWN_KEYS: &[(&str, Kind)] = &[
+    ("visualize_mesh", Kind::Bool),
+    ("visualize_zone", Kind::Bool),
+    ("zone", Kind::Number),
+    ("mesh_type", Kind::Number),
+    ("mesh_file", Kind::String),
+    ("mesh_scale", Kind::Number),
+    ("mesh_r", Kind::Number),
+    ("mesh_g", Kind::Number),
+    ("mesh_b", Kind::Number),
+    ("mesh_a", Kind::Number),
+    ("mesh_color", Kind::String),
+    ("override_meshes_dir", Kind::String),
+    ("mesh_use_embedded_materials", Kind::Bool),
+    ("primitive_size", Kind::Number),
+    ("shadow_radius", Kind::Number),
+    ("zone_2d", Kind::Bool),
+    ("render_priority", Kind::Number),
+    ("collision_mesh_file", Kind::String),
+    ("assembly", Kind::String),
+    ("layers", Kind::String),
+    ("frame_locked", Kind::Bool),
+    ("max_broadcast_hz", Kind::Number),
+    ("scale_scalar_key", Kind::String),
+    ("scale_scalar_min", Kind::Number),
+    ("scale_scalar_max", Kind::Number),
+    ("scale_min", Kind::Number),
+    ("scale_max", Kind::Number),
+    ("mesh_animation", Kind::String),
+    ("mesh_animation_hz", Kind::Number),
+    ("zone_shape", Kind::String),
+    ("zone_x", Kind::Number),
+    ("zone_y", Kind::Number),
+    ("zone_z", Kind::Number),
+    ("zone_rpy", Kind::String),
+    ("zone_quat", Kind::String),
+    ("zone_r", Kind::Number),
+    ("zone_g", Kind::Number),
+    ("zone_b", Kind::Number),
+    ("zone_a", Kind::Number),
+    ("zone_pulse", Kind::Bool),
+    ("zone_pulse_hz", Kind::Number),
+    ("zone_pulse_min", Kind::Number),
+    ("zone_pulse_max", Kind::Number),
+    ("show_label", Kind::Bool),
+    ("show_axes", Kind::Bool),
+    ("show_parent_link", Kind::Bool),
+    ("axis_length", Kind::Number),
+    ("joint_axis", Kind::String),
+    ("joint_min", Kind::Number),
+    ("joint_max", Kind::Number),
+    ("joint_value", Kind::Number),
+    ("joint_value_key", Kind::String),
+    ("joint_radius", Kind::Number),
+    ("label_offset", Kind::Number),
+    ("mesh_scale_x", Kind::Number),
+    ("mesh_scale_y", Kind::Number),
+    ("mesh_scale_z", Kind::Number),
+    ("mesh_offset_xyz", Kind::String),
+    ("mesh_offset_rpy", Kind::String),
+    ("mesh_offset_quat", Kind::String),
+    ("meshes", Kind::String),
+    ("color_by", Kind::String),
+    ("color_min", Kind::Number),
+    ("color_max", Kind::Number),
+    ("colormap", Kind::String),
+    ("triangles", Kind::String),
+    ("triangles_color", Kind::String),
+    ("mirrored_from_tf", Kind::Bool),
+    ("lifetime_secs", Kind::Number),
+    ("zone_is_radius", Kind::Bool),
+    ("last_updated", Kind::Number),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Bool,
+    Number,
+    String,
+}
+
+/// Whether the metadata sets the mesh color, as `mesh_color` or any channel.
+pub fn stores_mesh_color(map: &MapOrUnknown) -> bool {
+    ["mesh_color", "mesh_r", "mesh_g", "mesh_b", "mesh_a"]
+        .iter()
+        .any(|key| get_value(map, key).is_some())
+}
+
+/// Known keys whose value has the wrong type, and would silently decode to
+/// their default.
+pub fn decode_problems(map: &MapOrUnknown) -> Vec<String> {
+    KNOWN_KEYS
+        .iter()
+        .filter_map(|(key, kind)| {
+            let value = get_value(map, key)?;
+            let ok = match kind {
+                Kind::Bool => get_bool(map, key).is_some(),
+                Kind::Number => get_f64(map, key).is_some(),
+                Kind::String => get_string(map, key).is_some(),
+            };
+            (!ok).then(|| format!("'{}' should be a {:?} but is {:?}", key, kind, value))
+        })
+        .chain(
+            get_string(map, "mesh_color")
+                .and_then(|color| parse_hex_color(&color).err())
+                .map(|e| format!("'mesh_color' {}, using mesh_r/g/b/a", e)),
+        )
+        .collect()
+}
+
+/// Look up a metadata entry by its string key.
+pub fn get_value<'a>(map: &'a MapOrUnknown, key: &str) -> Option<&'a SPValue> {
+    match map {
+        MapOrUnknown::Map(entries) => entries.iter().find_map(|(k, v)| match k {
+            SPValue::String(StringOrUnknown::String(name)) if name == key => Some(v),
+            _ => None,
+        }),
+        MapOrUnknown::UNKNOWN => None,
+    }
+}
+
+/// Numeric metadata entry, accepting both float and integer values.
+pub fn get_f64(map: &MapOrUnknown, key: &str) -> Option<f64> {
+    match get_value(map, key)? {
+        SPValue::Float64(FloatOrUnknown::Float64(value)) => Some(value.into_inner()),
+        SPValue::Int64(IntOrUnknown::Int64(value)) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+pub fn get_bool(map: &MapOrUnknown, key: &str) -> Option<bool> {
+    match get_value(map, key)? {
+        SPValue::Bool(BoolOrUnknown::Bool(value)) => Some(*value),
+        _ => None,
+    }
+}
+
+pub fn get_string(map: &MapOrUnknown, key: &str) -> Option<String> {
+    match get_value(map, key)? {
+        SPValue::String(StringOrUnknown::String(value)) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protobuf::FrameFormat;
+    use crate::redis_reads::parse_frame;
+    use ordered_float::OrderedFloat;
+
+    fn map(entries: &[(&str, SPValue)]) -> MapOrUnknown {
+        let entries = entries
+            .iter()
+            .map(|(key, value)| {
+                (
+                    SPValue::String(StringOrUnknown::String(key.to_string())),
+                    value.clone(),
+                )
+            })
+            .collect();
+        MapOrUnknown::Map(entries)
+    }
+
+    fn number(value: f64) -> SPValue {
+        SPValue::Float64(FloatOrUnknown::Float64(OrderedFloat(value)))
+    }
+
+    fn string(value: &str) -> SPValue {
+        SPValue::String(StringOrUnknown::String(value.to_string()))
+    }
+
+    fn boolean(value: bool) -> SPValue {
+        SPValue::Bool(BoolOrUnknown::Bool(value))
+    }
+
+    #[test]
+    fn missing_metadata_decodes_to_the_defaults() {
+        let metadata = decode_metadata(&MapOrUnknown::UNKNOWN);
+        assert_eq!(decode_metadata(&map(&[])), metadata);
+        assert_eq!(metadata.render_priority, 0);
+        assert_eq!(metadata.scale_range, [0.5, 2.0]);
+        assert_eq!(
+            [metadata.zone_r, metadata.zone_g, metadata.zone_b],
+            [0.0, 1.0, 0.0]
+        );
+        assert_eq!(metadata.zone_a, 0.15);
+        assert_eq!(metadata.axis_length, 0.1);
+        assert!(metadata.layers.is_empty());
+        assert_eq!(metadata.show_label, None);
+        assert_eq!(metadata.frame_locked, None);
+        assert!(!metadata.mirrored_from_tf);
+        assert!(decode_problems(&MapOrUnknown::UNKNOWN).is_empty());
+    }
+
+    #[test]
+    fn full_metadata_is_decoded() {
+        let stored = map(&[
+            ("mesh_color", string("#FF000080")),
+            ("zone", number(0.4)),
+            ("zone_is_radius", boolean(true)),
+            ("zone_shape", string("box")),
+            ("render_priority", SPValue::Int64(IntOrUnknown::Int64(3))),
+            ("layers", string("robots, , cell_1")),
+            ("show_label", boolean(false)),
+            ("frame_locked", boolean(false)),
+            ("lifetime_secs", number(2.5)),
+            ("last_updated", number(1760486400.25)),
+        ]);
+        let metadata = decode_metadata(&stored);
+        assert_eq!(metadata.mesh_r, 1.0);
+        assert_eq!(metadata.mesh_a, 128.0 / 255.0);
+        assert_eq!(metadata.zone, 0.8);
+        assert_eq!(metadata.zone_shape.as_deref(), Some("box"));
+        assert_eq!(metadata.render_priority, 3);
+        assert_eq!(metadata.layers, ["robots", "cell_1"]);
+        assert_eq!(metadata.show_label, Some(false));
+        assert_eq!(metadata.frame_locked, Some(false));
+        assert_eq!(metadata.lifetime_secs, Some(2.5));
+        assert_eq!(metadata.last_updated, Some(1760486400.25));
+        assert!(decode_problems(&stored).is_empty());
+    }
+
+    #[test]
+    fn partial_metadata_keeps_the_other_defaults() {
+        let metadata = decode_metadata(&map(&[("zone_r", number(1.0)), ("zone_x", number(0.3))]));
+        assert_eq!(
+            [metadata.zone_r, metadata.zone_g, metadata.zone_a],
+            [1.0, 1.0, 0.15]
+        );
+        assert_eq!(metadata.zone_x, Some(0.3));
+        assert_eq!(metadata.zone_y, None);
+        assert_eq!(metadata.scale_scalar_range, [0.0, 1.0]);
+    }
+
+    #[test]
+    fn values_of_the_wrong_type_fall_back_and_are_reported() {
+        let stored = map(&[
+            ("zone_a", string("opaque")),
+            ("show_label", number(1.0)),
+            ("mesh_color", string("red")),
+            ("lifetime_secs", number(-1.0)),
+        ]);
+        let metadata = decode_metadata(&stored);
+        assert_eq!(metadata.zone_a, 0.15);
+        assert_eq!(metadata.show_label, None);
+        assert_eq!(metadata.lifetime_secs, None);
+        assert_eq!(decode_problems(&stored).len(), 3);
+    }
+
+    #[test]
+    fn garbage_frames_are_errors() {
+        let garbage = b"\xff\x00{not json";
+        assert!(parse_frame("broken", garbage, FrameFormat::Json).is_err());
+        assert!(parse_frame("broken", b"{\"metadata\": 1}", FrameFormat::Json).is_err());
+    }
+
+    #[test]
+    fn hex_colors_without_alpha_are_opaque() {
+        assert_eq!(
+            parse_hex_color("#FF8000"),
+            Ok([1.0, 128.0 / 255.0, 0.0, 1.0])
+        );
+        assert_eq!(
+            parse_hex_color(" #ff8000 "),
+            Ok([1.0, 128.0 / 255.0, 0.0, 1.0])
+        );
+    }
+
+    #[test]
+    fn hex_colors_take_an_alpha() {
+        assert_eq!(
+            parse_hex_color("#00FF0080"),
+            Ok([0.0, 1.0, 0.0, 128.0 / 255.0])
+        );
+    }
+
+    #[test]
+    fn malformed_hex_colors_are_errors() {
+        for color in ["FF8000", "#FF80", "#FF8000F", "#GG8000", "", "#"] {
+            assert!(parse_hex_color(color).is_err(), "{}", color);
+        }
+    }
+}
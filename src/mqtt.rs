@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use tokio::sync::watch;
+
+use crate::log_targets;
+use crate::NODE_ID;
+
+/// Mirror the JSON scene summary to an MQTT topic whenever it changes.
+pub fn spawn_mqtt_bridge(
+    host: String,
+    port: u16,
+    topic: String,
+    mut scene: watch::Receiver<String>,
+) {
+    let mut options = MqttOptions::new(NODE_ID, host, port);
+    options.set_keep_alive(Duration::from_secs(5));
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+
+    tokio::task::spawn(async move {
+        loop {
+            if let Err(e) = eventloop.poll().await {
+                log::error!(target: log_targets::NODE,
+                    "MQTT connection failed with: {}", e
+                );
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    });
+
+    tokio::task::spawn(async move {
+        while scene.changed().await.is_ok() {
+            let payload = scene.borrow_and_update().clone();
+            if let Err(e) = client
+                .publish(topic.as_str(), QoS::AtLeastOnce, false, payload.into_bytes())
+                .await
+            {
+                log::error!(target: log_targets::NODE,
+                    "Failed to mirror the scene to MQTT with: {}", e
+                );
+            }
+        }
+    });
+}
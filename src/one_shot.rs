@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+/// Publishes of the initial burst, a second apart, so subscribers that are
+/// still discovering the node get the scene too.
+const BURST_PUBLISHES: u32 = 3;
+const BURST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When a task publishes under ONE_SHOT: a short burst, then once every
+/// `idle` for late subscribers, or never again without it.
+#[derive(Debug, Clone)]
+pub struct OneShot {
+    idle: Option<Duration>,
+    publishes: u32,
+    last: Option<Instant>,
+}
+
+impl OneShot {
+    /// An `idle_secs` of 0 stops publishing after the burst.
+    pub fn new(idle_secs: f64) -> OneShot {
+        OneShot {
+            idle: (idle_secs > 0.0).then(|| Duration::from_secs_f64(idle_secs)),
+            publishes: 0,
+            last: None,
+        }
+    }
+
+    /// Whether to publish at `now`, which is then recorded as a publish.
+    pub fn due(&mut self, now: Instant) -> bool {
+        let wait = if self.publishes < BURST_PUBLISHES {
+            BURST_INTERVAL
+        } else {
+            match self.idle {
+                Some(idle) => idle,
+                None => return false,
+            }
+        };
+        if matches!(self.last, Some(last) if now.duration_since(last) < wait) {
+            return false;
+        }
+        self.publishes += 1;
+        self.last = Some(now);
+        true
+    }
+
+    /// Whether the burst is over, after which `--exit-after-publish` stops.
+    pub fn burst_done(&self) -> bool {
+        self.publishes >= BURST_PUBLISHES
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The ticks `one_shot` publishes on, ticking every 100ms for `secs`.
+    fn published_ticks(one_shot: &mut OneShot, secs: u64) -> Vec<u64> {
+        let start = Instant::now();
+        (0..secs * 10)
+            .filter(|tick| one_shot.due(start + Duration::from_millis(tick * 100)))
+            .collect()
+    }
+
+    #[test]
+    fn the_burst_publishes_and_then_stops() {
+        let mut one_shot = OneShot::new(0.0);
+        assert!(!one_shot.burst_done());
+        assert_eq!(published_ticks(&mut one_shot, 60), vec![0, 10, 20]);
+        assert!(one_shot.burst_done());
+    }
+
+    #[test]
+    fn the_burst_is_followed_by_rare_republishes() {
+        let mut one_shot = OneShot::new(5.0);
+        assert_eq!(published_ticks(&mut one_shot, 13), vec![0, 10, 20, 70, 120]);
+    }
+}
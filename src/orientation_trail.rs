@@ -0,0 +1,70 @@
+use micro_sp::*;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::geometry::{pose_in_root, Isometry};
+use crate::mesh_orientation::wildcard_match;
+
+/// Bounded history of the poses of the ORIENTATION_TRAIL_FRAMES, sampled every
+/// `interval`, for drawing where and how a frame was rotated recently.
+#[derive(Debug, Clone)]
+pub struct OrientationTrails {
+    /// Frame name patterns, `*` matches anything.
+    frames: Vec<String>,
+    samples: usize,
+    interval: Duration,
+    last_sample: Option<Instant>,
+    /// Oldest first, with the root frame each pose is expressed in.
+    history: HashMap<String, VecDeque<(String, Isometry)>>,
+}
+
+impl OrientationTrails {
+    /// None without frames or samples, the trail is then off.
+    pub fn new(frames: &[String], samples: usize, interval_secs: f64) -> Option<OrientationTrails> {
+        if frames.is_empty() || samples == 0 {
+            return None;
+        }
+        Some(OrientationTrails {
+            frames: frames.to_vec(),
+            samples,
+            interval: Duration::from_secs_f64(interval_secs.max(0.0)),
+            last_sample: None,
+            history: HashMap::new(),
+        })
+    }
+
+    /// Sample the poses of the trail frames when an interval has passed since
+    /// the last sample, and forget frames that are gone.
+    pub fn record(&mut self, frames: &HashMap<String, SPTransformStamped>, now: Instant) {
+        self.history.retain(|frame, _| frames.contains_key(frame));
+        let due = match self.last_sample {
+            Some(last) => now.duration_since(last) >= self.interval,
+            None => true,
+        };
+        if !due {
+            return;
+        }
+        self.last_sample = Some(now);
+        let trail_frames = frames.keys().filter(|frame| {
+            self.frames
+                .iter()
+                .any(|pattern| wildcard_match(pattern, frame))
+        });
+        for frame in trail_frames {
+            if let Some(sample) = pose_in_root(frames, frame) {
+                let history = self.history.entry(frame.clone()).or_default();
+                history.push_back(sample);
+                while history.len() > self.samples {
+                    history.pop_front();
+                }
+            }
+        }
+    }
+
+    /// The sampled poses of each trail frame, oldest first.
+    pub fn trails(&self) -> impl Iterator<Item = (&str, Vec<(String, Isometry)>)> {
+        self.history
+            .iter()
+            .map(|(frame, history)| (frame.as_str(), history.iter().cloned().collect()))
+    }
+}
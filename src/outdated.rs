@@ -0,0 +1,55 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::metadata::Metadata;
+
+/// What happens to a frame whose `last_updated` is older than
+/// MAX_TRANSFORM_AGE_SECS.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutdatedAction {
+    /// Leave it out of TF, so listeners notice it is gone.
+    Skip,
+    /// Keep broadcasting it and turn its mesh markers towards red.
+    Tint,
+}
+
+impl FromStr for OutdatedAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(OutdatedAction::Skip),
+            "tint" => Ok(OutdatedAction::Tint),
+            _ => Err(format!("unknown outdated transform action '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for OutdatedAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutdatedAction::Skip => write!(f, "skip"),
+            OutdatedAction::Tint => write!(f, "tint"),
+        }
+    }
+}
+
+/// Whether a frame last updated at `last_updated`, in seconds since the
+/// epoch, is more than `max_age_secs` old at `now_secs`. Frames without the
+/// stamp never are, and neither is anything with a `max_age_secs` of 0.
+pub fn is_outdated(last_updated: Option<f64>, now_secs: f64, max_age_secs: f64) -> bool {
+    match last_updated {
+        Some(last_updated) if max_age_secs > 0.0 => now_secs - last_updated > max_age_secs,
+        _ => false,
+    }
+}
+
+/// Halfway from the frame's mesh color to red, opaque if it was invisible.
+pub fn tint_outdated(metadata: &mut Metadata) {
+    metadata.mesh_r = (metadata.mesh_r + 1.0) / 2.0;
+    metadata.mesh_g /= 2.0;
+    metadata.mesh_b /= 2.0;
+    if metadata.mesh_a == 0.0 {
+        metadata.mesh_a = 1.0;
+    }
+}
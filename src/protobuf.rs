@@ -0,0 +1,175 @@
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::str::FromStr;
+
+use micro_sp::*;
+
+/// How frames are serialized in Redis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameFormat {
+    /// The JSON micro_sp writes, the default.
+    Json,
+    /// The `Frame` message of `proto/frame.proto`.
+    Protobuf,
+}
+
+impl FromStr for FrameFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(FrameFormat::Json),
+            "protobuf" => Ok(FrameFormat::Protobuf),
+            _ => Err(format!("unknown frame format '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for FrameFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameFormat::Json => write!(f, "json"),
+            FrameFormat::Protobuf => write!(f, "protobuf"),
+        }
+    }
+}
+
+/// A field of a protobuf message, only the wire types the schema uses.
+enum Field<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Split a protobuf message into (field number, value) pairs.
+fn fields(mut buf: &[u8]) -> Result<Vec<(u64, Field)>, String> {
+    let mut fields = vec![];
+    while !buf.is_empty() {
+        let key = varint(&mut buf)?;
+        let field = match key & 0x7 {
+            0 => Field::Varint(varint(&mut buf)?),
+            1 => {
+                let (bytes, rest) = split(buf, 8)?;
+                buf = rest;
+                Field::Fixed64(u64::from_le_bytes(
+                    bytes.try_into().map_err(|_| "truncated")?,
+                ))
+            }
+            2 => {
+                let len = varint(&mut buf)? as usize;
+                let (bytes, rest) = split(buf, len)?;
+                buf = rest;
+                Field::Bytes(bytes)
+            }
+            5 => {
+                // fixed32, not in the schema but skipped like any unknown field
+                buf = split(buf, 4)?.1;
+                continue;
+            }
+            wire_type => return Err(format!("unsupported wire type {}", wire_type)),
+        };
+        fields.push((key >> 3, field));
+    }
+    Ok(fields)
+}
+
+fn split(buf: &[u8], len: usize) -> Result<(&[u8], &[u8]), String> {
+    if buf.len() < len {
+        return Err("truncated message".to_string());
+    }
+    Ok(buf.split_at(len))
+}
+
+fn varint(buf: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first().ok_or("truncated varint")?;
+        *buf = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("varint longer than 64 bits".to_string())
+}
+
+fn string(bytes: &[u8]) -> Result<String, String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| "string field is not UTF-8".to_string())
+}
+
+/// Doubles of fields `1..=names.len()`, named by `names`, 0 when absent.
+fn doubles(buf: &[u8], names: &[&str], defaults: &[f64]) -> Result<Value, String> {
+    let mut values = defaults.to_vec();
+    for (number, field) in fields(buf)? {
+        let value = (number as usize).checked_sub(1).and_then(|i| values.get_mut(i));
+        if let (Field::Fixed64(bits), Some(value)) = (field, value) {
+            *value = f64::from_bits(bits);
+        }
+    }
+    Ok(Value::Object(
+        names
+            .iter()
+            .map(|name| name.to_string())
+            .zip(values.into_iter().map(Value::from))
+            .collect(),
+    ))
+}
+
+fn time_stamp_now() -> Value {
+    let since = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    json!({"secs_since_epoch": since.as_secs(), "nanos_since_epoch": since.subsec_nanos()})
+}
+
+/// Decode a `Frame` message. It is mapped onto the JSON layout of a frame and
+/// deserialized from there, so the result is the same as for a JSON frame.
+pub fn decode_protobuf_frame(buf: &[u8]) -> Result<SPTransformStamped, String> {
+    let mut frame = Map::new();
+    let mut translation = json!({"x": 0.0, "y": 0.0, "z": 0.0});
+    // an absent rotation is the identity
+    let mut rotation = json!({"x": 0.0, "y": 0.0, "z": 0.0, "w": 1.0});
+    frame.insert("active_transform".to_string(), Value::Bool(false));
+    frame.insert("metadata".to_string(), json!("UNKNOWN"));
+    // not carried by the message, given the values of a freshly written frame,
+    // serde ignores them should micro_sp not have them
+    frame.insert("enable_transform".to_string(), Value::Bool(true));
+    frame.insert("time_stamp".to_string(), time_stamp_now());
+    for (number, field) in fields(buf)? {
+        match (number, field) {
+            (1, Field::Bytes(bytes)) => {
+                frame.insert("parent_frame_id".to_string(), Value::String(string(bytes)?));
+            }
+            (2, Field::Bytes(bytes)) => {
+                frame.insert("child_frame_id".to_string(), Value::String(string(bytes)?));
+            }
+            (3, Field::Bytes(bytes)) => {
+                for (number, field) in fields(bytes)? {
+                    match (number, field) {
+                        (1, Field::Bytes(bytes)) => {
+                            translation = doubles(bytes, &["x", "y", "z"], &[0.0; 3])?
+                        }
+                        (2, Field::Bytes(bytes)) => {
+                            rotation = doubles(bytes, &["x", "y", "z", "w"], &[0.0; 4])?
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            (4, Field::Varint(value)) => {
+                frame.insert("active_transform".to_string(), Value::Bool(value != 0));
+            }
+            (5, Field::Bytes(bytes)) => {
+                let metadata = serde_json::from_slice(bytes)
+                    .map_err(|e| format!("metadata_json is not JSON: {}", e))?;
+                frame.insert("metadata".to_string(), metadata);
+            }
+            _ => (),
+        }
+    }
+    frame.insert(
+        "transform".to_string(),
+        json!({"translation": translation, "rotation": rotation}),
+    );
+    serde_json::from_value(Value::Object(frame)).map_err(|e| e.to_string())
+}
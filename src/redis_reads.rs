@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use micro_sp::*;
+use redis::aio::MultiplexedConnection;
+use serde_json::json;
+
+use crate::error::VizError;
+use crate::geometry::rpy_to_quaternion;
+use crate::log_targets;
+use crate::protobuf::{decode_protobuf_frame, FrameFormat};
+use crate::throttle::WarningThrottle;
+
+/// Keys SCAN is asked to look at per call, a hint Redis may exceed.
+const SCAN_COUNT: usize = 1000;
+
+/// Names of all frames stored under `key_prefix`. Uses SCAN rather than KEYS,
+/// which blocks Redis for every other client while it walks the keyspace.
+pub async fn frame_names(
+    con: &mut MultiplexedConnection,
+    key_prefix: &str,
+) -> Result<Vec<String>, VizError> {
+    let pattern = format!("{}*", key_prefix);
+    let mut names = vec![];
+    let mut cursor: u64 = 0;
+    loop {
+        let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(&pattern)
+            .arg("COUNT")
+            .arg(SCAN_COUNT)
+            .query_async(con)
+            .await?;
+        names.extend(
+            keys.into_iter()
+                .filter_map(|key| key.strip_prefix(key_prefix).map(|name| name.to_string())),
+        );
+        cursor = next;
+        if cursor == 0 {
+            break;
+        }
+    }
+    // SCAN may return a key more than once
+    names.sort_unstable();
+    names.dedup();
+    Ok(names)
+}
+
+/// Decode a frame in micro_sp's JSON layout, which may give its rotation as
+/// `transform.rpy`, `[roll, pitch, yaw]` in radians, instead of a quaternion.
+/// The second value is true when an `rpy` was ignored for the frame's
+/// `rotation`, which wins when both are there.
+pub fn frame_from_json(
+    mut value: serde_json::Value,
+) -> Result<(SPTransformStamped, bool), serde_json::Error> {
+    let mut ignored_rpy = false;
+    let transform = value.get_mut("transform").and_then(|t| t.as_object_mut());
+    if let Some(transform) = transform {
+        if let Some(rpy) = transform.remove("rpy") {
+            if transform.contains_key("rotation") {
+                ignored_rpy = true;
+            } else {
+                let [roll, pitch, yaw]: [f64; 3] = serde_json::from_value(rpy)?;
+                let q = rpy_to_quaternion(roll, pitch, yaw);
+                let rotation = json!({"x": q.x, "y": q.y, "z": q.z, "w": q.w});
+                transform.insert("rotation".to_string(), rotation);
+            }
+        }
+    }
+    Ok((serde_json::from_value(value)?, ignored_rpy))
+}
+
+/// Decode a stored frame of `format`, along with whether its `rpy` was
+/// ignored, see `frame_from_json`.
+pub fn parse_frame(
+    name: &str,
+    value: &[u8],
+    format: FrameFormat,
+) -> Result<(SPTransformStamped, bool), VizError> {
+    let decoded = match format {
+        FrameFormat::Json => serde_json::from_slice(value)
+            .and_then(frame_from_json)
+            .map_err(|e| e.to_string()),
+        FrameFormat::Protobuf => decode_protobuf_frame(value).map(|frame| (frame, false)),
+    };
+    decoded.map_err(|e| VizError::Decode(format!("the stored frame '{}': {}", name, e)))
+}
+
+/// Decode a stored frame, None (with a warning) when it isn't a valid frame.
+pub fn decode_frame(
+    name: &str,
+    value: &[u8],
+    format: FrameFormat,
+    warnings: &mut WarningThrottle,
+) -> Option<SPTransformStamped> {
+    match parse_frame(name, value, format) {
+        Ok((frame, ignored_rpy)) => {
+            if ignored_rpy && warnings.allow(name, "rpy with a rotation", Instant::now()) {
+                log::warn!(target: log_targets::REDIS,
+                    "The stored frame '{}' has both a rotation and rpy, using the rotation.", name
+                );
+            }
+            Some(frame)
+        }
+        Err(e) => {
+            if warnings.allow(name, "undecodable frames", Instant::now()) {
+                log::warn!(target: log_targets::REDIS, "Failed to {}", e);
+            }
+            None
+        }
+    }
+}
+
+/// Read all frames stored under `key_prefix` in `format`, which the micro_sp
+/// reads can't do for formats other than JSON. With `atomic` the frames are
+/// read inside a single MULTI/EXEC transaction, so the tick sees a coherent
+/// snapshot even while other processes write frames.
+///
+/// Otherwise the frames come in a single MGET, one round trip however many
+/// frames there are.
+///
+/// Listing the keys happens before the transaction, so a frame added in
+/// between shows up one tick later. The transaction blocks other clients
+/// while it runs, which gets noticeable with thousands of frames.
+pub async fn read_frames(
+    con: &mut MultiplexedConnection,
+    key_prefix: &str,
+    format: FrameFormat,
+    atomic: bool,
+    warnings: &mut WarningThrottle,
+) -> Result<HashMap<String, SPTransformStamped>, VizError> {
+    let names = frame_names(con, key_prefix).await?;
+    if names.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let keys: Vec<String> = names.iter().map(|name| format!("{}{}", key_prefix, name)).collect();
+    let values: Vec<Option<Vec<u8>>> = if atomic {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for key in &keys {
+            pipe.get(key);
+        }
+        pipe.query_async(con).await?
+    } else {
+        redis::cmd("MGET").arg(&keys).query_async(con).await?
+    };
+    Ok(names
+        .into_iter()
+        .zip(values)
+        .filter_map(|(name, value)| {
+            let frame = decode_frame(&name, &value?, format, warnings)?;
+            Some((name, frame))
+        })
+        .collect())
+}
@@ -0,0 +1,76 @@
+use std::collections::{HashMap, HashSet};
+
+use micro_sp::*;
+
+use crate::log_targets;
+
+/// Renames frames at publish time, applied to both the parent and the child of
+/// every edge so the published tree stays connected.
+#[derive(Debug, Clone, Default)]
+pub struct FrameRenames {
+    map: HashMap<String, String>,
+    warned: HashSet<String>,
+}
+
+impl FrameRenames {
+    /// Parse `old=new` entries, malformed ones are logged and skipped.
+    pub fn parse(entries: &[String]) -> FrameRenames {
+        let mut map: HashMap<String, String> = HashMap::new();
+        for entry in entries {
+            match entry.split_once('=') {
+                Some((old, new)) if !old.trim().is_empty() && !new.trim().is_empty() => {
+                    let (old, new) = (old.trim().to_string(), new.trim().to_string());
+                    if let Some(other) = map.iter().find(|(_, n)| **n == new).map(|(o, _)| o) {
+                        log::warn!(target: log_targets::TF,
+                            "Frames '{}' and '{}' are both renamed to '{}'.", other, old, new
+                        );
+                    }
+                    map.insert(old, new);
+                }
+                _ => log::warn!(target: log_targets::TF,
+                    "Ignoring malformed frame rename '{}', expected old=new.", entry
+                ),
+            }
+        }
+        FrameRenames {
+            map,
+            warned: HashSet::new(),
+        }
+    }
+
+    pub fn rename<'a>(&'a self, name: &'a str) -> &'a str {
+        self.map.get(name).map(|n| n.as_str()).unwrap_or(name)
+    }
+
+    /// Apply the renames to a frame set, keyed by the new child names. A rename
+    /// onto the name of another frame is a collision, logged once, and the
+    /// renamed frame replaces the other one.
+    pub fn apply(
+        &mut self,
+        frames: HashMap<String, SPTransformStamped>,
+    ) -> HashMap<String, SPTransformStamped> {
+        if self.map.is_empty() {
+            return frames;
+        }
+        let names: HashSet<String> = frames.values().map(|f| f.child_frame_id.clone()).collect();
+        for (old, new) in &self.map {
+            if names.contains(old)
+                && names.contains(new)
+                && !self.map.contains_key(new)
+                && self.warned.insert(old.clone())
+            {
+                log::warn!(target: log_targets::TF,
+                    "Renaming '{}' to '{}' collides with an existing frame.", old, new
+                );
+            }
+        }
+        frames
+            .into_values()
+            .map(|mut frame| {
+                frame.child_frame_id = self.rename(&frame.child_frame_id).to_string();
+                frame.parent_frame_id = self.rename(&frame.parent_frame_id).to_string();
+                (frame.child_frame_id.clone(), frame)
+            })
+            .collect()
+    }
+}
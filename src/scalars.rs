@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+use redis::aio::MultiplexedConnection;
+
+use crate::log_targets;
+
+/// Current float values of the live Redis scalar keys, keys that are missing
+/// or don't hold a number are left out. Read failures are logged and read nothing.
+pub async fn fetch_scalars(con: &mut MultiplexedConnection, keys: &[String]) -> HashMap<String, f64> {
+    if keys.is_empty() {
+        return HashMap::new();
+    }
+    match redis::cmd("MGET")
+        .arg(keys)
+        .query_async::<Vec<Option<String>>>(con)
+        .await
+    {
+        Ok(values) => keys
+            .iter()
+            .cloned()
+            .zip(values)
+            .filter_map(|(key, value)| Some((key, value?.trim().parse::<f64>().ok()?)))
+            .collect(),
+        Err(e) => {
+            log::error!(target: log_targets::REDIS,
+                "Failed to read the scalar keys with: {}", e
+            );
+            HashMap::new()
+        }
+    }
+}
+
+/// Map `value` linearly from `range` onto `target`, clamped to the ends of
+/// `target`. An empty range maps everything onto its start.
+pub fn map_scalar(value: f64, range: [f64; 2], target: [f64; 2]) -> f64 {
+    let span = range[1] - range[0];
+    let t = if span == 0.0 {
+        0.0
+    } else {
+        ((value - range[0]) / span).clamp(0.0, 1.0)
+    };
+    target[0] + t * (target[1] - target[0])
+}
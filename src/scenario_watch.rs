@@ -0,0 +1,179 @@
+use futures::{Stream, StreamExt};
+use notify::{RecursiveMode, Watcher};
+use r2r::std_srvs::srv::Trigger;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::error::VizError;
+use crate::loader::{ScenarioDiff, ScenarioReloader};
+use crate::log_targets;
+
+/// Quiet time after the last change in the scenario directory before it is
+/// reloaded, so a file is reloaded once its writes are done.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Reload the scenario into Redis whenever a `.json` file of SCENARIO_DIR is
+/// written, created, renamed or deleted, once the directories have been
+/// quiet for DEBOUNCE. Returns when a directory can't be watched.
+pub async fn watch_scenario(reloader: ScenarioReloader) {
+    let (sender, mut changes) = tokio::sync::mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        match event {
+            Ok(event) => {
+                let json = event
+                    .paths
+                    .iter()
+                    .any(|path| path.extension().and_then(|ext| ext.to_str()) == Some("json"));
+                if json && !event.kind.is_access() {
+                    let _ = sender.send(());
+                }
+            }
+            Err(e) => log::warn!(target: log_targets::SERVICES,
+                "Watching the scenario directory failed with: {}", e
+            ),
+        }
+    });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            log::error!(target: log_targets::SERVICES,
+                "WATCH_SCENARIO is unavailable: {}", e
+            );
+            return;
+        }
+    };
+    for dir in reloader.scenario_dirs() {
+        if let Err(e) = watcher.watch(Path::new(dir), RecursiveMode::NonRecursive) {
+            log::error!(target: log_targets::SERVICES,
+                "Failed to watch {} with: {}", dir, e
+            );
+            return;
+        }
+    }
+    let dir = reloader.scenario_dirs().join(":");
+    log::info!(target: log_targets::SERVICES,
+        "Reloading the scenario when {} changes.", dir
+    );
+    while settled(&mut changes, DEBOUNCE).await {
+        match reloader.reload().await {
+            Ok(diff) => log::info!(target: log_targets::SERVICES,
+                "Reloaded the scenario from {}: {}.", dir, diff
+            ),
+            // retried with the next change
+            Err(VizError::Decode(e)) => log::warn!(target: log_targets::SERVICES,
+                "Not reloading the scenario while a file doesn't decode: {}", e
+            ),
+            Err(e) => log::error!(target: log_targets::SERVICES,
+                "Failed to reload the scenario with: {}", e
+            ),
+        }
+    }
+}
+
+/// Wait for the next change and then for `debounce` without another one, so
+/// a burst of changes makes one reload. False once the watcher is gone.
+async fn settled(changes: &mut UnboundedReceiver<()>, debounce: Duration) -> bool {
+    if changes.recv().await.is_none() {
+        return false;
+    }
+    loop {
+        match tokio::time::timeout(debounce, changes.recv()).await {
+            Ok(Some(())) => continue,
+            Ok(None) => return false,
+            Err(_) => return true,
+        }
+    }
+}
+
+/// The `reload_scenario` response to a reload, with the counts or the error
+/// in `message`.
+pub fn reload_response(result: &Result<ScenarioDiff, VizError>) -> Trigger::Response {
+    match result {
+        Ok(diff) => Trigger::Response {
+            success: true,
+            message: format!("Reloaded the scenario: {}.", diff),
+        },
+        Err(e) => Trigger::Response {
+            success: false,
+            message: format!("Failed to reload the scenario: {}.", e),
+        },
+    }
+}
+
+/// Serve `reload_scenario`, which stores SCENARIO_DIR in Redis again like
+/// WATCH_SCENARIO does on a change.
+pub async fn serve_reload_scenario(
+    mut requests: impl Stream<Item = r2r::ServiceRequest<Trigger::Service>> + Unpin,
+    reloader: ScenarioReloader,
+) {
+    while let Some(request) = requests.next().await {
+        let result = reloader.reload().await;
+        if let Ok(diff) = &result {
+            log::info!(target: log_targets::SERVICES,
+                "Reloaded the scenario from {} on request: {}.",
+                reloader.scenario_dirs().join(":"),
+                diff
+            );
+        }
+        if let Err(e) = request.respond(reload_response(&result)) {
+            log::error!(target: log_targets::SERVICES,
+                "Failed to respond to a reload_scenario request with: '{}'", e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_burst_of_changes_reloads_once_after_the_debounce() {
+        let (sender, mut changes) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            for _ in 0..3 {
+                sender.send(()).unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            sender.send(()).unwrap();
+            tokio::time::sleep(Duration::from_millis(300)).await;
+        });
+        let debounce = Duration::from_millis(100);
+        let start = std::time::Instant::now();
+        assert!(settled(&mut changes, debounce).await);
+        // the burst is drained, and settles only after its last change
+        assert!(changes.try_recv().is_err());
+        assert!(start.elapsed() >= Duration::from_millis(120));
+        let mut reloads = 0;
+        while settled(&mut changes, debounce).await {
+            reloads += 1;
+        }
+        assert_eq!(reloads, 1);
+    }
+
+    #[test]
+    fn reload_responses_carry_the_counts_or_the_error() {
+        let diff = ScenarioDiff {
+            added: vec!["cup".to_string()],
+            updated: vec![],
+            removed: vec!["plate".to_string(), "tray".to_string()],
+        };
+        let response = reload_response(&Ok(diff));
+        assert!(response.success);
+        assert_eq!(
+            response.message,
+            "Reloaded the scenario: 1 added, 0 updated, 2 removed."
+        );
+        let failed = Err(VizError::Decode(
+            "scenario/table.json: bad JSON".to_string(),
+        ));
+        let response = reload_response(&failed);
+        assert!(!response.success);
+        assert_eq!(
+            response.message,
+            "Failed to reload the scenario: failed to decode scenario/table.json: bad JSON."
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+use micro_sp::*;
+use serde::Serialize;
+
+use crate::geometry::Isometry;
+use crate::metadata::decode_metadata;
+
+/// Compact, serializable view of one frame, shared by the scene exporters.
+/// The field names are what the exported JSON uses, renaming one breaks its
+/// consumers.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrameSummary {
+    pub child_frame_id: String,
+    pub parent_frame_id: String,
+    pub translation: [f64; 3],
+    /// `[x, y, z, w]`
+    pub rotation: [f64; 4],
+    pub active: bool,
+    pub metadata: SummaryMetadata,
+}
+
+/// The metadata dashboards need to draw a frame without decoding micro_sp's.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SummaryMetadata {
+    pub visualize_mesh: bool,
+    pub mesh_file: Option<String>,
+    pub visualize_zone: bool,
+    /// Zone diameter in meters.
+    pub zone: f64,
+    pub assembly: Option<String>,
+    pub layers: Vec<String>,
+}
+
+pub fn frame_summary(frame: &SPTransformStamped) -> FrameSummary {
+    let pose = Isometry::from_frame(frame);
+    let metadata = decode_metadata(&frame.metadata);
+    FrameSummary {
+        child_frame_id: frame.child_frame_id.clone(),
+        parent_frame_id: frame.parent_frame_id.clone(),
+        translation: pose.translation,
+        rotation: pose.rotation,
+        active: frame.active_transform,
+        metadata: SummaryMetadata {
+            visualize_mesh: metadata.visualize_mesh,
+            mesh_file: metadata.mesh_file,
+            visualize_zone: metadata.visualize_zone,
+            zone: metadata.zone,
+            assembly: metadata.assembly,
+            layers: metadata.layers,
+        },
+    }
+}
+
+/// Summaries of all frames, sorted by child frame so equal scenes serialize equally.
+pub fn scene_summary(frames: &HashMap<String, SPTransformStamped>) -> Vec<FrameSummary> {
+    let mut summary: Vec<FrameSummary> = frames.values().map(frame_summary).collect();
+    summary.sort_by(|a, b| a.child_frame_id.cmp(&b.child_frame_id));
+    summary
+}
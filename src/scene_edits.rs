@@ -0,0 +1,512 @@
+use futures::{Stream, StreamExt};
+use r2r::geometry_msgs::msg::Transform;
+use r2r::scene_manipulation_msgs::srv::ManipulateScene;
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use micro_sp::*;
+
+use crate::config::Config;
+use crate::geometry::{
+    quaternion_norm, rpy_to_quaternion, sanitize_quaternion, MIN_QUATERNION_NORM,
+};
+use crate::log_targets;
+use crate::metadata::get_bool;
+use crate::protobuf::FrameFormat;
+use crate::redis_reads::read_frames;
+use crate::tf_mirror::MIRRORED_KEY;
+use crate::throttle::WarningThrottle;
+
+/// A frame as written by micro_sp, built through its JSON layout like the
+/// protobuf frames are, without metadata.
+pub fn new_frame(
+    parent_frame_id: &str,
+    child_frame_id: &str,
+    transform: &Transform,
+    active: bool,
+) -> Result<SPTransformStamped, String> {
+    let norm = quaternion_norm(&transform.rotation);
+    if !(norm >= MIN_QUATERNION_NORM && norm.is_finite()) {
+        return Err(format!("the rotation has a norm of {}", norm));
+    }
+    let rotation = sanitize_quaternion(transform.rotation.clone());
+    let translation = &transform.translation;
+    let since = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    serde_json::from_value(json!({
+        "active_transform": active,
+        "enable_transform": true,
+        "time_stamp": {"secs_since_epoch": since.as_secs(), "nanos_since_epoch": since.subsec_nanos()},
+        "parent_frame_id": parent_frame_id,
+        "child_frame_id": child_frame_id,
+        "transform": {
+            "translation": {"x": translation.x, "y": translation.y, "z": translation.z},
+            "rotation": {"x": rotation.x, "y": rotation.y, "z": rotation.z, "w": rotation.w},
+        },
+        "metadata": "UNKNOWN",
+    }))
+    .map_err(|e| e.to_string())
+}
+
+/// Store a new frame under `key_prefix`, refusing to replace an existing one.
+pub async fn add_transform(
+    connection_manager: &ConnectionManager,
+    key_prefix: &str,
+    frame: &SPTransformStamped,
+) -> Result<(), String> {
+    let mut con = connection_manager.get_connection().await;
+    let key = format!("{}{}", key_prefix, frame.child_frame_id);
+    let value = serde_json::to_string(frame).map_err(|e| e.to_string())?;
+    // NX, so a frame written in between isn't overwritten either
+    let written: Option<String> = redis::cmd("SET")
+        .arg(&key)
+        .arg(value)
+        .arg("NX")
+        .query_async(&mut con)
+        .await
+        .map_err(|e| format!("failed to write '{}' with: {}", key, e))?;
+    match written {
+        Some(_) => Ok(()),
+        None => Err(format!("'{}' already exists", frame.child_frame_id)),
+    }
+}
+
+/// The frame an `add_transform` request asks for, which can't be its own
+/// parent. Its `command` holds whitespace separated options: `active`, and
+/// `rpy=roll,pitch,yaw` in radians for the rotation, ignored with a warning
+/// when `transformation` has a rotation too.
+fn requested_frame(message: &ManipulateScene::Request) -> Result<SPTransformStamped, String> {
+    if message.parent_frame_id == message.child_frame_id {
+        let child = &message.child_frame_id;
+        return Err(format!("'{}' can't be its own parent", child));
+    }
+    let mut active = false;
+    let mut rpy = None;
+    for option in message.command.split_whitespace() {
+        match option.split_once('=') {
+            None if option == "active" => active = true,
+            Some(("rpy", angles)) => {
+                let angles: Vec<f64> = angles
+                    .split(',')
+                    .map(|angle| angle.trim().parse::<f64>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| format!("malformed rpy '{}': {}", angles, e))?;
+                let [roll, pitch, yaw] = angles[..] else {
+                    return Err(format!("rpy needs three angles, got {}", angles.len()));
+                };
+                rpy = Some((roll, pitch, yaw));
+            }
+            // anything else, e.g. `static`, keeps meaning a static frame
+            _ => {}
+        }
+    }
+    let mut transform = message.transformation.clone();
+    if let Some((roll, pitch, yaw)) = rpy {
+        if quaternion_norm(&transform.rotation) >= MIN_QUATERNION_NORM {
+            log::warn!(target: log_targets::SERVICES,
+                "The request for '{}' has both a rotation and rpy, using the rotation.",
+                message.child_frame_id
+            );
+        } else {
+            transform.rotation = rpy_to_quaternion(roll, pitch, yaw);
+        }
+    }
+    new_frame(&message.parent_frame_id, &message.child_frame_id, &transform, active)
+}
+
+/// Serve `add_transform`, which stores the request's `child_frame_id` under
+/// `parent_frame_id` at `transformation`, see `requested_frame`. The frame
+/// shows up on the next tick.
+pub async fn serve_add_transform(
+    mut requests: impl Stream<Item = r2r::ServiceRequest<ManipulateScene::Service>> + Unpin,
+    connection_manager: Arc<ConnectionManager>,
+    config: Config,
+) {
+    while let Some(request) = requests.next().await {
+        let message = &request.message;
+        let result = if config.frame_format != FrameFormat::Json {
+            Err("frames can only be added with FRAME_FORMAT=json".to_string())
+        } else {
+            match requested_frame(message) {
+                Ok(frame) => {
+                    let prefix = &config.transform_key_prefix;
+                    add_transform(&connection_manager, prefix, &frame).await
+                }
+                Err(e) => Err(e),
+            }
+        };
+        let response = match result {
+            Ok(()) => {
+                log::info!(target: log_targets::SERVICES,
+                    "Added '{}' under '{}'.", message.child_frame_id, message.parent_frame_id
+                );
+                ManipulateScene::Response {
+                    success: true,
+                    info: format!("Added '{}'.", message.child_frame_id),
+                }
+            }
+            Err(e) => ManipulateScene::Response {
+                success: false,
+                info: format!("Failed to add '{}': {}.", message.child_frame_id, e),
+            },
+        };
+        if let Err(e) = request.respond(response) {
+            log::error!(target: log_targets::SERVICES,
+                "Failed to respond to an add_transform request with: '{}'", e
+            );
+        }
+    }
+}
+
+/// Delete a stored frame, refusing while other frames hang from it, which
+/// are listed in the error. Returns whether there was a frame to delete.
+pub async fn remove_transform(
+    connection_manager: &ConnectionManager,
+    config: &Config,
+    child_frame_id: &str,
+    warnings: &mut WarningThrottle,
+) -> Result<bool, String> {
+    let mut con = connection_manager.get_connection().await;
+    let frames = if config.frame_format != FrameFormat::Json {
+        let prefix = &config.transform_key_prefix;
+        read_frames(&mut con, prefix, config.frame_format, false, warnings)
+            .await
+            .map_err(|e| e.to_string())
+    } else {
+        TransformsManager::get_all_transforms(&mut con)
+            .await
+            .map_err(|e| e.to_string())
+    };
+    let frames = frames.map_err(|e| format!("failed to read the frames with: {}", e))?;
+    let mut children: Vec<&str> = frames
+        .values()
+        .filter(|frame| frame.parent_frame_id == child_frame_id)
+        .map(|frame| frame.child_frame_id.as_str())
+        .collect();
+    if !children.is_empty() {
+        children.sort();
+        return Err(format!(
+            "it is the parent of {}, remove or reparent them first",
+            children.join(", ")
+        ));
+    }
+    let key = format!("{}{}", config.transform_key_prefix, child_frame_id);
+    let removed: usize = redis::cmd("DEL")
+        .arg(&key)
+        .query_async(&mut con)
+        .await
+        .map_err(|e| format!("failed to delete '{}' with: {}", key, e))?;
+    Ok(removed > 0)
+}
+
+/// Serve `remove_transform`, which deletes the request's `child_frame_id`
+/// from Redis. Its markers are deleted on the next tick. `success` is false
+/// when there was no such frame.
+pub async fn serve_remove_transform(
+    mut requests: impl Stream<Item = r2r::ServiceRequest<ManipulateScene::Service>> + Unpin,
+    connection_manager: Arc<ConnectionManager>,
+    config: Config,
+) {
+    let mut warnings = WarningThrottle::new(config.warning_interval_secs);
+    while let Some(request) = requests.next().await {
+        let frame = request.message.child_frame_id.clone();
+        let result = remove_transform(&connection_manager, &config, &frame, &mut warnings).await;
+        let response = match result {
+            Ok(true) => {
+                log::info!(target: log_targets::SERVICES, "Removed '{}'.", frame);
+                ManipulateScene::Response {
+                    success: true,
+                    info: format!("Removed '{}'.", frame),
+                }
+            }
+            Ok(false) => ManipulateScene::Response {
+                success: false,
+                info: format!("There is no frame '{}'.", frame),
+            },
+            Err(e) => ManipulateScene::Response {
+                success: false,
+                info: format!("Failed to remove '{}': {}.", frame, e),
+            },
+        };
+        if let Err(e) = request.respond(response) {
+            log::error!(target: log_targets::SERVICES,
+                "Failed to respond to a remove_transform request with: '{}'", e
+            );
+        }
+    }
+}
+
+/// Where `save_scenario` writes `target`: a bare `.json` file name goes into
+/// the last SCENARIO_DIR, an absolute path has to be a `.json` file directly
+/// in one of them.
+pub fn save_path(config: &Config, target: &str) -> Result<PathBuf, String> {
+    let path = Path::new(target);
+    if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+        return Err(format!("'{}' isn't a .json file", target));
+    }
+    if !path.is_absolute() {
+        let mut components = path.components();
+        let (Some(Component::Normal(name)), None) = (components.next(), components.next()) else {
+            return Err(format!("'{}' isn't a file name or an absolute path", target));
+        };
+        let dir = config.scenario_dirs.last().ok_or("SCENARIO_DIR is not set")?;
+        return Ok(Path::new(dir).join(name));
+    }
+    let parent = path
+        .parent()
+        .and_then(|parent| parent.canonicalize().ok())
+        .ok_or_else(|| format!("the directory of '{}' doesn't exist", target))?;
+    let in_scenario = config
+        .scenario_dirs
+        .iter()
+        .filter_map(|dir| Path::new(dir).canonicalize().ok())
+        .any(|dir| dir == parent);
+    if !in_scenario {
+        return Err(format!("'{}' isn't in a SCENARIO_DIR", target));
+    }
+    Ok(path.to_path_buf())
+}
+
+/// Write the frames stored in Redis to `path`, see `write_scenario`. Returns
+/// the number of frames written.
+pub async fn save_scenario(
+    connection_manager: &ConnectionManager,
+    config: &Config,
+    path: &Path,
+    warnings: &mut WarningThrottle,
+) -> Result<usize, String> {
+    let mut con = connection_manager.get_connection().await;
+    let frames = if config.frame_format != FrameFormat::Json {
+        let prefix = &config.transform_key_prefix;
+        read_frames(&mut con, prefix, config.frame_format, false, warnings)
+            .await
+            .map_err(|e| e.to_string())
+    } else {
+        TransformsManager::get_all_transforms(&mut con)
+            .await
+            .map_err(|e| e.to_string())
+    };
+    let frames = frames.map_err(|e| format!("failed to read the frames with: {}", e))?;
+    write_scenario(&frames, path)
+}
+
+/// Write frames to `path` as a JSON list of frames, which the scenario loader
+/// reads back. Mirrored frames are left out, their source owns them. The file
+/// is replaced in one go, so a watcher never sees it half written. Returns the
+/// number of frames written.
+pub fn write_scenario(
+    frames: &HashMap<String, SPTransformStamped>,
+    path: &Path,
+) -> Result<usize, String> {
+    let mut frames: Vec<&SPTransformStamped> = frames
+        .values()
+        .filter(|frame| get_bool(&frame.metadata, MIRRORED_KEY) != Some(true))
+        .collect();
+    frames.sort_by(|a, b| a.child_frame_id.cmp(&b.child_frame_id));
+    let contents = serde_json::to_string_pretty(&frames).map_err(|e| e.to_string())?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let write = || -> std::io::Result<()> {
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        file.write_all(contents.as_bytes())?;
+        file.persist(path)?;
+        Ok(())
+    };
+    write().map_err(|e| format!("failed to write {} with: {}", path.display(), e))?;
+    Ok(frames.len())
+}
+
+/// Serve `save_scenario`, which writes the frames stored in Redis to the
+/// file named by the request's `command`, see `save_path`.
+pub async fn serve_save_scenario(
+    mut requests: impl Stream<Item = r2r::ServiceRequest<ManipulateScene::Service>> + Unpin,
+    connection_manager: Arc<ConnectionManager>,
+    config: Config,
+) {
+    let mut warnings = WarningThrottle::new(config.warning_interval_secs);
+    while let Some(request) = requests.next().await {
+        let target = request.message.command.clone();
+        let result = match save_path(&config, &target) {
+            Ok(path) => save_scenario(&connection_manager, &config, &path, &mut warnings)
+                .await
+                .map(|count| (path, count)),
+            Err(e) => Err(e),
+        };
+        let response = match result {
+            Ok((path, count)) => {
+                log::info!(target: log_targets::SERVICES,
+                    "Saved {} frames to {}.", count, path.display()
+                );
+                ManipulateScene::Response {
+                    success: true,
+                    info: format!("Saved {} frames to {}.", count, path.display()),
+                }
+            }
+            Err(e) => ManipulateScene::Response {
+                success: false,
+                info: format!("Failed to save the scenario: {}.", e),
+            },
+        };
+        if let Err(e) = request.respond(response) {
+            log::error!(target: log_targets::SERVICES,
+                "Failed to respond to a save_scenario request with: '{}'", e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::LengthUnits;
+    use crate::loader::merge_scenarios;
+    use r2r::geometry_msgs::msg::{Quaternion, Vector3};
+    use tempfile::TempDir;
+
+    fn frame(parent: &str, child: &str, x: f64, yaw: f64) -> SPTransformStamped {
+        let transform = Transform {
+            translation: Vector3 { x, y: 0.5, z: 0.0 },
+            rotation: rpy_to_quaternion(0.0, 0.0, yaw),
+        };
+        new_frame(parent, child, &transform, true).unwrap()
+    }
+
+    #[test]
+    fn saved_scenarios_load_back_the_same() {
+        let scenario = TempDir::new().unwrap();
+        let mut table = frame("world", "table", 1.0, 0.0);
+        table.active_transform = false;
+        table.metadata = MapOrUnknown::Map(vec![(
+            SPValue::String(StringOrUnknown::String("mesh_file".to_string())),
+            SPValue::String(StringOrUnknown::String("table.stl".to_string())),
+        )]);
+        let frames = [table, frame("table", "robot", 0.2, 1.5708)];
+        let contents = serde_json::to_string(&frames).unwrap();
+        std::fs::write(scenario.path().join("cell.json"), contents).unwrap();
+        let loaded =
+            merge_scenarios(&[scenario.path().to_path_buf()], LengthUnits::Meters).unwrap();
+
+        let saved = TempDir::new().unwrap();
+        let path = saved.path().join("saved.json");
+        assert_eq!(write_scenario(&loaded, &path), Ok(2));
+        let reloaded = merge_scenarios(&[saved.path().to_path_buf()], LengthUnits::Meters).unwrap();
+        // compared through their JSON like scenario_diff does, which covers every field
+        let json =
+            |frames: &HashMap<String, SPTransformStamped>| serde_json::to_value(frames).unwrap();
+        assert_eq!(reloaded.len(), 2);
+        assert_eq!(json(&reloaded), json(&loaded));
+    }
+
+    #[test]
+    fn mirrored_frames_are_not_saved() {
+        let mut mirrored = frame("world", "camera", 0.0, 0.0);
+        mirrored.metadata = MapOrUnknown::Map(vec![(
+            SPValue::String(StringOrUnknown::String(MIRRORED_KEY.to_string())),
+            SPValue::Bool(BoolOrUnknown::Bool(true)),
+        )]);
+        let frames = HashMap::from([
+            ("camera".to_string(), mirrored),
+            ("table".to_string(), frame("world", "table", 1.0, 0.0)),
+        ]);
+        let saved = TempDir::new().unwrap();
+        assert_eq!(
+            write_scenario(&frames, &saved.path().join("saved.json")),
+            Ok(1)
+        );
+    }
+
+    fn request(
+        parent: &str,
+        child: &str,
+        rotation: Quaternion,
+        command: &str,
+    ) -> ManipulateScene::Request {
+        ManipulateScene::Request {
+            command: command.to_string(),
+            parent_frame_id: parent.to_string(),
+            child_frame_id: child.to_string(),
+            transformation: Transform {
+                translation: Vector3 {
+                    x: 1.0,
+                    y: 2.0,
+                    z: 3.0,
+                },
+                rotation,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn identity() -> Quaternion {
+        rpy_to_quaternion(0.0, 0.0, 0.0)
+    }
+
+    const ZERO: Quaternion = Quaternion {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+        w: 0.0,
+    };
+
+    #[test]
+    fn new_frames_have_a_unit_rotation() {
+        let transform = Transform {
+            translation: Vector3 {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            },
+            rotation: Quaternion {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+                w: 2.0,
+            },
+        };
+        let frame = new_frame("world", "table", &transform, false).unwrap();
+        assert_eq!(frame.parent_frame_id, "world");
+        assert_eq!(frame.child_frame_id, "table");
+        assert!(!frame.active_transform);
+        assert_eq!(frame.transform.translation.z.into_inner(), 3.0);
+        assert_eq!(frame.transform.rotation.w.into_inner(), 1.0);
+
+        let transform = Transform {
+            rotation: ZERO,
+            ..transform
+        };
+        assert!(new_frame("world", "table", &transform, false).is_err());
+    }
+
+    #[test]
+    fn requests_take_their_options_from_the_command() {
+        let frame = requested_frame(&request("world", "robot", identity(), "active")).unwrap();
+        assert!(frame.active_transform);
+        assert_eq!(frame.transform.translation.x.into_inner(), 1.0);
+        let frame = requested_frame(&request("world", "robot", identity(), "static")).unwrap();
+        assert!(!frame.active_transform);
+
+        // rpy fills in a missing rotation, a given one wins
+        let turned = rpy_to_quaternion(0.0, 0.0, 1.0);
+        let frame = requested_frame(&request("world", "robot", ZERO, "rpy=0,0,1")).unwrap();
+        assert!((frame.transform.rotation.z.into_inner() - turned.z).abs() < 1e-9);
+        let frame = requested_frame(&request("world", "robot", identity(), "rpy=0,0,1")).unwrap();
+        assert_eq!(frame.transform.rotation.z.into_inner(), 0.0);
+    }
+
+    #[test]
+    fn bad_requests_are_rejected() {
+        for (parent, rotation, command) in [
+            // its own parent
+            ("robot", identity(), ""),
+            // no rotation at all
+            ("world", ZERO, ""),
+            ("world", ZERO, "rpy=0,1"),
+            ("world", ZERO, "rpy=0,0,up"),
+        ] {
+            assert!(requested_frame(&request(parent, "robot", rotation, command)).is_err());
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use r2r::std_msgs::msg::Bool;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::log_targets;
+use crate::server::publish;
+use crate::sink::MessageSink;
+
+/// Counts the successful scenario reloads, so the marker task can tell one
+/// happened.
+#[derive(Debug, Clone, Default)]
+pub struct Reloads(Arc<AtomicU64>);
+
+impl Reloads {
+    pub fn record(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The latched `scene_ready` signal, true once the scene has been published
+/// without errors. A scenario reload sets it back to false until frames read
+/// after the reload have been published.
+pub struct SceneReady {
+    publisher: Box<dyn MessageSink<Bool>>,
+    reloads: Reloads,
+    seen_reloads: u64,
+    /// When the marker task noticed the last reload, snapshots read before
+    /// it may still hold the old scenario.
+    reloaded_at: Option<Instant>,
+    ready: bool,
+}
+
+impl SceneReady {
+    pub fn new(publisher: Box<dyn MessageSink<Bool>>, reloads: Reloads) -> SceneReady {
+        SceneReady {
+            publisher,
+            seen_reloads: reloads.count(),
+            reloads,
+            reloaded_at: None,
+            ready: false,
+        }
+    }
+
+    /// Update the signal after a tick at `now` that built its markers from
+    /// frames read at `read_at`, and `published` them all without errors.
+    /// Failures are recorded in `errors`.
+    pub async fn update(
+        &mut self,
+        read_at: Instant,
+        published: bool,
+        now: Instant,
+        errors: &mut Vec<String>,
+    ) {
+        let reloads = self.reloads.count();
+        if reloads != self.seen_reloads {
+            self.seen_reloads = reloads;
+            self.reloaded_at = Some(now);
+            if self.ready {
+                self.ready = false;
+                publish(
+                    &self.publisher,
+                    &Bool { data: false },
+                    "scene_ready",
+                    errors,
+                )
+                .await;
+                log::info!(target: log_targets::MARKERS,
+                    "The scenario was reloaded, the scene is ready again once it is published."
+                );
+                return;
+            }
+        }
+        let stale = matches!(self.reloaded_at, Some(reloaded_at) if read_at < reloaded_at);
+        if self.ready || !published || stale {
+            return;
+        }
+        let failed = errors.len();
+        publish(&self.publisher, &Bool { data: true }, "scene_ready", errors).await;
+        self.ready = errors.len() == failed;
+        if self.ready {
+            log::info!(target: log_targets::MARKERS, "Scene ready.");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+    use std::time::Duration;
+
+    fn signals(sink: &RecordingSink<Bool>) -> Vec<bool> {
+        sink.sent().iter().map(|msg| msg.data).collect()
+    }
+
+    #[tokio::test]
+    async fn ready_only_after_the_first_successful_publish() {
+        let sink = RecordingSink::default();
+        let mut scene_ready = SceneReady::new(Box::new(sink.clone()), Reloads::default());
+        let now = Instant::now();
+        let mut errors = vec![];
+        scene_ready.update(now, false, now, &mut errors).await;
+        assert!(signals(&sink).is_empty());
+        scene_ready.update(now, true, now, &mut errors).await;
+        assert_eq!(signals(&sink), [true]);
+        // latched, not sent again
+        scene_ready.update(now, true, now, &mut errors).await;
+        assert_eq!(signals(&sink), [true]);
+    }
+
+    #[tokio::test]
+    async fn reasserted_after_a_reload() {
+        let sink = RecordingSink::default();
+        let reloads = Reloads::default();
+        let mut scene_ready = SceneReady::new(Box::new(sink.clone()), reloads.clone());
+        let start = Instant::now();
+        let mut errors = vec![];
+        scene_ready.update(start, true, start, &mut errors).await;
+
+        reloads.record();
+        let noticed = start + Duration::from_secs(1);
+        scene_ready.update(start, true, noticed, &mut errors).await;
+        assert_eq!(signals(&sink), [true, false]);
+        // frames read before the reload was noticed don't count
+        scene_ready.update(start, true, noticed, &mut errors).await;
+        assert_eq!(signals(&sink), [true, false]);
+        scene_ready
+            .update(noticed, true, noticed, &mut errors)
+            .await;
+        assert_eq!(signals(&sink), [true, false, true]);
+        assert!(errors.is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use micro_sp::*;
+
+use crate::error::VizError;
+use crate::geometry::Isometry;
+use crate::log_targets;
+use crate::metadata::Metadata;
+use crate::redis_reads::frame_from_json;
+use crate::tree::find_roots;
+
+/// A second scenario shown next to the live one for comparison. Its frames are
+/// prefixed so they don't collide with the live frames, and its roots hang
+/// from offset frames so the two scenes don't overlap.
+#[derive(Debug, Clone)]
+pub struct SecondaryScene {
+    pub frames: HashMap<String, SPTransformStamped>,
+    /// Prefixed frames whose markers get the tint, i.e. all but the offset frames.
+    tinted: HashSet<String>,
+    tint: [f32; 3],
+}
+
+impl SecondaryScene {
+    /// Prefix every frame with `prefix` and attach each prefixed root to the
+    /// original root through a frame translated by `offset` in its XY plane.
+    pub fn new(
+        frames: HashMap<String, SPTransformStamped>,
+        prefix: &str,
+        offset: [f64; 2],
+        tint: [f32; 3],
+    ) -> SecondaryScene {
+        let roots = find_roots(&frames);
+        let mut scene: HashMap<String, SPTransformStamped> = HashMap::new();
+        for frame in frames.values() {
+            let mut prefixed = frame.clone();
+            prefixed.child_frame_id = format!("{}{}", prefix, frame.child_frame_id);
+            prefixed.parent_frame_id = format!("{}{}", prefix, frame.parent_frame_id);
+            scene.insert(prefixed.child_frame_id.clone(), prefixed);
+        }
+        let tinted = scene.keys().cloned().collect();
+        for root in roots {
+            // built from a frame of the scene, so nothing has to be assumed
+            // about the fields of SPTransformStamped
+            let Some(template) = frames.values().find(|f| f.parent_frame_id == root) else {
+                continue;
+            };
+            let mut offset_frame = template.clone();
+            offset_frame.child_frame_id = format!("{}{}", prefix, root);
+            offset_frame.parent_frame_id = root.clone();
+            offset_frame.active_transform = false;
+            offset_frame.metadata = MapOrUnknown::UNKNOWN;
+            Isometry {
+                translation: [offset[0], offset[1], 0.0],
+                ..Isometry::identity()
+            }
+            .write_to(&mut offset_frame);
+            scene.insert(offset_frame.child_frame_id.clone(), offset_frame);
+        }
+        SecondaryScene {
+            frames: scene,
+            tinted,
+            tint,
+        }
+    }
+
+    /// Recolor the markers of a secondary frame, leaving others untouched.
+    pub fn tint(&self, child_frame_id: &str, metadata: &mut Metadata) {
+        if self.tinted.contains(child_frame_id) {
+            [metadata.mesh_r, metadata.mesh_g, metadata.mesh_b] = self.tint;
+            if metadata.mesh_a == 0.0 {
+                metadata.mesh_a = 1.0;
+            }
+        }
+    }
+}
+
+/// Read the frames of a scenario directory without storing them in Redis.
+/// Every `.json` file holds a single frame or a list of frames.
+pub fn load_scene_dir(dir: &str) -> Result<HashMap<String, SPTransformStamped>, VizError> {
+    let (frames, skipped) = read_scene_dir(dir)?;
+    for (path, e) in skipped {
+        log::warn!(target: log_targets::REDIS,
+            "Skipping secondary scene file {} with: {}", path.display(), e
+        );
+    }
+    Ok(frames)
+}
+
+/// The frames of a scenario directory, along with the files that don't
+/// decode, e.g. because they are still being written.
+pub fn read_scene_dir(
+    dir: &str,
+) -> Result<(HashMap<String, SPTransformStamped>, Vec<(PathBuf, serde_json::Error)>), VizError> {
+    let unreadable = |path: &dyn std::fmt::Display, e: std::io::Error| {
+        VizError::TransformLoad(format!("can't read {}: {}", path, e))
+    };
+    let mut frames = HashMap::new();
+    let mut skipped = vec![];
+    for entry in std::fs::read_dir(dir).map_err(|e| unreadable(&dir, e))? {
+        let path = entry.map_err(|e| unreadable(&dir, e))?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        let contents = std::fs::read_to_string(&path).map_err(|e| unreadable(&path.display(), e))?;
+        let decoded = serde_json::from_str::<serde_json::Value>(&contents).and_then(|value| match value {
+            serde_json::Value::Array(list) => list.into_iter().map(frame_from_json).collect(),
+            value => frame_from_json(value).map(|frame| vec![frame]),
+        });
+        let decoded: Vec<(SPTransformStamped, bool)> = match decoded {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                skipped.push((path, e));
+                continue;
+            }
+        };
+        for (frame, ignored_rpy) in decoded {
+            if ignored_rpy {
+                log::warn!(target: log_targets::REDIS,
+                    "Frame '{}' of {} has both a rotation and rpy, using the rotation.",
+                    frame.child_frame_id,
+                    path.display()
+                );
+            }
+            frames.insert(frame.child_frame_id.clone(), frame);
+        }
+    }
+    Ok((frames, skipped))
+}
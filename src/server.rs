@@ -0,0 +1,1153 @@
+use r2r::geometry_msgs::msg::Quaternion;
+use r2r::nav_msgs::msg::OccupancyGrid;
+use r2r::visualization_msgs::msg::{Marker, MarkerArray};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::animation::{pulse_alpha, MeshAnimations};
+use crate::assemblies::in_hidden_assembly;
+use crate::appearance::StateAppearances;
+use crate::appearance_rate::AppearanceRate;
+use crate::colormap::{apply_color_by, apply_palette};
+use crate::config::Config;
+use crate::control::PublishControl;
+use crate::despawn::Despawns;
+use crate::diagnostics::{SharedTickStatus, TickStatus};
+use crate::dry_run::DryRunLog;
+use crate::error::VizError;
+use crate::geometry::{pose_in_root, Isometry};
+use crate::governor::RateGovernor;
+use crate::heartbeat::Heartbeat;
+use crate::highlight::Highlights;
+use crate::layers::in_hidden_layer;
+use crate::lod::LodViewpoint;
+use crate::log_targets;
+use crate::marker_topics::{group_markers, MarkerTopics};
+use crate::markers::{
+    anchor, apply_alpha_floor, assign_marker_ids, chunk_markers, collision_marker, delete_hidden,
+    delete_vanished, delta_markers, frame_axes, frame_locked, highlight_marker, is_invisible,
+    joint_range_markers, label_marker, lifetime, marker_id_for, mesh_descriptors, mesh_marker,
+    mesh_offset, order_markers, orientation_trail_markers, parent_link_marker, prefix_namespaces,
+    reference_grid_marker, resolve_scale, shadow_marker, shadow_radius, stamp_markers,
+    tool_markers, triangle_list_marker, ttl_marker, validate_marker_type, zone_disc_marker,
+    zone_geometry, zone_marker, MarkerScale, ADD,
+};
+use crate::mesh_cache::{MeshKey, MeshMarkerCache};
+use crate::mesh_orientation::MeshOrientations;
+use crate::metadata::{decode_metadata, decode_problems, get_f64};
+use crate::one_shot::OneShot;
+use crate::orientation_trail::OrientationTrails;
+use crate::outdated::{tint_outdated, OutdatedAction};
+use crate::scalars::map_scalar;
+use crate::scene_ready::SceneReady;
+use crate::secondary::SecondaryScene;
+use crate::sidecar::MeshSidecars;
+use crate::sink::{MarkerSink, MessageSink};
+use crate::tf_server::SnapshotReceiver;
+use crate::throttle::WarningThrottle;
+use crate::tick_metrics::{TickMetrics, TickSample};
+use crate::tree::find_orphan_frames;
+use crate::ttl::ttl_fraction;
+use crate::web::web_markers;
+use crate::zone_grid::{zone_grid, Footprint};
+use crate::zone_transition::ZoneTransitions;
+
+/// Everything the visualization server publishes on.
+pub struct Publishers {
+    pub mesh: MarkerSink,
+    /// None with COMBINED_MARKERS or COMBINED_MARKER_TOPIC, the zone markers
+    /// then go out on `mesh`.
+    pub zone: Option<MarkerSink>,
+    pub collision: MarkerSink,
+    /// Frame name labels, see `show_label`. None with COMBINED_MARKER_TOPIC,
+    /// they then go out on `mesh`.
+    pub text: Option<MarkerSink>,
+    /// Frame axis triads, see `show_axes`. None with COMBINED_MARKER_TOPIC.
+    pub axes: Option<MarkerSink>,
+    /// Lines from frames to their parents, see `show_parent_link`.
+    pub links: MarkerSink,
+    pub web: Option<MarkerSink>,
+    /// Reported on `/diagnostics` by the diagnostics task and by the HTTP
+    /// status endpoint.
+    pub diagnostics: Option<SharedTickStatus>,
+    pub zone_grid: Option<r2r::Publisher<OccupancyGrid>>,
+    pub delta: Option<MarkerSink>,
+    /// Some with REFERENCE_GRID_FRAME.
+    pub reference_grid: Option<MarkerSink>,
+    /// None without ORIENTATION_TRAIL_FRAMES.
+    pub orientation_trail: Option<MarkerSink>,
+    /// Per-group topics, see MARKER_GROUP_TOPICS.
+    pub marker_topics: Option<MarkerTopics>,
+    /// Latched, true once the scene has been published without errors.
+    pub scene_ready: SceneReady,
+}
+
+/// Where frames get their appearance from besides their Redis metadata.
+pub struct FrameOverrides {
+    pub appearances: StateAppearances,
+    /// Tints the secondary frames, which the TF server adds to the snapshot.
+    pub secondary: Option<SecondaryScene>,
+    pub highlights: Highlights,
+}
+
+/// Attempts at publishing a message before giving up on it for the tick.
+const PUBLISH_ATTEMPTS: u32 = 3;
+
+/// Publish a message, trying again after 1ms and 2ms when it fails. The
+/// task yields while it waits, so other tasks on its worker keep running.
+pub async fn publish_with_retry<T, S: MessageSink<T> + ?Sized>(
+    publisher: &S,
+    msg: &T,
+    topic: &str,
+) -> Result<(), VizError> {
+    let mut attempt = 1;
+    loop {
+        match publisher.publish(msg) {
+            Ok(()) => {
+                if attempt > 1 {
+                    log::debug!(target: log_targets::MARKERS,
+                        "Published a {} message on attempt {}.", topic, attempt
+                    );
+                }
+                return Ok(());
+            }
+            Err(source) if attempt >= PUBLISH_ATTEMPTS => {
+                return Err(VizError::Publish {
+                    topic: topic.to_string(),
+                    source,
+                });
+            }
+            Err(_) => {
+                tokio::time::sleep(std::time::Duration::from_millis(1 << (attempt - 1))).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Publish a message, logging a failure and recording it for the diagnostics.
+pub async fn publish<T, S: MessageSink<T> + ?Sized>(
+    publisher: &S,
+    msg: &T,
+    topic: &str,
+    errors: &mut Vec<String>,
+) {
+    if let Err(e) = publish_with_retry(publisher, msg, topic).await {
+        log::error!(target: log_targets::MARKERS, "{}", e);
+        errors.push(e.to_string());
+    }
+}
+
+/// Publish a MarkerArray in arrays of at most `max_per_msg` markers, so big
+/// scenes stay below the DDS message size. 0, or an array that fits, is
+/// published as it is.
+pub async fn publish_markers<S: MessageSink<MarkerArray> + ?Sized>(
+    publisher: &S,
+    msg: &MarkerArray,
+    topic: &str,
+    max_per_msg: usize,
+    errors: &mut Vec<String>,
+) {
+    if max_per_msg == 0 || msg.markers.len() <= max_per_msg {
+        publish(publisher, msg, topic, errors).await;
+        return;
+    }
+    for chunk in chunk_markers(msg.markers.clone(), max_per_msg) {
+        publish(publisher, &chunk, topic, errors).await;
+    }
+}
+
+/// The marker task, put together with `VisualizationServer::builder`.
+pub struct VisualizationServer {
+    publishers: Publishers,
+    snapshots: SnapshotReceiver,
+    timer: r2r::Timer,
+    config: Config,
+    overrides: FrameOverrides,
+    control: PublishControl,
+}
+
+/// The parts of a `VisualizationServer`. The publishers, snapshots, timer and
+/// configuration are required. Without overrides frames look as stored, with
+/// highlights lasting HIGHLIGHT_SECS, and without a control the server never
+/// pauses.
+#[derive(Default)]
+pub struct VisualizationServerBuilder {
+    publishers: Option<Publishers>,
+    snapshots: Option<SnapshotReceiver>,
+    timer: Option<r2r::Timer>,
+    config: Option<Config>,
+    overrides: Option<FrameOverrides>,
+    control: PublishControl,
+}
+
+impl VisualizationServerBuilder {
+    pub fn publishers(mut self, publishers: Publishers) -> Self {
+        self.publishers = Some(publishers);
+        self
+    }
+
+    pub fn snapshots(mut self, snapshots: SnapshotReceiver) -> Self {
+        self.snapshots = Some(snapshots);
+        self
+    }
+
+    /// Ticks every MARKER_PERIOD_MS.
+    pub fn timer(mut self, timer: r2r::Timer) -> Self {
+        self.timer = Some(timer);
+        self
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn overrides(mut self, overrides: FrameOverrides) -> Self {
+        self.overrides = Some(overrides);
+        self
+    }
+
+    pub fn control(mut self, control: PublishControl) -> Self {
+        self.control = control;
+        self
+    }
+
+    /// The server, or a configuration error naming the missing parts.
+    pub fn build(self) -> Result<VisualizationServer, VizError> {
+        let missing = missing_parts(&[
+            ("publishers", self.publishers.is_some()),
+            ("snapshots", self.snapshots.is_some()),
+            ("timer", self.timer.is_some()),
+            ("config", self.config.is_some()),
+        ]);
+        let (Some(publishers), Some(snapshots), Some(timer), Some(config)) =
+            (self.publishers, self.snapshots, self.timer, self.config)
+        else {
+            return Err(VizError::Config(format!(
+                "the visualization server is missing {}",
+                missing
+            )));
+        };
+        let overrides = self.overrides.unwrap_or_else(|| FrameOverrides {
+            appearances: StateAppearances::default(),
+            secondary: None,
+            highlights: Highlights::new(config.highlight_secs),
+        });
+        Ok(VisualizationServer {
+            publishers,
+            snapshots,
+            timer,
+            config,
+            overrides,
+            control: self.control,
+        })
+    }
+}
+
+/// The names of the parts that aren't set, for a builder's error.
+pub fn missing_parts(parts: &[(&str, bool)]) -> String {
+    parts
+        .iter()
+        .filter(|(_, set)| !set)
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl VisualizationServer {
+    pub fn builder() -> VisualizationServerBuilder {
+        VisualizationServerBuilder::default()
+    }
+
+    /// Build and publish the markers of every frame snapshot, once per timer
+    /// tick, until the timer fails.
+    pub async fn run(self) -> Result<(), VizError> {
+        publish_scene(self).await
+    }
+}
+
+async fn publish_scene(server: VisualizationServer) -> Result<(), VizError> {
+    let VisualizationServer {
+        mut publishers,
+        mut snapshots,
+        mut timer,
+        config,
+        overrides,
+        control,
+    } = server;
+    let mut warnings = WarningThrottle::new(config.warning_interval_secs);
+    let mut heartbeat = Heartbeat::new(config.heartbeat_secs);
+    let (mut last_frame_count, mut last_marker_count) = (0, 0);
+    let mesh_orientations = MeshOrientations::parse(&config.mesh_orientations);
+    let frame_filter = match config.frame_filter.as_deref().map(regex::Regex::new) {
+        Some(Ok(filter)) => Some(filter),
+        Some(Err(e)) => {
+            log::error!(target: log_targets::MARKERS,
+                "Ignoring FRAME_FILTER, it isn't a valid regex: {}", e
+            );
+            None
+        }
+        None => None,
+    };
+    let mut governor = RateGovernor::new(
+        config.adaptive_rate,
+        std::time::Duration::from_millis(config.marker_period_ms),
+        config.adaptive_rate_max_divisor,
+    );
+    let mut zone_transitions = ZoneTransitions::new(config.zone_transition_secs);
+    let mut appearance_rate =
+        (config.appearance_update_hz > 0.0).then(|| AppearanceRate::new(config.appearance_update_hz));
+    let mut animations = MeshAnimations::default();
+    let mut sidecars = MeshSidecars::default();
+    let mut mesh_cache = MeshMarkerCache::default();
+    // frames warned about once for an invisible mesh
+    let mut invisible_meshes: HashSet<String> = HashSet::new();
+    // of the mesh and the zone markers
+    let mut despawns = (config.despawn_secs > 0.0)
+        .then(|| [Despawns::new(config.despawn_secs), Despawns::new(config.despawn_secs)]);
+    let mut metrics = TickMetrics::new(std::time::Duration::from_secs(1));
+    let mut one_shot = config.one_shot.then(|| OneShot::new(config.one_shot_idle_secs()));
+    // every zone pulses in phase, from the start of the server
+    let pulse_start = std::time::Instant::now();
+    let mut pulsing_zones = false;
+    let mut orientation_trails = OrientationTrails::new(
+        &config.orientation_trail_frames,
+        config.orientation_trail_samples,
+        config.orientation_trail_secs,
+    );
+    let mut trail_published: HashSet<(String, i32)> = HashSet::new();
+    let mut link_published: HashSet<(String, i32)> = HashSet::new();
+    // (ns, id) of the markers added on the last publish, per topic
+    let mut published: [HashSet<(String, i32)>; 5] = Default::default();
+    let mut dry_run = DryRunLog::default();
+    let mut orphans: Vec<(String, String)> = vec![];
+    loop {
+        timer.tick().await?;
+        if control.paused() {
+            continue;
+        }
+        if matches!(&one_shot, Some(one_shot) if config.exit_after_publish && one_shot.burst_done())
+        {
+            return Ok(());
+        }
+        // with REDIS_NOTIFY new snapshots only come on changes and heartbeats
+        let changed = snapshots
+            .has_changed()
+            .map_err(|_| VizError::TransformLoad("the TF task stopped".to_string()))?;
+        // pulsing zones change every tick, with or without new frames
+        if config.redis_notify && !changed && !pulsing_zones && one_shot.is_none() {
+            continue;
+        }
+        let Some(snapshot) = snapshots.borrow_and_update().clone() else {
+            // Redis is unreachable, failed to read, or hasn't been read yet
+            heartbeat.beat(last_frame_count, last_marker_count, false);
+            continue;
+        };
+        let tick_start = std::time::Instant::now();
+        if matches!(&mut one_shot, Some(one_shot) if !one_shot.due(tick_start)) {
+            continue;
+        }
+        let frames_local = &snapshot.frames;
+        let hidden = &snapshot.hidden_assemblies;
+        let layers_hidden = &snapshot.hidden_layers;
+        let scalars = &snapshot.scalars;
+        let ttls = &snapshot.ttls;
+        let time_stamp = snapshot.time_stamp.clone();
+        last_frame_count = frames_local.len();
+        // TF drops them and their subtrees without a word, so say it, once per change
+        let tick_orphans = find_orphan_frames(frames_local, &config.world_frame);
+        if tick_orphans != orphans {
+            if !tick_orphans.is_empty() {
+                let listed: Vec<String> = tick_orphans
+                    .iter()
+                    .map(|(child, parent)| format!("'{}' under '{}'", child, parent))
+                    .collect();
+                log::warn!(target: log_targets::MARKERS,
+                    "{} frames hang from parents that are neither frames nor '{}', TF can't place them or anything below them: {}.",
+                    tick_orphans.len(),
+                    config.world_frame,
+                    listed.join(", ")
+                );
+            }
+            orphans = tick_orphans;
+        }
+        let mut mesh_markers: Vec<Marker> = vec![];
+        let mut zone_markers: Vec<Marker> = vec![];
+        let mut collision_markers: Vec<Marker> = vec![];
+        let mut text_markers: Vec<Marker> = vec![];
+        let mut axis_markers: Vec<Marker> = vec![];
+        let mut link_markers: Vec<Marker> = vec![];
+        let highlights = overrides.highlights.active(tick_start);
+        let mut hidden_ids: HashSet<i32> = HashSet::new();
+        let mut zone_pulses: HashMap<i32, (f64, [f32; 2])> = HashMap::new();
+        let lod = match &config.lod_viewpoint_frame {
+            Some(viewpoint) if config.lod_max_distance > 0.0 => {
+                let lod = LodViewpoint::locate(frames_local, viewpoint, config.lod_max_distance);
+                if lod.is_none() && warnings.allow(viewpoint, "missing LOD viewpoint", tick_start) {
+                    log::warn!(target: log_targets::MARKERS,
+                        "LOD_VIEWPOINT_FRAME '{}' isn't in the scene, publishing every mesh.", viewpoint
+                    );
+                }
+                lod
+            }
+            _ => None,
+        };
+        let (assigned_ids, moved_ids) = assign_marker_ids(
+            frames_local.values().map(|frame| frame.child_frame_id.as_str()),
+            &config.marker_ns_prefix,
+            config.marker_id_probing,
+        );
+        // ~1% of the frames colliding means tens of millions of frames, or
+        // names crafted to collide
+        if moved_ids > 0
+            && moved_ids * 100 >= assigned_ids.len()
+            && warnings.allow("marker ids", "marker id pressure", tick_start)
+        {
+            log::warn!(target: log_targets::MARKERS,
+                "{} of {} frames collided on their hashed marker id and were given the next free one, their ids change as colliding frames come and go.",
+                moved_ids, assigned_ids.len()
+            );
+        }
+        let defined: HashSet<&str> = frames_local
+            .values()
+            .map(|frame| frame.child_frame_id.as_str())
+            .collect();
+        let mut marker_ids: HashMap<i32, &str> = HashMap::new();
+        let mut render_priorities: HashMap<i32, i32> = HashMap::new();
+        let mut footprints: Vec<Footprint> = vec![];
+        for frame in frames_local.values() {
+            let id = assigned_ids[frame.child_frame_id.as_str()];
+            let hashed_id = marker_id_for(&config.marker_ns_prefix, &frame.child_frame_id);
+            if id != hashed_id {
+                log::debug!(target: log_targets::MARKERS,
+                    "Frame '{}' collided on marker id {}, using {}.",
+                    frame.child_frame_id, hashed_id, id
+                );
+            }
+            if let Some(other) = marker_ids.insert(id, &frame.child_frame_id) {
+                if warnings.allow(&frame.child_frame_id, "marker id collisions", tick_start) {
+                    log::warn!(target: log_targets::MARKERS,
+                        "Frames '{}' and '{}' have the same marker id {}, one hides the other's markers.",
+                        other, frame.child_frame_id, id
+                    );
+                }
+            }
+            let mut metadata = decode_metadata(&frame.metadata);
+            let palette = &config.mesh_palette;
+            apply_palette(palette, &frame.child_frame_id, &frame.metadata, &mut metadata);
+            if let Some(mesh_file) = metadata.mesh_file.as_deref() {
+                let dir = metadata.override_meshes_dir.as_deref().unwrap_or(&config.meshes_dir);
+                if let Some(sidecar) = sidecars.get(dir, mesh_file) {
+                    sidecar.apply(&frame.metadata, &mut metadata);
+                }
+            }
+            let problems = decode_problems(&frame.metadata);
+            if !problems.is_empty()
+                && warnings.allow(&frame.child_frame_id, "malformed metadata", tick_start)
+            {
+                log::warn!(target: log_targets::MARKERS,
+                    "Metadata of '{}' is malformed, using the defaults for: {}.",
+                    frame.child_frame_id,
+                    problems.join(", ")
+                );
+            }
+            animations.apply(&frame.child_frame_id, &mut metadata, tick_start);
+            if let Err(e) = apply_color_by(&frame.metadata, &mut metadata) {
+                if warnings.allow(&frame.child_frame_id, "invalid color_by", tick_start) {
+                    log::warn!(target: log_targets::MARKERS,
+                        "Keeping the mesh color of '{}', its color_by failed: {}.",
+                        frame.child_frame_id, e
+                    );
+                }
+            }
+            overrides.appearances.apply(&frame.child_frame_id, &mut metadata);
+            if let Some(secondary) = &overrides.secondary {
+                secondary.tint(&frame.child_frame_id, &mut metadata);
+            }
+            if config.outdated_transforms == OutdatedAction::Tint
+                && snapshot.outdated.contains(&frame.child_frame_id)
+            {
+                tint_outdated(&mut metadata);
+            }
+
+            // filtered frames stay in TF, so the tree is complete
+            if matches!(&frame_filter, Some(filter) if !filter.is_match(&frame.child_frame_id)) {
+                continue;
+            }
+
+            // beyond LOD_MAX_DISTANCE the meshes are left out, and so deleted
+            let beyond_lod =
+                matches!(&lod, Some(lod) if !lod.shows_meshes(frames_local, &frame.child_frame_id));
+            if metadata.visualize_mesh && !beyond_lod {
+                let mesh_type = validate_marker_type(metadata.mesh_type);
+                if mesh_type != metadata.mesh_type {
+                    if warnings.allow(&frame.child_frame_id, "invalid mesh types", tick_start) {
+                        log::warn!(target: log_targets::MARKERS,
+                            "Frame '{}' has mesh_type {}, which is no marker type, drawing it as a MESH_RESOURCE.",
+                            frame.child_frame_id, metadata.mesh_type
+                        );
+                    }
+                    metadata.mesh_type = mesh_type;
+                }
+                let mut scale = resolve_scale(&metadata, &config);
+                if let Some(key) = &metadata.scale_scalar_key {
+                    match scalars.get(key) {
+                        Some(value) => {
+                            let factor = map_scalar(
+                                *value,
+                                metadata.scale_scalar_range,
+                                metadata.scale_range,
+                            );
+                            scale = scale.scaled(factor);
+                        }
+                        None => {
+                            if warnings.allow(
+                                &frame.child_frame_id,
+                                "missing scale scalars",
+                                tick_start,
+                            ) {
+                                log::warn!(target: log_targets::MARKERS,
+                                    "Scale scalar '{}' of '{}' is missing or not a number, using the unscaled marker.",
+                                    key, frame.child_frame_id
+                                );
+                            }
+                        }
+                    }
+                }
+                if scale.is_suspicious()
+                    && warnings.allow(&frame.child_frame_id, "1m primitives", tick_start)
+                {
+                    log::warn!(target: log_targets::MARKERS,
+                        "Primitive marker of '{}' has no primitive_size and renders as a 1m shape, set primitive_size if this is unintended.",
+                        frame.child_frame_id
+                    );
+                }
+                let [_, _, _, frame_w] = Isometry::from_frame(frame).rotation;
+                // a stored identity, or no rotation at all
+                let unrotated = (frame_w.abs() - 1.0).abs() < 1e-9;
+                let key = MeshKey { scale, id, unrotated };
+                let marker = mesh_cache.get_or_build(&frame.child_frame_id, &metadata, key, || {
+                    let mut marker = mesh_marker(frame, &metadata, scale, id, &config)?;
+                    let offset = match mesh_offset(&metadata) {
+                        Ok(offset) => offset,
+                        Err(e) => {
+                            if warnings.allow(
+                                &frame.child_frame_id,
+                                "invalid mesh offsets",
+                                tick_start,
+                            ) {
+                                log::warn!(target: log_targets::MARKERS,
+                                    "Ignoring the mesh offset of '{}': {}.",
+                                    frame.child_frame_id, e
+                                );
+                            }
+                            None
+                        }
+                    };
+                    let offset_rotation = metadata.mesh_offset_rpy.is_some()
+                        || metadata.mesh_offset_quat.is_some();
+                    if let Some(pose) = offset {
+                        marker.pose = pose;
+                    }
+                    let default_orientation = metadata
+                        .mesh_file
+                        .as_deref()
+                        .filter(|_| !offset_rotation)
+                        .and_then(|path| mesh_orientations.orientation(path));
+                    if let Some([x, y, z, w]) = default_orientation.filter(|_| unrotated) {
+                        marker.pose.orientation = Quaternion { x, y, z, w };
+                    }
+                    Some(marker)
+                });
+                if let Some(marker) = marker {
+                    if is_invisible(&marker)
+                        && invisible_meshes.insert(frame.child_frame_id.clone())
+                    {
+                        log::warn!(target: log_targets::MARKERS,
+                            "Mesh of '{}' has alpha 0 and no embedded materials shown, so it is invisible{}.",
+                            frame.child_frame_id,
+                            if config.min_alpha > 0.0 {
+                                ", raising it to MIN_MARKER_ALPHA"
+                            } else {
+                                ", check mesh_a"
+                            }
+                        );
+                    }
+                    mesh_markers.push(marker);
+                    if config.show_shadows {
+                        if let Some((root, pose)) = pose_in_root(&frames_local, &frame.child_frame_id) {
+                            mesh_markers.push(shadow_marker(
+                                &root,
+                                pose.translation,
+                                shadow_radius(&metadata, scale, config.shadow_radius),
+                                config.shadow_ground_height,
+                                id,
+                                lifetime(config.mesh_lifetime),
+                            ));
+                        }
+                    }
+                }
+                let mut descriptors = match mesh_descriptors(&metadata) {
+                    Ok(descriptors) => descriptors,
+                    Err(e) => {
+                        if warnings.allow(&frame.child_frame_id, "invalid meshes", tick_start) {
+                            log::warn!(target: log_targets::MARKERS,
+                                "Skipping the further meshes of '{}': {}.",
+                                frame.child_frame_id, e
+                            );
+                        }
+                        vec![]
+                    }
+                };
+                // the frame's id with a namespace per mesh, like its other markers
+                for (index, descriptor) in descriptors.iter_mut().enumerate() {
+                    let mesh_type = validate_marker_type(descriptor.mesh_type);
+                    if mesh_type != descriptor.mesh_type {
+                        if warnings.allow(&frame.child_frame_id, "invalid mesh types", tick_start) {
+                            log::warn!(target: log_targets::MARKERS,
+                                "Mesh {} of '{}' has type {}, which is no marker type, drawing it as a MESH_RESOURCE.",
+                                index + 1, frame.child_frame_id, descriptor.mesh_type
+                            );
+                        }
+                        descriptor.mesh_type = mesh_type;
+                    }
+                    let scale = resolve_scale(descriptor, &config);
+                    let Some(mut marker) = mesh_marker(&frame, descriptor, scale, id, &config) else {
+                        continue;
+                    };
+                    marker.ns = format!("mesh/{}", index + 1);
+                    match mesh_offset(descriptor) {
+                        Ok(Some(pose)) => marker.pose = pose,
+                        Ok(None) => {}
+                        Err(e) => {
+                            if warnings.allow(&frame.child_frame_id, "invalid mesh offsets", tick_start) {
+                                log::warn!(target: log_targets::MARKERS,
+                                    "Ignoring the offset of mesh {} of '{}': {}.",
+                                    index + 1, frame.child_frame_id, e
+                                );
+                            }
+                        }
+                    }
+                    mesh_markers.push(marker);
+                }
+            }
+            if metadata.visualize_zone {
+                metadata.zone = zone_transitions.size(&frame.child_frame_id, metadata.zone, tick_start);
+                let marker = if metadata.zone_2d.unwrap_or(config.zone_2d) {
+                    pose_in_root(&frames_local, &frame.child_frame_id).and_then(|(root, pose)| {
+                        zone_disc_marker(&root, pose.translation, &metadata, id, &config)
+                    })
+                } else {
+                    match zone_geometry(&metadata) {
+                        Ok(geometry) => {
+                            zone_marker(&frame, &metadata, &geometry, id, &config)
+                        }
+                        Err(e) => {
+                            if warnings.allow(&frame.child_frame_id, "invalid zones", tick_start) {
+                                log::warn!(target: log_targets::MARKERS,
+                                    "Skipping the zone of '{}', {}.", frame.child_frame_id, e
+                                );
+                            }
+                            None
+                        }
+                    }
+                };
+                if let Some(marker) = marker {
+                    if metadata.zone_pulse {
+                        zone_pulses.insert(id, (metadata.zone_pulse_hz, metadata.zone_pulse_alpha));
+                    }
+                    zone_markers.push(marker);
+                }
+                let hidden_frame = in_hidden_assembly(metadata.assembly.as_deref(), hidden)
+                    || in_hidden_layer(&metadata.layers, &layers_hidden);
+                if publishers.zone_grid.is_some() && metadata.zone > 0.0 && !hidden_frame {
+                    if let Some((root, pose)) = pose_in_root(&frames_local, &frame.child_frame_id) {
+                        if root == config.zone_grid_frame {
+                            footprints.push(Footprint {
+                                center: [pose.translation[0], pose.translation[1]],
+                                radius: metadata.zone / 2.0,
+                            });
+                        }
+                    }
+                }
+            }
+            if let Some(marker) = collision_marker(&frame, &metadata, id, &config) {
+                collision_markers.push(marker);
+            }
+            if metadata.show_label.unwrap_or(config.show_labels) {
+                text_markers.push(label_marker(&frame, &metadata, id, &config));
+            }
+            if metadata.show_axes.unwrap_or(config.show_axes) {
+                let axes = frame_axes(&frame, &metadata, id, lifetime(config.mesh_lifetime));
+                axis_markers.extend(axes);
+            }
+            if metadata.show_parent_link.unwrap_or(config.show_parent_links) {
+                if defined.contains(frame.parent_frame_id.as_str()) {
+                    let link_lifetime = lifetime(config.mesh_lifetime);
+                    link_markers.push(parent_link_marker(&frame, id, link_lifetime));
+                } else if frame.parent_frame_id != config.world_frame
+                    && warnings.allow(&frame.child_frame_id, "missing link parents", tick_start)
+                {
+                    log::warn!(target: log_targets::MARKERS,
+                        "Frame '{}' references parent '{}', which isn't a frame, drawing no link.",
+                        frame.child_frame_id, frame.parent_frame_id
+                    );
+                }
+            }
+            if let Some(remaining) = highlights.get(&frame.child_frame_id) {
+                let size = match resolve_scale(&metadata, &config) {
+                    MarkerScale::PrimitiveSize(size) | MarkerScale::PrimitiveFallback(size) => {
+                        size * 1.2
+                    }
+                    MarkerScale::MeshMultiplier(_) => config.highlight_size,
+                };
+                mesh_markers.push(highlight_marker(
+                    &frame.child_frame_id,
+                    size,
+                    id,
+                    lifetime(remaining.as_secs_f64()),
+                ));
+            }
+            let joint_value = match &metadata.joint_value_key {
+                Some(key) => scalars.get(key).copied(),
+                None => metadata.joint_value,
+            };
+            let joint_lifetime = lifetime(config.mesh_lifetime);
+            match joint_range_markers(&frame, &metadata, joint_value, id, joint_lifetime) {
+                Ok(Some(markers)) => mesh_markers.extend(markers),
+                Ok(None) => {}
+                Err(e) => {
+                    if warnings.allow(&frame.child_frame_id, "invalid joint ranges", tick_start) {
+                        log::warn!(target: log_targets::MARKERS,
+                            "Skipping the joint range of '{}': {}.",
+                            frame.child_frame_id, e
+                        );
+                    }
+                }
+            }
+            match triangle_list_marker(&frame, &metadata, id, lifetime(config.mesh_lifetime)) {
+                Ok(Some(marker)) => mesh_markers.push(marker),
+                Ok(None) => {}
+                Err(e) => {
+                    if warnings.allow(&frame.child_frame_id, "invalid triangles", tick_start) {
+                        log::warn!(target: log_targets::MARKERS,
+                            "Skipping the triangles of '{}': {}.", frame.child_frame_id, e
+                        );
+                    }
+                }
+            }
+            if let Some(remaining_ms) = ttls.get(&frame.child_frame_id) {
+                mesh_markers.push(ttl_marker(
+                    &frame.child_frame_id,
+                    ttl_fraction(*remaining_ms, (config.ttl_full_secs * 1000.0) as i64),
+                    config.ttl_indicator_size,
+                    id,
+                    lifetime(config.mesh_lifetime),
+                    frame_locked(&metadata, &config),
+                ));
+            }
+            if snapshot.stale.contains(&frame.child_frame_id) {
+                if let Some((root, pose)) = pose_in_root(&frames_local, &frame.child_frame_id) {
+                    for marker in mesh_markers
+                        .iter_mut()
+                        .chain(zone_markers.iter_mut())
+                        .chain(collision_markers.iter_mut())
+                        .chain(text_markers.iter_mut())
+                        .filter(|m| m.id == id && m.header.frame_id == frame.child_frame_id)
+                    {
+                        anchor(marker, &root, &pose);
+                    }
+                }
+            }
+            let confidence = config
+                .confidence_key
+                .as_ref()
+                .and_then(|key| get_f64(&frame.metadata, key));
+            if let Some(confidence) = confidence {
+                let factor = map_scalar(confidence, config.confidence_range, config.confidence_alpha);
+                for marker in mesh_markers
+                    .iter_mut()
+                    .chain(zone_markers.iter_mut())
+                    .chain(collision_markers.iter_mut())
+                    .filter(|m| m.id == id)
+                {
+                    marker.color.a *= factor as f32;
+                }
+            }
+            if metadata.render_priority != 0 {
+                render_priorities.insert(id, metadata.render_priority);
+            }
+            if in_hidden_assembly(metadata.assembly.as_deref(), hidden)
+                || in_hidden_layer(&metadata.layers, &layers_hidden)
+            {
+                hidden_ids.insert(id);
+            }
+        }
+
+        let tool = config.tool_frame.as_ref().filter(|tool| frames_local.contains_key(*tool));
+        if let Some(tool) = tool {
+            // the tool's pose in the reference frame, when both are in one tree
+            let relative = config.tool_reference_frame.as_ref().and_then(|reference| {
+                let tool_pose = pose_in_root(&frames_local, tool)?;
+                let reference_pose = match pose_in_root(&frames_local, reference) {
+                    Some(pose) => pose,
+                    // the reference is the root itself
+                    None => (reference.clone(), Isometry::identity()),
+                };
+                let pose = reference_pose.1.inverse().compose(&tool_pose.1);
+                (tool_pose.0 == reference_pose.0).then_some((reference.as_str(), pose))
+            });
+            mesh_markers.extend(tool_markers(
+                tool,
+                config.tool_axis_length,
+                relative.as_ref().map(|(reference, pose)| (*reference, pose)),
+                lifetime(config.mesh_lifetime),
+            ));
+        }
+
+        zone_transitions.retain(|frame| frames_local.contains_key(frame));
+        animations.retain(|frame| frames_local.contains_key(frame));
+        mesh_cache.retain(|frame| frames_local.contains_key(frame));
+        invisible_meshes.retain(|frame| frames_local.contains_key(frame));
+        if let Some(trails) = orientation_trails.as_mut() {
+            trails.record(&frames_local, tick_start);
+        }
+
+        // markers of hidden assemblies and layers
+        let all_markers = mesh_markers
+            .iter_mut()
+            .chain(zone_markers.iter_mut())
+            .chain(collision_markers.iter_mut())
+            .chain(text_markers.iter_mut())
+            .chain(axis_markers.iter_mut())
+            .chain(link_markers.iter_mut());
+        delete_hidden(all_markers, &hidden_ids);
+
+        last_marker_count = mesh_markers.len() + zone_markers.len();
+        heartbeat.beat(last_frame_count, last_marker_count, true);
+
+        if governor.publish_markers() {
+            let [
+                mesh_published,
+                zone_published,
+                collision_published,
+                text_published,
+                axis_published,
+            ] = &mut published;
+            if let Some([mesh_despawns, zone_despawns]) = despawns.as_mut() {
+                mesh_despawns.apply(&mut mesh_markers, tick_start);
+                zone_despawns.apply(&mut zone_markers, tick_start);
+            }
+            delete_vanished(&mut mesh_markers, mesh_published);
+            delete_vanished(&mut zone_markers, zone_published);
+            delete_vanished(&mut collision_markers, collision_published);
+            delete_vanished(&mut text_markers, text_published);
+            delete_vanished(&mut axis_markers, axis_published);
+            delete_vanished(&mut link_markers, &mut link_published);
+            if let Some(appearance_rate) = appearance_rate.as_mut() {
+                let markers = mesh_markers
+                    .iter_mut()
+                    .chain(zone_markers.iter_mut())
+                    .chain(collision_markers.iter_mut());
+                appearance_rate.apply(markers, tick_start);
+            }
+        }
+        // after the appearance rate, which would hold the pulse back
+        let pulse_secs = tick_start.duration_since(pulse_start).as_secs_f64();
+        for marker in zone_markers.iter_mut().filter(|m| m.action == ADD) {
+            if let Some((hz, range)) = zone_pulses.get(&marker.id) {
+                marker.color.a = pulse_alpha(pulse_secs, *hz, *range);
+            }
+        }
+        pulsing_zones = !zone_pulses.is_empty();
+
+        let render_priority = |m: &Marker| render_priorities.get(&m.id).copied().unwrap_or(0);
+        order_markers(&mut zone_markers, render_priority);
+        order_markers(&mut mesh_markers, render_priority);
+        order_markers(&mut collision_markers, render_priority);
+        if config.min_alpha > 0.0 {
+            apply_alpha_floor(&mut zone_markers, config.min_alpha);
+            apply_alpha_floor(&mut mesh_markers, config.min_alpha);
+            apply_alpha_floor(&mut collision_markers, config.min_alpha);
+        }
+        for markers in [
+            &mut zone_markers,
+            &mut mesh_markers,
+            &mut collision_markers,
+            &mut text_markers,
+            &mut axis_markers,
+            &mut link_markers,
+        ] {
+            prefix_namespaces(markers, &config.marker_ns_prefix);
+            stamp_markers(markers, &time_stamp);
+        }
+
+        let zone_array_msg = MarkerArray {
+            markers: zone_markers,
+        };
+
+        let mesh_array_msg = MarkerArray {
+            markers: mesh_markers,
+        };
+
+        let collision_array_msg = MarkerArray {
+            markers: collision_markers,
+        };
+
+        let text_array_msg = MarkerArray {
+            markers: text_markers,
+        };
+
+        let axis_array_msg = MarkerArray {
+            markers: axis_markers,
+        };
+
+        let link_array_msg = MarkerArray {
+            markers: link_markers,
+        };
+
+        let mut tick_status = TickStatus {
+            redis_healthy: true,
+            frame_count: last_frame_count,
+            mesh_markers: mesh_array_msg.markers.len(),
+            zone_markers: zone_array_msg.markers.len(),
+            publish_errors: snapshot.publish_errors.clone(),
+            last_tick: Some(std::time::SystemTime::now()),
+        };
+
+        if config.dry_run {
+            let summary = format!(
+                "{} frames, {} mesh, {} zone, {} collision, {} label, {} axis and {} link markers",
+                last_frame_count,
+                mesh_array_msg.markers.len(),
+                zone_array_msg.markers.len(),
+                collision_array_msg.markers.len(),
+                text_array_msg.markers.len(),
+                axis_array_msg.markers.len(),
+                link_array_msg.markers.len()
+            );
+            if dry_run.changed(&summary) {
+                log::info!(target: log_targets::MARKERS,
+                    "Dry run, not publishing {}.", summary
+                );
+            }
+            warnings.summarize(tick_start);
+            governor.record(tick_start.elapsed());
+            continue;
+        }
+
+        let errors = &mut tick_status.publish_errors;
+        let max = config.max_markers_per_msg;
+
+        if governor.publish_markers() {
+            match &publishers.zone {
+                Some(zone_publisher) => {
+                    let (zone_topic, mesh_topic) =
+                        (&config.zone_markers_topic, &config.mesh_markers_topic);
+                    publish_markers(zone_publisher, &zone_array_msg, zone_topic, max, errors).await;
+                    let mesh = &publishers.mesh;
+                    publish_markers(mesh, &mesh_array_msg, mesh_topic, max, errors).await;
+                }
+                None => {
+                    // namespaced like the web markers, the local mesh URIs are kept
+                    let mut combined_msg = web_markers(
+                        &mesh_array_msg.markers,
+                        &zone_array_msg.markers,
+                        &config.meshes_dir,
+                        None,
+                    );
+                    if publishers.text.is_none() {
+                        combined_msg.markers.extend(text_array_msg.markers.iter().cloned());
+                    }
+                    if publishers.axes.is_none() {
+                        combined_msg.markers.extend(axis_array_msg.markers.iter().cloned());
+                    }
+                    order_markers(&mut combined_msg.markers, render_priority);
+                    let topic = config.combined_topic().unwrap_or("markers");
+                    publish_markers(&publishers.mesh, &combined_msg, topic, max, errors).await;
+                }
+            }
+            publish_markers(
+                &publishers.collision,
+                &collision_array_msg,
+                "collision_markers",
+                max,
+                errors,
+            )
+            .await;
+            if let Some(text_publisher) = &publishers.text {
+                publish_markers(text_publisher, &text_array_msg, "text_markers", max, errors).await;
+            }
+            if let Some(axis_publisher) = &publishers.axes {
+                publish_markers(axis_publisher, &axis_array_msg, "axis_markers", max, errors).await;
+            }
+            let links = &publishers.links;
+            publish_markers(links, &link_array_msg, "link_markers", max, errors).await;
+
+            if let Some(marker_topics) = publishers.marker_topics.as_mut() {
+                let mut groups = BTreeMap::new();
+                let prefix = &config.marker_ns_prefix;
+                group_markers(&mesh_array_msg.markers, "mesh", prefix, &mut groups);
+                group_markers(&zone_array_msg.markers, "zone", prefix, &mut groups);
+                group_markers(&collision_array_msg.markers, "collision", prefix, &mut groups);
+                group_markers(&text_array_msg.markers, "label", prefix, &mut groups);
+                group_markers(&axis_array_msg.markers, "axes", prefix, &mut groups);
+                group_markers(&link_array_msg.markers, "links", prefix, &mut groups);
+                for (group, markers) in groups {
+                    marker_topics.publish(&group, markers, max, errors).await;
+                }
+            }
+
+            if let Some(web_publisher) = &publishers.web {
+                let mut web_msg = web_markers(
+                    &mesh_array_msg.markers,
+                    &zone_array_msg.markers,
+                    &config.meshes_dir,
+                    config.web_mesh_url.as_deref(),
+                );
+                // ros3djs reads a zero stamp as the latest transform
+                stamp_markers(&mut web_msg.markers, &Default::default());
+                publish_markers(web_publisher, &web_msg, "web_markers", max, errors).await;
+            }
+
+            if let (Some(delta_publisher), [from, to]) =
+                (&publishers.delta, config.delta_frames.as_slice())
+            {
+                let poses = (pose_in_root(&frames_local, from), pose_in_root(&frames_local, to));
+                if let (Some((from_root, from_pose)), Some((to_root, to_pose))) = poses {
+                    if from_root == to_root {
+                        let offset = from_pose.inverse().compose(&to_pose).translation;
+                        let mut delta_msg = MarkerArray {
+                            markers: delta_markers(from, offset, lifetime(config.mesh_lifetime)),
+                        };
+                        prefix_namespaces(&mut delta_msg.markers, &config.marker_ns_prefix);
+                        publish(delta_publisher, &delta_msg, "delta_markers", errors).await;
+                    }
+                }
+            }
+
+            if let (Some(grid_publisher), Some(frame)) =
+                (&publishers.reference_grid, &config.reference_grid_frame)
+            {
+                let mut grid_msg = MarkerArray {
+                    markers: vec![reference_grid_marker(
+                        frame,
+                        config.reference_grid_size,
+                        config.reference_grid_spacing,
+                        config.reference_grid_color,
+                        lifetime(config.mesh_lifetime),
+                    )],
+                };
+                prefix_namespaces(&mut grid_msg.markers, &config.marker_ns_prefix);
+                publish(grid_publisher, &grid_msg, "reference_grid", errors).await;
+            }
+
+            if let (Some(trail_publisher), Some(trails)) =
+                (&publishers.orientation_trail, &orientation_trails)
+            {
+                let mut markers: Vec<Marker> = trails
+                    .trails()
+                    .flat_map(|(frame, poses)| {
+                        orientation_trail_markers(
+                            frame,
+                            &poses,
+                            config.orientation_trail_length,
+                            lifetime(config.mesh_lifetime),
+                        )
+                    })
+                    .collect();
+                delete_vanished(&mut markers, &mut trail_published);
+                prefix_namespaces(&mut markers, &config.marker_ns_prefix);
+                let trail_msg = MarkerArray { markers };
+                let topic = "orientation_trail";
+                publish_markers(trail_publisher, &trail_msg, topic, max, errors).await;
+            }
+
+            if let Some(grid_publisher) = &publishers.zone_grid {
+                let grid = zone_grid(&footprints, config.zone_grid_resolution, &config.zone_grid_frame);
+                if let Some(grid) = grid {
+                    publish(grid_publisher, &grid, "zone_grid", errors).await;
+                }
+            }
+        }
+
+        // ticks that published both the frames and the markers
+        let published = governor.publish_markers() && tick_status.publish_errors.is_empty();
+        let errors = &mut tick_status.publish_errors;
+        publishers
+            .scene_ready
+            .update(snapshot.read_at, published, tick_start, errors)
+            .await;
+
+        let sample = TickSample {
+            frames: last_frame_count,
+            active_transforms: snapshot.active_count,
+            static_transforms: snapshot.static_count,
+            mesh_markers: tick_status.mesh_markers,
+            zone_markers: tick_status.zone_markers,
+            fetch: snapshot.fetch_duration,
+            tick: tick_start.elapsed(),
+        };
+        if let Some(summary) = metrics.record(&sample, tick_start) {
+            log::debug!(target: log_targets::MARKERS, "{}", summary);
+        }
+
+        if let Some(diagnostics) = &publishers.diagnostics {
+            diagnostics.set(tick_status);
+        }
+
+        warnings.summarize(tick_start);
+        governor.record(tick_start.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config_with;
+    use crate::sink::RecordingSink;
+    use crate::tf_server::TfServer;
+    use r2r::std_msgs::msg::Bool;
+    use tokio::sync::watch;
+
+    fn build_error<T>(built: Result<T, VizError>) -> String {
+        match built {
+            Ok(_) => panic!("built without its required parts"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn publishing_is_retried_until_it_succeeds() {
+        let sink = RecordingSink::failing(2);
+        let msg = Bool { data: true };
+        assert!(publish_with_retry(&sink, &msg, "scene_ready").await.is_ok());
+        assert_eq!(sink.attempts(), 3);
+        assert_eq!(sink.sent(), [msg]);
+    }
+
+    #[tokio::test]
+    async fn publishing_gives_up_after_the_last_attempt() {
+        let sink = RecordingSink::failing(PUBLISH_ATTEMPTS);
+        let mut errors = vec![];
+        publish(&sink, &Bool { data: true }, "scene_ready", &mut errors).await;
+        assert_eq!(sink.attempts(), PUBLISH_ATTEMPTS);
+        assert!(sink.sent().is_empty());
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn builders_name_the_missing_parts() {
+        let error = build_error(VisualizationServer::builder().build());
+        assert!(error.contains("missing publishers, snapshots, timer, config"));
+        let error = build_error(
+            VisualizationServer::builder()
+                .snapshots(watch::channel(None).1)
+                .config(config_with(&[]))
+                .build(),
+        );
+        assert!(error.contains("missing publishers, timer"));
+        let error = build_error(TfServer::builder().config(config_with(&[])).build());
+        assert!(error.contains("missing publishers, connection_manager, timer, clock, snapshots"));
+    }
+}
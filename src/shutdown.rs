@@ -0,0 +1,172 @@
+use r2r::visualization_msgs::msg::MarkerArray;
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use crate::log_targets;
+use crate::markers::delete_all_marker;
+use crate::sink::MessageSink;
+
+/// Exit code when the node shuts down because the ROS spin thread panicked.
+pub const SPIN_PANIC_EXIT_CODE: i32 = 3;
+
+/// Wait between the two DELETEALLs at startup.
+const CLEAR_ON_START_DELAY: Duration = Duration::from_millis(500);
+
+/// Send a DELETEALL on each marker topic so a crashed node leaves no markers behind.
+pub fn clear_markers<S: MessageSink<MarkerArray>>(publishers: &[(&str, S)]) {
+    let msg = MarkerArray {
+        markers: vec![delete_all_marker()],
+    };
+    for (topic, publisher) in publishers {
+        if let Err(e) = publisher.publish(&msg) {
+            log::error!(target: log_targets::NODE,
+                "Failed to clear the {} with: '{}'", topic, e
+            );
+        }
+    }
+}
+
+/// Clear the markers a previous run left in a running RViz, before the first
+/// publish (CLEAR_ON_START). The second DELETEALL, CLEAR_ON_START_DELAY
+/// later, reaches an RViz that hadn't discovered the publishers yet.
+pub async fn clear_previous_markers<S: MessageSink<MarkerArray>>(publishers: &[(&str, S)]) {
+    clear_markers(publishers);
+    tokio::time::sleep(CLEAR_ON_START_DELAY).await;
+    clear_markers(publishers);
+}
+
+/// Text of a panic payload, which is a `&str` or `String` for `panic!` messages.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Call `spin_once` until it panics, and return the panic's message.
+pub fn spin_until_panic(mut spin_once: impl FnMut()) -> String {
+    loop {
+        if let Err(payload) = std::panic::catch_unwind(AssertUnwindSafe(&mut spin_once)) {
+            return panic_message(payload.as_ref());
+        }
+    }
+}
+
+/// Shut down after the ROS spin thread ended with `crash`, the message of
+/// the panic it caught or the thread's own panic. Stops the `tasks`, which
+/// closes their Redis connections, clears the markers on `publishers` and
+/// returns the exit code.
+pub async fn shut_down_after_panic<S: MessageSink<MarkerArray>>(
+    crash: std::thread::Result<String>,
+    tasks: Vec<JoinHandle<()>>,
+    publishers: &[(&str, S)],
+) -> i32 {
+    let message = match crash {
+        Ok(message) => message,
+        Err(payload) => panic_message(payload.as_ref()),
+    };
+    log::error!(target: log_targets::NODE,
+        "The ROS spin thread panicked with: '{}', shutting down.", message
+    );
+    for task in &tasks {
+        task.abort();
+    }
+    for task in tasks {
+        let _ = task.await;
+    }
+    clear_markers(publishers);
+    SPIN_PANIC_EXIT_CODE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markers::{ADD, DELETEALL};
+    use crate::server::publish_markers;
+    use crate::sink::RecordingSink;
+    use r2r::visualization_msgs::msg::Marker;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn startup_clears_before_the_first_publish() {
+        let mesh = RecordingSink::<MarkerArray>::default();
+        clear_previous_markers(&[("mesh_markers", mesh.clone())]).await;
+        let scene = MarkerArray {
+            markers: vec![Marker::default()],
+        };
+        publish_markers(&mesh, &scene, "mesh_markers", 0, &mut vec![]).await;
+
+        let actions: Vec<Vec<i32>> = mesh
+            .sent()
+            .iter()
+            .map(|msg| msg.markers.iter().map(|marker| marker.action).collect())
+            .collect();
+        assert_eq!(actions, [vec![DELETEALL], vec![DELETEALL], vec![ADD]]);
+    }
+
+    #[test]
+    fn every_topic_gets_one_deleteall() {
+        let mesh = RecordingSink::<MarkerArray>::failing(1);
+        let zone = RecordingSink::<MarkerArray>::default();
+        clear_markers(&[
+            ("mesh_markers", mesh.clone()),
+            ("zone_markers", zone.clone()),
+        ]);
+
+        // a failed topic doesn't keep the others from being cleared
+        assert_eq!(mesh.attempts(), 1);
+        assert!(mesh.sent().is_empty());
+        let sent = zone.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].markers.len(), 1);
+        // an empty namespace, so every namespace is cleared
+        assert_eq!(sent[0].markers[0].action, DELETEALL);
+        assert_eq!(sent[0].markers[0].ns, "");
+    }
+
+    #[test]
+    fn spinning_ends_with_the_panic_message() {
+        let mut spins = 0;
+        let message = spin_until_panic(|| {
+            spins += 1;
+            if spins == 3 {
+                panic!("spun {} times", spins);
+            }
+        });
+        assert_eq!(message, "spun 3 times");
+        assert_eq!(spins, 3);
+    }
+
+    /// Sets its flag when dropped, i.e. when the task holding it stopped.
+    struct Stopped(Arc<AtomicBool>);
+
+    impl Drop for Stopped {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_spin_panic_stops_the_tasks_and_clears_the_markers() {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let guard = Stopped(stopped.clone());
+        let server = tokio::task::spawn(async move {
+            let _guard = guard;
+            std::future::pending::<()>().await
+        });
+        let mesh = RecordingSink::<MarkerArray>::default();
+        let crash = std::thread::spawn(|| -> String { panic!("spin thread died") }).join();
+
+        let exit_code =
+            shut_down_after_panic(crash, vec![server], &[("mesh_markers", mesh.clone())]).await;
+        assert_eq!(exit_code, SPIN_PANIC_EXIT_CODE);
+        assert!(stopped.load(Ordering::Relaxed));
+        assert_eq!(mesh.sent()[0].markers[0].action, DELETEALL);
+    }
+}
@@ -0,0 +1,100 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use micro_sp::*;
+
+use crate::log_targets;
+use crate::metadata::{get_value, stores_mesh_color, Metadata};
+use crate::validation::local_mesh_path;
+
+/// Appearance defaults of a mesh file from the YAML file next to it, the
+/// mesh path with a `.yaml` extension, keyed like the entries of `meshes`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeshSidecar {
+    /// Mesh multiplier, like `mesh_scale`.
+    pub scale: Option<f32>,
+    /// RGBA in 0.0..=1.0
+    pub color: Option<[f32; 4]>,
+    pub offset_xyz: Option<String>,
+    pub offset_rpy: Option<String>,
+    pub offset_quat: Option<String>,
+}
+
+impl MeshSidecar {
+    /// Fill in what the frame's Redis metadata `map` leaves unset, Redis wins
+    /// key by key: a stored color or offset key of any form keeps the sidecar's.
+    pub fn apply(&self, map: &MapOrUnknown, metadata: &mut Metadata) {
+        let stored = |keys: &[&str]| keys.iter().any(|key| get_value(map, key).is_some());
+        if let Some(scale) = self.scale {
+            if !stored(&["mesh_scale", "mesh_scale_x", "mesh_scale_y", "mesh_scale_z"]) {
+                metadata.mesh_scale = scale;
+            }
+        }
+        if let Some([r, g, b, a]) = self.color {
+            if !stores_mesh_color(map) {
+                [metadata.mesh_r, metadata.mesh_g, metadata.mesh_b, metadata.mesh_a] = [r, g, b, a];
+            }
+        }
+        if !stored(&["mesh_offset_xyz", "mesh_offset_rpy", "mesh_offset_quat"]) {
+            metadata.mesh_offset_xyz = self.offset_xyz.clone();
+            metadata.mesh_offset_rpy = self.offset_rpy.clone();
+            metadata.mesh_offset_quat = self.offset_quat.clone();
+        }
+    }
+}
+
+pub fn sidecar_path(mesh_path: &Path) -> PathBuf {
+    mesh_path.with_extension("yaml")
+}
+
+/// The sidecars of the local mesh files, each read once, the first time its
+/// mesh is drawn. Edits to a sidecar show after a restart.
+#[derive(Debug, Clone, Default)]
+pub struct MeshSidecars {
+    /// None for meshes without a sidecar, or with one that doesn't parse.
+    cache: HashMap<PathBuf, Option<MeshSidecar>>,
+}
+
+impl MeshSidecars {
+    /// The sidecar of `mesh_file` resolved against `meshes_dir`, None for
+    /// `package://` meshes, which only RViz resolves. A sidecar that can't be
+    /// read or parsed is logged once and ignored.
+    pub fn get(&mut self, meshes_dir: &str, mesh_file: &str) -> Option<&MeshSidecar> {
+        let mesh_path = local_mesh_path(meshes_dir, mesh_file)?;
+        self.cache
+            .entry(mesh_path)
+            .or_insert_with_key(|mesh_path| read_sidecar(&sidecar_path(mesh_path)))
+            .as_ref()
+    }
+}
+
+fn read_sidecar(path: &Path) -> Option<MeshSidecar> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            log::warn!(target: log_targets::MARKERS,
+                "Ignoring the mesh sidecar {}, reading it failed with: {}", path.display(), e
+            );
+            return None;
+        }
+    };
+    match serde_yaml::from_str(&text) {
+        Ok(sidecar) => {
+            log::debug!(target: log_targets::MARKERS,
+                "Read the mesh sidecar {}.", path.display()
+            );
+            Some(sidecar)
+        }
+        Err(e) => {
+            log::warn!(target: log_targets::MARKERS,
+                "Ignoring the malformed mesh sidecar {}, using the Redis metadata only: {}",
+                path.display(),
+                e
+            );
+            None
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use futures::{Stream, StreamExt};
+use r2r::builtin_interfaces::msg::Time;
+use r2r::rosgraph_msgs::msg::Clock as ClockMsg;
+use tokio::sync::watch;
+
+/// Where the node takes its stamps from, chosen once at startup by the
+/// `use_sim_time` parameter: the ROS clock, or the last time published on
+/// `/clock` by a simulator or a bag playback.
+pub enum StampClock {
+    Ros(r2r::Clock),
+    Sim(watch::Receiver<Time>),
+}
+
+impl StampClock {
+    /// The ROS clock, or the simulated one following `sim_time`, which reads
+    /// zero until the first message on `/clock`, like in rclcpp.
+    pub fn new(sim_time: Option<watch::Receiver<Time>>) -> Result<StampClock, r2r::Error> {
+        match sim_time {
+            Some(receiver) => Ok(StampClock::Sim(receiver)),
+            None => Ok(StampClock::Ros(r2r::Clock::create(r2r::ClockType::RosTime)?)),
+        }
+    }
+
+    pub fn now(&mut self) -> Result<Time, r2r::Error> {
+        match self {
+            StampClock::Ros(clock) => Ok(r2r::Clock::to_builtin_time(&clock.get_now()?)),
+            StampClock::Sim(receiver) => Ok(receiver.borrow().clone()),
+        }
+    }
+}
+
+/// Forward the times published on `/clock` to a simulated `StampClock`.
+pub async fn follow_sim_time(
+    mut messages: impl Stream<Item = ClockMsg> + Unpin,
+    sender: watch::Sender<Time>,
+) {
+    while let Some(msg) = messages.next().await {
+        sender.send_replace(msg.clock);
+    }
+}
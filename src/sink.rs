@@ -0,0 +1,77 @@
+use r2r::tf2_msgs::msg::TFMessage;
+use r2r::visualization_msgs::msg::MarkerArray;
+
+/// Where the messages of one topic go, an r2r publisher outside of tests, so
+/// the servers can be run against a recording fake without a ROS context.
+/// Sync, as the tasks hold on to their sinks while waiting to retry.
+pub trait MessageSink<T>: Send + Sync {
+    fn publish(&self, msg: &T) -> r2r::Result<()>;
+}
+
+impl<T: r2r::WrappedTypesupport> MessageSink<T> for r2r::Publisher<T> {
+    fn publish(&self, msg: &T) -> r2r::Result<()> {
+        r2r::Publisher::publish(self, msg)
+    }
+}
+
+impl<T, S: MessageSink<T> + ?Sized> MessageSink<T> for Box<S> {
+    fn publish(&self, msg: &T) -> r2r::Result<()> {
+        (**self).publish(msg)
+    }
+}
+
+pub type MarkerSink = Box<dyn MessageSink<MarkerArray>>;
+pub type TfSink = Box<dyn MessageSink<TFMessage>>;
+
+/// A sink keeping what it is sent, failing its first `failures` publishes, to
+/// run publishing code without a ROS context.
+#[cfg(test)]
+#[derive(Clone)]
+pub struct RecordingSink<T> {
+    sent: std::sync::Arc<std::sync::Mutex<Vec<T>>>,
+    attempts: std::sync::Arc<std::sync::atomic::AtomicU32>,
+    failures: u32,
+}
+
+#[cfg(test)]
+impl<T> Default for RecordingSink<T> {
+    fn default() -> RecordingSink<T> {
+        RecordingSink::failing(0)
+    }
+}
+
+#[cfg(test)]
+impl<T> RecordingSink<T> {
+    pub fn failing(failures: u32) -> RecordingSink<T> {
+        RecordingSink {
+            sent: Default::default(),
+            attempts: Default::default(),
+            failures,
+        }
+    }
+
+    /// Every publish, failed or not.
+    pub fn attempts(&self) -> u32 {
+        self.attempts.load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+impl<T: Clone> RecordingSink<T> {
+    /// The messages published successfully, in order.
+    pub fn sent(&self) -> Vec<T> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl<T: Clone + Send> MessageSink<T> for RecordingSink<T> {
+    fn publish(&self, msg: &T) -> r2r::Result<()> {
+        let attempt = self.attempts.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+        if attempt <= self.failures {
+            return Err(r2r::Error::RCL_RET_ERROR);
+        }
+        self.sent.lock().unwrap().push(msg.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+
+use crate::geometry::Isometry;
+
+/// Exponential smoothing of the active transforms: each tick the broadcast
+/// pose of a frame moves `factor` of the way from where it was toward the
+/// pose read from Redis, so slow or noisy updates glide instead of jumping.
+#[derive(Debug, Clone)]
+pub struct TransformSmoother {
+    factor: f64,
+    poses: HashMap<String, Isometry>,
+}
+
+impl TransformSmoother {
+    /// A `factor` of 1 (or more) follows the target exactly, values close to
+    /// 0 smooth the most. 0 itself would never move, so it is raised a little.
+    pub fn new(factor: f64) -> TransformSmoother {
+        TransformSmoother {
+            factor: factor.clamp(0.01, 1.0),
+            poses: HashMap::new(),
+        }
+    }
+
+    /// The pose to broadcast for `frame` this tick, given the latest `target`.
+    /// A frame seen for the first time starts at its target.
+    pub fn smooth(&mut self, frame: &str, target: Isometry) -> Isometry {
+        let pose = match self.poses.get(frame) {
+            Some(previous) => previous.interpolate(&target, self.factor),
+            None => target,
+        };
+        self.poses.insert(frame.to_string(), pose);
+        pose
+    }
+
+    /// Forget frames that are gone or no longer active, they start at their
+    /// target if they return.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.poses.retain(|frame, _| keep(frame));
+    }
+}
@@ -0,0 +1,128 @@
+use futures::{Stream, StreamExt};
+use r2r::geometry_msgs::msg::TransformStamped;
+use r2r::tf2_msgs::msg::TFMessage;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use micro_sp::*;
+
+use crate::config::Config;
+use crate::geometry::Isometry;
+use crate::log_targets;
+use crate::metadata::get_bool;
+use crate::scene_edits::new_frame;
+
+/// Metadata flag of frames written by the mirror. The server doesn't
+/// broadcast them, their source already does.
+pub const MIRRORED_KEY: &str = "mirrored_from_tf";
+
+/// The stored form of a transform received on `tf`: an active frame tagged
+/// with `MIRRORED_KEY`.
+pub fn mirrored_frame(transform: &TransformStamped) -> Result<SPTransformStamped, String> {
+    let mut frame = new_frame(
+        &transform.header.frame_id,
+        &transform.child_frame_id,
+        &transform.transform,
+        true,
+    )?;
+    frame.metadata = MapOrUnknown::Map(vec![(
+        SPValue::String(StringOrUnknown::String(MIRRORED_KEY.to_string())),
+        SPValue::Bool(BoolOrUnknown::Bool(true)),
+    )]);
+    Ok(frame)
+}
+
+/// Whether a frame moved by more than `epsilon`, in meters or quaternion
+/// components, from `last`.
+fn moved(last: &Isometry, pose: &Isometry, epsilon: f64) -> bool {
+    let translation = last.translation.iter().zip(pose.translation.iter());
+    let rotation = last.rotation.iter().zip(pose.rotation.iter());
+    translation.chain(rotation).any(|(a, b)| (a - b).abs() > epsilon)
+}
+
+/// Who a child frame on `tf` belongs to, decided on its first transform.
+#[derive(Debug, Clone)]
+enum Owner {
+    /// Stored by someone else, e.g. the scenario, and broadcast by this node.
+    Redis,
+    /// Mirrored, with the pose last written.
+    Mirror(Option<Isometry>),
+}
+
+/// Write the transforms other nodes publish on `tf` into Redis as active
+/// frames, whenever one moved by more than MIRROR_TF_EPSILON. Frames already
+/// stored without the mirror tag are left alone, which includes everything
+/// this node broadcasts itself.
+pub async fn mirror_tf(
+    mut messages: impl Stream<Item = TFMessage> + Unpin,
+    connection_manager: Arc<ConnectionManager>,
+    config: Config,
+) {
+    let mut owners: HashMap<String, Owner> = HashMap::new();
+    while let Some(msg) = messages.next().await {
+        let mut con = connection_manager.get_connection().await;
+        let mut pipe = redis::pipe();
+        let mut written = vec![];
+        for transform in &msg.transforms {
+            let key = format!("{}{}", config.transform_key_prefix, transform.child_frame_id);
+            if !owners.contains_key(&transform.child_frame_id) {
+                let stored = redis::cmd("GET").arg(&key).query_async::<Option<String>>(&mut con);
+                let stored = match stored.await {
+                    Ok(stored) => stored,
+                    Err(e) => {
+                        log::error!(target: log_targets::TF,
+                            "Failed to read '{}' to mirror it with: {}", key, e
+                        );
+                        continue;
+                    }
+                };
+                let mirrored = stored
+                    .and_then(|stored| serde_json::from_str::<SPTransformStamped>(&stored).ok())
+                    .map(|frame| get_bool(&frame.metadata, MIRRORED_KEY) == Some(true));
+                let owner = match mirrored {
+                    Some(false) => Owner::Redis,
+                    _ => Owner::Mirror(None),
+                };
+                owners.insert(transform.child_frame_id.clone(), owner);
+            }
+            let Some(Owner::Mirror(last)) = owners.get_mut(&transform.child_frame_id) else {
+                continue;
+            };
+            let frame = match mirrored_frame(transform) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    log::warn!(target: log_targets::TF,
+                        "Not mirroring '{}': {}.", transform.child_frame_id, e
+                    );
+                    continue;
+                }
+            };
+            let pose = Isometry::from_frame(&frame);
+            if matches!(last, Some(last) if !moved(last, &pose, config.mirror_tf_epsilon)) {
+                continue;
+            }
+            match serde_json::to_string(&frame) {
+                Ok(value) => {
+                    pipe.set(key, value).ignore();
+                    *last = Some(pose);
+                    written.push(transform.child_frame_id.clone());
+                }
+                Err(e) => log::error!(target: log_targets::TF,
+                    "Failed to serialize the mirrored '{}' with: {}", transform.child_frame_id, e
+                ),
+            }
+        }
+        if written.is_empty() {
+            continue;
+        }
+        if let Err(e) = pipe.query_async::<()>(&mut con).await {
+            log::error!(target: log_targets::TF,
+                "Failed to mirror {} to Redis with: {}", written.join(", "), e
+            );
+            // written again with the next transform
+            for frame in written {
+                owners.insert(frame, Owner::Mirror(None));
+            }
+        }
+    }
+}
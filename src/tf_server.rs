@@ -0,0 +1,657 @@
+use r2r::builtin_interfaces::msg::Time;
+use r2r::geometry_msgs::msg::{Quaternion, TransformStamped};
+use r2r::std_msgs::msg::Header;
+use r2r::tf2_msgs::msg::TFMessage;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+use micro_sp::*;
+
+use crate::assemblies::hidden_assemblies;
+use crate::backoff::Backoff;
+use crate::broadcast::{BroadcastLimiter, Decimation, StaticChanges};
+use crate::config::Config;
+use crate::control::PublishControl;
+use crate::dry_run::DryRunLog;
+use crate::empty_reads::EmptyReadHold;
+use crate::error::VizError;
+use crate::events::FrameEventTracker;
+use crate::geometry::{
+    quantize_frames, quaternion_norm, sanitize_quaternion, to_enu, Isometry, MIN_QUATERNION_NORM,
+};
+use crate::health::HealthTransitions;
+use crate::keyspace::{RebuildTrigger, SceneChanges};
+use crate::layers::hidden_layers;
+use crate::log_targets;
+use crate::metadata::{decode_metadata, get_string};
+use crate::one_shot::OneShot;
+use crate::outdated::{is_outdated, OutdatedAction};
+use crate::protobuf::FrameFormat;
+use crate::redis_reads::read_frames;
+use crate::rename::FrameRenames;
+use crate::replicas::ReadRouter;
+use crate::scalars::fetch_scalars;
+use crate::scene::scene_summary;
+use crate::secondary::SecondaryScene;
+use crate::sim_time::StampClock;
+use crate::server::{missing_parts, publish};
+use crate::sink::TfSink;
+use crate::smoothing::TransformSmoother;
+use crate::throttle::WarningThrottle;
+use crate::tree::{
+    case_duplicates, chain_depths, detect_tf_cycles, merge_case_duplicates,
+    resolve_parent_conflicts, CaseDuplicatePolicy, ParentConflictPolicy,
+};
+use crate::tree_stats::TreeStatsReporter;
+use crate::ttl::fetch_ttls;
+
+/// Everything one read of Redis gave, which the markers are built from. The
+/// frames are only ever read here, the marker task works off the latest
+/// snapshot.
+#[derive(Debug, Clone)]
+pub struct FrameSnapshot {
+    /// Renamed, conflict resolved and converted, as broadcast on TF.
+    pub frames: HashMap<String, SPTransformStamped>,
+    /// Remaining milliseconds of the frames with SHOW_TTL.
+    pub ttls: HashMap<String, i64>,
+    pub hidden_assemblies: HashSet<String>,
+    pub hidden_layers: HashSet<String>,
+    /// Values of the `scale_scalar_key` and `joint_value_key` keys.
+    pub scalars: HashMap<String, f64>,
+    /// Stamp of the transforms broadcast from these frames.
+    pub time_stamp: Time,
+    /// Frames not broadcast for longer than STALE_TF_SECS.
+    pub stale: HashSet<String>,
+    /// Frames whose `last_updated` is older than MAX_TRANSFORM_AGE_SECS,
+    /// to be tinted with OUTDATED_TRANSFORMS=tint.
+    pub outdated: HashSet<String>,
+    /// Failures broadcasting the transforms, for the diagnostics.
+    pub publish_errors: Vec<String>,
+    /// When the frames were read from Redis, and how long that took.
+    pub read_at: std::time::Instant,
+    pub fetch_duration: std::time::Duration,
+    /// Transforms broadcast on `tf` and `tf_static`.
+    pub active_count: usize,
+    pub static_count: usize,
+}
+
+/// The latest snapshot, None while Redis is unreachable.
+pub type SnapshotReceiver = watch::Receiver<Option<Arc<FrameSnapshot>>>;
+
+/// Everything the TF server publishes on.
+pub struct FramePublishers {
+    pub active_frames: TfSink,
+    pub static_frames: TfSink,
+    pub frame_events: Option<r2r::Publisher<r2r::std_msgs::msg::String>>,
+    /// Latched Redis reachability on `redis_visualization/ok`.
+    pub health: r2r::Publisher<r2r::std_msgs::msg::Bool>,
+}
+
+/// The TF task, put together with `TfServer::builder`.
+pub struct TfServer {
+    publishers: FramePublishers,
+    connection_manager: Arc<ConnectionManager>,
+    timer: r2r::Timer,
+    config: Config,
+    clock: StampClock,
+    snapshots: watch::Sender<Option<Arc<FrameSnapshot>>>,
+    secondary: Option<SecondaryScene>,
+    control: PublishControl,
+    scene_sender: watch::Sender<String>,
+    changes: Option<SceneChanges>,
+}
+
+/// The parts of a `TfServer`. The publishers, connection manager, timer,
+/// configuration, clock and snapshot sender are required. By default there
+/// is no secondary scene, the server never pauses, nothing receives the
+/// scene summary and Redis is read every tick.
+#[derive(Default)]
+pub struct TfServerBuilder {
+    publishers: Option<FramePublishers>,
+    connection_manager: Option<Arc<ConnectionManager>>,
+    timer: Option<r2r::Timer>,
+    config: Option<Config>,
+    clock: Option<StampClock>,
+    snapshots: Option<watch::Sender<Option<Arc<FrameSnapshot>>>>,
+    secondary: Option<SecondaryScene>,
+    control: PublishControl,
+    scene_sender: Option<watch::Sender<String>>,
+    changes: Option<SceneChanges>,
+}
+
+impl TfServerBuilder {
+    pub fn publishers(mut self, publishers: FramePublishers) -> Self {
+        self.publishers = Some(publishers);
+        self
+    }
+
+    pub fn connection_manager(mut self, connection_manager: Arc<ConnectionManager>) -> Self {
+        self.connection_manager = Some(connection_manager);
+        self
+    }
+
+    /// Ticks every TF_PERIOD_MS.
+    pub fn timer(mut self, timer: r2r::Timer) -> Self {
+        self.timer = Some(timer);
+        self
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Stamps the transforms of each tick.
+    pub fn clock(mut self, clock: StampClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Hands the frames of each tick to the marker task.
+    pub fn snapshots(mut self, snapshots: watch::Sender<Option<Arc<FrameSnapshot>>>) -> Self {
+        self.snapshots = Some(snapshots);
+        self
+    }
+
+    pub fn secondary(mut self, secondary: Option<SecondaryScene>) -> Self {
+        self.secondary = secondary;
+        self
+    }
+
+    pub fn control(mut self, control: PublishControl) -> Self {
+        self.control = control;
+        self
+    }
+
+    /// Receives the scene summary whenever it changes.
+    pub fn scene_sender(mut self, scene_sender: watch::Sender<String>) -> Self {
+        self.scene_sender = Some(scene_sender);
+        self
+    }
+
+    /// Reads Redis only after these changes, see REDIS_NOTIFY.
+    pub fn changes(mut self, changes: Option<SceneChanges>) -> Self {
+        self.changes = changes;
+        self
+    }
+
+    /// The server, or a configuration error naming the missing parts.
+    pub fn build(self) -> Result<TfServer, VizError> {
+        let missing = missing_parts(&[
+            ("publishers", self.publishers.is_some()),
+            ("connection_manager", self.connection_manager.is_some()),
+            ("timer", self.timer.is_some()),
+            ("config", self.config.is_some()),
+            ("clock", self.clock.is_some()),
+            ("snapshots", self.snapshots.is_some()),
+        ]);
+        let (
+            Some(publishers),
+            Some(connection_manager),
+            Some(timer),
+            Some(config),
+            Some(clock),
+            Some(snapshots),
+        ) = (
+            self.publishers,
+            self.connection_manager,
+            self.timer,
+            self.config,
+            self.clock,
+            self.snapshots,
+        )
+        else {
+            return Err(VizError::Config(format!(
+                "the TF server is missing {}",
+                missing
+            )));
+        };
+        // without a receiver the summary is never built
+        let scene_sender = self
+            .scene_sender
+            .unwrap_or_else(|| watch::channel(String::new()).0);
+        Ok(TfServer {
+            publishers,
+            connection_manager,
+            timer,
+            config,
+            clock,
+            snapshots,
+            secondary: self.secondary,
+            control: self.control,
+            scene_sender,
+            changes: self.changes,
+        })
+    }
+}
+
+impl TfServer {
+    pub fn builder() -> TfServerBuilder {
+        TfServerBuilder::default()
+    }
+
+    /// Read the frames from Redis every TF_PERIOD_MS, broadcast their
+    /// transforms and hand them to the marker task through the snapshots.
+    pub async fn run(self) -> Result<(), VizError> {
+        broadcast_frames(self).await
+    }
+}
+
+async fn broadcast_frames(server: TfServer) -> Result<(), VizError> {
+    let TfServer {
+        publishers,
+        connection_manager,
+        mut timer,
+        config,
+        mut clock,
+        snapshots,
+        secondary,
+        control,
+        scene_sender,
+        changes,
+    } = server;
+    let mut warnings = WarningThrottle::new(config.warning_interval_secs);
+    let mut rebuilds =
+        changes.map(|changes| RebuildTrigger::new(changes, config.redis_notify_heartbeat_secs));
+    let mut read_router = ReadRouter::new(connection_manager, &config.redis_replicas);
+    let mut reconnect = Backoff::new(
+        std::time::Duration::from_millis(100),
+        std::time::Duration::from_secs(5),
+    );
+    // a separate backoff, Redis is reachable when a read fails
+    let mut read_retry = reconnect.clone();
+    let mut failed_reads: u64 = 0;
+    let mut renames = FrameRenames::parse(&config.frame_renames);
+    let mut empty_reads =
+        (config.empty_read_grace_secs > 0.0).then(|| EmptyReadHold::new(config.empty_read_grace_secs));
+    let mut broadcasts = BroadcastLimiter::default();
+    let mut static_changes = StaticChanges::default();
+    let mut one_shot = config.one_shot.then(|| OneShot::new(config.one_shot_idle_secs()));
+    let mut smoother = config
+        .smooth_transforms
+        .then(|| TransformSmoother::new(config.smoothing_factor));
+    let decimation = Decimation::new(&config.important_frames, config.tf_decimation);
+    let mut tick: u64 = 0;
+    let stale_after = std::time::Duration::from_secs_f64(config.stale_tf_secs.max(0.0));
+    let mut frame_events = FrameEventTracker::new(config.frame_event_threshold);
+    let mut tree_stats = (config.tree_stats_secs > 0)
+        .then(|| TreeStatsReporter::new(config.tree_stats_secs, config.frame_event_threshold));
+    let mut dry_run = DryRunLog::default();
+    let mut health = HealthTransitions::default();
+    loop {
+        timer.tick().await?;
+        if control.paused() {
+            continue;
+        }
+        if one_shot.is_none()
+            && matches!(&mut rebuilds, Some(rebuilds) if !rebuilds.due(std::time::Instant::now()))
+        {
+            continue;
+        }
+        let tick_start = std::time::Instant::now();
+        let mut con = match read_router.read_connection().await {
+            Some(con) => {
+                if reconnect.backing_off() {
+                    log::info!(target: log_targets::TF, "Redis is reachable again.");
+                    reconnect.reset();
+                }
+                health.publish(&publishers.health, true).await;
+                con
+            }
+            None => {
+                health.publish(&publishers.health, false).await;
+                snapshots.send_replace(None);
+                // don't hammer Redis at the tick rate while it is down
+                let delay = reconnect.delay();
+                log::warn!(target: log_targets::TF,
+                    "Redis is unreachable, retrying in {:?}.", delay
+                );
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+        };
+        if matches!(&mut one_shot, Some(one_shot) if !one_shot.due(tick_start)) {
+            continue;
+        }
+        let mut active_transforms = vec![];
+        let mut static_transforms = vec![];
+        let fetch_start = std::time::Instant::now();
+        let frames_stored = if config.pipelined_reads
+            || config.atomic_reads
+            || config.frame_format != FrameFormat::Json
+        {
+            read_frames(
+                &mut con,
+                &config.transform_key_prefix,
+                config.frame_format,
+                config.atomic_reads,
+                &mut warnings,
+            )
+            .await
+        } else {
+            TransformsManager::get_all_transforms(&mut con)
+                .await
+                .map_err(|e| VizError::TransformLoad(e.to_string()))
+        };
+        let frames_stored = match frames_stored {
+            Ok(frames) => {
+                if failed_reads > 0 {
+                    log::info!(target: log_targets::TF,
+                        "Read the frames again after {} failed reads.", failed_reads
+                    );
+                    failed_reads = 0;
+                    read_retry.reset();
+                }
+                frames
+            }
+            Err(e) => {
+                // the marker task waits for frames, like while Redis is unreachable
+                snapshots.send_replace(None);
+                failed_reads += 1;
+                let delay = read_retry.delay();
+                if warnings.allow("", "failed reads", tick_start) {
+                    log::error!(target: log_targets::TF,
+                        "Failed to read the frames with: {}, {} failed in a row, retrying in {:?}.",
+                        e, failed_reads, delay
+                    );
+                }
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+        };
+        let fetch_duration = fetch_start.elapsed();
+        let frames_stored = match empty_reads.as_mut() {
+            Some(empty_reads) => empty_reads.filter(frames_stored, tick_start),
+            None => frames_stored,
+        };
+        let ttls: HashMap<String, i64> = if config.show_ttl {
+            let names: Vec<String> = frames_stored.keys().cloned().collect();
+            match fetch_ttls(&mut con, &config.transform_key_prefix, &names).await {
+                Ok(ttls) => ttls
+                    .into_iter()
+                    .map(|(name, ttl)| (renames.rename(&name).to_string(), ttl))
+                    .collect(),
+                Err(e) => {
+                    log::error!(target: log_targets::TF,
+                        "Failed to read frame TTLs with: {}", e
+                    );
+                    HashMap::new()
+                }
+            }
+        } else {
+            HashMap::new()
+        };
+        let mut frames_local = renames.apply(frames_stored);
+        if config.case_duplicates != CaseDuplicatePolicy::Off {
+            let groups = case_duplicates(&frames_local);
+            for names in &groups {
+                if warnings.allow(&names[0], "case duplicates", tick_start) {
+                    log::warn!(target: log_targets::TF,
+                        "Frame names {} differ only by case, TF treats them as different frames{}.",
+                        names.iter().map(|n| format!("'{}'", n)).collect::<Vec<_>>().join(", "),
+                        match config.case_duplicates {
+                            CaseDuplicatePolicy::Merge => format!(", merging them into '{}'", names[0]),
+                            _ => String::new(),
+                        }
+                    );
+                }
+            }
+            if config.case_duplicates == CaseDuplicatePolicy::Merge {
+                merge_case_duplicates(&mut frames_local, &groups);
+            }
+        }
+        for conflict in resolve_parent_conflicts(&mut frames_local, config.parent_conflict_policy) {
+            if warnings.allow(&conflict.child_frame_id, "parent conflicts", tick_start) {
+                let parents: Vec<String> = conflict
+                    .definitions
+                    .iter()
+                    .map(|(key, parent)| format!("'{}' (key '{}')", parent, key))
+                    .collect();
+                let resolution = match config.parent_conflict_policy {
+                    ParentConflictPolicy::Error => "publishing none of them",
+                    ParentConflictPolicy::First => "keeping the first",
+                    ParentConflictPolicy::Last => "keeping the last",
+                };
+                log::error!(target: log_targets::TF,
+                    "Frame '{}' is defined under several parents: {}, {}.",
+                    conflict.child_frame_id,
+                    parents.join(", "),
+                    resolution
+                );
+            }
+        }
+        // TF rejects the whole tree over a loop, so the frames on one are left
+        // out of the transforms, their markers are still published
+        let mut cyclic: HashSet<String> = HashSet::new();
+        for cycle in detect_tf_cycles(&frames_local) {
+            if warnings.allow(&cycle[0], "cycles", tick_start) {
+                log::error!(target: log_targets::TF,
+                    "Frames {} form a cycle, not broadcasting their transforms.",
+                    cycle.join(" -> ")
+                );
+            }
+            cyclic.extend(cycle);
+        }
+        to_enu(&mut frames_local, config.frame_convention);
+        quantize_frames(
+            &mut frames_local,
+            config.quantize_translation,
+            config.quantize_rotation,
+        );
+        if let Some(secondary) = &secondary {
+            frames_local.extend(secondary.frames.clone());
+        }
+        if let Some(tree_stats) = tree_stats.as_mut() {
+            tree_stats.update(&frames_local, config.force_static, tick_start);
+        }
+        if !scene_sender.is_closed() {
+            let summary = serde_json::to_string(&scene_summary(&frames_local))?;
+            scene_sender.send_if_modified(|scene| {
+                if *scene != summary {
+                    *scene = summary;
+                    true
+                } else {
+                    false
+                }
+            });
+        }
+        if let Some(events_publisher) = publishers.frame_events.as_ref().filter(|_| !config.dry_run) {
+            let stamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|since| since.as_secs_f64())
+                .unwrap_or(0.0);
+            for event in frame_events.update(&frames_local, stamp) {
+                let msg = r2r::std_msgs::msg::String {
+                    data: serde_json::to_string(&event)?,
+                };
+                if let Err(e) = events_publisher.publish(&msg) {
+                    log::error!(target: log_targets::TF,
+                        "Publisher failed to send a frame_events message with: '{}'", e
+                    );
+                }
+            }
+        }
+        let hidden = match &config.assembly_visibility_key {
+            Some(key) => hidden_assemblies(&mut con, key).await,
+            None => HashSet::new(),
+        };
+        let layers_hidden = match &config.layer_visibility_key {
+            Some(key) => hidden_layers(&mut con, key).await,
+            None => HashSet::new(),
+        };
+        if config.max_tree_depth > 0 {
+            let mut deep: Vec<(String, usize)> = chain_depths(&frames_local)
+                .into_iter()
+                .filter(|(_, depth)| *depth > config.max_tree_depth)
+                .collect();
+            deep.sort();
+            for (frame, depth) in deep {
+                if warnings.allow(&frame, "deep chains", tick_start) {
+                    log::warn!(target: log_targets::TF,
+                        "Frame '{}' is {} levels below its root, more than MAX_TREE_DEPTH of {}, which usually means broken parent links.",
+                        frame, depth, config.max_tree_depth
+                    );
+                }
+            }
+        }
+        let scalar_keys: Vec<String> = frames_local
+            .values()
+            .flat_map(|frame| {
+                let scale_key = get_string(&frame.metadata, "scale_scalar_key");
+                scale_key.into_iter().chain(get_string(&frame.metadata, "joint_value_key"))
+            })
+            .collect::<HashSet<String>>()
+            .into_iter()
+            .collect();
+        let scalars = fetch_scalars(&mut con, &scalar_keys).await;
+        // every transform of a tick is stamped identically, so they form one
+        // consistent snapshot for TF listeners
+        let time_stamp = clock.now()?;
+        let now_secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since| since.as_secs_f64())
+            .unwrap_or(0.0);
+        let mut outdated: HashSet<String> = HashSet::new();
+        for frame in frames_local.values() {
+            let metadata = decode_metadata(&frame.metadata);
+            if is_outdated(metadata.last_updated, now_secs, config.max_transform_age_secs) {
+                if warnings.allow(&frame.child_frame_id, "outdated frames", tick_start) {
+                    log::warn!(target: log_targets::TF,
+                        "Frame '{}' was last updated {:.1}s ago, more than MAX_TRANSFORM_AGE_SECS of {}, {}.",
+                        frame.child_frame_id,
+                        now_secs - metadata.last_updated.unwrap_or(now_secs),
+                        config.max_transform_age_secs,
+                        match config.outdated_transforms {
+                            OutdatedAction::Skip => "leaving it out of TF and the markers",
+                            OutdatedAction::Tint => "tinting its markers",
+                        }
+                    );
+                }
+                outdated.insert(frame.child_frame_id.clone());
+                if config.outdated_transforms == OutdatedAction::Skip {
+                    continue;
+                }
+            }
+            let mut pose = Isometry::from_frame(frame);
+            let [x, y, z, w] = pose.rotation;
+            let stored = Quaternion { x, y, z, w };
+            let norm = quaternion_norm(&stored);
+            if !(norm >= MIN_QUATERNION_NORM && norm.is_finite())
+                && warnings.allow(&frame.child_frame_id, "degenerate rotations", tick_start)
+            {
+                log::warn!(target: log_targets::TF,
+                    "Frame '{}' has a rotation of norm {}, publishing it unrotated.",
+                    frame.child_frame_id, norm
+                );
+            }
+            let Quaternion { x, y, z, w } = sanitize_quaternion(stored);
+            pose.rotation = [x, y, z, w];
+            let active = frame.active_transform && !config.force_static;
+            if let Some(smoother) = smoother.as_mut().filter(|_| active) {
+                pose = smoother.smooth(&frame.child_frame_id, pose);
+            }
+            // the stored frames stay as they are, only what goes out on TF is reversed
+            let (parent, child, pose) = if config.swap_parent_child {
+                (&frame.child_frame_id, &frame.parent_frame_id, pose.inverse())
+            } else {
+                (&frame.parent_frame_id, &frame.child_frame_id, pose)
+            };
+            let transform = TransformStamped {
+                header: Header {
+                    stamp: time_stamp.clone(),
+                    frame_id: parent.clone(),
+                },
+                child_frame_id: child.clone(),
+                transform: pose.to_msg(),
+            };
+            if cyclic.contains(&frame.child_frame_id) || metadata.mirrored_from_tf {
+                // left out of TF, mirrored frames are broadcast by their source
+            } else if active {
+                if decimation.due(&frame.child_frame_id, tick)
+                    && broadcasts.due(&frame.child_frame_id, metadata.max_broadcast_hz, tick_start)
+                {
+                    active_transforms.push(transform);
+                }
+            } else {
+                static_transforms.push(transform);
+            }
+        }
+        if config.outdated_transforms == OutdatedAction::Skip {
+            // no markers either, they would hang on a frame TF doesn't know
+            frames_local.retain(|_, frame| !outdated.contains(&frame.child_frame_id));
+        }
+        broadcasts.retain(|frame| frames_local.contains_key(frame));
+        if let Some(smoother) = smoother.as_mut() {
+            smoother.retain(|frame| {
+                matches!(frames_local.get(frame), Some(stored) if stored.active_transform)
+            });
+        }
+        let stale: HashSet<String> = if config.stale_tf_secs > 0.0 {
+            frames_local
+                .values()
+                .map(|frame| &frame.child_frame_id)
+                .filter(|frame| broadcasts.stale(frame, tick_start, stale_after))
+                .cloned()
+                .collect()
+        } else {
+            HashSet::new()
+        };
+
+        let active_msg = TFMessage {
+            transforms: active_transforms,
+        };
+
+        let static_msg = TFMessage {
+            transforms: static_transforms,
+        };
+
+        let mut errors = vec![];
+        if config.dry_run {
+            let summary = format!(
+                "{} active and {} static transforms",
+                active_msg.transforms.len(),
+                static_msg.transforms.len()
+            );
+            if dry_run.changed(&summary) {
+                log::info!(target: log_targets::TF,
+                    "Dry run, not broadcasting {}.", summary
+                );
+            }
+        } else {
+            let topic = &config.tf_topic;
+            publish(&publishers.active_frames, &active_msg, topic, &mut errors).await;
+        }
+        // latched, late subscribers get the last set without it being resent,
+        // except for the ONE_SHOT republishes
+        if !config.dry_run
+            && (one_shot.is_some() || static_changes.changed(&static_msg.transforms))
+        {
+            let failed = errors.len();
+            let topic = &config.tf_static_topic;
+            publish(&publishers.static_frames, &static_msg, topic, &mut errors).await;
+            if errors.len() == failed {
+                static_changes.record(&static_msg.transforms);
+            }
+        }
+
+        snapshots.send_replace(Some(Arc::new(FrameSnapshot {
+            frames: frames_local,
+            ttls,
+            hidden_assemblies: hidden,
+            hidden_layers: layers_hidden,
+            scalars,
+            time_stamp,
+            stale,
+            outdated,
+            publish_errors: errors,
+            read_at: fetch_start,
+            fetch_duration,
+            active_count: active_msg.transforms.len(),
+            static_count: static_msg.transforms.len(),
+        })));
+
+        tick += 1;
+        warnings.summarize(tick_start);
+    }
+}
@@ -0,0 +1,27 @@
+use std::time::Duration;
+use tokio::sync::watch;
+
+use crate::log_targets;
+
+/// Publish the JSON scene summary the TF task builds from its reads on
+/// `tf_snapshot` every `period_secs`, the latest one each time, so dashboards
+/// get the frame graph without TF. Nothing is published before the first read.
+pub async fn publish_tf_snapshots(
+    publisher: r2r::Publisher<r2r::std_msgs::msg::String>,
+    scene: watch::Receiver<String>,
+    period_secs: f64,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(period_secs.max(0.01)));
+    loop {
+        interval.tick().await;
+        let data = scene.borrow().clone();
+        if data.is_empty() {
+            continue;
+        }
+        if let Err(e) = publisher.publish(&r2r::std_msgs::msg::String { data }) {
+            log::error!(target: log_targets::TF,
+                "Publisher failed to send a tf_snapshot message with: '{}'", e
+            );
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::log_targets;
+
+/// Per-frame, per-category throttle for warnings that would otherwise repeat
+/// every tick. Each frame logs a given kind of warning at most once per
+/// interval, and the warnings held back are summarized once per interval.
+pub struct WarningThrottle {
+    interval: Duration,
+    last: HashMap<(String, &'static str), Instant>,
+    suppressed: BTreeMap<&'static str, usize>,
+    last_summary: Option<Instant>,
+}
+
+impl WarningThrottle {
+    /// An interval of 0 lets every warning through.
+    pub fn new(interval_secs: f64) -> WarningThrottle {
+        WarningThrottle {
+            interval: Duration::from_secs_f64(interval_secs.max(0.0)),
+            last: HashMap::new(),
+            suppressed: BTreeMap::new(),
+            last_summary: None,
+        }
+    }
+
+    /// Whether a `category` warning about `frame` should be logged at `now`.
+    /// Warnings that shouldn't are counted for the summary.
+    pub fn allow(&mut self, frame: &str, category: &'static str, now: Instant) -> bool {
+        let key = (frame.to_string(), category);
+        match self.last.get(&key) {
+            Some(last) if now.duration_since(*last) < self.interval => {
+                *self.suppressed.entry(category).or_insert(0) += 1;
+                false
+            }
+            _ => {
+                self.last.insert(key, now);
+                true
+            }
+        }
+    }
+
+    /// Log how many warnings were suppressed, at most once per interval.
+    pub fn summarize(&mut self, now: Instant) {
+        let last_summary = *self.last_summary.get_or_insert(now);
+        if self.suppressed.is_empty() || now.duration_since(last_summary) < self.interval {
+            return;
+        }
+        let counts: Vec<String> = self
+            .suppressed
+            .iter()
+            .map(|(category, count)| format!("{} {}", count, category))
+            .collect();
+        log::warn!(target: log_targets::NODE,
+            "Suppressed repeated warnings in the last {:?}: {}.",
+            now.duration_since(last_summary),
+            counts.join(", ")
+        );
+        self.suppressed.clear();
+        self.last_summary = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_warning_is_suppressed_inside_its_interval() {
+        let mut warnings = WarningThrottle::new(1.0);
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        assert!(warnings.allow("table", "invalid zones", at(0)));
+        assert!(!warnings.allow("table", "invalid zones", at(500)));
+        // other frames and categories have their own intervals
+        assert!(warnings.allow("robot", "invalid zones", at(500)));
+        assert!(warnings.allow("table", "undecodable frames", at(500)));
+        assert!(warnings.allow("table", "invalid zones", at(1000)));
+        assert_eq!(warnings.suppressed["invalid zones"], 1);
+    }
+
+    #[test]
+    fn an_interval_of_zero_suppresses_nothing() {
+        let mut warnings = WarningThrottle::new(0.0);
+        let now = Instant::now();
+        assert!(warnings.allow("table", "invalid zones", now));
+        assert!(warnings.allow("table", "invalid zones", now));
+    }
+}
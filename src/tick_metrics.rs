@@ -0,0 +1,114 @@
+use std::time::{Duration, Instant};
+
+/// What one marker tick handled and how long it took.
+#[derive(Debug, Clone, Default)]
+pub struct TickSample {
+    pub frames: usize,
+    pub active_transforms: usize,
+    pub static_transforms: usize,
+    pub mesh_markers: usize,
+    pub zone_markers: usize,
+    /// Reading the frames from Redis, in the TF task.
+    pub fetch: Duration,
+    /// Building and publishing the markers.
+    pub tick: Duration,
+}
+
+/// Per-tick counts and durations, averaged over the ticks between two debug
+/// logs, one per second at most, so the tick rate doesn't flood the log.
+#[derive(Debug)]
+pub struct TickMetrics {
+    interval: Duration,
+    since: Option<Instant>,
+    ticks: u32,
+    sum: TickSample,
+    longest_tick: Duration,
+}
+
+impl TickMetrics {
+    pub fn new(interval: Duration) -> TickMetrics {
+        TickMetrics {
+            interval,
+            since: None,
+            ticks: 0,
+            sum: TickSample::default(),
+            longest_tick: Duration::ZERO,
+        }
+    }
+
+    /// Add a tick at `now`, returning the summary of the ticks since the last
+    /// one once an interval has passed.
+    pub fn record(&mut self, sample: &TickSample, now: Instant) -> Option<String> {
+        let since = *self.since.get_or_insert(now);
+        self.ticks += 1;
+        self.sum.frames += sample.frames;
+        self.sum.active_transforms += sample.active_transforms;
+        self.sum.static_transforms += sample.static_transforms;
+        self.sum.mesh_markers += sample.mesh_markers;
+        self.sum.zone_markers += sample.zone_markers;
+        self.sum.fetch += sample.fetch;
+        self.sum.tick += sample.tick;
+        self.longest_tick = self.longest_tick.max(sample.tick);
+        if now.duration_since(since) < self.interval {
+            return None;
+        }
+        let ticks = self.ticks as usize;
+        let summary = format!(
+            "{} ticks, per tick: {} frames ({} active, {} static transforms), {} mesh and {} zone markers, fetch {:?}, tick {:?} (longest {:?}).",
+            ticks,
+            self.sum.frames / ticks,
+            self.sum.active_transforms / ticks,
+            self.sum.static_transforms / ticks,
+            self.sum.mesh_markers / ticks,
+            self.sum.zone_markers / ticks,
+            self.sum.fetch / self.ticks,
+            self.sum.tick / self.ticks,
+            self.longest_tick
+        );
+        *self = TickMetrics::new(self.interval);
+        self.since = Some(now);
+        Some(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(frames: usize, tick_ms: u64) -> TickSample {
+        TickSample {
+            frames,
+            active_transforms: 3,
+            static_transforms: 2,
+            mesh_markers: 1,
+            zone_markers: 0,
+            fetch: Duration::from_millis(1),
+            tick: Duration::from_millis(tick_ms),
+        }
+    }
+
+    #[test]
+    fn ticks_are_averaged_into_one_summary_per_interval() {
+        let mut metrics = TickMetrics::new(Duration::from_secs(1));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(metrics.record(&sample(2, 10), at(0)), None);
+        assert_eq!(metrics.record(&sample(4, 20), at(400)), None);
+        assert_eq!(metrics.record(&sample(6, 40), at(999)), None);
+        assert_eq!(
+            metrics.record(&sample(8, 30), at(1000)).as_deref(),
+            Some(
+                "4 ticks, per tick: 5 frames (3 active, 2 static transforms), 1 mesh and 0 zone \
+                 markers, fetch 1ms, tick 25ms (longest 40ms)."
+            )
+        );
+        // the next interval starts at the summary
+        assert_eq!(metrics.record(&sample(2, 10), at(1500)), None);
+        let summary = metrics.record(&sample(2, 10), at(2000)).unwrap();
+        assert!(
+            summary.starts_with("2 ticks, per tick: 2 frames"),
+            "{}",
+            summary
+        );
+    }
+}
@@ -0,0 +1,418 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use micro_sp::*;
+
+/// Parent frame ids that are not themselves defined as frames, i.e. the roots
+/// the trees hang from. Sorted.
+pub fn find_roots(frames: &HashMap<String, SPTransformStamped>) -> Vec<String> {
+    let children: HashSet<&str> = frames.values().map(|f| f.child_frame_id.as_str()).collect();
+    let mut roots: Vec<String> = frames
+        .values()
+        .filter(|f| !children.contains(f.parent_frame_id.as_str()))
+        .map(|f| f.parent_frame_id.clone())
+        .collect::<HashSet<String>>()
+        .into_iter()
+        .collect();
+    roots.sort();
+    roots
+}
+
+/// Frames whose parent is neither a frame nor `root_frame`, as (child,
+/// missing parent), sorted. TF can't place them, so they and everything below
+/// them don't render.
+pub fn find_orphan_frames(
+    frames: &HashMap<String, SPTransformStamped>,
+    root_frame: &str,
+) -> Vec<(String, String)> {
+    let children: HashSet<&str> = frames.values().map(|f| f.child_frame_id.as_str()).collect();
+    let mut orphans: Vec<(String, String)> = frames
+        .values()
+        .filter(|f| f.parent_frame_id != root_frame)
+        .filter(|f| !children.contains(f.parent_frame_id.as_str()))
+        .map(|f| (f.child_frame_id.clone(), f.parent_frame_id.clone()))
+        .collect();
+    orphans.sort();
+    orphans
+}
+
+/// Cycles in the parent links, each as the frame names along it, starting
+/// from its alphabetically first frame so the result is deterministic.
+pub fn detect_tf_cycles(frames: &HashMap<String, SPTransformStamped>) -> Vec<Vec<String>> {
+    let parents: HashMap<&str, &str> = frames
+        .values()
+        .map(|f| (f.child_frame_id.as_str(), f.parent_frame_id.as_str()))
+        .collect();
+    let mut cycles = vec![];
+    let mut done: HashSet<&str> = HashSet::new();
+    let mut names: Vec<&str> = parents.keys().copied().collect();
+    names.sort();
+    for start in names {
+        let mut path: Vec<&str> = vec![];
+        let mut current = start;
+        while !done.contains(current) {
+            if let Some(position) = path.iter().position(|n| *n == current) {
+                let mut cycle: Vec<String> = path[position..].iter().map(|n| n.to_string()).collect();
+                let first = (0..cycle.len()).min_by_key(|i| &cycle[*i]).unwrap_or(0);
+                cycle.rotate_left(first);
+                cycles.push(cycle);
+                break;
+            }
+            path.push(current);
+            match parents.get(current) {
+                Some(parent) => current = *parent,
+                None => break,
+            }
+        }
+        done.extend(path);
+    }
+    cycles
+}
+
+/// Number of parent links from each frame up to its root, 1 for a frame whose
+/// parent is a root. Walks the chains iteratively and reuses the depths it
+/// already knows, so deep trees cost linear time and no stack. Frames on or
+/// below a cycle are left out.
+pub fn chain_depths(frames: &HashMap<String, SPTransformStamped>) -> HashMap<String, usize> {
+    let parents: HashMap<&str, &str> = frames
+        .values()
+        .map(|f| (f.child_frame_id.as_str(), f.parent_frame_id.as_str()))
+        .collect();
+    let mut depths: HashMap<&str, usize> = HashMap::new();
+    let mut cyclic: HashSet<&str> = HashSet::new();
+    for start in parents.keys().copied() {
+        let mut path: Vec<&str> = vec![];
+        let mut on_path: HashSet<&str> = HashSet::new();
+        let mut current = start;
+        let base = loop {
+            if let Some(depth) = depths.get(current) {
+                break Some(*depth);
+            }
+            if cyclic.contains(current) || !on_path.insert(current) {
+                break None;
+            }
+            path.push(current);
+            match parents.get(current) {
+                Some(parent) => current = *parent,
+                // `current` hangs from a root
+                None => break Some(0),
+            }
+        };
+        match base {
+            Some(base) => {
+                for (i, name) in path.iter().rev().enumerate() {
+                    depths.insert(*name, base + i + 1);
+                }
+            }
+            None => cyclic.extend(path),
+        }
+    }
+    depths
+        .into_iter()
+        .map(|(name, depth)| (name.to_string(), depth))
+        .collect()
+}
+
+/// What to do with frames that define the same child under different parents.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParentConflictPolicy {
+    /// Publish none of the conflicting definitions.
+    Error,
+    /// Keep the definition whose key sorts first.
+    First,
+    /// Keep the definition whose key sorts last.
+    Last,
+}
+
+impl FromStr for ParentConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "error" => Ok(ParentConflictPolicy::Error),
+            "first" => Ok(ParentConflictPolicy::First),
+            "last" => Ok(ParentConflictPolicy::Last),
+            _ => Err(format!("unknown parent conflict policy '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for ParentConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParentConflictPolicy::Error => write!(f, "error"),
+            ParentConflictPolicy::First => write!(f, "first"),
+            ParentConflictPolicy::Last => write!(f, "last"),
+        }
+    }
+}
+
+/// A child frame defined under more than one parent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParentConflict {
+    pub child_frame_id: String,
+    /// (key, parent) of every definition, sorted by key.
+    pub definitions: Vec<(String, String)>,
+}
+
+/// Find child frames defined more than once with different parents, as can
+/// happen when live sources are merged, and drop definitions per `policy` so
+/// each child keeps at most one parent.
+pub fn resolve_parent_conflicts(
+    frames: &mut HashMap<String, SPTransformStamped>,
+    policy: ParentConflictPolicy,
+) -> Vec<ParentConflict> {
+    let mut by_child: HashMap<&str, Vec<(&str, &str)>> = HashMap::new();
+    for (key, frame) in frames.iter() {
+        by_child
+            .entry(frame.child_frame_id.as_str())
+            .or_default()
+            .push((key.as_str(), frame.parent_frame_id.as_str()));
+    }
+    let mut conflicts: Vec<ParentConflict> = by_child
+        .into_iter()
+        .filter(|(_, definitions)| {
+            definitions.iter().any(|(_, parent)| *parent != definitions[0].1)
+        })
+        .map(|(child, mut definitions)| {
+            definitions.sort();
+            ParentConflict {
+                child_frame_id: child.to_string(),
+                definitions: definitions
+                    .into_iter()
+                    .map(|(key, parent)| (key.to_string(), parent.to_string()))
+                    .collect(),
+            }
+        })
+        .collect();
+    conflicts.sort_by(|a, b| a.child_frame_id.cmp(&b.child_frame_id));
+    for conflict in &conflicts {
+        let keep = match policy {
+            ParentConflictPolicy::Error => None,
+            ParentConflictPolicy::First => conflict.definitions.first(),
+            ParentConflictPolicy::Last => conflict.definitions.last(),
+        };
+        for (key, _) in &conflict.definitions {
+            if Some(key) != keep.map(|(kept, _)| kept) {
+                frames.remove(key);
+            }
+        }
+    }
+    conflicts
+}
+
+/// What to do with frame names that differ only by case, e.g. `Base` and
+/// `base`, which TF treats as unrelated frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseDuplicatePolicy {
+    /// Don't look for them.
+    Off,
+    /// Warn about them and publish the frames as they are.
+    Warn,
+    /// Rename every spelling to the one that sorts first, dropping the frames
+    /// that then define an already defined child.
+    Merge,
+}
+
+impl FromStr for CaseDuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(CaseDuplicatePolicy::Off),
+            "warn" => Ok(CaseDuplicatePolicy::Warn),
+            "merge" => Ok(CaseDuplicatePolicy::Merge),
+            _ => Err(format!("unknown case duplicate policy '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for CaseDuplicatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaseDuplicatePolicy::Off => write!(f, "off"),
+            CaseDuplicatePolicy::Warn => write!(f, "warn"),
+            CaseDuplicatePolicy::Merge => write!(f, "merge"),
+        }
+    }
+}
+
+/// Frame names, children and parents alike, that are equal ignoring case.
+/// Each group is sorted and has at least two spellings, the groups are sorted
+/// by their first spelling.
+pub fn case_duplicates(frames: &HashMap<String, SPTransformStamped>) -> Vec<Vec<String>> {
+    let mut spellings: HashMap<String, HashSet<&str>> = HashMap::new();
+    for frame in frames.values() {
+        for name in [
+            frame.child_frame_id.as_str(),
+            frame.parent_frame_id.as_str(),
+        ] {
+            spellings
+                .entry(name.to_lowercase())
+                .or_default()
+                .insert(name);
+        }
+    }
+    let mut groups: Vec<Vec<String>> = spellings
+        .into_values()
+        .filter(|names| names.len() > 1)
+        .map(|names| {
+            let mut names: Vec<String> = names.into_iter().map(str::to_string).collect();
+            names.sort();
+            names
+        })
+        .collect();
+    groups.sort();
+    groups
+}
+
+/// Rename the spellings of each group of `case_duplicates` to the first one.
+/// A frame whose child is renamed onto a child that is already defined is
+/// dropped, the frame with the first spelling wins.
+pub fn merge_case_duplicates(
+    frames: &mut HashMap<String, SPTransformStamped>,
+    groups: &[Vec<String>],
+) {
+    let canonical: HashMap<&str, &str> = groups
+        .iter()
+        .flat_map(|names| {
+            names[1..]
+                .iter()
+                .map(move |name| (name.as_str(), names[0].as_str()))
+        })
+        .collect();
+    let defined: HashSet<String> = frames.values().map(|f| f.child_frame_id.clone()).collect();
+    let mut keys: Vec<String> = frames.keys().cloned().collect();
+    keys.sort();
+    let mut merged: HashSet<String> = HashSet::new();
+    for key in keys {
+        let Some(frame) = frames.get_mut(&key) else {
+            continue;
+        };
+        if let Some(parent) = canonical.get(frame.parent_frame_id.as_str()) {
+            frame.parent_frame_id = parent.to_string();
+        }
+        if let Some(child) = canonical.get(frame.child_frame_id.as_str()) {
+            if defined.contains(*child) || !merged.insert(child.to_string()) {
+                frames.remove(&key);
+            } else {
+                frame.child_frame_id = child.to_string();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_edits::new_frame;
+    use r2r::geometry_msgs::msg::{Quaternion, Transform};
+
+    /// Frames by key from `(key, child, parent)`.
+    fn frames(links: &[(&str, &str, &str)]) -> HashMap<String, SPTransformStamped> {
+        let identity = Transform {
+            rotation: Quaternion {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+                w: 1.0,
+            },
+            ..Default::default()
+        };
+        links
+            .iter()
+            .map(|(key, child, parent)| {
+                let frame = new_frame(parent, child, &identity, false).unwrap();
+                (key.to_string(), frame)
+            })
+            .collect()
+    }
+
+    fn tree(links: &[(&str, &str)]) -> HashMap<String, SPTransformStamped> {
+        let links: Vec<_> = links
+            .iter()
+            .map(|(child, parent)| (*child, *child, *parent))
+            .collect();
+        frames(&links)
+    }
+
+    #[test]
+    fn a_clean_tree_has_no_cycles() {
+        let frames = tree(&[("table", "world"), ("robot", "world"), ("tool", "robot")]);
+        assert!(detect_tf_cycles(&frames).is_empty());
+    }
+
+    #[test]
+    fn a_frame_can_be_its_own_cycle() {
+        let frames = tree(&[("a", "a"), ("b", "world")]);
+        assert_eq!(detect_tf_cycles(&frames), vec![vec!["a".to_string()]]);
+    }
+
+    #[test]
+    fn cycles_start_at_their_first_frame() {
+        let frames = tree(&[("b", "c"), ("c", "a"), ("a", "b"), ("d", "a")]);
+        let cycle: Vec<String> = ["a", "b", "c"].iter().map(|n| n.to_string()).collect();
+        assert_eq!(detect_tf_cycles(&frames), vec![cycle]);
+    }
+
+    fn children(frames: &HashMap<String, SPTransformStamped>) -> Vec<(&str, &str)> {
+        let mut children: Vec<(&str, &str)> = frames
+            .values()
+            .map(|f| (f.child_frame_id.as_str(), f.parent_frame_id.as_str()))
+            .collect();
+        children.sort();
+        children
+    }
+
+    /// `robot` claimed by two parents.
+    const CONFLICTING: [(&str, &str, &str); 3] = [
+        ("robot", "robot", "world"),
+        ("robot_copy", "robot", "table"),
+        ("table", "table", "world"),
+    ];
+
+    #[test]
+    fn first_keeps_the_definition_whose_key_sorts_first() {
+        let mut frames = frames(&CONFLICTING);
+        let conflicts = resolve_parent_conflicts(&mut frames, ParentConflictPolicy::First);
+        assert_eq!(
+            conflicts,
+            [ParentConflict {
+                child_frame_id: "robot".to_string(),
+                definitions: vec![
+                    ("robot".to_string(), "world".to_string()),
+                    ("robot_copy".to_string(), "table".to_string()),
+                ],
+            }]
+        );
+        assert_eq!(children(&frames), [("robot", "world"), ("table", "world")]);
+    }
+
+    #[test]
+    fn last_keeps_the_definition_whose_key_sorts_last() {
+        let mut frames = frames(&CONFLICTING);
+        let conflicts = resolve_parent_conflicts(&mut frames, ParentConflictPolicy::Last);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(children(&frames), [("robot", "table"), ("table", "world")]);
+    }
+
+    #[test]
+    fn error_drops_every_definition_of_the_child() {
+        let mut frames = frames(&CONFLICTING);
+        let conflicts = resolve_parent_conflicts(&mut frames, ParentConflictPolicy::Error);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(children(&frames), [("table", "world")]);
+    }
+
+    #[test]
+    fn duplicates_under_the_same_parent_are_no_conflict() {
+        let mut frames = frames(&[
+            ("robot", "robot", "world"),
+            ("robot_copy", "robot", "world"),
+        ]);
+        let conflicts = resolve_parent_conflicts(&mut frames, ParentConflictPolicy::Error);
+        assert!(conflicts.is_empty());
+        assert_eq!(frames.len(), 2);
+    }
+}
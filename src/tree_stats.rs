@@ -0,0 +1,113 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::Instant;
+
+use micro_sp::*;
+
+use crate::events::{FrameEventKind, FrameEventTracker};
+use crate::heartbeat::Heartbeat;
+use crate::log_targets;
+use crate::tree::{chain_depths, find_roots};
+
+/// Busiest frames named in the periodic log, all of them are logged at debug.
+const BUSIEST_FRAMES: usize = 5;
+
+/// Shape of the TF tree at one tick.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStats {
+    pub frames: usize,
+    pub active: usize,
+    pub static_frames: usize,
+    pub roots: usize,
+    pub max_depth: usize,
+    /// Average number of children of the frames and roots that have any.
+    pub branching: f64,
+}
+
+pub fn tree_stats(frames: &HashMap<String, SPTransformStamped>, force_static: bool) -> TreeStats {
+    let active = frames
+        .values()
+        .filter(|f| f.active_transform && !force_static)
+        .count();
+    let parents: HashSet<&str> = frames.values().map(|f| f.parent_frame_id.as_str()).collect();
+    TreeStats {
+        frames: frames.len(),
+        active,
+        static_frames: frames.len() - active,
+        roots: find_roots(frames).len(),
+        max_depth: chain_depths(frames).into_values().max().unwrap_or(0),
+        branching: match parents.len() {
+            0 => 0.0,
+            n => frames.len() as f64 / n as f64,
+        },
+    }
+}
+
+impl fmt::Display for TreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} frames ({} active, {} static), {} roots, max depth {}, branching {:.2}",
+            self.frames, self.active, self.static_frames, self.roots, self.max_depth, self.branching
+        )
+    }
+}
+
+/// Logs the tree statistics and how often each frame moved, once per interval.
+pub struct TreeStatsReporter {
+    interval: Heartbeat,
+    changes: FrameEventTracker,
+    /// Moves of each frame since the last report.
+    updates: HashMap<String, usize>,
+    since: Instant,
+}
+
+impl TreeStatsReporter {
+    /// Frames count as updated when they moved `move_threshold` meters.
+    pub fn new(interval_secs: u64, move_threshold: f64) -> TreeStatsReporter {
+        TreeStatsReporter {
+            interval: Heartbeat::new(interval_secs),
+            changes: FrameEventTracker::new(move_threshold),
+            updates: HashMap::new(),
+            since: Instant::now(),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        frames: &HashMap<String, SPTransformStamped>,
+        force_static: bool,
+        now: Instant,
+    ) {
+        for event in self.changes.update(frames, 0.0) {
+            if event.event == FrameEventKind::Moved {
+                *self.updates.entry(event.frame).or_insert(0) += 1;
+            }
+        }
+        if !self.interval.due(now) {
+            return;
+        }
+        let secs = now.duration_since(self.since).as_secs_f64().max(f64::EPSILON);
+        let mut rates: Vec<(String, f64)> = self
+            .updates
+            .drain()
+            .map(|(frame, count)| (frame, count as f64 / secs))
+            .collect();
+        rates.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let busiest: Vec<String> = rates
+            .iter()
+            .take(BUSIEST_FRAMES)
+            .map(|(frame, rate)| format!("'{}' {:.1} Hz", frame, rate))
+            .collect();
+        log::info!(target: log_targets::TF,
+            "TF tree: {}, {} frames moving, busiest: {}.",
+            tree_stats(frames, force_static),
+            rates.len(),
+            if busiest.is_empty() { "none".to_string() } else { busiest.join(", ") }
+        );
+        log::debug!(target: log_targets::TF,
+            "TF update rates over {:.0}s: {:?}", secs, rates
+        );
+        self.since = now;
+    }
+}
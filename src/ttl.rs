@@ -0,0 +1,33 @@
+use std::collections::HashMap;
+
+use redis::aio::MultiplexedConnection;
+
+use crate::error::VizError;
+
+/// Remaining time to live in milliseconds of each frame's Redis key, frames
+/// whose key has no expiry (or vanished) are left out.
+pub async fn fetch_ttls(
+    con: &mut MultiplexedConnection,
+    key_prefix: &str,
+    names: &[String],
+) -> Result<HashMap<String, i64>, VizError> {
+    let mut pipe = redis::pipe();
+    for name in names {
+        pipe.cmd("PTTL").arg(format!("{}{}", key_prefix, name));
+    }
+    let ttls: Vec<i64> = pipe.query_async(con).await?;
+    Ok(names
+        .iter()
+        .cloned()
+        .zip(ttls)
+        .filter(|(_, ttl)| *ttl >= 0)
+        .collect())
+}
+
+/// Indicator fraction in 0.0..=1.0, 1.0 for anything with at least `full_ms` left.
+pub fn ttl_fraction(remaining_ms: i64, full_ms: i64) -> f64 {
+    if full_ms <= 0 {
+        return 1.0;
+    }
+    (remaining_ms as f64 / full_ms as f64).clamp(0.0, 1.0)
+}
@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use micro_sp::*;
+
+use crate::config::Config;
+use crate::metadata::{decode_metadata, decode_problems};
+use crate::tree::{detect_tf_cycles, find_orphan_frames, find_roots};
+
+/// One-time summary of the loaded scene.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    pub frames: usize,
+    pub roots: Vec<String>,
+    pub cycles: Vec<Vec<String>>,
+    /// (child, missing parent)
+    pub dangling_parents: Vec<(String, String)>,
+    /// (frame, mesh path)
+    pub missing_meshes: Vec<(String, String)>,
+    /// (frame, problem)
+    pub decode_failures: Vec<(String, String)>,
+}
+
+impl ValidationReport {
+    pub fn has_problems(&self) -> bool {
+        !(self.cycles.is_empty()
+            && self.dangling_parents.is_empty()
+            && self.missing_meshes.is_empty()
+            && self.decode_failures.is_empty())
+    }
+}
+
+pub fn validate_scene(frames: &HashMap<String, SPTransformStamped>, config: &Config) -> ValidationReport {
+    let mut report = ValidationReport {
+        frames: frames.len(),
+        roots: find_roots(frames),
+        cycles: detect_tf_cycles(frames),
+        dangling_parents: find_orphan_frames(frames, &config.world_frame),
+        ..Default::default()
+    };
+
+    let mut names: Vec<&String> = frames.keys().collect();
+    names.sort();
+    for name in names {
+        let frame = &frames[name];
+        for problem in decode_problems(&frame.metadata) {
+            report.decode_failures.push((frame.child_frame_id.clone(), problem));
+        }
+        let metadata = decode_metadata(&frame.metadata);
+        if let (true, Some(path)) = (metadata.visualize_mesh, &metadata.mesh_file) {
+            let dir = metadata.override_meshes_dir.as_ref().unwrap_or(&config.meshes_dir);
+            if local_mesh_path(dir, path).is_some_and(|path| !path.exists()) {
+                report
+                    .missing_meshes
+                    .push((frame.child_frame_id.clone(), path.clone()));
+            }
+        }
+    }
+    report
+}
+
+/// Where a mesh file is on disk, None for `package://` URIs, which only RViz
+/// resolves.
+pub fn local_mesh_path(meshes_dir: &str, path: &str) -> Option<PathBuf> {
+    if path.starts_with("package://") {
+        return None;
+    }
+    match path.strip_prefix("file://") {
+        Some(path) => Some(PathBuf::from(path)),
+        // joining an absolute path replaces the directory
+        None => Some(Path::new(meshes_dir).join(path)),
+    }
+}
+
+/// Unique mesh files the scene references, visual, animated and collision, resolved
+/// against their meshes directory and mapped to whether they exist. `package://`
+/// meshes are left out.
+pub fn mesh_working_set(
+    frames: &HashMap<String, SPTransformStamped>,
+    config: &Config,
+) -> BTreeMap<PathBuf, bool> {
+    let mut meshes = BTreeMap::new();
+    for frame in frames.values() {
+        let metadata = decode_metadata(&frame.metadata);
+        let dir = metadata.override_meshes_dir.as_ref().unwrap_or(&config.meshes_dir);
+        let visual = metadata.mesh_file.iter().chain(metadata.mesh_animation.iter().flatten());
+        let visual = visual.filter(|_| metadata.visualize_mesh);
+        for path in visual.chain(metadata.collision_mesh_file.as_ref()) {
+            let Some(path) = local_mesh_path(dir, path) else {
+                continue;
+            };
+            let exists = path.exists();
+            meshes.insert(path, exists);
+        }
+    }
+    meshes
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Scene validation report:")?;
+        writeln!(f, "  frames loaded: {}", self.frames)?;
+        writeln!(f, "  roots: {}", self.roots.join(", "))?;
+        writeln!(f, "  cycles: {}", self.cycles.len())?;
+        for cycle in &self.cycles {
+            writeln!(f, "    {}", cycle.join(" -> "))?;
+        }
+        writeln!(f, "  dangling parents: {}", self.dangling_parents.len())?;
+        for (child, parent) in &self.dangling_parents {
+            writeln!(f, "    '{}' references missing parent '{}'", child, parent)?;
+        }
+        writeln!(f, "  missing mesh files: {}", self.missing_meshes.len())?;
+        for (frame, path) in &self.missing_meshes {
+            writeln!(f, "    '{}' uses missing mesh '{}'", frame, path)?;
+        }
+        write!(f, "  metadata decode failures: {}", self.decode_failures.len())?;
+        for (frame, problem) in &self.decode_failures {
+            write!(f, "\n    '{}': {}", frame, problem)?;
+        }
+        Ok(())
+    }
+}
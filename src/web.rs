@@ -0,0 +1,44 @@
+use r2r::visualization_msgs::msg::{Marker, MarkerArray};
+use r2r::QosProfile;
+
+/// QoS of the TF topics. In web compatibility mode `tf` is published volatile,
+/// which is what rosbridge subscribes with, `tf_static` stays transient local.
+pub fn tf_qos(web_compat: bool) -> QosProfile {
+    if web_compat {
+        QosProfile::default()
+    } else {
+        QosProfile::transient_local(QosProfile::default())
+    }
+}
+
+/// The mesh and zone markers combined for ros3djs/roslibjs viewers: empty
+/// namespaces become `mesh`/`zone`, and `file://` mesh URIs under the meshes
+/// directory are rewritten onto `web_mesh_url` so a browser can fetch them.
+pub fn web_markers(
+    mesh_markers: &[Marker],
+    zone_markers: &[Marker],
+    meshes_dir: &str,
+    web_mesh_url: Option<&str>,
+) -> MarkerArray {
+    let local_prefix = format!("file://{}/", meshes_dir.trim_end_matches('/'));
+    let with_ns = |markers: &[Marker], ns: &str| -> Vec<Marker> {
+        markers
+            .iter()
+            .cloned()
+            .map(|mut marker| {
+                if marker.ns.is_empty() {
+                    marker.ns = ns.to_string();
+                }
+                if let (Some(url), Some(relative)) =
+                    (web_mesh_url, marker.mesh_resource.strip_prefix(&local_prefix))
+                {
+                    marker.mesh_resource = format!("{}/{}", url.trim_end_matches('/'), relative);
+                }
+                marker
+            })
+            .collect()
+    };
+    let mut markers = with_ns(mesh_markers, "mesh");
+    markers.extend(with_ns(zone_markers, "zone"));
+    MarkerArray { markers }
+}
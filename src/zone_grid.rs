@@ -0,0 +1,71 @@
+use r2r::builtin_interfaces::msg::Time;
+use r2r::geometry_msgs::msg::{Point, Pose};
+use r2r::nav_msgs::msg::{MapMetaData, OccupancyGrid};
+use r2r::std_msgs::msg::Header;
+
+use crate::markers::identity_pose;
+
+pub const FREE: i8 = 0;
+pub const OCCUPIED: i8 = 100;
+
+/// Floor footprint of a zone, a disc in the XY plane of the grid frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Footprint {
+    pub center: [f64; 2],
+    pub radius: f64,
+}
+
+/// Rasterize zone footprints onto an occupancy grid in `frame_id`. The grid
+/// covers the extent of the footprints, cells whose center lies inside a
+/// footprint are occupied. None without footprints.
+pub fn zone_grid(footprints: &[Footprint], resolution: f64, frame_id: &str) -> Option<OccupancyGrid> {
+    if footprints.is_empty() || resolution <= 0.0 {
+        return None;
+    }
+    let min_x = footprints.iter().map(|f| f.center[0] - f.radius).fold(f64::INFINITY, f64::min);
+    let min_y = footprints.iter().map(|f| f.center[1] - f.radius).fold(f64::INFINITY, f64::min);
+    let max_x = footprints.iter().map(|f| f.center[0] + f.radius).fold(f64::NEG_INFINITY, f64::max);
+    let max_y = footprints.iter().map(|f| f.center[1] + f.radius).fold(f64::NEG_INFINITY, f64::max);
+    let origin = [
+        (min_x / resolution).floor() * resolution,
+        (min_y / resolution).floor() * resolution,
+    ];
+    let width = (((max_x - origin[0]) / resolution).ceil() as u32).max(1);
+    let height = (((max_y - origin[1]) / resolution).ceil() as u32).max(1);
+
+    let mut data = vec![FREE; (width * height) as usize];
+    for row in 0..height {
+        for column in 0..width {
+            let x = origin[0] + (column as f64 + 0.5) * resolution;
+            let y = origin[1] + (row as f64 + 0.5) * resolution;
+            let inside = footprints.iter().any(|f| {
+                (x - f.center[0]).powi(2) + (y - f.center[1]).powi(2) <= f.radius.powi(2)
+            });
+            if inside {
+                data[(row * width + column) as usize] = OCCUPIED;
+            }
+        }
+    }
+
+    Some(OccupancyGrid {
+        header: Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: frame_id.to_string(),
+        },
+        info: MapMetaData {
+            map_load_time: Time { sec: 0, nanosec: 0 },
+            resolution: resolution as f32,
+            width,
+            height,
+            origin: Pose {
+                position: Point {
+                    x: origin[0],
+                    y: origin[1],
+                    z: 0.0,
+                },
+                ..identity_pose()
+            },
+        },
+        data,
+    })
+}
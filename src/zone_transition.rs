@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+struct Transition {
+    from: f64,
+    to: f64,
+    start: Instant,
+}
+
+/// Animates zone size changes linearly over a fixed duration instead of snapping.
+pub struct ZoneTransitions {
+    duration: Duration,
+    zones: HashMap<String, Transition>,
+}
+
+impl ZoneTransitions {
+    /// A duration of 0 disables the animation.
+    pub fn new(duration_secs: f64) -> ZoneTransitions {
+        ZoneTransitions {
+            duration: Duration::from_secs_f64(duration_secs.max(0.0)),
+            zones: HashMap::new(),
+        }
+    }
+
+    /// The size to render for `frame` at `now`, given its current target size.
+    pub fn size(&mut self, frame: &str, target: f64, now: Instant) -> f64 {
+        if self.duration.is_zero() {
+            return target;
+        }
+        let duration = self.duration;
+        let transition = self.zones.entry(frame.to_string()).or_insert(Transition {
+            from: target,
+            to: target,
+            start: now,
+        });
+        if transition.to != target {
+            *transition = Transition {
+                from: interpolate(transition, now, duration),
+                to: target,
+                start: now,
+            };
+        }
+        interpolate(transition, now, duration)
+    }
+
+    /// Forget zones of frames that no longer exist.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.zones.retain(|frame, _| keep(frame));
+    }
+}
+
+fn interpolate(transition: &Transition, now: Instant, duration: Duration) -> f64 {
+    let t = (now.duration_since(transition.start).as_secs_f64() / duration.as_secs_f64()).min(1.0);
+    transition.from + (transition.to - transition.from) * t
+}